@@ -0,0 +1,950 @@
+//! Bookmarks: a tree of folders and tagged links, saved as RON next to the
+//! other asset files, with import and export in the Netscape bookmark file
+//! format that other browsers read and write.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::constants::BOOKMARKS_PATH;
+use crate::dom::{Document, NodeId};
+use crate::history::{Navigate, NavigationAction};
+use crate::tabs::TabAction;
+use crate::{Bookmarks, BrowsingHistory, ShowBookmarksWindow, Tabs};
+
+/// Id of the bookmarks bar folder.
+pub const BAR_FOLDER: u64 = 0;
+/// Id of the folder for bookmarks that are not on the bar.
+pub const OTHER_FOLDER: u64 = 1;
+/// Longest label of a button on the bookmarks bar.
+const MAX_BAR_LABEL_CHARS: usize = 24;
+/// File the manager window imports from and exports to unless changed.
+const DEFAULT_EXCHANGE_FILE: &str = "bookmarks.html";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Bookmark {
+    pub id: u64,
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Unix seconds.
+    pub added: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Folder {
+    pub id: u64,
+    pub title: String,
+    pub added: u64,
+    pub children: Vec<BookmarkNode>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum BookmarkNode {
+    Bookmark(Bookmark),
+    Folder(Folder),
+}
+
+impl BookmarkNode {
+    pub fn id(&self) -> u64 {
+        match self {
+            BookmarkNode::Bookmark(bookmark) => bookmark.id,
+            BookmarkNode::Folder(folder) => folder.id,
+        }
+    }
+}
+
+impl Folder {
+    fn new(id: u64, title: impl Into<String>) -> Self {
+        Folder {
+            id,
+            title: title.into(),
+            added: now(),
+            children: Vec::new(),
+        }
+    }
+
+    /// Finds this folder or a folder below it.
+    fn folder(&self, id: u64) -> Option<&Folder> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|node| match node {
+            BookmarkNode::Folder(folder) => folder.folder(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    fn folder_mut(&mut self, id: u64) -> Option<&mut Folder> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|node| match node {
+            BookmarkNode::Folder(folder) => folder.folder_mut(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    /// Finds a node below this folder.
+    fn find(&self, id: u64) -> Option<&BookmarkNode> {
+        self.children.iter().find_map(|node| match node {
+            _ if node.id() == id => Some(node),
+            BookmarkNode::Folder(folder) => folder.find(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    fn find_mut(&mut self, id: u64) -> Option<&mut BookmarkNode> {
+        for node in &mut self.children {
+            if node.id() == id {
+                return Some(node);
+            }
+            if let BookmarkNode::Folder(folder) = node
+                && let Some(found) = folder.find_mut(id)
+            {
+                return Some(found);
+            }
+        }
+        None
+    }
+
+    /// Id of the folder directly holding the node.
+    fn parent_of(&self, id: u64) -> Option<u64> {
+        self.children.iter().find_map(|node| match node {
+            _ if node.id() == id => Some(self.id),
+            BookmarkNode::Folder(folder) => folder.parent_of(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    /// Removes a node below this folder and returns it.
+    fn take(&mut self, id: u64) -> Option<BookmarkNode> {
+        if let Some(index) = self.children.iter().position(|node| node.id() == id) {
+            return Some(self.children.remove(index));
+        }
+        self.children.iter_mut().find_map(|node| match node {
+            BookmarkNode::Folder(folder) => folder.take(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    fn max_id(&self) -> u64 {
+        self.children
+            .iter()
+            .map(|node| match node {
+                BookmarkNode::Folder(folder) => folder.max_id(),
+                BookmarkNode::Bookmark(bookmark) => bookmark.id,
+            })
+            .fold(self.id, u64::max)
+    }
+
+    fn collect_bookmarks<'a>(&'a self, path: &str, out: &mut Vec<(&'a Bookmark, String)>) {
+        for node in &self.children {
+            match node {
+                BookmarkNode::Bookmark(bookmark) => out.push((bookmark, path.to_string())),
+                BookmarkNode::Folder(folder) => folder.collect_bookmarks(&format!("{path} / {}", folder.title), out),
+            }
+        }
+    }
+
+    fn collect_folders(&self, path: &str, out: &mut Vec<(u64, String)>) {
+        out.push((self.id, path.to_string()));
+        for node in &self.children {
+            if let BookmarkNode::Folder(folder) = node {
+                folder.collect_folders(&format!("{path} / {}", folder.title), out);
+            }
+        }
+    }
+}
+
+/// All bookmarks: the folder shown as the bookmarks bar and the one for the rest.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BookmarkStore {
+    pub bar: Folder,
+    pub other: Folder,
+    next_id: u64,
+}
+
+impl Default for BookmarkStore {
+    fn default() -> Self {
+        BookmarkStore {
+            bar: Folder::new(BAR_FOLDER, "Bookmarks bar"),
+            other: Folder::new(OTHER_FOLDER, "Other bookmarks"),
+            next_id: OTHER_FOLDER + 1,
+        }
+    }
+}
+
+/// Where the bookmarks are saved: `BOOKMARKS_PATH` under the assets directory.
+pub fn store_path() -> PathBuf {
+    FileAssetReader::get_base_path().join("assets").join(BOOKMARKS_PATH)
+}
+
+impl BookmarkStore {
+    /// Reads the saved bookmarks, starting empty if there are none yet.
+    pub fn load(path: &Path) -> Self {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return BookmarkStore::default(),
+            Err(e) => {
+                error!("Failed to read bookmarks from {}: {}", path.display(), e);
+                return BookmarkStore::default();
+            }
+        };
+        match ron::from_str::<BookmarkStore>(&text) {
+            Ok(mut store) => {
+                // 手で書き換えられていても id が重ならないようにする
+                store.next_id = store.next_id.max(store.bar.max_id().max(store.other.max_id()) + 1);
+                store
+            }
+            Err(e) => {
+                error!("Failed to parse bookmarks in {}: {}", path.display(), e);
+                BookmarkStore::default()
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let text = ron::ser::to_string_pretty(self, PrettyConfig::default()).map_err(std::io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, text)
+    }
+
+    fn allocate_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    pub fn folder(&self, id: u64) -> Option<&Folder> {
+        self.bar.folder(id).or_else(|| self.other.folder(id))
+    }
+
+    fn folder_mut(&mut self, id: u64) -> Option<&mut Folder> {
+        match self.bar.folder_mut(id) {
+            Some(folder) => Some(folder),
+            None => self.other.folder_mut(id),
+        }
+    }
+
+    pub fn find(&self, id: u64) -> Option<&BookmarkNode> {
+        self.bar.find(id).or_else(|| self.other.find(id))
+    }
+
+    fn find_mut(&mut self, id: u64) -> Option<&mut BookmarkNode> {
+        match self.bar.find_mut(id) {
+            Some(node) => Some(node),
+            None => self.other.find_mut(id),
+        }
+    }
+
+    pub fn parent_of(&self, id: u64) -> Option<u64> {
+        self.bar.parent_of(id).or_else(|| self.other.parent_of(id))
+    }
+
+    /// The first bookmark of this URL, in bar-then-other order.
+    pub fn find_url(&self, url: &str) -> Option<&Bookmark> {
+        self.bookmarks().into_iter().map(|(bookmark, _)| bookmark).find(|bookmark| bookmark.url == url)
+    }
+
+    /// Every bookmark with the path of the folder it is in.
+    pub fn bookmarks(&self) -> Vec<(&Bookmark, String)> {
+        let mut out = Vec::new();
+        self.bar.collect_bookmarks(&self.bar.title, &mut out);
+        self.other.collect_bookmarks(&self.other.title, &mut out);
+        out
+    }
+
+    /// Every folder, including the two roots, with its path.
+    pub fn folders(&self) -> Vec<(u64, String)> {
+        let mut out = Vec::new();
+        self.bar.collect_folders(&self.bar.title, &mut out);
+        self.other.collect_folders(&self.other.title, &mut out);
+        out
+    }
+
+    /// All tags in use, sorted.
+    pub fn tags(&self) -> Vec<String> {
+        let mut tags: Vec<String> = self.bookmarks().into_iter().flat_map(|(bookmark, _)| bookmark.tags.iter().cloned()).collect();
+        tags.sort_by_key(|tag| tag.to_lowercase());
+        tags.dedup();
+        tags
+    }
+
+    pub fn add_bookmark(&mut self, folder: u64, title: String, url: String, tags: Vec<String>) -> Option<u64> {
+        let id = self.allocate_id();
+        self.folder_mut(folder)?.children.push(BookmarkNode::Bookmark(Bookmark {
+            id,
+            title,
+            url,
+            tags,
+            added: now(),
+        }));
+        Some(id)
+    }
+
+    pub fn add_folder(&mut self, parent: u64, title: String) -> Option<u64> {
+        let id = self.allocate_id();
+        self.folder_mut(parent)?.children.push(BookmarkNode::Folder(Folder::new(id, title)));
+        Some(id)
+    }
+
+    /// Removes a bookmark or a folder with everything in it. The two root folders stay.
+    pub fn remove(&mut self, id: u64) -> Option<BookmarkNode> {
+        self.bar.take(id).or_else(|| self.other.take(id))
+    }
+
+    /// Changes the title, and for bookmarks the URL and tags.
+    pub fn update(&mut self, id: u64, title: String, url: String, tags: Vec<String>) {
+        match self.find_mut(id) {
+            Some(BookmarkNode::Bookmark(bookmark)) => {
+                bookmark.title = title;
+                bookmark.url = url;
+                bookmark.tags = tags;
+            }
+            Some(BookmarkNode::Folder(folder)) => folder.title = title,
+            None => {}
+        }
+    }
+
+    /// Moves a node to the end of another folder. Fails for a folder moved into itself.
+    pub fn move_to(&mut self, id: u64, folder: u64) -> bool {
+        let inside_itself = match self.find(id) {
+            Some(BookmarkNode::Folder(moved)) => moved.folder(folder).is_some(),
+            Some(BookmarkNode::Bookmark(_)) => false,
+            None => return false,
+        };
+        if inside_itself || self.folder(folder).is_none() {
+            return false;
+        }
+        let Some(node) = self.remove(id) else {
+            return false;
+        };
+        if let Some(target) = self.folder_mut(folder) {
+            target.children.push(node);
+        }
+        true
+    }
+
+    /// Writes the bookmarks as a Netscape bookmark file. The bar becomes the
+    /// toolbar folder and the other bookmarks go at the top level, as Firefox does.
+    pub fn to_netscape_html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+             <!-- This is an automatically generated file.\n     It will be read and overwritten.\n     DO NOT EDIT! -->\n\
+             <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+             <TITLE>Bookmarks</TITLE>\n\
+             <H1>Bookmarks</H1>\n\
+             <DL><p>\n",
+        );
+        write_folder(&mut out, &self.bar, 1, " PERSONAL_TOOLBAR_FOLDER=\"true\"");
+        for node in &self.other.children {
+            write_node(&mut out, node, 1);
+        }
+        out.push_str("</DL><p>\n");
+        out
+    }
+
+    /// Adds the bookmarks of a Netscape bookmark file and returns how many were new.
+    ///
+    /// The toolbar folder goes into the bar and everything else into the other
+    /// bookmarks. Folders with the same title are merged and a URL already in
+    /// the same folder is skipped, so importing an exported file again is harmless.
+    pub fn import_netscape_html(&mut self, html: &str) -> usize {
+        let document = crate::html_parser::parse_document(html, None);
+        let Some(list) = document.find_element("dl") else {
+            return 0;
+        };
+        let mut import = NetscapeImport {
+            document: &document,
+            consumed: HashSet::new(),
+            toolbar: Vec::new(),
+            unfiled: Vec::new(),
+        };
+        let mut top = Vec::new();
+        import.read_list(list, &mut top, true);
+        let (toolbar, unfiled) = (std::mem::take(&mut import.toolbar), std::mem::take(&mut import.unfiled));
+        self.merge(BAR_FOLDER, toolbar) + self.merge(OTHER_FOLDER, unfiled) + self.merge(OTHER_FOLDER, top)
+    }
+
+    fn merge(&mut self, into: u64, nodes: Vec<BookmarkNode>) -> usize {
+        let mut added = 0;
+        for node in nodes {
+            match node {
+                BookmarkNode::Bookmark(mut bookmark) => {
+                    let id = self.allocate_id();
+                    let Some(folder) = self.folder_mut(into) else {
+                        continue;
+                    };
+                    if folder.children.iter().any(|n| matches!(n, BookmarkNode::Bookmark(b) if b.url == bookmark.url)) {
+                        continue;
+                    }
+                    bookmark.id = id;
+                    folder.children.push(BookmarkNode::Bookmark(bookmark));
+                    added += 1;
+                }
+                BookmarkNode::Folder(Folder { title, added: date, children, .. }) => {
+                    let Some(folder) = self.folder(into) else {
+                        continue;
+                    };
+                    let existing = folder.children.iter().find_map(|n| match n {
+                        BookmarkNode::Folder(f) if f.title == title => Some(f.id),
+                        _ => None,
+                    });
+                    let id = match existing {
+                        Some(id) => id,
+                        None => {
+                            let id = self.allocate_id();
+                            if let Some(folder) = self.folder_mut(into) {
+                                folder.children.push(BookmarkNode::Folder(Folder {
+                                    id,
+                                    title,
+                                    added: date,
+                                    children: Vec::new(),
+                                }));
+                            }
+                            id
+                        }
+                    };
+                    added += self.merge(id, children);
+                }
+            }
+        }
+        added
+    }
+}
+
+/// Splits comma-separated tags, dropping blanks and repeats.
+pub fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in text.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+fn write_node(out: &mut String, node: &BookmarkNode, depth: usize) {
+    match node {
+        BookmarkNode::Bookmark(bookmark) => {
+            let indent = "    ".repeat(depth);
+            out.push_str(&format!("{indent}<DT><A HREF=\"{}\" ADD_DATE=\"{}\"", escape(&bookmark.url), bookmark.added));
+            if !bookmark.tags.is_empty() {
+                out.push_str(&format!(" TAGS=\"{}\"", escape(&bookmark.tags.join(","))));
+            }
+            out.push_str(&format!(">{}</A>\n", escape(&bookmark.title)));
+        }
+        BookmarkNode::Folder(folder) => write_folder(out, folder, depth, ""),
+    }
+}
+
+fn write_folder(out: &mut String, folder: &Folder, depth: usize, attributes: &str) {
+    let indent = "    ".repeat(depth);
+    out.push_str(&format!("{indent}<DT><H3 ADD_DATE=\"{}\"{attributes}>{}</H3>\n", folder.added, escape(&folder.title)));
+    out.push_str(&format!("{indent}<DL><p>\n"));
+    for node in &folder.children {
+        write_node(out, node, depth + 1);
+    }
+    out.push_str(&format!("{indent}</DL><p>\n"));
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Walks the `<DL>` lists of a parsed Netscape bookmark file.
+struct NetscapeImport<'a> {
+    document: &'a Document,
+    /// `<DL>` elements already read as the contents of a folder.
+    consumed: HashSet<NodeId>,
+    toolbar: Vec<BookmarkNode>,
+    unfiled: Vec<BookmarkNode>,
+}
+
+impl NetscapeImport<'_> {
+    // <DT><A> はブックマーク、<DT><H3> はフォルダで、中身は直後の <DL> に入っている
+    // <DT> や <p> の閉じ方はファイルによってまちまちなので、要素の入れ子には頼らずに読む
+    fn read_list(&mut self, node: NodeId, out: &mut Vec<BookmarkNode>, top: bool) {
+        let document = self.document;
+        let children = document.children(node);
+        for (index, &child) in children.iter().enumerate() {
+            if self.consumed.contains(&child) {
+                continue;
+            }
+            let Some(element) = document.element(child) else {
+                continue;
+            };
+            match element.name.as_str() {
+                "a" => {
+                    // ページのリンクと同じく辿れないもの (javascript: など) は取り込まない。place: は Firefox の検索フォルダ
+                    let Some(url) = element
+                        .attr("href")
+                        .and_then(|href| reqwest::Url::parse(href.trim()).ok())
+                        .map(String::from)
+                        .filter(|url| crate::links::is_navigable(url) && !url.starts_with("place:"))
+                    else {
+                        continue;
+                    };
+                    let title = document.text_content(child).trim().to_string();
+                    out.push(BookmarkNode::Bookmark(Bookmark {
+                        id: 0,
+                        title: if title.is_empty() { url.clone() } else { title },
+                        url,
+                        tags: parse_tags(element.attr("tags").unwrap_or_default()),
+                        added: added_date(element.attr("add_date")),
+                    }));
+                }
+                "h3" => {
+                    let mut folder = Folder {
+                        id: 0,
+                        title: document.text_content(child).trim().to_string(),
+                        added: added_date(element.attr("add_date")),
+                        children: Vec::new(),
+                    };
+                    // フォルダの中身: 同じ <DT> の中か、<DT> の次にある <DL>
+                    let list = children[index + 1..]
+                        .iter()
+                        .copied()
+                        .find(|&n| document.is_element_named(n, "dl"))
+                        .or_else(|| self.following_list(node));
+                    if let Some(list) = list {
+                        self.consumed.insert(list);
+                        self.read_list(list, &mut folder.children, false);
+                    }
+                    let flag = |name: &str| element.attr(name).is_some_and(|v| v.eq_ignore_ascii_case("true"));
+                    // ツールバーと未分類のフォルダは、中身をそれぞれバーとその他に入れる
+                    if top && flag("personal_toolbar_folder") {
+                        self.toolbar.append(&mut folder.children);
+                    } else if top && flag("unfiled_bookmarks_folder") {
+                        self.unfiled.append(&mut folder.children);
+                    } else {
+                        out.push(BookmarkNode::Folder(folder));
+                    }
+                }
+                // <DT>, <DD>, <p> などは中を同じ階層として読む
+                _ => self.read_list(child, out, top),
+            }
+        }
+    }
+
+    /// The `<DL>` right after a `<DT>` whose heading had no list inside it.
+    fn following_list(&self, dt: NodeId) -> Option<NodeId> {
+        let document = self.document;
+        if !document.is_element_named(dt, "dt") {
+            return None;
+        }
+        let siblings = document.children(document.parent(dt)?);
+        let position = siblings.iter().position(|&n| n == dt)?;
+        siblings[position + 1..]
+            .iter()
+            .copied()
+            .find(|&n| document.element(n).is_some())
+            .filter(|&n| document.is_element_named(n, "dl"))
+    }
+}
+
+fn added_date(value: Option<&str>) -> u64 {
+    value.and_then(|v| v.trim().parse().ok()).unwrap_or_else(now)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn save(store: &BookmarkStore) {
+    if let Err(e) = store.save(&store_path()) {
+        error!("Failed to save bookmarks: {}", e);
+    }
+}
+
+/// A bookmark clicked on the bar or in the manager.
+enum OpenBookmark {
+    Here(String),
+    InBackground(String),
+}
+
+impl OpenBookmark {
+    fn send(self, tab: Entity, navigate: &mut EventWriter<Navigate>, tab_actions: &mut EventWriter<TabAction>) {
+        match self {
+            OpenBookmark::Here(url) => {
+                navigate.write(Navigate { tab, action: NavigationAction::To(url) });
+            }
+            OpenBookmark::InBackground(url) => {
+                tab_actions.write(TabAction::OpenInBackground { url, initiator: None });
+            }
+        }
+    }
+}
+
+fn short_title(bookmark: &Bookmark) -> String {
+    let title = if bookmark.title.is_empty() { &bookmark.url } else { &bookmark.title };
+    if title.chars().count() > MAX_BAR_LABEL_CHARS {
+        let cut: String = title.chars().take(MAX_BAR_LABEL_CHARS - 1).collect();
+        return format!("{cut}…");
+    }
+    title.clone()
+}
+
+// URL バーの下にブックマークバーを出すシステム (url_panel の後に描く)
+pub fn bookmarks_bar(
+    mut contexts: EguiContexts,
+    tabs: Res<Tabs>,
+    pages: Query<&BrowsingHistory>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut navigate: EventWriter<Navigate>,
+    mut tab_actions: EventWriter<TabAction>,
+) {
+    let page = pages.get(tabs.active).ok().and_then(|history| history.0.current_entry());
+    let ctx = contexts.ctx_mut();
+    let mut open = None;
+    let mut toggle = false;
+    egui::TopBottomPanel::top("bookmarks_bar").show(ctx, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            let bookmarked = page.is_some_and(|entry| bookmarks.0.find_url(&entry.url).is_some());
+            let (star, hint) = if bookmarked { ("★", "Remove bookmark") } else { ("☆", "Bookmark this page") };
+            if ui.add_enabled(page.is_some(), egui::Button::new(star)).on_hover_text(hint).clicked() {
+                toggle = true;
+            }
+            ui.separator();
+            if bookmarks.0.bar.children.is_empty() {
+                ui.weak("Pages bookmarked with ☆ show up here");
+            }
+            bar_items_ui(ui, &bookmarks.0.bar, &mut open);
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.menu_button(format!("📁 {}", bookmarks.0.other.title), |ui| folder_menu_ui(ui, &bookmarks.0.other, &mut open));
+            });
+        });
+    });
+    if toggle && let Some(entry) = page {
+        let store = &mut bookmarks.0;
+        match store.find_url(&entry.url).map(|bookmark| bookmark.id) {
+            Some(id) => {
+                store.remove(id);
+            }
+            None => {
+                let title = entry.title.clone().unwrap_or_else(|| entry.url.clone());
+                store.add_bookmark(BAR_FOLDER, title, entry.url.clone(), Vec::new());
+            }
+        }
+        save(store);
+    }
+    if let Some(open) = open {
+        open.send(tabs.active, &mut navigate, &mut tab_actions);
+    }
+}
+
+// バーの上のボタン: ブックマークはそのまま、フォルダはメニューにする
+fn bar_items_ui(ui: &mut egui::Ui, folder: &Folder, open: &mut Option<OpenBookmark>) {
+    for node in &folder.children {
+        match node {
+            BookmarkNode::Bookmark(bookmark) => {
+                let response = ui.button(short_title(bookmark)).on_hover_text(&bookmark.url);
+                if response.clicked() {
+                    *open = Some(OpenBookmark::Here(bookmark.url.clone()));
+                } else if response.middle_clicked() {
+                    *open = Some(OpenBookmark::InBackground(bookmark.url.clone()));
+                }
+            }
+            BookmarkNode::Folder(folder) => {
+                ui.menu_button(format!("📁 {}", folder.title), |ui| folder_menu_ui(ui, folder, open));
+            }
+        }
+    }
+}
+
+fn folder_menu_ui(ui: &mut egui::Ui, folder: &Folder, open: &mut Option<OpenBookmark>) {
+    if folder.children.is_empty() {
+        ui.weak("(empty)");
+    }
+    for node in &folder.children {
+        match node {
+            BookmarkNode::Bookmark(bookmark) => {
+                let response = ui.button(short_title(bookmark)).on_hover_text(&bookmark.url);
+                if response.clicked() {
+                    *open = Some(OpenBookmark::Here(bookmark.url.clone()));
+                    ui.close_menu();
+                } else if response.middle_clicked() {
+                    *open = Some(OpenBookmark::InBackground(bookmark.url.clone()));
+                }
+            }
+            BookmarkNode::Folder(folder) => {
+                ui.menu_button(format!("📁 {}", folder.title), |ui| folder_menu_ui(ui, folder, open));
+            }
+        }
+    }
+}
+
+/// The bookmark or folder being edited in the manager window.
+struct EditForm {
+    id: u64,
+    title: String,
+    /// None for a folder.
+    url: Option<String>,
+    tags: String,
+    folder: u64,
+}
+
+/// What the manager window keeps between frames.
+#[derive(Default)]
+pub struct ManagerState {
+    search: String,
+    tag: Option<String>,
+    new_folder: String,
+    editing: Option<EditForm>,
+    file: String,
+    status: Option<String>,
+}
+
+/// A change picked in the manager's list, applied after drawing it.
+enum ManagerAction {
+    Edit(u64),
+    Remove(u64),
+    FilterTag(String),
+}
+
+// ブックマークの管理画面: フォルダのツリー、検索とタグでの絞り込み、編集、インポート・エクスポート
+pub fn bookmarks_window(
+    mut contexts: EguiContexts,
+    mut show_bookmarks_window: ResMut<ShowBookmarksWindow>,
+    mut bookmarks: ResMut<Bookmarks>,
+    tabs: Res<Tabs>,
+    mut navigate: EventWriter<Navigate>,
+    mut tab_actions: EventWriter<TabAction>,
+    mut state: Local<ManagerState>,
+) {
+    if !show_bookmarks_window.0 {
+        return;
+    }
+    let state = &mut *state;
+    if state.file.is_empty() {
+        state.file = DEFAULT_EXCHANGE_FILE.to_string();
+    }
+    let ctx = contexts.ctx_mut();
+    // 描いている間は読むだけにして、変更は最後にまとめて反映する
+    let store = bookmarks.bypass_change_detection();
+    let folders = store.0.folders();
+    let tags = store.0.tags();
+    let mut open = None;
+    let mut action = None;
+    let mut changed = false;
+    egui::Window::new("Bookmarks")
+        .open(&mut show_bookmarks_window.0)
+        .default_size(egui::vec2(600.0, 450.0))
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Search:");
+                ui.text_edit_singleline(&mut state.search);
+                egui::ComboBox::from_id_salt("bookmark_tag_filter")
+                    .selected_text(state.tag.as_deref().map_or_else(|| "All tags".to_string(), |tag| format!("#{tag}")))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut state.tag, None, "All tags");
+                        for tag in &tags {
+                            ui.selectable_value(&mut state.tag, Some(tag.clone()), format!("#{tag}"));
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label("New folder:");
+                ui.text_edit_singleline(&mut state.new_folder);
+                if ui.add_enabled(!state.new_folder.trim().is_empty(), egui::Button::new("Add")).clicked() {
+                    let title = std::mem::take(&mut state.new_folder).trim().to_string();
+                    store.0.add_folder(OTHER_FOLDER, title);
+                    changed = true;
+                }
+            });
+            if let Some(form) = &mut state.editing {
+                let mut close = false;
+                ui.group(|ui| {
+                    egui::Grid::new("bookmark_edit").num_columns(2).show(ui, |ui| {
+                        ui.label("Title:");
+                        ui.text_edit_singleline(&mut form.title);
+                        ui.end_row();
+                        if let Some(url) = &mut form.url {
+                            ui.label("URL:");
+                            ui.text_edit_singleline(url);
+                            ui.end_row();
+                            ui.label("Tags:");
+                            ui.text_edit_singleline(&mut form.tags).on_hover_text("Comma-separated");
+                            ui.end_row();
+                        }
+                        ui.label("Folder:");
+                        let current = folders.iter().find(|(id, _)| *id == form.folder).map_or("", |(_, path)| path.as_str());
+                        egui::ComboBox::from_id_salt("bookmark_edit_folder").selected_text(current).show_ui(ui, |ui| {
+                            for (id, path) in &folders {
+                                ui.selectable_value(&mut form.folder, *id, path);
+                            }
+                        });
+                        ui.end_row();
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            let url = form.url.clone().unwrap_or_default();
+                            store.0.update(form.id, form.title.trim().to_string(), url.trim().to_string(), parse_tags(&form.tags));
+                            if store.0.parent_of(form.id) != Some(form.folder) && !store.0.move_to(form.id, form.folder) {
+                                state.status = Some("A folder can't be moved into itself.".to_string());
+                            }
+                            changed = true;
+                            close = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+                if close {
+                    state.editing = None;
+                }
+            }
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(ui.available_height() - 60.0).show(ui, |ui| {
+                let search = state.search.trim().to_lowercase();
+                if search.is_empty() && state.tag.is_none() {
+                    for root in [&store.0.bar, &store.0.other] {
+                        folder_tree_ui(ui, root, true, &mut open, &mut action);
+                    }
+                    return;
+                }
+                // 絞り込み中はフォルダを開かずに一覧で出す
+                let mut found = 0;
+                for (bookmark, path) in store.0.bookmarks() {
+                    let tagged = state.tag.as_ref().is_none_or(|tag| bookmark.tags.contains(tag));
+                    let matches = search.is_empty()
+                        || bookmark.title.to_lowercase().contains(&search)
+                        || bookmark.url.to_lowercase().contains(&search)
+                        || bookmark.tags.iter().any(|tag| tag.to_lowercase().contains(&search));
+                    if tagged && matches {
+                        bookmark_row_ui(ui, bookmark, Some(&path), &mut open, &mut action);
+                        found += 1;
+                    }
+                }
+                if found == 0 {
+                    ui.weak("No matching bookmarks.");
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("File:");
+                ui.text_edit_singleline(&mut state.file).on_hover_text("Netscape bookmark HTML, as other browsers export");
+                if ui.button("Import").clicked() {
+                    state.status = Some(match std::fs::read(&state.file) {
+                        Ok(bytes) => {
+                            let (html, ..) = crate::charset::decode_html(&bytes, None, "", None);
+                            let added = store.0.import_netscape_html(&html);
+                            changed = true;
+                            format!("Imported {added} bookmarks from {}", state.file)
+                        }
+                        Err(e) => format!("Failed to read {}: {}", state.file, e),
+                    });
+                }
+                if ui.button("Export").clicked() {
+                    state.status = Some(match std::fs::write(&state.file, store.0.to_netscape_html()) {
+                        Ok(()) => format!("Exported to {}", state.file),
+                        Err(e) => format!("Failed to write {}: {}", state.file, e),
+                    });
+                }
+            });
+            if let Some(status) = &state.status {
+                ui.label(status);
+            }
+        });
+    match action {
+        Some(ManagerAction::Edit(id)) => {
+            state.editing = store.0.find(id).map(|node| match node {
+                BookmarkNode::Bookmark(bookmark) => EditForm {
+                    id,
+                    title: bookmark.title.clone(),
+                    url: Some(bookmark.url.clone()),
+                    tags: bookmark.tags.join(", "),
+                    folder: store.0.parent_of(id).unwrap_or(OTHER_FOLDER),
+                },
+                BookmarkNode::Folder(folder) => EditForm {
+                    id,
+                    title: folder.title.clone(),
+                    url: None,
+                    tags: String::new(),
+                    folder: store.0.parent_of(id).unwrap_or(OTHER_FOLDER),
+                },
+            });
+        }
+        Some(ManagerAction::Remove(id)) => {
+            store.0.remove(id);
+            if state.editing.as_ref().is_some_and(|form| form.id == id) {
+                state.editing = None;
+            }
+            changed = true;
+        }
+        Some(ManagerAction::FilterTag(tag)) => state.tag = Some(tag),
+        None => {}
+    }
+    if changed {
+        bookmarks.set_changed();
+        save(&bookmarks.0);
+    }
+    if let Some(open) = open {
+        open.send(tabs.active, &mut navigate, &mut tab_actions);
+    }
+}
+
+fn folder_tree_ui(ui: &mut egui::Ui, folder: &Folder, root: bool, open: &mut Option<OpenBookmark>, action: &mut Option<ManagerAction>) {
+    egui::CollapsingHeader::new(format!("📁 {}", folder.title))
+        .id_salt(("bookmark_folder", folder.id))
+        .default_open(root)
+        .show(ui, |ui| {
+            if !root {
+                ui.horizontal(|ui| {
+                    if ui.small_button("✏ Edit folder").clicked() {
+                        *action = Some(ManagerAction::Edit(folder.id));
+                    }
+                    if ui.small_button("🗑 Delete folder").on_hover_text("Deletes everything in it").clicked() {
+                        *action = Some(ManagerAction::Remove(folder.id));
+                    }
+                });
+            }
+            if folder.children.is_empty() {
+                ui.weak("(empty)");
+            }
+            for node in &folder.children {
+                match node {
+                    BookmarkNode::Bookmark(bookmark) => bookmark_row_ui(ui, bookmark, None, open, action),
+                    BookmarkNode::Folder(folder) => folder_tree_ui(ui, folder, false, open, action),
+                }
+            }
+        });
+}
+
+fn bookmark_row_ui(ui: &mut egui::Ui, bookmark: &Bookmark, path: Option<&str>, open: &mut Option<OpenBookmark>, action: &mut Option<ManagerAction>) {
+    ui.horizontal(|ui| {
+        let title = if bookmark.title.is_empty() { &bookmark.url } else { &bookmark.title };
+        let response = ui.link(title).on_hover_text(&bookmark.url);
+        if response.clicked() {
+            *open = Some(OpenBookmark::Here(bookmark.url.clone()));
+        } else if response.middle_clicked() {
+            *open = Some(OpenBookmark::InBackground(bookmark.url.clone()));
+        }
+        if let Some(path) = path {
+            ui.weak(path);
+        }
+        for tag in &bookmark.tags {
+            if ui.small_button(format!("#{tag}")).on_hover_text("Show bookmarks with this tag").clicked() {
+                *action = Some(ManagerAction::FilterTag(tag.clone()));
+            }
+        }
+        ui.weak(crate::history::format_timestamp(bookmark.added));
+        if ui.small_button("✏").on_hover_text("Edit").clicked() {
+            *action = Some(ManagerAction::Edit(bookmark.id));
+        }
+        if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+            *action = Some(ManagerAction::Remove(bookmark.id));
+        }
+    });
+}
@@ -0,0 +1,338 @@
+//! Picks the character encoding of fetched documents and decodes them,
+//! following the encoding sniffing algorithm of the HTML standard: BOM, user
+//! override, Content-Type charset, `<meta>` prescan, then statistical
+//! detection (chardetng) as the last resort.
+
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252, X_USER_DEFINED};
+
+/// How many bytes the `<meta>` prescan looks at.
+const PRESCAN_BYTES: usize = 1024;
+
+/// Encodings offered in the "Text Encoding" menu.
+pub const OVERRIDE_CHOICES: &[&Encoding] = &[
+    UTF_8,
+    encoding_rs::SHIFT_JIS,
+    encoding_rs::EUC_JP,
+    encoding_rs::ISO_2022_JP,
+    WINDOWS_1252,
+    encoding_rs::GBK,
+    encoding_rs::BIG5,
+    encoding_rs::EUC_KR,
+    encoding_rs::WINDOWS_1251,
+    encoding_rs::KOI8_R,
+    UTF_16LE,
+    UTF_16BE,
+];
+
+/// Which step of the sniffing algorithm decided the encoding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EncodingSource {
+    /// Byte order mark at the start of the body.
+    Bom,
+    /// Chosen by the user in the "Text Encoding" menu.
+    UserOverride,
+    /// `charset` parameter of the Content-Type header.
+    Header,
+    /// `<meta charset>` or `<meta http-equiv="Content-Type">`.
+    Meta,
+    /// Guessed from the bytes.
+    #[default]
+    Detected,
+}
+
+/// Decodes an HTML document. `url` is used for the top-level domain hint of
+/// the statistical detector.
+pub fn decode_html(bytes: &[u8], content_type: Option<&str>, url: &str, forced: Option<&'static Encoding>) -> (String, &'static Encoding, EncodingSource) {
+    let (encoding, source) = sniff_html(bytes, content_type, url, forced);
+    (decode(bytes, encoding), encoding, source)
+}
+
+fn sniff_html(bytes: &[u8], content_type: Option<&str>, url: &str, forced: Option<&'static Encoding>) -> (&'static Encoding, EncodingSource) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, EncodingSource::Bom);
+    }
+    if let Some(encoding) = forced {
+        return (encoding, EncodingSource::UserOverride);
+    }
+    if let Some(encoding) = content_type.and_then(|ct| charset_from_content(ct.as_bytes())) {
+        return (encoding, EncodingSource::Header);
+    }
+    if let Some(encoding) = prescan(&bytes[..bytes.len().min(PRESCAN_BYTES)]) {
+        return (encoding, EncodingSource::Meta);
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, true);
+    let tld = reqwest::Url::parse(url).ok().and_then(|u| u.host_str().and_then(|h| h.rsplit('.').next()).map(str::to_ascii_lowercase));
+    (detector.guess(tld.as_deref().map(str::as_bytes), true), EncodingSource::Detected)
+}
+
+/// Decodes a stylesheet (CSS Syntax 3.2): BOM, Content-Type charset,
+/// `@charset`, then the encoding of the document that linked it.
+pub fn decode_stylesheet(bytes: &[u8], content_type: Option<&str>, document_encoding: &'static Encoding) -> String {
+    let encoding = Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| content_type.and_then(|ct| charset_from_content(ct.as_bytes())))
+        .or_else(|| at_charset(bytes))
+        .unwrap_or(document_encoding);
+    decode(bytes, encoding)
+}
+
+/// `@charset "name";` at the very start of a stylesheet.
+fn at_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let rest = bytes.strip_prefix(b"@charset \"")?;
+    let end = rest.iter().take(1024).position(|&b| b == b'"')?;
+    if rest.get(end + 1) != Some(&b';') {
+        return None;
+    }
+    // UTF-16 の宣言は ASCII 互換の本文ではありえないので UTF-8 とみなす
+    Encoding::for_label(&rest[..end]).map(|e| e.output_encoding())
+}
+
+fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    let body = match Encoding::for_bom(bytes) {
+        Some((bom, length)) if bom == encoding => &bytes[length..],
+        _ => bytes,
+    };
+    encoding.decode_without_bom_handling(body).0.into_owned()
+}
+
+fn is_space(b: u8) -> bool {
+    matches!(b, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+/// The "prescan a byte stream to determine its encoding" algorithm.
+pub fn prescan(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        if rest.starts_with(b"<!--") {
+            // "<!-->" のように開きと閉じの "-" が重なってもよい
+            i += 2 + find(&rest[2..], b"-->")? + 3;
+            continue;
+        }
+        if rest.len() > 5 && rest[..5].eq_ignore_ascii_case(b"<meta") && (is_space(rest[5]) || rest[5] == b'/') {
+            i += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut i) {
+                return Some(encoding);
+            }
+            continue;
+        }
+        let is_tag = rest.first() == Some(&b'<')
+            && match rest.get(1) {
+                Some(b'/') => rest.get(2).is_some_and(u8::is_ascii_alphabetic),
+                Some(b) => b.is_ascii_alphabetic(),
+                None => false,
+            };
+        if is_tag {
+            // タグ名を飛ばして属性を読み捨てる
+            while i < bytes.len() && !is_space(bytes[i]) && bytes[i] != b'>' {
+                i += 1;
+            }
+            while get_attribute(bytes, &mut i).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            i += find(rest, b">")?;
+        }
+        i += 1;
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Reads the attributes of a `<meta>` tag and returns the encoding it declares.
+fn meta_encoding(bytes: &[u8], i: &mut usize) -> Option<&'static Encoding> {
+    let mut seen: Vec<Vec<u8>> = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+    while let Some((name, value)) = get_attribute(bytes, i) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_slice() {
+            b"http-equiv" if value == b"content-type" => got_pragma = true,
+            b"content" if charset.is_none() => {
+                if let Some(encoding) = charset_from_content(&value) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            }
+            b"charset" if charset.is_none() => {
+                charset = Encoding::for_label(&value);
+                need_pragma = Some(false);
+            }
+            _ => {}
+        }
+        seen.push(name);
+    }
+    match need_pragma? {
+        true if !got_pragma => None,
+        _ => charset.map(|encoding| match encoding {
+            e if e == UTF_16BE || e == UTF_16LE => UTF_8,
+            e if e == X_USER_DEFINED => WINDOWS_1252,
+            e => e,
+        }),
+    }
+}
+
+/// The "get an attribute" step of the prescan. Names and values come back
+/// lowercased; `None` at the end of the tag (or the input).
+fn get_attribute(bytes: &[u8], i: &mut usize) -> Option<(Vec<u8>, Vec<u8>)> {
+    while *i < bytes.len() && (is_space(bytes[*i]) || bytes[*i] == b'/') {
+        *i += 1;
+    }
+    if *i >= bytes.len() || bytes[*i] == b'>' {
+        return None;
+    }
+    let mut name = Vec::new();
+    let mut value = Vec::new();
+    loop {
+        let b = *bytes.get(*i)?;
+        if b == b'=' && !name.is_empty() {
+            *i += 1;
+            break;
+        }
+        if is_space(b) {
+            while bytes.get(*i).is_some_and(|&b| is_space(b)) {
+                *i += 1;
+            }
+            if bytes.get(*i) != Some(&b'=') {
+                return Some((name, value));
+            }
+            *i += 1;
+            break;
+        }
+        if b == b'/' || b == b'>' {
+            return Some((name, value));
+        }
+        name.push(b.to_ascii_lowercase());
+        *i += 1;
+    }
+    while bytes.get(*i).is_some_and(|&b| is_space(b)) {
+        *i += 1;
+    }
+    let quote = *bytes.get(*i)?;
+    if quote == b'"' || quote == b'\'' {
+        *i += 1;
+        loop {
+            let b = *bytes.get(*i)?;
+            *i += 1;
+            if b == quote {
+                return Some((name, value));
+            }
+            value.push(b.to_ascii_lowercase());
+        }
+    }
+    if quote == b'>' {
+        return Some((name, value));
+    }
+    while let Some(&b) = bytes.get(*i) {
+        if is_space(b) || b == b'>' {
+            break;
+        }
+        value.push(b.to_ascii_lowercase());
+        *i += 1;
+    }
+    Some((name, value))
+}
+
+/// The "extract a character encoding from a meta element" algorithm; also
+/// used for the Content-Type header, whose syntax it accepts.
+pub fn charset_from_content(content: &[u8]) -> Option<&'static Encoding> {
+    let mut pos = 0;
+    loop {
+        let found = content[pos..].windows(7).position(|w| w.eq_ignore_ascii_case(b"charset"))?;
+        pos += found + 7;
+        while content.get(pos).is_some_and(|&b| is_space(b)) {
+            pos += 1;
+        }
+        if content.get(pos) == Some(&b'=') {
+            break;
+        }
+    }
+    pos += 1;
+    while content.get(pos).is_some_and(|&b| is_space(b)) {
+        pos += 1;
+    }
+    match content.get(pos)? {
+        &quote @ (b'"' | b'\'') => {
+            let rest = &content[pos + 1..];
+            let end = rest.iter().position(|&b| b == quote)?;
+            Encoding::for_label(&rest[..end])
+        }
+        _ => {
+            let rest = &content[pos..];
+            let end = rest.iter().position(|&b| is_space(b) || b == b';').unwrap_or(rest.len());
+            Encoding::for_label(&rest[..end])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{EUC_JP, ISO_2022_JP, SHIFT_JIS};
+
+    const URL: &str = "http://example.jp/";
+
+    fn sniff(bytes: &[u8], content_type: Option<&str>) -> (&'static Encoding, EncodingSource) {
+        let (_, encoding, source) = decode_html(bytes, content_type, URL, None);
+        (encoding, source)
+    }
+
+    #[test]
+    fn bom_wins_over_everything_and_is_stripped() {
+        let bytes = b"\xEF\xBB\xBF<meta charset=shift_jis>ok";
+        let (text, encoding, source) = decode_html(bytes, Some("text/html; charset=euc-jp"), URL, Some(SHIFT_JIS));
+        assert_eq!((encoding, source), (UTF_8, EncodingSource::Bom));
+        assert_eq!(text, "<meta charset=shift_jis>ok");
+        assert_eq!(sniff(b"\xFF\xFEa\0", None), (UTF_16LE, EncodingSource::Bom));
+    }
+
+    #[test]
+    fn user_override_wins_over_the_header() {
+        let (_, encoding, source) = decode_html(b"", Some("text/html; charset=utf-8"), URL, Some(EUC_JP));
+        assert_eq!((encoding, source), (EUC_JP, EncodingSource::UserOverride));
+    }
+
+    #[test]
+    fn header_charset_wins_over_meta() {
+        let (bytes, _, _) = SHIFT_JIS.encode("<meta charset=euc-jp>日本語");
+        let (text, encoding, source) = decode_html(&bytes, Some("text/html; charset=\"Shift_JIS\""), URL, None);
+        assert_eq!((encoding, source), (SHIFT_JIS, EncodingSource::Header));
+        assert_eq!(text, "<meta charset=euc-jp>日本語");
+        assert_eq!(sniff(b"", Some("text/html;charset=ISO-2022-JP ")), (ISO_2022_JP, EncodingSource::Header));
+    }
+
+    #[test]
+    fn meta_charset_and_http_equiv() {
+        assert_eq!(sniff(b"<!DOCTYPE html><head><meta charset=\"EUC-JP\">", None), (EUC_JP, EncodingSource::Meta));
+        let http_equiv = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">";
+        assert_eq!(sniff(http_equiv, Some("text/html")), (SHIFT_JIS, EncodingSource::Meta));
+        // content だけで http-equiv がなければ使わない
+        assert_eq!(prescan(b"<meta content=\"text/html; charset=Shift_JIS\">"), None);
+        // コメントの中の meta は飛ばし、UTF-16 の宣言は UTF-8 とみなす
+        assert_eq!(prescan(b"<!-- <meta charset=koi8-r> --><meta charset=utf-16le>"), Some(UTF_8));
+    }
+
+    #[test]
+    fn meta_is_only_looked_for_within_the_prescan_limit() {
+        let mut near = " ".repeat(PRESCAN_BYTES - 30).into_bytes();
+        near.extend_from_slice(b"<meta charset=euc-jp>");
+        assert_eq!(sniff(&near, None), (EUC_JP, EncodingSource::Meta));
+        let mut far = " ".repeat(PRESCAN_BYTES).into_bytes();
+        far.extend_from_slice(b"<meta charset=euc-jp>");
+        assert_eq!(sniff(&far, None).1, EncodingSource::Detected);
+    }
+
+    #[test]
+    fn undeclared_pages_are_detected() {
+        let (bytes, _, _) = SHIFT_JIS.encode("<p>このページは文字コードを宣言していませんが、日本語として正しく表示されるはずです。</p>");
+        let (text, encoding, source) = decode_html(&bytes, Some("text/html"), URL, None);
+        assert_eq!((encoding, source), (SHIFT_JIS, EncodingSource::Detected));
+        assert!(text.contains("日本語"));
+        assert_eq!(sniff(b"<p>plain ascii</p>", None).1, EncodingSource::Detected);
+    }
+}
@@ -3,6 +3,9 @@ use bevy::prelude::*; // Component, Handle など Bevys の型を使うため
 /// Where to find the serialized animation graph.
 pub static ANIMATION_GRAPH_PATH: &str = "animation_graphs/Fox.animgraph.ron";
 
+/// Where the bookmarks are saved, relative to the assets directory.
+pub static BOOKMARKS_PATH: &str = "bookmarks/bookmarks.ron";
+
 /// The indices of the nodes containing animation clips in the graph.
 pub static CLIP_NODE_INDICES: [u32; 3] = [2, 3, 4];
 
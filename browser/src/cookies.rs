@@ -0,0 +1,550 @@
+//! Cookie jar (RFC 6265) stored in the Netscape `cookies.txt` format, the
+//! same file curl reads with `-b` and writes with `-c`.
+//!
+//! The file format has no column for SameSite, so it is kept in memory only;
+//! cookies loaded from disk behave as if it was not set (treated as `Lax`).
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use reqwest::Url;
+
+use crate::{HttpClient, ShowCookieWindow};
+
+/// Prefix curl puts on the domain column of HttpOnly cookies.
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SameSite {
+    /// No attribute; treated as `Lax`.
+    #[default]
+    Unspecified,
+    Strict,
+    Lax,
+    None,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot.
+    pub domain: String,
+    /// Set without a Domain attribute: only sent to exactly `domain`.
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: SameSite,
+    /// Unix seconds; `None` for session cookies.
+    pub expires: Option<u64>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|t| t <= now)
+    }
+
+    fn matches_host(&self, host: &str) -> bool {
+        if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(host, &self.domain)
+        }
+    }
+}
+
+/// Where a request comes from, for the SameSite rules and the cache.
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+    /// URL of the page that caused the request; `None` when the user asked
+    /// for it directly (URL bar, history, bookmarks).
+    pub initiator: Option<String>,
+    /// Top-level navigation (as opposed to a stylesheet, image, ...).
+    pub navigation: bool,
+    /// The user pressed reload: cached copies must be revalidated.
+    pub reload: bool,
+}
+
+impl RequestContext {
+    pub fn navigation() -> Self {
+        RequestContext {
+            initiator: None,
+            navigation: true,
+            reload: false,
+        }
+    }
+
+    pub fn reload() -> Self {
+        RequestContext {
+            reload: true,
+            ..RequestContext::navigation()
+        }
+    }
+
+    pub fn subresource(page_url: &str) -> Self {
+        RequestContext {
+            initiator: Some(page_url.to_string()),
+            navigation: false,
+            reload: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    pub cookies: Vec<Cookie>,
+}
+
+impl CookieJar {
+    /// Reads a cookies.txt file. A missing file is an empty jar.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse_netscape(&text)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_netscape()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse_netscape(text: &str) -> Self {
+        let now = now();
+        let mut jar = CookieJar::default();
+        for line in text.lines() {
+            let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+                Some(rest) => (rest, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [domain, subdomains, path, secure, expires, name, value] = fields[..] else {
+                continue;
+            };
+            let expires = expires.parse::<u64>().unwrap_or(0);
+            let cookie = Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: SameSite::Unspecified,
+                expires: (expires != 0).then_some(expires),
+            };
+            if !cookie.is_expired(now) {
+                jar.insert(cookie);
+            }
+        }
+        jar
+    }
+
+    pub fn to_netscape(&self) -> String {
+        let mut out = String::from("# Netscape HTTP Cookie File\n# This file was generated by the browser. Edit at your own risk.\n\n");
+        let now = now();
+        for cookie in self.cookies.iter().filter(|c| !c.is_expired(now)) {
+            let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+            let domain = if cookie.host_only { cookie.domain.clone() } else { format!(".{}", cookie.domain) };
+            out.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { HTTP_ONLY_PREFIX } else { "" },
+                domain,
+                flag(!cookie.host_only),
+                cookie.path,
+                flag(cookie.secure),
+                cookie.expires.unwrap_or(0),
+                cookie.name,
+                cookie.value
+            ));
+        }
+        out
+    }
+
+    /// Adds or replaces the cookie with the same name, domain and path.
+    fn insert(&mut self, cookie: Cookie) {
+        self.cookies
+            .retain(|c| !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path));
+        self.cookies.push(cookie);
+    }
+
+    /// Processes the `Set-Cookie` headers of a response to `url`. Returns true
+    /// if the jar changed.
+    pub fn store_response<'a>(&mut self, url: &Url, set_cookies: impl Iterator<Item = &'a str>) -> bool {
+        let now = now();
+        let mut changed = false;
+        for header in set_cookies {
+            let Some(cookie) = parse_set_cookie(header, url, now) else {
+                continue;
+            };
+            // 期限切れの Set-Cookie は削除の指示
+            if cookie.is_expired(now) {
+                let before = self.cookies.len();
+                self.cookies
+                    .retain(|c| !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path));
+                changed |= self.cookies.len() != before;
+            } else {
+                self.insert(cookie);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The `Cookie` header value to send with a request to `url`.
+    pub fn header_for(&self, url: &Url, context: &RequestContext) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let secure_channel = url.scheme() == "https";
+        let now = now();
+        let same_site = context
+            .initiator
+            .as_deref()
+            .and_then(|i| Url::parse(i).ok())
+            .is_none_or(|initiator| initiator.host_str().is_some_and(|h| site_of(h) == site_of(&host)));
+
+        let mut matching: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|c| !c.is_expired(now) && c.matches_host(&host) && path_match(url.path(), &c.path))
+            .filter(|c| !c.secure || secure_channel)
+            .filter(|c| match c.same_site {
+                _ if same_site => true,
+                SameSite::None => true,
+                SameSite::Lax | SameSite::Unspecified => context.navigation,
+                SameSite::Strict => false,
+            })
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // パスの長いものを先に送る (RFC 6265 5.4)
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        Some(
+            matching
+                .iter()
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+
+    /// Sites (registrable domains) that have cookies, sorted.
+    pub fn sites(&self) -> Vec<String> {
+        let mut sites: Vec<String> = self.cookies.iter().map(|c| site_of(&c.domain)).collect();
+        sites.sort();
+        sites.dedup();
+        sites
+    }
+
+    pub fn cookies_for_site<'a>(&'a self, site: &'a str) -> impl Iterator<Item = &'a Cookie> + 'a {
+        self.cookies.iter().filter(move |c| site_of(&c.domain) == site)
+    }
+
+    pub fn remove(&mut self, name: &str, domain: &str, path: &str) {
+        self.cookies.retain(|c| !(c.name == name && c.domain == domain && c.path == path));
+    }
+
+    pub fn remove_site(&mut self, site: &str) {
+        self.cookies.retain(|c| site_of(&c.domain) != site);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// RFC 6265 5.1.3 domain matching.
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// RFC 6265 5.1.4 path matching.
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// RFC 6265 5.1.4 default-path: the directory of the request path.
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+/// Second-level labels under which registrations happen one level deeper
+/// (`example.co.jp`). A stand-in for the public suffix list.
+const SECOND_LEVEL_SUFFIXES: &[&str] = &["ac", "co", "com", "ed", "edu", "go", "gov", "gr", "lg", "ne", "net", "or", "org"];
+
+/// How many trailing labels make up a registrable domain: three under a
+/// `co.jp`-style suffix, otherwise two.
+fn registrable_labels(host: &str) -> usize {
+    let labels: Vec<&str> = host.split('.').collect();
+    match labels.as_slice() {
+        [.., second, tld] if tld.len() == 2 && SECOND_LEVEL_SUFFIXES.contains(second) => 3,
+        _ => 2,
+    }
+}
+
+/// The registrable domain ("site") of `host`, used for SameSite and for
+/// grouping in the cookie manager.
+pub fn site_of(host: &str) -> String {
+    let host = host.trim_start_matches('.').to_ascii_lowercase();
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    labels[labels.len().saturating_sub(registrable_labels(&host))..].join(".")
+}
+
+/// Whether `domain` is too broad to set cookies for (a bare TLD or a
+/// `co.jp`-style suffix).
+fn is_public_suffix(domain: &str) -> bool {
+    domain.split('.').count() < registrable_labels(domain)
+}
+
+/// Parses one `Set-Cookie` header received from `url` (RFC 6265 5.2, 5.3).
+fn parse_set_cookie(header: &str, url: &Url, now: u64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        secure: false,
+        http_only: false,
+        same_site: SameSite::Unspecified,
+        expires: None,
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "expires" => {
+                if let Some(time) = crate::http_client::parse_http_date(value) {
+                    cookie.expires = Some(time);
+                }
+            }
+            "max-age" => {
+                if let Ok(seconds) = value.parse::<i64>() {
+                    max_age = Some(if seconds <= 0 { 0 } else { now.saturating_add(seconds as u64) });
+                }
+            }
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                // 他のサイトや公開サフィックスには設定させない
+                if !domain_match(&host, &domain) || (is_public_suffix(&domain) && domain != host) {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" => {
+                cookie.same_site = match value.to_ascii_lowercase().as_str() {
+                    "strict" => SameSite::Strict,
+                    "lax" => SameSite::Lax,
+                    "none" => SameSite::None,
+                    _ => SameSite::Unspecified,
+                }
+            }
+            _ => {}
+        }
+    }
+    // Max-Age は Expires より優先される
+    if max_age.is_some() {
+        cookie.expires = max_age;
+    }
+    // Secure 付きのクッキーは https からしか設定できず、SameSite=None には Secure が要る
+    if (cookie.secure || cookie.same_site == SameSite::None) && url.scheme() != "https" {
+        return None;
+    }
+    if cookie.same_site == SameSite::None && !cookie.secure {
+        return None;
+    }
+    Some(cookie)
+}
+
+fn describe_expiry(cookie: &Cookie) -> String {
+    match cookie.expires {
+        Some(time) => crate::history::format_timestamp(time),
+        None => "Session".to_string(),
+    }
+}
+
+// サイトごとにクッキーを一覧・削除するウィンドウ
+pub fn cookie_manager_window(
+    mut contexts: EguiContexts,
+    mut show_cookie_window: ResMut<ShowCookieWindow>,
+    http_client: Res<HttpClient>,
+    mut filter: Local<String>,
+) {
+    if !show_cookie_window.0 {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    let mut changed = false;
+    egui::Window::new("Cookies")
+        .open(&mut show_cookie_window.0)
+        .default_size(egui::vec2(600.0, 400.0))
+        .show(ctx, |ui| {
+            let mut jar = http_client.0.cookie_jar().lock().unwrap();
+            ui.horizontal(|ui| {
+                ui.label("Filter:");
+                ui.text_edit_singleline(&mut *filter);
+                if ui.button("Delete all").clicked() {
+                    jar.cookies.clear();
+                    changed = true;
+                }
+            });
+            ui.label(format!("{} cookies", jar.cookies.len()));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for site in jar.sites().into_iter().filter(|s| s.contains(filter.as_str())) {
+                    let cookies: Vec<Cookie> = jar.cookies_for_site(&site).cloned().collect();
+                    ui.horizontal(|ui| {
+                        egui::CollapsingHeader::new(format!("{} ({})", site, cookies.len()))
+                            .id_salt(&site)
+                            .show(ui, |ui| {
+                                egui::Grid::new(("cookies", &site)).striped(true).show(ui, |ui| {
+                                    for cookie in &cookies {
+                                        ui.label(&cookie.name).on_hover_text(&cookie.value);
+                                        ui.label(format!("{}{}", cookie.domain, cookie.path));
+                                        let mut flags = Vec::new();
+                                        if cookie.secure {
+                                            flags.push("Secure".to_string());
+                                        }
+                                        if cookie.http_only {
+                                            flags.push("HttpOnly".to_string());
+                                        }
+                                        if cookie.same_site != SameSite::Unspecified {
+                                            flags.push(format!("SameSite={:?}", cookie.same_site));
+                                        }
+                                        ui.label(flags.join(" "));
+                                        ui.label(describe_expiry(cookie));
+                                        if ui.small_button("🗑").on_hover_text("Delete cookie").clicked() {
+                                            jar.remove(&cookie.name, &cookie.domain, &cookie.path);
+                                            changed = true;
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                            });
+                        if ui.small_button("Delete site").clicked() {
+                            jar.remove_site(&site);
+                            changed = true;
+                        }
+                    });
+                }
+            });
+        });
+    if changed {
+        http_client.0.save_cookies();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(text: &str) -> Url {
+        Url::parse(text).expect("url")
+    }
+
+    fn jar_with(from: &str, set_cookies: &[&str]) -> CookieJar {
+        let mut jar = CookieJar::default();
+        jar.store_response(&url(from), set_cookies.iter().copied());
+        jar
+    }
+
+    fn header(jar: &CookieJar, to: &str) -> Option<String> {
+        jar.header_for(&url(to), &RequestContext::navigation())
+    }
+
+    #[test]
+    fn domain_and_path_matching() {
+        assert!(domain_match("www.example.com", "example.com"));
+        assert!(domain_match("example.com", "example.com"));
+        assert!(!domain_match("badexample.com", "example.com"));
+        assert!(!domain_match("10.0.0.1", "0.0.1"));
+        assert!(path_match("/docs/a", "/docs"));
+        assert!(path_match("/docs/a", "/docs/"));
+        assert!(!path_match("/docsx", "/docs"));
+
+        let jar = jar_with("http://example.com/docs/index.html", &["a=1", "b=2; Path=/"]);
+        assert_eq!(jar.cookies[0].path, "/docs");
+        assert_eq!(header(&jar, "http://example.com/docs/page").as_deref(), Some("a=1; b=2"));
+        assert_eq!(header(&jar, "http://example.com/other").as_deref(), Some("b=2"));
+    }
+
+    #[test]
+    fn host_only_and_domain_cookies() {
+        let jar = jar_with("http://www.example.com/", &["host=1", "wide=2; Domain=.example.com"]);
+        assert_eq!(header(&jar, "http://www.example.com/").as_deref(), Some("host=1; wide=2"));
+        assert_eq!(header(&jar, "http://api.example.com/").as_deref(), Some("wide=2"));
+        assert_eq!(header(&jar, "http://example.org/"), None);
+
+        // 他のサイトや公開サフィックスの Domain は受け付けない
+        let jar = jar_with("http://www.example.co.jp/", &["a=1; Domain=other.co.jp", "b=2; Domain=co.jp", "c=3; Domain=example.co.jp"]);
+        let names: Vec<&str> = jar.cookies.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["c"]);
+    }
+
+    #[test]
+    fn secure_and_http_only() {
+        assert!(jar_with("http://example.com/", &["s=1; Secure"]).cookies.is_empty());
+        let jar = jar_with("https://example.com/", &["s=1; Secure", "h=2; HttpOnly"]);
+        assert_eq!(header(&jar, "https://example.com/").as_deref(), Some("s=1; h=2"));
+        assert_eq!(header(&jar, "http://example.com/").as_deref(), Some("h=2"));
+        assert!(jar.cookies.iter().any(|c| c.name == "h" && c.http_only));
+    }
+
+    #[test]
+    fn expiry() {
+        let mut jar = jar_with("http://example.com/", &["a=1; Max-Age=3600", "b=2", "c=3; Max-Age=60; Expires=Sun, 06 Nov 1994 08:49:37 GMT"]);
+        let a = jar.cookies.iter().find(|c| c.name == "a").expect("a");
+        assert!(a.expires.is_some_and(|t| t > now()));
+        assert_eq!(jar.cookies.iter().find(|c| c.name == "b").map(|c| c.expires), Some(None));
+        // Max-Age は Expires より優先される
+        assert!(jar.cookies.iter().any(|c| c.name == "c"));
+
+        // 過去の Expires や Max-Age=0 は削除
+        let url = url("http://example.com/");
+        assert!(jar.store_response(&url, ["a=; Max-Age=0", "b=; Expires=Sun, 06 Nov 1994 08:49:37 GMT"].into_iter()));
+        let names: Vec<&str> = jar.cookies.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["c"]);
+
+        let expired = CookieJar::parse_netscape("example.com\tFALSE\t/\tFALSE\t784111777\told\t1\n");
+        assert!(expired.cookies.is_empty());
+    }
+
+    #[test]
+    fn netscape_round_trip_keeps_http_only_prefix() {
+        let jar = jar_with("https://www.example.com/app/", &["sid=abc; Domain=example.com; Path=/; Secure; HttpOnly; Max-Age=3600", "theme=dark"]);
+        let text = jar.to_netscape();
+        assert!(text.lines().any(|line| line.starts_with("#HttpOnly_.example.com\tTRUE\t/\tTRUE\t")));
+        assert!(text.lines().any(|line| line == "www.example.com\tFALSE\t/app\tFALSE\t0\ttheme\tdark"));
+        let parsed = CookieJar::parse_netscape(&text);
+        assert_eq!(parsed.cookies, jar.cookies);
+    }
+}
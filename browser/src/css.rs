@@ -0,0 +1,971 @@
+//! CSS tokenizer and parser (CSS Syntax Module Level 3, simplified).
+//!
+//! Stylesheets are parsed into rules whose selectors are already parsed and
+//! whose declaration values are kept as component values; interpreting a value
+//! is up to [`crate::style`], which knows what each property accepts.
+
+use crate::css_selector::{parse_selector_list, Selector};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CssToken {
+    Ident(String),
+    Function(String),
+    AtKeyword(String),
+    /// `#name`; the flag is true when the name is a valid identifier (an id selector).
+    Hash(String, bool),
+    String(String),
+    BadString,
+    Url(String),
+    BadUrl,
+    Delim(char),
+    Number(f32),
+    Percentage(f32),
+    Dimension(f32, String),
+    Whitespace,
+    Cdo,
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    LeftBracket,
+    RightBracket,
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+}
+
+/// A token, a `{}`/`[]`/`()` block or a function call.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ComponentValue {
+    Token(CssToken),
+    Block { open: char, contents: Vec<ComponentValue> },
+    Function { name: String, args: Vec<ComponentValue> },
+}
+
+impl ComponentValue {
+    pub fn is_whitespace(&self) -> bool {
+        matches!(self, ComponentValue::Token(CssToken::Whitespace))
+    }
+
+    /// Lower-cased identifier, if this is one.
+    pub fn ident(&self) -> Option<String> {
+        match self {
+            ComponentValue::Token(CssToken::Ident(name)) => Some(name.to_ascii_lowercase()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Declaration {
+    /// Lower-cased property name.
+    pub name: String,
+    /// Value with leading/trailing whitespace and `!important` removed.
+    pub value: Vec<ComponentValue>,
+    pub important: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct StyleRule {
+    pub selectors: Vec<Selector>,
+    pub declarations: Vec<Declaration>,
+}
+
+/// A parsed `@media` query list. Only the features a desktop browser window
+/// can answer are understood.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaQuery {
+    pub not: bool,
+    /// `all`, `screen`, `print`, ...
+    pub media_type: String,
+    /// `(min-width: 600px)` style features as (name, value in px).
+    pub features: Vec<(String, f32)>,
+}
+
+#[derive(Clone, Debug)]
+pub enum CssRule {
+    Style(StyleRule),
+    Media { queries: Vec<MediaQuery>, rules: Vec<CssRule> },
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stylesheet {
+    pub rules: Vec<CssRule>,
+    /// URLs from `@import` rules, in order.
+    pub imports: Vec<String>,
+}
+
+/// Size of the viewport that media queries are evaluated against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MediaContext {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for MediaContext {
+    fn default() -> Self {
+        Self { width: 1024.0, height: 768.0 }
+    }
+}
+
+impl MediaQuery {
+    pub fn matches(&self, ctx: &MediaContext) -> bool {
+        let type_ok = matches!(self.media_type.as_str(), "all" | "screen");
+        let features_ok = self.features.iter().all(|(name, value)| match name.as_str() {
+            "min-width" => ctx.width >= *value,
+            "max-width" => ctx.width <= *value,
+            "min-height" => ctx.height >= *value,
+            "max-height" => ctx.height <= *value,
+            "width" => (ctx.width - value).abs() < 0.5,
+            // 分からない条件は満たさないものとする
+            _ => false,
+        });
+        (type_ok && features_ok) != self.not
+    }
+}
+
+impl Stylesheet {
+    /// Makes the `url()` values of the declarations absolute against `base`,
+    /// the URL of the sheet, since they are relative to the sheet and not to
+    /// the document it ends up applying to.
+    pub fn resolve_urls(&mut self, base: &str) {
+        fn resolve_values(values: &mut [ComponentValue], base: &reqwest::Url) {
+            for value in values {
+                match value {
+                    ComponentValue::Token(CssToken::Url(url)) => resolve(url, base),
+                    ComponentValue::Function { name, args } if name.eq_ignore_ascii_case("url") => {
+                        for arg in args {
+                            if let ComponentValue::Token(CssToken::String(url)) = arg {
+                                resolve(url, base);
+                            }
+                        }
+                    }
+                    ComponentValue::Function { args: contents, .. } | ComponentValue::Block { contents, .. } => resolve_values(contents, base),
+                    _ => {}
+                }
+            }
+        }
+        fn resolve(url: &mut String, base: &reqwest::Url) {
+            if let Ok(absolute) = base.join(url.trim()) {
+                *url = absolute.into();
+            }
+        }
+        fn resolve_rules(rules: &mut [CssRule], base: &reqwest::Url) {
+            for rule in rules {
+                match rule {
+                    CssRule::Style(style) => {
+                        for declaration in &mut style.declarations {
+                            resolve_values(&mut declaration.value, base);
+                        }
+                    }
+                    CssRule::Media { rules, .. } => resolve_rules(rules, base),
+                }
+            }
+        }
+        if let Ok(base) = reqwest::Url::parse(base) {
+            resolve_rules(&mut self.rules, &base);
+        }
+    }
+
+    /// Style rules that apply to `ctx`, with `@media` blocks flattened.
+    pub fn style_rules<'a>(&'a self, ctx: &MediaContext) -> Vec<&'a StyleRule> {
+        fn collect<'a>(rules: &'a [CssRule], ctx: &MediaContext, out: &mut Vec<&'a StyleRule>) {
+            for rule in rules {
+                match rule {
+                    CssRule::Style(style) => out.push(style),
+                    CssRule::Media { queries, rules } => {
+                        if queries.is_empty() || queries.iter().any(|q| q.matches(ctx)) {
+                            collect(rules, ctx, out);
+                        }
+                    }
+                }
+            }
+        }
+        let mut out = Vec::new();
+        collect(&self.rules, ctx, &mut out);
+        out
+    }
+}
+
+// ---- tokenizer ---------------------------------------------------------------
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+struct Tokenizer {
+    input: Vec<char>,
+    pos: usize,
+}
+
+impl Tokenizer {
+    fn new(input: &str) -> Self {
+        // 改行の正規化と NULL の置き換え (3.3 Preprocessing)
+        let input = input
+            .replace("\r\n", "\n")
+            .replace(['\r', '\x0C'], "\n")
+            .replace('\0', "\u{FFFD}");
+        Self {
+            input: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.input.get(self.pos + offset).copied()
+    }
+
+    fn valid_escape(&self, offset: usize) -> bool {
+        self.peek(offset) == Some('\\') && !matches!(self.peek(offset + 1), None | Some('\n'))
+    }
+
+    fn starts_identifier(&self, offset: usize) -> bool {
+        match self.peek(offset) {
+            Some('-') => {
+                matches!(self.peek(offset + 1), Some(c) if is_name_start(c) || c == '-')
+                    || self.valid_escape(offset + 1)
+            }
+            Some('\\') => self.valid_escape(offset),
+            Some(c) => is_name_start(c),
+            None => false,
+        }
+    }
+
+    fn starts_number(&self) -> bool {
+        match self.peek(0) {
+            Some('+' | '-') => {
+                matches!(self.peek(1), Some(c) if c.is_ascii_digit())
+                    || (self.peek(1) == Some('.') && matches!(self.peek(2), Some(c) if c.is_ascii_digit()))
+            }
+            Some('.') => matches!(self.peek(1), Some(c) if c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    fn consume_escape(&mut self) -> char {
+        // `\` は消費済み
+        let Some(c) = self.peek(0) else {
+            return '\u{FFFD}';
+        };
+        self.pos += 1;
+        if !c.is_ascii_hexdigit() {
+            return c;
+        }
+        let mut hex = String::from(c);
+        while hex.len() < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.peek(0).unwrap());
+            self.pos += 1;
+        }
+        if matches!(self.peek(0), Some(' ' | '\t' | '\n')) {
+            self.pos += 1;
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap_or(0);
+        match char::from_u32(code) {
+            Some(c) if code != 0 => c,
+            _ => '\u{FFFD}',
+        }
+    }
+
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name_char(c) => {
+                    name.push(c);
+                    self.pos += 1;
+                }
+                Some('\\') if self.valid_escape(0) => {
+                    self.pos += 1;
+                    name.push(self.consume_escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    fn consume_number(&mut self) -> f32 {
+        let start = self.pos;
+        if matches!(self.peek(0), Some('+' | '-')) {
+            self.pos += 1;
+        }
+        while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+        }
+        if matches!(self.peek(0), Some('e' | 'E')) {
+            let digit_at = if matches!(self.peek(1), Some('+' | '-')) { 2 } else { 1 };
+            if self.peek(digit_at).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += digit_at;
+                while self.peek(0).is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+            }
+        }
+        let text: String = self.input[start..self.pos].iter().collect();
+        text.parse().unwrap_or(0.0)
+    }
+
+    fn consume_numeric(&mut self) -> CssToken {
+        let value = self.consume_number();
+        if self.starts_identifier(0) {
+            return CssToken::Dimension(value, self.consume_name());
+        }
+        if self.peek(0) == Some('%') {
+            self.pos += 1;
+            return CssToken::Percentage(value);
+        }
+        CssToken::Number(value)
+    }
+
+    fn consume_string(&mut self, quote: char) -> CssToken {
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => return CssToken::String(value),
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return CssToken::String(value);
+                }
+                Some('\n') => return CssToken::BadString,
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek(0) {
+                        None => {}
+                        Some('\n') => self.pos += 1,
+                        Some(_) => value.push(self.consume_escape()),
+                    }
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn consume_url(&mut self) -> CssToken {
+        while matches!(self.peek(0), Some(' ' | '\t' | '\n')) {
+            self.pos += 1;
+        }
+        let mut value = String::new();
+        loop {
+            match self.peek(0) {
+                None => return CssToken::Url(value),
+                Some(')') => {
+                    self.pos += 1;
+                    return CssToken::Url(value);
+                }
+                Some(' ' | '\t' | '\n') => {
+                    while matches!(self.peek(0), Some(' ' | '\t' | '\n')) {
+                        self.pos += 1;
+                    }
+                    if matches!(self.peek(0), None | Some(')')) {
+                        self.pos += usize::from(self.peek(0).is_some());
+                        return CssToken::Url(value);
+                    }
+                    self.consume_bad_url();
+                    return CssToken::BadUrl;
+                }
+                Some('"' | '\'' | '(') => {
+                    self.consume_bad_url();
+                    return CssToken::BadUrl;
+                }
+                Some('\\') if self.valid_escape(0) => {
+                    self.pos += 1;
+                    value.push(self.consume_escape());
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn consume_bad_url(&mut self) {
+        while let Some(c) = self.peek(0) {
+            self.pos += 1;
+            if c == ')' {
+                return;
+            }
+            if c == '\\' && self.peek(0).is_some() {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn consume_ident_like(&mut self) -> CssToken {
+        let name = self.consume_name();
+        if self.peek(0) == Some('(') {
+            self.pos += 1;
+            if name.eq_ignore_ascii_case("url") {
+                // `url("...")` は通常の関数として扱う
+                let mut look = 0;
+                while matches!(self.peek(look), Some(' ' | '\t' | '\n')) {
+                    look += 1;
+                }
+                if matches!(self.peek(look), Some('"' | '\'')) {
+                    return CssToken::Function(name);
+                }
+                return self.consume_url();
+            }
+            return CssToken::Function(name);
+        }
+        CssToken::Ident(name)
+    }
+
+    fn next_token(&mut self) -> Option<CssToken> {
+        // コメントは読み飛ばす
+        while self.peek(0) == Some('/') && self.peek(1) == Some('*') {
+            self.pos += 2;
+            while self.peek(0).is_some() && !(self.peek(0) == Some('*') && self.peek(1) == Some('/')) {
+                self.pos += 1;
+            }
+            self.pos = (self.pos + 2).min(self.input.len());
+        }
+        let c = self.peek(0)?;
+        let token = match c {
+            ' ' | '\t' | '\n' => {
+                while matches!(self.peek(0), Some(' ' | '\t' | '\n')) {
+                    self.pos += 1;
+                }
+                return Some(CssToken::Whitespace);
+            }
+            '"' | '\'' => {
+                self.pos += 1;
+                return Some(self.consume_string(c));
+            }
+            '#' => {
+                if self.peek(1).is_some_and(is_name_char) || self.valid_escape(1) {
+                    self.pos += 1;
+                    let is_id = self.starts_identifier(0);
+                    return Some(CssToken::Hash(self.consume_name(), is_id));
+                }
+                CssToken::Delim('#')
+            }
+            '(' => CssToken::LeftParen,
+            ')' => CssToken::RightParen,
+            '[' => CssToken::LeftBracket,
+            ']' => CssToken::RightBracket,
+            '{' => CssToken::LeftBrace,
+            '}' => CssToken::RightBrace,
+            ',' => CssToken::Comma,
+            ':' => CssToken::Colon,
+            ';' => CssToken::Semicolon,
+            '+' | '.' if self.starts_number() => return Some(self.consume_numeric()),
+            '-' => {
+                if self.starts_number() {
+                    return Some(self.consume_numeric());
+                }
+                if self.peek(1) == Some('-') && self.peek(2) == Some('>') {
+                    self.pos += 3;
+                    return Some(CssToken::Cdc);
+                }
+                if self.starts_identifier(0) {
+                    return Some(self.consume_ident_like());
+                }
+                CssToken::Delim('-')
+            }
+            '<' if self.peek(1) == Some('!') && self.peek(2) == Some('-') && self.peek(3) == Some('-') => {
+                self.pos += 4;
+                return Some(CssToken::Cdo);
+            }
+            '@' => {
+                if self.starts_identifier(1) {
+                    self.pos += 1;
+                    return Some(CssToken::AtKeyword(self.consume_name()));
+                }
+                CssToken::Delim('@')
+            }
+            '\\' => {
+                if self.valid_escape(0) {
+                    return Some(self.consume_ident_like());
+                }
+                CssToken::Delim('\\')
+            }
+            c if c.is_ascii_digit() => return Some(self.consume_numeric()),
+            c if is_name_start(c) => return Some(self.consume_ident_like()),
+            c => CssToken::Delim(c),
+        };
+        self.pos += 1;
+        Some(token)
+    }
+}
+
+/// Splits CSS source into tokens. Comments are dropped.
+pub fn tokenize(input: &str) -> Vec<CssToken> {
+    let mut tokenizer = Tokenizer::new(input);
+    std::iter::from_fn(|| tokenizer.next_token()).collect()
+}
+
+// ---- parser ------------------------------------------------------------------
+
+struct Parser {
+    tokens: Vec<CssToken>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Self {
+            tokens: tokenize(input),
+            pos: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<CssToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn consume_component_value(&mut self, token: CssToken) -> ComponentValue {
+        match token {
+            CssToken::LeftBrace => self.consume_block('{', CssToken::RightBrace),
+            CssToken::LeftBracket => self.consume_block('[', CssToken::RightBracket),
+            CssToken::LeftParen => self.consume_block('(', CssToken::RightParen),
+            CssToken::Function(name) => {
+                let mut args = Vec::new();
+                while let Some(token) = self.next() {
+                    if token == CssToken::RightParen {
+                        break;
+                    }
+                    args.push(self.consume_component_value(token));
+                }
+                ComponentValue::Function { name: name.to_ascii_lowercase(), args }
+            }
+            token => ComponentValue::Token(token),
+        }
+    }
+
+    fn consume_block(&mut self, open: char, close: CssToken) -> ComponentValue {
+        let mut contents = Vec::new();
+        while let Some(token) = self.next() {
+            if token == close {
+                break;
+            }
+            contents.push(self.consume_component_value(token));
+        }
+        ComponentValue::Block { open, contents }
+    }
+
+    /// 5.4.1 Consume a list of rules.
+    fn consume_rules(&mut self, top_level: bool, sheet: &mut Stylesheet) -> Vec<CssRule> {
+        let mut rules = Vec::new();
+        while let Some(token) = self.next() {
+            match token {
+                CssToken::Whitespace => {}
+                CssToken::Cdo | CssToken::Cdc if top_level => {}
+                CssToken::AtKeyword(name) => {
+                    if let Some(rule) = self.consume_at_rule(&name, sheet) {
+                        rules.push(rule);
+                    }
+                }
+                token => {
+                    if let Some(rule) = self.consume_qualified_rule(token) {
+                        rules.push(rule);
+                    }
+                }
+            }
+        }
+        rules
+    }
+
+    fn consume_prelude_and_block(&mut self, first: Option<CssToken>) -> (Vec<ComponentValue>, Option<Vec<ComponentValue>>) {
+        let mut prelude = Vec::new();
+        let mut token = first.or_else(|| self.next());
+        while let Some(t) = token {
+            match t {
+                CssToken::Semicolon => return (prelude, None),
+                CssToken::LeftBrace => {
+                    let ComponentValue::Block { contents, .. } = self.consume_block('{', CssToken::RightBrace) else {
+                        unreachable!()
+                    };
+                    return (prelude, Some(contents));
+                }
+                t => prelude.push(self.consume_component_value(t)),
+            }
+            token = self.next();
+        }
+        (prelude, None)
+    }
+
+    fn consume_at_rule(&mut self, name: &str, sheet: &mut Stylesheet) -> Option<CssRule> {
+        let (prelude, block) = self.consume_prelude_and_block(None);
+        match name.to_ascii_lowercase().as_str() {
+            "import" => {
+                let url = prelude.iter().find_map(|v| match v {
+                    ComponentValue::Token(CssToken::String(s) | CssToken::Url(s)) => Some(s.clone()),
+                    ComponentValue::Function { name, args } if name == "url" => args.iter().find_map(|a| match a {
+                        ComponentValue::Token(CssToken::String(s)) => Some(s.clone()),
+                        _ => None,
+                    }),
+                    _ => None,
+                });
+                if let Some(url) = url {
+                    sheet.imports.push(url);
+                }
+                None
+            }
+            "media" => {
+                let block = block?;
+                let queries = parse_media_queries(&prelude);
+                let mut inner = Parser {
+                    tokens: flatten(&block),
+                    pos: 0,
+                };
+                let rules = inner.consume_rules(false, sheet);
+                Some(CssRule::Media { queries, rules })
+            }
+            // @font-face, @keyframes, @supports などは今のところ無視する
+            _ => None,
+        }
+    }
+
+    fn consume_qualified_rule(&mut self, first: CssToken) -> Option<CssRule> {
+        let (prelude, block) = self.consume_prelude_and_block(Some(first));
+        let block = block?;
+        let selectors = parse_selector_list(&prelude)?;
+        let declarations = parse_declaration_list(&block);
+        Some(CssRule::Style(StyleRule { selectors, declarations }))
+    }
+}
+
+/// Turns component values back into a token stream so a nested block can be
+/// parsed with the same code as a top-level stylesheet.
+fn flatten(values: &[ComponentValue]) -> Vec<CssToken> {
+    let mut out = Vec::new();
+    for value in values {
+        match value {
+            ComponentValue::Token(t) => out.push(t.clone()),
+            ComponentValue::Block { open, contents } => {
+                let (open_token, close_token) = match open {
+                    '{' => (CssToken::LeftBrace, CssToken::RightBrace),
+                    '[' => (CssToken::LeftBracket, CssToken::RightBracket),
+                    _ => (CssToken::LeftParen, CssToken::RightParen),
+                };
+                out.push(open_token);
+                out.extend(flatten(contents));
+                out.push(close_token);
+            }
+            ComponentValue::Function { name, args } => {
+                out.push(CssToken::Function(name.clone()));
+                out.extend(flatten(args));
+                out.push(CssToken::RightParen);
+            }
+        }
+    }
+    out
+}
+
+fn parse_media_queries(prelude: &[ComponentValue]) -> Vec<MediaQuery> {
+    let mut queries = Vec::new();
+    for part in prelude.split(|v| matches!(v, ComponentValue::Token(CssToken::Comma))) {
+        let mut query = MediaQuery {
+            not: false,
+            media_type: "all".to_string(),
+            features: Vec::new(),
+        };
+        let mut valid = true;
+        for value in part.iter().filter(|v| !v.is_whitespace()) {
+            match value {
+                ComponentValue::Token(CssToken::Ident(ident)) => match ident.to_ascii_lowercase().as_str() {
+                    "not" => query.not = true,
+                    "only" | "and" => {}
+                    media_type => query.media_type = media_type.to_string(),
+                },
+                ComponentValue::Block { open: '(', contents } => {
+                    let mut items = contents.iter().filter(|v| !v.is_whitespace());
+                    let name = items.next().and_then(|v| v.ident());
+                    let colon = items.next();
+                    let value = items.next().and_then(|v| match v {
+                        ComponentValue::Token(CssToken::Dimension(n, unit)) => match unit.to_ascii_lowercase().as_str() {
+                            "px" => Some(*n),
+                            "em" | "rem" => Some(n * 16.0),
+                            _ => None,
+                        },
+                        ComponentValue::Token(CssToken::Number(n)) if *n == 0.0 => Some(0.0),
+                        _ => None,
+                    });
+                    match (name, colon, value) {
+                        (Some(name), Some(ComponentValue::Token(CssToken::Colon)), Some(value)) => {
+                            query.features.push((name, value));
+                        }
+                        _ => valid = false,
+                    }
+                }
+                _ => valid = false,
+            }
+        }
+        if valid {
+            queries.push(query);
+        } else {
+            // 解釈できないクエリは `not all` 扱い
+            queries.push(MediaQuery {
+                not: true,
+                media_type: "all".to_string(),
+                features: Vec::new(),
+            });
+        }
+    }
+    queries
+}
+
+/// Parses a full stylesheet, e.g. the contents of a `<style>` element.
+pub fn parse_stylesheet(input: &str) -> Stylesheet {
+    let mut sheet = Stylesheet::default();
+    let mut parser = Parser::new(input);
+    sheet.rules = parser.consume_rules(true, &mut sheet);
+    sheet
+}
+
+/// Parses a declaration list such as the value of a `style` attribute.
+pub fn parse_declarations(input: &str) -> Vec<Declaration> {
+    let mut parser = Parser::new(input);
+    let mut values = Vec::new();
+    while let Some(token) = parser.next() {
+        values.push(parser.consume_component_value(token));
+    }
+    parse_declaration_list(&values)
+}
+
+fn parse_declaration_list(values: &[ComponentValue]) -> Vec<Declaration> {
+    let mut declarations = Vec::new();
+    for chunk in values.split(|v| matches!(v, ComponentValue::Token(CssToken::Semicolon))) {
+        let mut items = chunk.iter().skip_while(|v| v.is_whitespace());
+        let Some(name) = items.next().and_then(|v| match v {
+            ComponentValue::Token(CssToken::Ident(name)) => Some(name.to_ascii_lowercase()),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let mut items = items.skip_while(|v| v.is_whitespace());
+        if items.next() != Some(&ComponentValue::Token(CssToken::Colon)) {
+            continue;
+        }
+        let mut value: Vec<ComponentValue> = items.cloned().collect();
+        trim_whitespace(&mut value);
+        let mut important = false;
+        let len = value.len();
+        if len >= 2 {
+            let is_bang = value[len - 2] == ComponentValue::Token(CssToken::Delim('!'));
+            let is_important = value[len - 1].ident().as_deref() == Some("important");
+            if is_bang && is_important {
+                important = true;
+                value.truncate(len - 2);
+                trim_whitespace(&mut value);
+            }
+        }
+        if value.is_empty() {
+            continue;
+        }
+        declarations.push(Declaration { name, value, important });
+    }
+    declarations
+}
+
+fn trim_whitespace(values: &mut Vec<ComponentValue>) {
+    while values.last().is_some_and(|v| v.is_whitespace()) {
+        values.pop();
+    }
+    let leading = values.iter().take_while(|v| v.is_whitespace()).count();
+    values.drain(..leading);
+}
+
+// ---- colors ------------------------------------------------------------------
+
+/// An sRGB color with alpha.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+    pub const TRANSPARENT: Color = Color { r: 0, g: 0, b: 0, a: 0 };
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 255 }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.a == 0
+    }
+
+    /// Parses a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` hex string (without `#`).
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|d| d * 17);
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        match hex.len() {
+            3 => Some(Color::rgb(digit(0)?, digit(1)?, digit(2)?)),
+            4 => Some(Color { r: digit(0)?, g: digit(1)?, b: digit(2)?, a: digit(3)? }),
+            6 => Some(Color::rgb(pair(0)?, pair(2)?, pair(4)?)),
+            8 => Some(Color { r: pair(0)?, g: pair(2)?, b: pair(4)?, a: pair(6)? }),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name == "transparent" {
+            return Some(Color::TRANSPARENT);
+        }
+        NAMED_COLORS
+            .binary_search_by_key(&name.as_str(), |(n, _)| n)
+            .ok()
+            .map(|i| {
+                let v = NAMED_COLORS[i].1;
+                Color::rgb((v >> 16) as u8, (v >> 8) as u8, v as u8)
+            })
+    }
+
+    /// Parses a color from a `style` value. `currentcolor` is not handled here.
+    pub fn from_value(value: &ComponentValue) -> Option<Self> {
+        match value {
+            ComponentValue::Token(CssToken::Hash(hex, _)) => Color::from_hex(hex),
+            ComponentValue::Token(CssToken::Ident(name)) => Color::from_name(name),
+            ComponentValue::Function { name, args } => {
+                let numbers: Vec<&CssToken> = args
+                    .iter()
+                    .filter_map(|a| match a {
+                        ComponentValue::Token(t @ (CssToken::Number(_) | CssToken::Percentage(_) | CssToken::Dimension(..))) => Some(t),
+                        _ => None,
+                    })
+                    .collect();
+                let channel = |t: &CssToken| match t {
+                    CssToken::Number(n) => n.clamp(0.0, 255.0).round() as u8,
+                    CssToken::Percentage(p) => (p.clamp(0.0, 100.0) * 2.55).round() as u8,
+                    _ => 0,
+                };
+                let alpha = |t: Option<&&CssToken>| match t {
+                    Some(CssToken::Number(n)) => (n.clamp(0.0, 1.0) * 255.0).round() as u8,
+                    Some(CssToken::Percentage(p)) => (p.clamp(0.0, 100.0) * 2.55).round() as u8,
+                    _ => 255,
+                };
+                match name.as_str() {
+                    "rgb" | "rgba" if numbers.len() >= 3 => Some(Color {
+                        r: channel(numbers[0]),
+                        g: channel(numbers[1]),
+                        b: channel(numbers[2]),
+                        a: alpha(numbers.get(3)),
+                    }),
+                    "hsl" | "hsla" if numbers.len() >= 3 => {
+                        let hue = match numbers[0] {
+                            CssToken::Number(n) | CssToken::Dimension(n, _) => *n,
+                            _ => 0.0,
+                        };
+                        let percent = |t: &CssToken| match t {
+                            CssToken::Percentage(p) | CssToken::Number(p) => p.clamp(0.0, 100.0) / 100.0,
+                            _ => 0.0,
+                        };
+                        let (r, g, b) = hsl_to_rgb(hue, percent(numbers[1]), percent(numbers[2]));
+                        Some(Color { r, g, b, a: alpha(numbers.get(3)) })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (u8, u8, u8) {
+    let hue = hue.rem_euclid(360.0) / 360.0;
+    let t2 = if lightness <= 0.5 {
+        lightness * (saturation + 1.0)
+    } else {
+        lightness + saturation - lightness * saturation
+    };
+    let t1 = lightness * 2.0 - t2;
+    let convert = |mut h: f32| {
+        if h < 0.0 {
+            h += 1.0;
+        }
+        if h > 1.0 {
+            h -= 1.0;
+        }
+        let v = if h * 6.0 < 1.0 {
+            t1 + (t2 - t1) * h * 6.0
+        } else if h * 2.0 < 1.0 {
+            t2
+        } else if h * 3.0 < 2.0 {
+            t1 + (t2 - t1) * (2.0 / 3.0 - h) * 6.0
+        } else {
+            t1
+        };
+        (v * 255.0).round() as u8
+    };
+    (convert(hue + 1.0 / 3.0), convert(hue), convert(hue - 1.0 / 3.0))
+}
+
+/// CSS named colors, sorted for binary search.
+static NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4), ("azure", 0xf0ffff), ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4), ("black", 0x000000), ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e), ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c), ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b), ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc), ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3), ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700), ("goldenrod", 0xdaa520), ("gray", 0x808080),
+    ("green", 0x008000), ("greenyellow", 0xadff2f), ("grey", 0x808080),
+    ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa), ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a), ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899), ("lightslategrey", 0x778899), ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa), ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371), ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6), ("olive", 0x808000), ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f), ("pink", 0xffc0cb), ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513), ("salmon", 0xfa8072), ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee), ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f), ("steelblue", 0x4682b4), ("tan", 0xd2b48c),
+    ("teal", 0x008080), ("thistle", 0xd8bfd8), ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+    ("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
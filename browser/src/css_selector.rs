@@ -0,0 +1,573 @@
+//! Selectors Level 4 subset: parsing, specificity and matching against [`Document`].
+
+use crate::css::{ComponentValue, CssToken};
+use crate::dom::{Document, NodeData, NodeId, QuirksMode};
+
+/// `(id, class/attribute/pseudo-class, type)` counts. Compared lexicographically.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity(pub u32, pub u32, pub u32);
+
+impl std::ops::Add for Specificity {
+    type Output = Specificity;
+
+    fn add(self, rhs: Self) -> Self {
+        Specificity(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Combinator {
+    /// whitespace
+    Descendant,
+    /// `>`
+    Child,
+    /// `+`
+    NextSibling,
+    /// `~`
+    SubsequentSibling,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttrOperator {
+    /// `[attr]`
+    Exists,
+    /// `[attr=v]`
+    Equals,
+    /// `[attr~=v]`
+    Includes,
+    /// `[attr|=v]`
+    DashMatch,
+    /// `[attr^=v]`
+    Prefix,
+    /// `[attr$=v]`
+    Suffix,
+    /// `[attr*=v]`
+    Substring,
+}
+
+/// `an+b` for the `:nth-*` pseudo-classes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    /// Whether the 1-based `index` is in the sequence.
+    pub fn matches(&self, index: i32) -> bool {
+        if self.a == 0 {
+            return index == self.b;
+        }
+        let n = index - self.b;
+        n % self.a == 0 && n / self.a >= 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PseudoClass {
+    Root,
+    Empty,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    FirstOfType,
+    LastOfType,
+    OnlyOfType,
+    NthChild(Nth),
+    NthLastChild(Nth),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    /// `:link` / `:any-link`. Visited history is not tracked, so this matches every link.
+    Link,
+    Checked,
+    Disabled,
+    Enabled,
+    Not(Vec<Selector>),
+    Is(Vec<Selector>),
+    /// Like `:is()` but contributes no specificity.
+    Where(Vec<Selector>),
+    /// Dynamic states (`:hover`, `:focus`, `:visited`, ...) that never match a static page.
+    Never,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SimpleSelector {
+    Universal,
+    Type(String),
+    Id(String),
+    Class(String),
+    Attribute {
+        name: String,
+        op: AttrOperator,
+        value: String,
+        case_insensitive: bool,
+    },
+    Pseudo(PseudoClass),
+}
+
+/// Simple selectors without combinators, e.g. `a.external[href]`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompoundSelector(pub Vec<SimpleSelector>);
+
+/// A complex selector such as `ul > li.item a`.
+///
+/// Stored right to left: `compounds[0]` is the subject and `combinators[i]`
+/// joins `compounds[i]` to `compounds[i + 1]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selector {
+    pub compounds: Vec<CompoundSelector>,
+    pub combinators: Vec<Combinator>,
+    /// Selectors with a pseudo-element never match an element box.
+    pub pseudo_element: Option<String>,
+}
+
+impl Selector {
+    pub fn specificity(&self) -> Specificity {
+        self.compounds
+            .iter()
+            .flat_map(|c| c.0.iter())
+            .map(simple_specificity)
+            .fold(
+                Specificity(0, 0, u32::from(self.pseudo_element.is_some())),
+                |acc, s| acc + s,
+            )
+    }
+
+    /// Id, class or tag name of the subject, used to bucket rules.
+    pub fn key(&self) -> SelectorKey {
+        let subject = &self.compounds[0].0;
+        let find = |f: fn(&SimpleSelector) -> Option<&String>| subject.iter().find_map(f).cloned();
+        if let Some(id) = find(|s| if let SimpleSelector::Id(v) = s { Some(v) } else { None }) {
+            return SelectorKey::Id(id);
+        }
+        if let Some(class) = find(|s| if let SimpleSelector::Class(v) = s { Some(v) } else { None }) {
+            return SelectorKey::Class(class);
+        }
+        if let Some(tag) = find(|s| if let SimpleSelector::Type(v) = s { Some(v) } else { None }) {
+            return SelectorKey::Tag(tag);
+        }
+        SelectorKey::Universal
+    }
+
+    pub fn matches(&self, doc: &Document, node: NodeId) -> bool {
+        self.pseudo_element.is_none() && self.matches_from(doc, node, 0)
+    }
+
+    fn matches_from(&self, doc: &Document, node: NodeId, index: usize) -> bool {
+        if !compound_matches(&self.compounds[index], doc, node) {
+            return false;
+        }
+        let Some(combinator) = self.combinators.get(index) else {
+            return true;
+        };
+        let next = index + 1;
+        match combinator {
+            Combinator::Child => parent_element(doc, node).is_some_and(|p| self.matches_from(doc, p, next)),
+            Combinator::Descendant => {
+                let mut current = parent_element(doc, node);
+                while let Some(p) = current {
+                    if self.matches_from(doc, p, next) {
+                        return true;
+                    }
+                    current = parent_element(doc, p);
+                }
+                false
+            }
+            Combinator::NextSibling => {
+                previous_element_sibling(doc, node).is_some_and(|s| self.matches_from(doc, s, next))
+            }
+            Combinator::SubsequentSibling => {
+                let mut current = previous_element_sibling(doc, node);
+                while let Some(s) = current {
+                    if self.matches_from(doc, s, next) {
+                        return true;
+                    }
+                    current = previous_element_sibling(doc, s);
+                }
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SelectorKey {
+    Id(String),
+    Class(String),
+    Tag(String),
+    Universal,
+}
+
+fn simple_specificity(simple: &SimpleSelector) -> Specificity {
+    match simple {
+        SimpleSelector::Universal => Specificity::default(),
+        SimpleSelector::Id(_) => Specificity(1, 0, 0),
+        SimpleSelector::Class(_) | SimpleSelector::Attribute { .. } => Specificity(0, 1, 0),
+        SimpleSelector::Type(_) => Specificity(0, 0, 1),
+        SimpleSelector::Pseudo(PseudoClass::Where(_)) => Specificity::default(),
+        // :not() と :is() は引数の中で最も詳細度の高いもの
+        SimpleSelector::Pseudo(PseudoClass::Not(list) | PseudoClass::Is(list)) => {
+            list.iter().map(Selector::specificity).max().unwrap_or_default()
+        }
+        SimpleSelector::Pseudo(_) => Specificity(0, 1, 0),
+    }
+}
+
+fn parent_element(doc: &Document, node: NodeId) -> Option<NodeId> {
+    doc.parent(node).filter(|p| doc.element(*p).is_some())
+}
+
+fn element_siblings(doc: &Document, node: NodeId) -> Vec<NodeId> {
+    match doc.parent(node) {
+        Some(parent) => doc
+            .children(parent)
+            .iter()
+            .copied()
+            .filter(|c| doc.element(*c).is_some())
+            .collect(),
+        None => vec![node],
+    }
+}
+
+fn previous_element_sibling(doc: &Document, node: NodeId) -> Option<NodeId> {
+    let siblings = element_siblings(doc, node);
+    let index = siblings.iter().position(|s| *s == node)?;
+    index.checked_sub(1).map(|i| siblings[i])
+}
+
+fn compound_matches(compound: &CompoundSelector, doc: &Document, node: NodeId) -> bool {
+    let Some(element) = doc.element(node) else {
+        return false;
+    };
+    let quirks = doc.mode == QuirksMode::Quirks;
+    compound.0.iter().all(|simple| match simple {
+        SimpleSelector::Universal => true,
+        SimpleSelector::Type(name) => element.name.eq_ignore_ascii_case(name), // SVG の clipPath なども小文字で書ける
+        // 互換モードではクラス名と ID を大文字小文字を区別せずに比べる
+        SimpleSelector::Id(id) if quirks => element.id().is_some_and(|v| v.eq_ignore_ascii_case(id)),
+        SimpleSelector::Id(id) => element.id() == Some(id.as_str()),
+        SimpleSelector::Class(class) if quirks => element.classes().any(|c| c.eq_ignore_ascii_case(class)),
+        SimpleSelector::Class(class) => element.classes().any(|c| c == class),
+        SimpleSelector::Attribute { name, op, value, case_insensitive } => {
+            let Some(actual) = element.attr(name) else {
+                return false;
+            };
+            let (actual, value) = if *case_insensitive {
+                (actual.to_lowercase(), value.to_lowercase())
+            } else {
+                (actual.to_string(), value.clone())
+            };
+            match op {
+                AttrOperator::Exists => true,
+                AttrOperator::Equals => actual == value,
+                AttrOperator::Includes => actual.split_ascii_whitespace().any(|w| w == value),
+                AttrOperator::DashMatch => actual == value || actual.starts_with(&format!("{}-", value)),
+                AttrOperator::Prefix => !value.is_empty() && actual.starts_with(&value),
+                AttrOperator::Suffix => !value.is_empty() && actual.ends_with(&value),
+                AttrOperator::Substring => !value.is_empty() && actual.contains(&value),
+            }
+        }
+        SimpleSelector::Pseudo(pseudo) => pseudo_matches(pseudo, doc, node),
+    })
+}
+
+fn pseudo_matches(pseudo: &PseudoClass, doc: &Document, node: NodeId) -> bool {
+    let element = doc.element(node).expect("compound_matches checked for an element");
+    let position = |of_type: bool, from_end: bool| -> i32 {
+        let mut siblings = element_siblings(doc, node);
+        if of_type {
+            siblings.retain(|s| doc.tag_name(*s) == Some(element.name.as_str()));
+        }
+        if from_end {
+            siblings.reverse();
+        }
+        siblings.iter().position(|s| *s == node).map_or(0, |i| i as i32 + 1)
+    };
+    let count = |of_type: bool| -> usize {
+        let siblings = element_siblings(doc, node);
+        if of_type {
+            siblings.iter().filter(|s| doc.tag_name(**s) == Some(element.name.as_str())).count()
+        } else {
+            siblings.len()
+        }
+    };
+    match pseudo {
+        PseudoClass::Root => doc.parent(node) == Some(doc.root()),
+        PseudoClass::Empty => doc.children(node).iter().all(|c| match &doc.node(*c).data {
+            NodeData::Text(t) => t.is_empty(),
+            NodeData::Comment(_) => true,
+            _ => false,
+        }),
+        PseudoClass::FirstChild => position(false, false) == 1,
+        PseudoClass::LastChild => position(false, true) == 1,
+        PseudoClass::OnlyChild => count(false) == 1,
+        PseudoClass::FirstOfType => position(true, false) == 1,
+        PseudoClass::LastOfType => position(true, true) == 1,
+        PseudoClass::OnlyOfType => count(true) == 1,
+        PseudoClass::NthChild(nth) => nth.matches(position(false, false)),
+        PseudoClass::NthLastChild(nth) => nth.matches(position(false, true)),
+        PseudoClass::NthOfType(nth) => nth.matches(position(true, false)),
+        PseudoClass::NthLastOfType(nth) => nth.matches(position(true, true)),
+        PseudoClass::Link => matches!(element.name.as_str(), "a" | "area") && element.has_attr("href"),
+        PseudoClass::Checked => {
+            (element.name == "input" && element.has_attr("checked"))
+                || (element.name == "option" && element.has_attr("selected"))
+        }
+        PseudoClass::Disabled => is_form_control(&element.name) && element.has_attr("disabled"),
+        PseudoClass::Enabled => is_form_control(&element.name) && !element.has_attr("disabled"),
+        PseudoClass::Not(list) => !list.iter().any(|s| s.matches(doc, node)),
+        PseudoClass::Is(list) | PseudoClass::Where(list) => list.iter().any(|s| s.matches(doc, node)),
+        PseudoClass::Never => false,
+    }
+}
+
+fn is_form_control(name: &str) -> bool {
+    matches!(name, "input" | "button" | "select" | "textarea" | "option" | "optgroup" | "fieldset")
+}
+
+// ---- parsing -----------------------------------------------------------------
+
+/// Parses a comma separated selector list. Returns `None` if any selector in
+/// the list is invalid, which per spec drops the whole rule.
+pub fn parse_selector_list(values: &[ComponentValue]) -> Option<Vec<Selector>> {
+    values
+        .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+        .map(parse_selector)
+        .collect()
+}
+
+fn parse_selector(values: &[ComponentValue]) -> Option<Selector> {
+    let mut compounds = vec![CompoundSelector::default()];
+    let mut combinators = Vec::new();
+    let mut pending: Option<Combinator> = None;
+    let mut pseudo_element = None;
+    let mut i = 0;
+
+    let push_simple = |compounds: &mut Vec<CompoundSelector>,
+                       combinators: &mut Vec<Combinator>,
+                       pending: &mut Option<Combinator>,
+                       simple: SimpleSelector| {
+        if let Some(c) = pending.take() {
+            if compounds.last().is_some_and(|c| c.0.is_empty()) {
+                return false;
+            }
+            combinators.push(c);
+            compounds.push(CompoundSelector::default());
+        }
+        compounds.last_mut().unwrap().0.push(simple);
+        true
+    };
+
+    while i < values.len() {
+        if pseudo_element.is_some() && !values[i].is_whitespace() {
+            // 疑似要素の後には何も書けない
+            return None;
+        }
+        let simple = match &values[i] {
+            ComponentValue::Token(CssToken::Whitespace) => {
+                if pending.is_none() && compounds.last().is_some_and(|c| !c.0.is_empty()) {
+                    pending = Some(Combinator::Descendant);
+                }
+                i += 1;
+                continue;
+            }
+            ComponentValue::Token(CssToken::Delim(c @ ('>' | '+' | '~'))) => {
+                if compounds.last().is_some_and(|c| c.0.is_empty()) {
+                    return None;
+                }
+                pending = Some(match c {
+                    '>' => Combinator::Child,
+                    '+' => Combinator::NextSibling,
+                    _ => Combinator::SubsequentSibling,
+                });
+                i += 1;
+                continue;
+            }
+            ComponentValue::Token(CssToken::Delim('*')) => SimpleSelector::Universal,
+            ComponentValue::Token(CssToken::Ident(name)) => SimpleSelector::Type(name.to_ascii_lowercase()),
+            ComponentValue::Token(CssToken::Hash(name, true)) => SimpleSelector::Id(name.clone()),
+            ComponentValue::Token(CssToken::Delim('.')) => match values.get(i + 1) {
+                Some(ComponentValue::Token(CssToken::Ident(name))) => {
+                    i += 1;
+                    SimpleSelector::Class(name.clone())
+                }
+                _ => return None,
+            },
+            ComponentValue::Block { open: '[', contents } => parse_attribute_selector(contents)?,
+            ComponentValue::Token(CssToken::Colon) => {
+                let double = matches!(values.get(i + 1), Some(ComponentValue::Token(CssToken::Colon)));
+                if double {
+                    i += 1;
+                }
+                match values.get(i + 1) {
+                    Some(ComponentValue::Token(CssToken::Ident(name))) => {
+                        i += 1;
+                        let name = name.to_ascii_lowercase();
+                        let legacy_element = matches!(name.as_str(), "before" | "after" | "first-line" | "first-letter");
+                        if double || legacy_element {
+                            pseudo_element = Some(name);
+                            i += 1;
+                            continue;
+                        }
+                        SimpleSelector::Pseudo(parse_pseudo_class(&name)?)
+                    }
+                    Some(ComponentValue::Function { name, args }) if !double => {
+                        i += 1;
+                        SimpleSelector::Pseudo(parse_functional_pseudo_class(name, args)?)
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        if !push_simple(&mut compounds, &mut combinators, &mut pending, simple) {
+            return None;
+        }
+        i += 1;
+    }
+
+    if compounds.last().is_some_and(|c| c.0.is_empty()) {
+        if pseudo_element.is_some() && compounds.len() == 1 {
+            // `::before` 単体は `*::before` と同じ
+            compounds[0].0.push(SimpleSelector::Universal);
+        } else {
+            return None;
+        }
+    }
+    if matches!(pending, Some(c) if c != Combinator::Descendant) {
+        return None;
+    }
+    compounds.reverse();
+    combinators.reverse();
+    Some(Selector {
+        compounds,
+        combinators,
+        pseudo_element,
+    })
+}
+
+fn parse_attribute_selector(contents: &[ComponentValue]) -> Option<SimpleSelector> {
+    let items: Vec<&ComponentValue> = contents.iter().filter(|v| !v.is_whitespace()).collect();
+    let name = items.first()?.ident()?;
+    if items.len() == 1 {
+        return Some(SimpleSelector::Attribute {
+            name,
+            op: AttrOperator::Exists,
+            value: String::new(),
+            case_insensitive: false,
+        });
+    }
+    let (op, rest) = match (items.get(1)?, items.get(2)) {
+        (ComponentValue::Token(CssToken::Delim('=')), _) => (AttrOperator::Equals, 2),
+        (ComponentValue::Token(CssToken::Delim(c)), Some(ComponentValue::Token(CssToken::Delim('=')))) => {
+            let op = match c {
+                '~' => AttrOperator::Includes,
+                '|' => AttrOperator::DashMatch,
+                '^' => AttrOperator::Prefix,
+                '$' => AttrOperator::Suffix,
+                '*' => AttrOperator::Substring,
+                _ => return None,
+            };
+            (op, 3)
+        }
+        _ => return None,
+    };
+    let value = match items.get(rest)? {
+        ComponentValue::Token(CssToken::Ident(v) | CssToken::String(v)) => v.clone(),
+        ComponentValue::Token(CssToken::Number(n)) => n.to_string(),
+        _ => return None,
+    };
+    let case_insensitive = match items.get(rest + 1).map(|v| v.ident()) {
+        None => false,
+        Some(Some(flag)) if flag == "i" => true,
+        Some(Some(flag)) if flag == "s" => false,
+        _ => return None,
+    };
+    Some(SimpleSelector::Attribute {
+        name,
+        op,
+        value,
+        case_insensitive,
+    })
+}
+
+fn parse_pseudo_class(name: &str) -> Option<PseudoClass> {
+    Some(match name {
+        "root" => PseudoClass::Root,
+        "empty" => PseudoClass::Empty,
+        "first-child" => PseudoClass::FirstChild,
+        "last-child" => PseudoClass::LastChild,
+        "only-child" => PseudoClass::OnlyChild,
+        "first-of-type" => PseudoClass::FirstOfType,
+        "last-of-type" => PseudoClass::LastOfType,
+        "only-of-type" => PseudoClass::OnlyOfType,
+        "link" | "any-link" => PseudoClass::Link,
+        "checked" => PseudoClass::Checked,
+        "disabled" => PseudoClass::Disabled,
+        "enabled" => PseudoClass::Enabled,
+        "visited" | "hover" | "active" | "focus" | "focus-within" | "focus-visible" | "target" => PseudoClass::Never,
+        _ => return None,
+    })
+}
+
+fn parse_functional_pseudo_class(name: &str, args: &[ComponentValue]) -> Option<PseudoClass> {
+    Some(match name {
+        "not" => PseudoClass::Not(parse_selector_list(args)?),
+        "is" | "matches" | "any" => PseudoClass::Is(parse_selector_list(args)?),
+        "where" => PseudoClass::Where(parse_selector_list(args)?),
+        "nth-child" => PseudoClass::NthChild(parse_nth(args)?),
+        "nth-last-child" => PseudoClass::NthLastChild(parse_nth(args)?),
+        "nth-of-type" => PseudoClass::NthOfType(parse_nth(args)?),
+        "nth-last-of-type" => PseudoClass::NthLastOfType(parse_nth(args)?),
+        _ => return None,
+    })
+}
+
+/// Parses the `an+b` microsyntax. The tokenizer splits it in awkward places
+/// (`2n+1` is a dimension `2n` and a number `+1`), so the tokens are joined
+/// back into text and parsed from there.
+fn parse_nth(args: &[ComponentValue]) -> Option<Nth> {
+    let mut text = String::new();
+    for arg in args {
+        match arg {
+            ComponentValue::Token(CssToken::Whitespace) => {}
+            ComponentValue::Token(CssToken::Ident(s)) => text.push_str(s),
+            ComponentValue::Token(CssToken::Number(n)) => {
+                if *n >= 0.0 && !text.is_empty() && !text.ends_with(['+', '-']) {
+                    text.push('+');
+                }
+                text.push_str(&n.to_string());
+            }
+            ComponentValue::Token(CssToken::Dimension(n, unit)) => {
+                if *n >= 0.0 && !text.is_empty() && !text.ends_with(['+', '-']) {
+                    text.push('+');
+                }
+                text.push_str(&n.to_string());
+                text.push_str(unit);
+            }
+            ComponentValue::Token(CssToken::Delim(c @ ('+' | '-'))) => text.push(*c),
+            _ => return None,
+        }
+    }
+    let text = text.to_ascii_lowercase();
+    match text.as_str() {
+        "odd" => return Some(Nth { a: 2, b: 1 }),
+        "even" => return Some(Nth { a: 2, b: 0 }),
+        _ => {}
+    }
+    let Some(n_pos) = text.find('n') else {
+        return Some(Nth { a: 0, b: text.parse().ok()? });
+    };
+    let a = match &text[..n_pos] {
+        "" | "+" => 1,
+        "-" => -1,
+        a => a.parse().ok()?,
+    };
+    let rest = text[n_pos + 1..].replace("+-", "-");
+    let b = if rest.is_empty() {
+        0
+    } else {
+        rest.trim_start_matches('+').parse().ok()?
+    };
+    Some(Nth { a, b })
+}
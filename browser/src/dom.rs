@@ -0,0 +1,425 @@
+use std::fmt::Write as _;
+
+/// Index of a node inside a [`Document`] arena.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+/// A single `name="value"` pair on an element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: String,
+}
+
+/// Namespace of an element. The parser puts `<svg>` and `<math>` and
+/// everything inside them (outside integration points) in their own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Namespace {
+    #[default]
+    Html,
+    Svg,
+    MathMl,
+}
+
+/// Tag name and attributes of an element node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ElementData {
+    /// Local name: lower case for HTML and MathML, the SVG spelling
+    /// (`clipPath`, `foreignObject`, ...) for SVG.
+    pub name: String,
+    pub attrs: Vec<Attribute>,
+    pub namespace: Namespace,
+}
+
+impl ElementData {
+    pub fn new(name: impl Into<String>, attrs: Vec<Attribute>) -> Self {
+        Self { name: name.into(), attrs, namespace: Namespace::Html }
+    }
+
+    /// Returns the value of the first attribute called `name`.
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|a| a.name == name)
+            .map(|a| a.value.as_str())
+    }
+
+    pub fn has_attr(&self, name: &str) -> bool {
+        self.attrs.iter().any(|a| a.name == name)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.attr("id")
+    }
+
+    /// Whitespace separated entries of the `class` attribute.
+    pub fn classes(&self) -> impl Iterator<Item = &str> {
+        self.attr("class").unwrap_or("").split_ascii_whitespace()
+    }
+}
+
+/// What kind of node this is, plus its payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeData {
+    Document,
+    Doctype {
+        name: String,
+        public_id: String,
+        system_id: String,
+    },
+    Element(ElementData),
+    Text(String),
+    Comment(String),
+}
+
+/// The document's compatibility mode, chosen by the parser from the doctype.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuirksMode {
+    /// Standards mode.
+    #[default]
+    NoQuirks,
+    /// Standards mode except for the inline line-height calculation.
+    LimitedQuirks,
+    Quirks,
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    pub data: NodeData,
+}
+
+/// A parsed HTML document.
+///
+/// Nodes live in a flat arena and refer to each other through [`NodeId`]s, so
+/// the tree can be handed to Bevy systems without reference counting. Node 0 is
+/// always the document node.
+#[derive(Clone, Debug)]
+pub struct Document {
+    pub nodes: Vec<Node>,
+    /// Messages for every parse error the tree builder recovered from.
+    pub parse_errors: Vec<String>,
+    pub mode: QuirksMode,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: None,
+                children: Vec::new(),
+                data: NodeData::Document,
+            }],
+            parse_errors: Vec::new(),
+            mode: QuirksMode::NoQuirks,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn element(&self, id: NodeId) -> Option<&ElementData> {
+        match &self.nodes[id.0].data {
+            NodeData::Element(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Tag name of `id`, or `None` for non-element nodes.
+    pub fn tag_name(&self, id: NodeId) -> Option<&str> {
+        self.element(id).map(|e| e.name.as_str())
+    }
+
+    pub fn is_element_named(&self, id: NodeId, name: &str) -> bool {
+        self.tag_name(id) == Some(name)
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.nodes[id.0].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.nodes[id.0].children
+    }
+
+    /// Adds a detached node to the arena.
+    pub fn create_node(&mut self, data: NodeData) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node {
+            parent: None,
+            children: Vec::new(),
+            data,
+        });
+        id
+    }
+
+    pub fn create_element(&mut self, name: &str, attrs: Vec<Attribute>) -> NodeId {
+        self.create_node(NodeData::Element(ElementData::new(name, attrs)))
+    }
+
+    pub fn create_element_ns(&mut self, name: &str, attrs: Vec<Attribute>, namespace: Namespace) -> NodeId {
+        self.create_node(NodeData::Element(ElementData { namespace, ..ElementData::new(name, attrs) }))
+    }
+
+    /// Detaches `child` from its current parent, if any.
+    pub fn detach(&mut self, child: NodeId) {
+        if let Some(parent) = self.nodes[child.0].parent.take() {
+            self.nodes[parent.0].children.retain(|c| *c != child);
+        }
+    }
+
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        self.detach(child);
+        self.nodes[child.0].parent = Some(parent);
+        self.nodes[parent.0].children.push(child);
+    }
+
+    /// Inserts `child` into `parent` right before `reference`.
+    /// Falls back to appending when `reference` is not a child of `parent`.
+    pub fn insert_before(&mut self, parent: NodeId, child: NodeId, reference: NodeId) {
+        self.detach(child);
+        self.nodes[child.0].parent = Some(parent);
+        let children = &mut self.nodes[parent.0].children;
+        match children.iter().position(|c| *c == reference) {
+            Some(index) => children.insert(index, child),
+            None => children.push(child),
+        }
+    }
+
+    /// Appends text to `parent`, merging with a trailing text node.
+    pub fn append_text(&mut self, parent: NodeId, text: &str) {
+        if let Some(&last) = self.nodes[parent.0].children.last()
+            && let NodeData::Text(existing) = &mut self.nodes[last.0].data
+        {
+            existing.push_str(text);
+            return;
+        }
+        let node = self.create_node(NodeData::Text(text.to_string()));
+        self.append_child(parent, node);
+    }
+
+    /// Inserts text before `reference`, merging with the previous sibling if it
+    /// is a text node.
+    pub fn insert_text_before(&mut self, parent: NodeId, text: &str, reference: NodeId) {
+        let children = &self.nodes[parent.0].children;
+        let prev = children
+            .iter()
+            .position(|c| *c == reference)
+            .filter(|index| *index > 0)
+            .map(|index| children[index - 1]);
+        if let Some(prev) = prev
+            && let NodeData::Text(existing) = &mut self.nodes[prev.0].data
+        {
+            existing.push_str(text);
+            return;
+        }
+        let node = self.create_node(NodeData::Text(text.to_string()));
+        self.insert_before(parent, node, reference);
+    }
+
+    /// All nodes below `id` in tree order, not including `id` itself.
+    /// The contents of `<template>` elements below `id` are inert and left
+    /// out; pass the template itself to walk its contents.
+    pub fn descendants(&self, id: NodeId) -> Vec<NodeId> {
+        let mut out = Vec::new();
+        let mut stack: Vec<NodeId> = self.children(id).iter().rev().copied().collect();
+        while let Some(next) = stack.pop() {
+            out.push(next);
+            if !self.is_element_named(next, "template") {
+                stack.extend(self.children(next).iter().rev().copied());
+            }
+        }
+        out
+    }
+
+    /// Ancestors of `id`, nearest first.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), move |n| self.parent(*n))
+    }
+
+    /// First element in tree order with the given tag name.
+    pub fn find_element(&self, name: &str) -> Option<NodeId> {
+        self.descendants(self.root())
+            .into_iter()
+            .find(|n| self.is_element_named(*n, name))
+    }
+
+    /// Every element in tree order with the given tag name.
+    pub fn elements_by_tag_name(&self, name: &str) -> Vec<NodeId> {
+        self.descendants(self.root())
+            .into_iter()
+            .filter(|n| self.is_element_named(*n, name))
+            .collect()
+    }
+
+    pub fn document_element(&self) -> Option<NodeId> {
+        self.children(self.root())
+            .iter()
+            .copied()
+            .find(|n| self.element(*n).is_some())
+    }
+
+    pub fn head(&self) -> Option<NodeId> {
+        let html = self.document_element()?;
+        self.children(html)
+            .iter()
+            .copied()
+            .find(|n| self.is_element_named(*n, "head"))
+    }
+
+    pub fn body(&self) -> Option<NodeId> {
+        let html = self.document_element()?;
+        self.children(html)
+            .iter()
+            .copied()
+            .find(|n| self.is_element_named(*n, "body"))
+    }
+
+    pub fn doctype(&self) -> Option<NodeId> {
+        self.children(self.root())
+            .iter()
+            .copied()
+            .find(|n| matches!(self.node(*n).data, NodeData::Doctype { .. }))
+    }
+
+    /// Concatenated text of all text nodes below `id`.
+    pub fn text_content(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        if let NodeData::Text(t) = &self.node(id).data {
+            out.push_str(t);
+        }
+        for n in self.descendants(id) {
+            if let NodeData::Text(t) = &self.node(n).data {
+                out.push_str(t);
+            }
+        }
+        out
+    }
+
+    /// The `<title>` text with whitespace collapsed.
+    pub fn title(&self) -> Option<String> {
+        // SVG の <title> は文書のタイトルではない
+        let title = self
+            .elements_by_tag_name("title")
+            .into_iter()
+            .find(|n| self.element(*n).is_some_and(|e| e.namespace == Namespace::Html))?;
+        let text = self.text_content(title);
+        let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!collapsed.is_empty()).then_some(collapsed)
+    }
+
+    /// Serializes the subtree below `id` back to HTML.
+    pub fn to_html(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        self.write_html(id, &mut out);
+        out
+    }
+
+    fn write_html(&self, id: NodeId, out: &mut String) {
+        match &self.node(id).data {
+            NodeData::Document => {}
+            NodeData::Doctype { name, .. } => {
+                let _ = write!(out, "<!DOCTYPE {}>", name);
+                return;
+            }
+            NodeData::Text(t) => {
+                let raw = self
+                    .parent(id)
+                    .and_then(|p| self.tag_name(p))
+                    .is_some_and(|p| matches!(p, "style" | "script" | "xmp" | "iframe" | "noembed" | "noframes" | "plaintext"));
+                if raw {
+                    out.push_str(t);
+                } else {
+                    out.push_str(&escape_text(t));
+                }
+                return;
+            }
+            NodeData::Comment(c) => {
+                let _ = write!(out, "<!--{}-->", c);
+                return;
+            }
+            NodeData::Element(e) => {
+                out.push('<');
+                out.push_str(&e.name);
+                for a in &e.attrs {
+                    let _ = write!(out, " {}=\"{}\"", a.name, a.value.replace('&', "&amp;").replace('"', "&quot;"));
+                }
+                out.push('>');
+                if is_void_element(&e.name) {
+                    return;
+                }
+            }
+        }
+        for child in self.children(id) {
+            self.write_html(*child, out);
+        }
+        if let NodeData::Element(e) = &self.node(id).data {
+            let _ = write!(out, "</{}>", e.name);
+        }
+    }
+
+    /// Indented outline of the tree, one node per line. Handy for logging.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        self.dump_node(self.root(), 0, &mut out);
+        out
+    }
+
+    fn dump_node(&self, id: NodeId, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        match &self.node(id).data {
+            NodeData::Document => out.push_str("#document\n"),
+            NodeData::Doctype { name, .. } => {
+                let _ = writeln!(out, "{}<!DOCTYPE {}>", indent, name);
+            }
+            NodeData::Element(e) => {
+                let _ = write!(out, "{}<{}", indent, e.name);
+                for a in &e.attrs {
+                    let _ = write!(out, " {}=\"{}\"", a.name, a.value);
+                }
+                out.push_str(">\n");
+            }
+            NodeData::Text(t) => {
+                let _ = writeln!(out, "{}\"{}\"", indent, t);
+            }
+            NodeData::Comment(c) => {
+                let _ = writeln!(out, "{}<!-- {} -->", indent, c);
+            }
+        }
+        for child in self.children(id) {
+            self.dump_node(*child, depth + 1, out);
+        }
+    }
+}
+
+/// Elements that never have children or an end tag.
+pub fn is_void_element(name: &str) -> bool {
+    matches!(
+        name,
+        "area" | "base" | "br" | "col" | "embed" | "hr" | "img" | "input" | "link" | "meta"
+            | "param" | "source" | "track" | "wbr" | "basefont" | "bgsound" | "frame" | "keygen"
+    )
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\u{a0}', "&nbsp;")
+}
@@ -0,0 +1,670 @@
+//! Downloads: responses that are not documents (archives, PDFs, media, ...)
+//! are saved to the download directory instead of being shown in a tab.
+//!
+//! HTTP downloads stream into a `.part` file next to the final one, so a
+//! paused or broken download picks up where it stopped with a `Range`
+//! request.
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use futures_lite::future;
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
+use tokio::io::AsyncWriteExt;
+
+use crate::cookies::RequestContext;
+use crate::http_client::{Client, FetchError, Response, ResponseSource};
+use crate::menu::Fetcher;
+use crate::{DownloadTask, Downloads, ShowDownloadsWindow};
+
+/// Where downloads are saved unless `--download-dir` says otherwise.
+pub const DEFAULT_DIR: &str = "downloads";
+
+/// How quickly the displayed speed follows the actual one.
+const SPEED_SMOOTHING_SECS: f64 = 2.0;
+
+/// Whether a response is to be saved rather than shown: the server asked
+/// for it (`Content-Disposition: attachment`) or no internal viewer takes
+/// its content type. With the `body` at hand, a missing type is sniffed.
+pub fn is_download(headers: &HeaderMap, body: Option<&[u8]>) -> bool {
+    let disposition = headers.get(reqwest::header::CONTENT_DISPOSITION).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if disposition.split(';').next().is_some_and(|d| d.trim().eq_ignore_ascii_case("attachment")) {
+        return true;
+    }
+    let declared = headers.get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+    let content_type = match body {
+        Some(body) => crate::viewers::effective_content_type(declared, body),
+        None => declared.map(str::to_string),
+    };
+    crate::viewers::viewer_for(content_type.as_deref()) == crate::viewers::Viewer::Download
+}
+
+/// Sent when a page load turns out to be a download.
+#[derive(Event, Clone, Debug)]
+pub struct StartDownload {
+    pub response: Response,
+}
+
+/// Buttons of the downloads window.
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DownloadAction {
+    Pause(u64),
+    /// Continue a paused or failed download.
+    Resume(u64),
+    /// Stop and delete what was received.
+    Cancel(u64),
+    /// Take a finished, failed or cancelled download off the list.
+    Remove(u64),
+    /// Play a finished video in the ffmpeg window.
+    OpenInViewer(u64),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DownloadState {
+    Running,
+    Paused,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+/// One entry of the downloads window.
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub id: u64,
+    pub url: String,
+    /// Where the file ends up; it is `<path>.part` until it is complete.
+    pub path: PathBuf,
+    pub content_type: Option<String>,
+    pub received: u64,
+    pub total: Option<u64>,
+    /// Bytes per second, smoothed.
+    pub speed: f64,
+    /// ETag or Last-Modified of the response, sent as `If-Range` on resume.
+    pub validator: Option<String>,
+    /// The server said it takes `Range` requests.
+    pub resumable: bool,
+    pub state: DownloadState,
+    /// Why it was saved rather than shown, when the browser could have been
+    /// expected to show it.
+    pub note: Option<String>,
+}
+
+impl Download {
+    pub fn part_path(&self) -> PathBuf {
+        part_path(&self.path)
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path.file_name().map_or_else(|| self.path.display().to_string(), |n| n.to_string_lossy().into_owned())
+    }
+
+    /// Whether the download can be paused and continued or started over.
+    /// Only HTTP(S) ones can: the others (`file:`, `data:`, ...) are saved
+    /// from a body the page load had already read.
+    pub fn can_resume(&self) -> bool {
+        reqwest::Url::parse(&self.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+    }
+
+    /// Seconds left at the current speed, if the size is known.
+    pub fn eta(&self) -> Option<f64> {
+        let total = self.total?;
+        (self.speed > 1.0).then(|| total.saturating_sub(self.received) as f64 / self.speed)
+    }
+}
+
+/// All downloads of the session and the directory new ones go to.
+#[derive(Clone, Debug)]
+pub struct DownloadList {
+    pub items: Vec<Download>,
+    pub dir: PathBuf,
+    next_id: u64,
+}
+
+impl DownloadList {
+    pub fn new(dir: PathBuf) -> Self {
+        DownloadList {
+            items: Vec::new(),
+            dir,
+            next_id: 1,
+        }
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Download> {
+        self.items.iter_mut().find(|d| d.id == id)
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Download> {
+        self.items.iter().find(|d| d.id == id)
+    }
+}
+
+/// How far a running download has got, published by its task.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub received: u64,
+    pub total: Option<u64>,
+    pub validator: Option<String>,
+    pub resumable: bool,
+}
+
+pub type DownloadProgressSender = tokio::sync::watch::Sender<DownloadProgress>;
+
+// ページの代わりに届いたダウンロードを一覧に加え、保存を始めるシステム
+pub fn start_downloads(
+    mut commands: Commands,
+    mut events: EventReader<StartDownload>,
+    mut downloads: ResMut<Downloads>,
+    mut show_downloads_window: ResMut<ShowDownloadsWindow>,
+    fetcher: Fetcher,
+) {
+    for event in events.read() {
+        let response = &event.response;
+        let list = &mut downloads.0;
+        if let Err(e) = std::fs::create_dir_all(&list.dir) {
+            error!("Failed to create the download directory {}: {}", list.dir.display(), e);
+        }
+        let name = suggested_filename(&response.headers, &response.url);
+        let content_type = response.headers.get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let effective = crate::viewers::effective_content_type(content_type.as_deref(), &response.body);
+        let note = crate::viewers::essence(effective.as_deref())
+            .starts_with("audio/")
+            .then(|| "Audio can't be played here: the player needs a video stream.".to_string());
+        let download = Download {
+            id: list.next_id,
+            url: response.url.to_string(),
+            path: unique_path(&list.dir, &name, &list.items),
+            content_type,
+            received: 0,
+            total: response.headers.get(reqwest::header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()?.parse().ok()),
+            speed: 0.0,
+            validator: validator_of(&response.headers),
+            resumable: accepts_ranges(&response.headers),
+            state: DownloadState::Running,
+            note,
+        };
+        list.next_id += 1;
+        info!("Downloading {} to {}", download.url, download.path.display());
+        // 本文を読まずに渡されたもの (HTTP) は取り直し、読み済みのもの (file: など) はそのまま書く
+        let body = (response.source != ResponseSource::Download).then(|| response.body.clone());
+        commands.spawn(spawn_transfer(&fetcher, &download, body));
+        list.items.push(download);
+        show_downloads_window.0 = true;
+    }
+}
+
+// ダウンロードのタスクを起動する
+fn spawn_transfer(fetcher: &Fetcher, download: &Download, body: Option<Vec<u8>>) -> DownloadTask {
+    let (progress, receiver) = tokio::sync::watch::channel(DownloadProgress {
+        received: download.received,
+        total: download.total,
+        validator: download.validator.clone(),
+        resumable: download.resumable,
+    });
+    let transfer = Transfer {
+        url: download.url.clone(),
+        path: download.path.clone(),
+        validator: download.validator.clone(),
+    };
+    let task = match body {
+        Some(body) => fetcher.run(save_body(transfer, body, progress)),
+        None => fetcher.run(transfer.run(fetcher.client().clone(), progress)),
+    };
+    DownloadTask {
+        id: download.id,
+        task,
+        progress: receiver,
+    }
+}
+
+// 進み具合と速度を一覧に反映し、終わったタスクを片付けるシステム
+pub fn poll_download_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut DownloadTask)>,
+    mut downloads: ResMut<Downloads>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs_f64();
+    for (entity, mut transfer) in &mut tasks {
+        let Some(download) = downloads.0.get_mut(transfer.id) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        let before = download.received;
+        if transfer.progress.has_changed().unwrap_or(false) {
+            let progress = transfer.progress.borrow_and_update().clone();
+            download.received = progress.received;
+            download.total = progress.total;
+            download.validator = progress.validator;
+            download.resumable = progress.resumable;
+        }
+        // 瞬間の速度はばらつくので指数移動平均で均す
+        if dt > 0.0 {
+            let current = download.received.saturating_sub(before) as f64 / dt;
+            let alpha = 1.0 - (-dt / SPEED_SMOOTHING_SECS).exp();
+            download.speed += (current - download.speed) * alpha;
+        }
+        let Some(result) = future::block_on(future::poll_once(&mut transfer.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        download.speed = 0.0;
+        match result {
+            Ok(()) => {
+                info!("Download finished: {}", download.path.display());
+                download.total = Some(download.received);
+                download.state = DownloadState::Finished;
+            }
+            Err(e) => {
+                warn!("Download of {} failed: {}", download.url, e);
+                download.state = DownloadState::Failed(e.to_string());
+            }
+        }
+    }
+}
+
+// ダウンロードの一時停止・再開・中止を行うシステム
+pub fn apply_download_actions(
+    mut commands: Commands,
+    mut events: EventReader<DownloadAction>,
+    mut downloads: ResMut<Downloads>,
+    tasks: Query<(Entity, &DownloadTask)>,
+    fetcher: Fetcher,
+) {
+    for action in events.read() {
+        let stop = |id: u64, commands: &mut Commands| {
+            // タスクを捨てると Tokio 側の受信も止まる。受け取った分は .part に残る
+            for (entity, transfer) in &tasks {
+                if transfer.id == id {
+                    commands.entity(entity).despawn();
+                }
+            }
+        };
+        match *action {
+            DownloadAction::Pause(id) => {
+                if let Some(download) = downloads.0.get_mut(id).filter(|d| d.state == DownloadState::Running && d.can_resume()) {
+                    stop(id, &mut commands);
+                    download.state = DownloadState::Paused;
+                    download.speed = 0.0;
+                }
+            }
+            DownloadAction::Resume(id) => {
+                if let Some(download) = downloads.0.get_mut(id).filter(|d| matches!(d.state, DownloadState::Paused | DownloadState::Failed(_)) && d.can_resume()) {
+                    download.state = DownloadState::Running;
+                    commands.spawn(spawn_transfer(&fetcher, download, None));
+                }
+            }
+            DownloadAction::Cancel(id) => {
+                if let Some(download) = downloads.0.get_mut(id).filter(|d| d.state != DownloadState::Finished) {
+                    stop(id, &mut commands);
+                    download.state = DownloadState::Cancelled;
+                    download.speed = 0.0;
+                    download.received = 0;
+                    remove_file(&download.part_path());
+                }
+            }
+            DownloadAction::Remove(id) => {
+                downloads.0.items.retain(|d| d.id != id || d.state == DownloadState::Running || d.state == DownloadState::Paused);
+            }
+            DownloadAction::OpenInViewer(_) => {}
+        }
+    }
+}
+
+fn remove_file(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to delete {}: {}", path.display(), e),
+    }
+}
+
+pub fn downloads_window(
+    mut contexts: EguiContexts,
+    mut show_downloads_window: ResMut<ShowDownloadsWindow>,
+    mut downloads: ResMut<Downloads>,
+    mut actions: EventWriter<DownloadAction>,
+) {
+    if !show_downloads_window.0 {
+        return;
+    }
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("Downloads")
+        .open(&mut show_downloads_window.0)
+        .default_size(egui::vec2(560.0, 320.0))
+        .show(ctx, |ui| {
+            let list = &mut downloads.0;
+            ui.horizontal(|ui| {
+                ui.label("Save to:");
+                let mut dir = list.dir.display().to_string();
+                if ui.text_edit_singleline(&mut dir).changed() {
+                    list.dir = PathBuf::from(dir);
+                }
+                let finished = list.items.iter().filter(|d| !matches!(d.state, DownloadState::Running | DownloadState::Paused));
+                let finished: Vec<u64> = finished.map(|d| d.id).collect();
+                if ui.add_enabled(!finished.is_empty(), egui::Button::new("Clear list")).clicked() {
+                    for id in finished {
+                        actions.write(DownloadAction::Remove(id));
+                    }
+                }
+            });
+            ui.separator();
+            if list.items.is_empty() {
+                ui.label("No downloads yet.");
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                // 新しいものを上に並べる
+                for download in list.items.iter().rev() {
+                    download_row_ui(ui, download, &mut actions);
+                    ui.separator();
+                }
+            });
+        });
+}
+
+// ダウンロード 1 件の行
+fn download_row_ui(ui: &mut egui::Ui, download: &Download, actions: &mut EventWriter<DownloadAction>) {
+    let id = download.id;
+    ui.horizontal(|ui| {
+        ui.strong(download.file_name()).on_hover_text(download.path.display().to_string());
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| match &download.state {
+            DownloadState::Running => {
+                if ui.small_button("✕").on_hover_text("Cancel").clicked() {
+                    actions.write(DownloadAction::Cancel(id));
+                }
+                if download.can_resume() && ui.small_button("⏸").on_hover_text("Pause").clicked() {
+                    actions.write(DownloadAction::Pause(id));
+                }
+            }
+            DownloadState::Paused | DownloadState::Failed(_) => {
+                if ui.small_button("✕").on_hover_text("Cancel").clicked() {
+                    actions.write(DownloadAction::Cancel(id));
+                }
+                let hint = if download.resumable { "Resume" } else { "Start over (the server does not resume)" };
+                if download.can_resume() && ui.small_button("▶").on_hover_text(hint).clicked() {
+                    actions.write(DownloadAction::Resume(id));
+                }
+            }
+            DownloadState::Finished => {
+                if ui.small_button("🗑").on_hover_text("Remove from list").clicked() {
+                    actions.write(DownloadAction::Remove(id));
+                }
+                if crate::ffmpeg::can_play(download.content_type.as_deref(), &download.path)
+                    && ui.small_button("Open").on_hover_text("Play in the built-in viewer").clicked()
+                {
+                    actions.write(DownloadAction::OpenInViewer(id));
+                }
+            }
+            DownloadState::Cancelled => {
+                if ui.small_button("🗑").on_hover_text("Remove from list").clicked() {
+                    actions.write(DownloadAction::Remove(id));
+                }
+            }
+        });
+    });
+    ui.label(egui::RichText::new(&download.url).small().weak());
+    if let Some(note) = &download.note {
+        ui.label(egui::RichText::new(note).small().italics());
+    }
+    let fraction = download.total.filter(|t| *t > 0).map(|total| download.received as f32 / total as f32);
+    match &download.state {
+        DownloadState::Running => {
+            let bar = match fraction {
+                Some(fraction) => egui::ProgressBar::new(fraction).show_percentage(),
+                None => egui::ProgressBar::new(0.0).animate(true),
+            };
+            ui.add(bar);
+            let mut status = format!("{} — {}/s", progress_text(download), format_bytes(download.speed as u64));
+            if let Some(eta) = download.eta() {
+                status.push_str(&format!(" — {} left", format_duration(eta)));
+            }
+            ui.label(status);
+        }
+        DownloadState::Paused => {
+            ui.add(egui::ProgressBar::new(fraction.unwrap_or(0.0)));
+            ui.label(format!("Paused — {}", progress_text(download)));
+        }
+        DownloadState::Finished => {
+            ui.label(format!("Done — {}", format_bytes(download.received)));
+        }
+        DownloadState::Failed(error) => {
+            ui.colored_label(egui::Color32::RED, format!("Failed — {}", error));
+        }
+        DownloadState::Cancelled => {
+            ui.label("Cancelled");
+        }
+    }
+}
+
+fn progress_text(download: &Download) -> String {
+    match download.total {
+        Some(total) => format!("{} of {}", format_bytes(download.received), format_bytes(total)),
+        None => format_bytes(download.received),
+    }
+}
+
+/// Formats a byte count with a binary unit (`1.5 MiB`).
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match secs {
+        0..60 => format!("{} s", secs),
+        60..3_600 => format!("{} min {} s", secs / 60, secs % 60),
+        _ => format!("{} h {} min", secs / 3_600, secs / 60 % 60),
+    }
+}
+
+/// What a download task needs to know.
+struct Transfer {
+    url: String,
+    path: PathBuf,
+    validator: Option<String>,
+}
+
+impl Transfer {
+    /// Streams the body into the `.part` file, continuing after what is
+    /// already there, and renames it to the final name once complete.
+    async fn run(self, http: Client, progress: DownloadProgressSender) -> Result<(), FetchError> {
+        let part = part_path(&self.path);
+        let offset = tokio::fs::metadata(&part).await.map_or(0, |m| m.len());
+        let validator = self.validator.as_deref().filter(|_| offset > 0);
+        let mut response = match http.open_download(&self.url, offset, validator, &RequestContext::navigation()).await {
+            // 416 は要求した位置以降がない、つまり受け取り済み
+            Err(FetchError::Http(StatusCode::RANGE_NOT_SATISFIABLE)) if offset > 0 => {
+                return rename(&part, &self.path).await;
+            }
+            response => response?,
+        };
+        let url = response.url().clone();
+        let headers = response.headers();
+        // 206 なら続きから、200 ならサーバーが Range を無視したか中身が変わったので最初から
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        let start = if resumed { offset } else { 0 };
+        let total = if resumed {
+            content_range_total(headers).or_else(|| response.content_length().map(|len| offset + len))
+        } else {
+            response.content_length()
+        };
+        progress.send_replace(DownloadProgress {
+            received: start,
+            total,
+            validator: validator_of(headers).or(self.validator.clone()),
+            resumable: resumed || accepts_ranges(headers),
+        });
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&part)
+            .await
+            .map_err(|e| file_error(&part, e))?;
+        let mut received = start;
+        while let Some(chunk) = response.chunk().await.map_err(|e| FetchError::from_reqwest(&e, &url))? {
+            file.write_all(&chunk).await.map_err(|e| file_error(&part, e))?;
+            received += chunk.len() as u64;
+            progress.send_modify(|p| p.received = received);
+        }
+        file.flush().await.map_err(|e| file_error(&part, e))?;
+        if let Some(total) = total
+            && received < total
+        {
+            return Err(FetchError::Other(format!("Connection closed after {} of {} bytes", received, total)));
+        }
+        rename(&part, &self.path).await
+    }
+}
+
+// 読み済みの本文をそのまま書き出す
+async fn save_body(transfer: Transfer, body: Vec<u8>, progress: DownloadProgressSender) -> Result<(), FetchError> {
+    let part = part_path(&transfer.path);
+    tokio::fs::write(&part, &body).await.map_err(|e| file_error(&part, e))?;
+    let len = body.len() as u64;
+    progress.send_modify(|p| {
+        p.received = len;
+        p.total = Some(len);
+    });
+    rename(&part, &transfer.path).await
+}
+
+async fn rename(part: &Path, path: &Path) -> Result<(), FetchError> {
+    tokio::fs::rename(part, path).await.map_err(|e| file_error(path, e))
+}
+
+fn file_error(path: &Path, error: std::io::Error) -> FetchError {
+    FetchError::File(format!("{}: {}", path.display(), error))
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+// If-Range に使える検証子: 強い ETag、なければ Last-Modified
+fn validator_of(headers: &HeaderMap) -> Option<String> {
+    let header = |name| headers.get(name).and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok()).map(str::to_string);
+    header(reqwest::header::ETAG).filter(|etag| !etag.starts_with("W/")).or_else(|| header(reqwest::header::LAST_MODIFIED))
+}
+
+fn accepts_ranges(headers: &HeaderMap) -> bool {
+    headers.get(reqwest::header::ACCEPT_RANGES).and_then(|v| v.to_str().ok()).is_some_and(|v| v.trim().eq_ignore_ascii_case("bytes"))
+}
+
+/// The full length from `Content-Range: bytes 100-999/1000`.
+fn content_range_total(headers: &HeaderMap) -> Option<u64> {
+    let range = headers.get(reqwest::header::CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit('/').next()?.trim().parse().ok()
+}
+
+/// File name for a download: `filename*` or `filename` of the
+/// `Content-Disposition` header, else the last segment of the URL path.
+pub fn suggested_filename(headers: &HeaderMap, url: &reqwest::Url) -> String {
+    let disposition = headers.get(reqwest::header::CONTENT_DISPOSITION).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let from_header = disposition_filename(disposition);
+    let from_url = || {
+        let segment = url.path_segments()?.next_back().filter(|s| !s.is_empty())?;
+        Some(percent_encoding::percent_decode_str(segment).decode_utf8_lossy().into_owned())
+    };
+    let name = from_header.or_else(from_url).map(|name| sanitize_filename(&name)).unwrap_or_default();
+    if name.is_empty() {
+        url.host_str().map_or_else(|| "download".to_string(), sanitize_filename)
+    } else {
+        name
+    }
+}
+
+// Content-Disposition の filename* (RFC 5987) を優先し、なければ filename
+fn disposition_filename(disposition: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in split_params(disposition).into_iter().skip(1) {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded
+                let mut parts = value.splitn(3, '\'');
+                let (Some(charset), Some(_), Some(encoded)) = (parts.next(), parts.next(), parts.next()) else {
+                    continue;
+                };
+                let bytes: Vec<u8> = percent_encoding::percent_decode_str(encoded).collect();
+                let encoding = encoding_rs::Encoding::for_label(charset.as_bytes()).unwrap_or(encoding_rs::UTF_8);
+                extended = Some(encoding.decode_without_bom_handling(&bytes).0.into_owned());
+            }
+            "filename" => {
+                let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).map_or_else(|| value.to_string(), |v| v.replace("\\\"", "\"").replace("\\\\", "\\"));
+                plain = Some(value);
+            }
+            _ => {}
+        }
+    }
+    extended.or(plain).filter(|name| !name.is_empty())
+}
+
+// ; で区切る (引用符の中の ; は区切らない)
+fn split_params(value: &str) -> Vec<&str> {
+    let mut params = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                params.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    params.push(&value[start..]);
+    params
+}
+
+// ディレクトリを抜けたり隠しファイルになったりしない名前にする
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|') { '_' } else { c })
+        .collect();
+    cleaned.trim().trim_start_matches('.').trim_end_matches(['.', ' ']).to_string()
+}
+
+/// `dir/name`, or `dir/name (1)` and so on if that file (or its `.part`)
+/// already exists or another download in `items` is going there.
+fn unique_path(dir: &Path, name: &str, items: &[Download]) -> PathBuf {
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot..]),
+        _ => (name, ""),
+    };
+    let taken = |path: &Path| path.exists() || part_path(path).exists() || items.iter().any(|d| d.path == path);
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while taken(&path) {
+        path = dir.join(format!("{} ({}){}", stem, n, extension));
+        n += 1;
+    }
+    path
+}
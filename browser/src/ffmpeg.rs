@@ -2,7 +2,7 @@
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use ffmpeg_next as ffmpeg; // Cargo.toml に "ffmpeg_next = "0.x" を追加してください
 use ffmpeg_next::format::input;
 use ffmpeg_next::frame::Video;
@@ -12,7 +12,8 @@ use bevy::render::render_resource::{TextureDimension, TextureFormat, TextureUsag
 use bevy::render::render_asset::RenderAssetUsages;
 use bevy::color::palettes::css::PINK;
 use tracing::{info, error};
-use crate::ShowFfmpegWindow;
+use crate::{NativePage, ShowFfmpegWindow};
+use crate::viewers::MediaState;
 use crate::ffmpeg::egui::load::SizedTexture;
 use ffmpeg_sys_next::AVMediaType;
 
@@ -41,13 +42,14 @@ pub struct VideoPlayerNonSendData {
 #[derive(Component)]
 pub struct VideoPlayer {
     pub image_handle: Handle<Image>,
+    pub path: PathBuf, // 再生中のファイル (セッションに保存する)
     pub video_stream_index: usize,
 }
 
 impl VideoPlayer {
     fn new<'a, P>(
         path: P,
-        mut images: ResMut<Assets<Image>>,
+        images: &mut Assets<Image>,
     ) -> Result<(VideoPlayer, VideoPlayerNonSendData), ffmpeg::Error>
     where
         P: AsRef<Path>,
@@ -96,6 +98,7 @@ impl VideoPlayer {
         Ok((
             VideoPlayer {
                 image_handle,
+                path: path.as_ref().to_path_buf(),
                 video_stream_index,
             },
             VideoPlayerNonSendData {
@@ -112,15 +115,23 @@ pub fn init_video_player_system(
     mut commands: Commands,
     mut images: ResMut<Assets<Image>>,
     mut video_resource: NonSendMut<VideoResource>,
+    restored: Option<Res<crate::RestoredSession>>,
 ) {
     //file pass
-    let video_path = "./assets/video/video.mp4"; 
+    // 前回のセッションがあれば、そのとき再生していた動画を開く
+    let video_path = match restored {
+        Some(session) => match &session.0.video {
+            Some(path) => path.clone(),
+            None => return,
+        },
+        None => PathBuf::from("./assets/video/video.mp4"),
+    };
 
-    match VideoPlayer::new(video_path, images) {
+    match VideoPlayer::new(&video_path, &mut images) {
         Ok((video_player, video_player_non_send)) => {
             let entity = commands.spawn(video_player).id();
             video_resource.video_players.insert(entity, video_player_non_send);
-            info!("Video player initialized for: {}", video_path);
+            info!("Video player initialized for: {}", video_path.display());
         }
         Err(e) => {
             error!("Failed to initialize video player: {}", e);
@@ -128,54 +139,147 @@ pub fn init_video_player_system(
     }
 }
 
+/// Opens `path` and spawns a [`VideoPlayer`] entity for it together with
+/// `bundle`. Returns the entity, the frame texture and the picture size.
+pub fn spawn_video_player(
+    commands: &mut Commands,
+    images: &mut Assets<Image>,
+    video_resource: &mut VideoResource,
+    path: &Path,
+    bundle: impl Bundle,
+) -> Result<(Entity, Handle<Image>, Vec2), ffmpeg::Error> {
+    let (video_player, video_player_non_send) = VideoPlayer::new(path, images)?;
+    let texture = video_player.image_handle.clone();
+    let size = images.get(&texture).map_or(Vec2::ZERO, |image| image.size_f32());
+    let entity = commands.spawn((video_player, bundle)).id();
+    video_resource.video_players.insert(entity, video_player_non_send);
+    Ok((entity, texture, size))
+}
+
+/// Whether a downloaded file is something the player can show: a video
+/// type, or a video file extension when the type says nothing useful.
+pub fn can_play(content_type: Option<&str>, path: &Path) -> bool {
+    let essence = content_type.unwrap_or("").split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    if essence.starts_with("video/") {
+        return true;
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    matches!(essence.as_str(), "" | "application/octet-stream")
+        && matches!(extension.as_str(), "mp4" | "m4v" | "mkv" | "webm" | "mov" | "avi" | "ogv")
+}
+
+// ダウンロード一覧の「Open」で、落とした動画を今の動画の代わりに再生するシステム
+pub fn open_downloaded_video(
+    mut commands: Commands,
+    mut actions: EventReader<crate::downloads::DownloadAction>,
+    downloads: Res<crate::Downloads>,
+    players: Query<Entity, (With<VideoPlayer>, Without<crate::PageViewer>)>,
+    mut images: ResMut<Assets<Image>>,
+    mut video_resource: NonSendMut<VideoResource>,
+    mut show_ffmpeg_window: ResMut<ShowFfmpegWindow>,
+) {
+    for action in actions.read() {
+        let crate::downloads::DownloadAction::OpenInViewer(id) = *action else {
+            continue;
+        };
+        let Some(download) = downloads.0.get(id) else {
+            continue;
+        };
+        match spawn_video_player(&mut commands, &mut images, &mut video_resource, &download.path, ()) {
+            Ok(_) => {
+                // タブで再生中のものは残し、ウィンドウのプレイヤーだけ差し替える
+                for entity in &players {
+                    commands.entity(entity).despawn();
+                    video_resource.video_players.remove(&entity);
+                }
+                show_ffmpeg_window.0 = true;
+                info!("Video player opened: {}", download.path.display());
+            }
+            Err(e) => {
+                error!("Failed to open {}: {}", download.path.display(), e);
+            }
+        }
+    }
+}
+
 // 動画フレームをデコードし、BevyのImageアセットを更新するシステム
 // このシステムは毎フレーム実行され、動画の進行を管理します
 pub fn play_video(
-    mut video_player_query: Query<(&mut VideoPlayer, Entity)>,
+    video_player_query: Query<(&VideoPlayer, Entity, Option<&crate::PageViewer>)>,
+    mut pages: Query<&mut NativePage>,
     mut video_resource: NonSendMut<VideoResource>,
     mut images: ResMut<Assets<Image>>,
 ) {
-    for (video_player, entity) in video_player_query.iter_mut() {
-        let video_player_non_send = video_resource.video_players.get_mut(&entity).unwrap();
-        
-        // 1フレームを処理するまでパケットを読み込み、デコードを試みます
-        while let Some((stream, packet)) = video_player_non_send.input_context.packets().next() {
-            // パケットが動画ストリームのものであることを確認
-            if stream.index() == video_player.video_stream_index {
-                // パケットをデコーダーに送信
-                video_player_non_send.decoder.send_packet(&packet).unwrap();
-                let mut decoded = Video::empty();
-                
-                // 完全なフレームがデコードされたか確認
-                if let Ok(()) = video_player_non_send.decoder.receive_frame(&mut decoded) {
-                    let mut rgb_frame = Video::empty();
-                    
-                    // フレームをスケーラーに通してRGBAに変換
-                    video_player_non_send
-                        .scaler_context
-                        .run(&decoded, &mut rgb_frame)
-                        .unwrap();
-                    
-                    let image = images.get_mut(&video_player.image_handle).unwrap();
-                    if let Some(data) = image.data.as_mut() {
-                        data.copy_from_slice(rgb_frame.data(0));
-                    } else {
-                        error!("Image data is None");
-                    }
-                    
-                    // 1フレーム更新したら、一旦システムを終了して次のBevyフレームで続きを処理
-                    return;
-                }
+    for (video_player, entity, page_viewer) in video_player_query.iter() {
+        // 差し替えたばかりの古いプレイヤーはもうデータがない
+        let Some(video_player_non_send) = video_resource.video_players.get_mut(&entity) else {
+            continue;
+        };
+        // 壊れた動画でブラウザごと落ちないよう、エラーになったプレイヤーは止めて捨てる
+        if let Err(e) = decode_next_frame(video_player, video_player_non_send, &mut images) {
+            error!("Stopped playing {}: {}", video_player.path.display(), e);
+            video_resource.video_players.remove(&entity);
+            // タブのページならそこに理由を出す (プレイヤーは despawn_stale_viewers が片付ける)
+            if let Some(viewer) = page_viewer
+                && let Ok(mut page) = pages.get_mut(viewer.tab)
+                && matches!(&*page, NativePage::Media(MediaState::Playing { player, .. }) if *player == entity)
+            {
+                *page = NativePage::Media(MediaState::Failed(format!("Playback stopped: {}", e)));
             }
         }
-        // フレームが受信できなかった場合（ファイルの終端など）
-        // デコーダーに再生の終了を通知
-        match video_player_non_send.decoder.send_eof() {
-            Err(ffmpeg::Error::Eof) => {
-                // info!("End of video stream reached for entity: {:?}", entity);
-                // 必要であれば、ここで動画のループ再生や停止などのロジックを追加
+    }
+}
+
+// 1フレームを処理するまでパケットを読み込み、デコードして画像に書き込む
+// 1フレーム更新したら、次のBevyフレームで続きを処理する
+fn decode_next_frame(
+    video_player: &VideoPlayer,
+    video_player_non_send: &mut VideoPlayerNonSendData,
+    images: &mut Assets<Image>,
+) -> Result<(), ffmpeg::Error> {
+    while let Some((stream, packet)) = video_player_non_send.input_context.packets().next() {
+        // パケットが動画ストリームのものであることを確認
+        if stream.index() != video_player.video_stream_index {
+            continue;
+        }
+        match video_player_non_send.decoder.send_packet(&packet) {
+            // デコーダーにフレームが溜まっているだけなら、先に受け取ればよい
+            Err(ffmpeg::Error::Other { errno }) if errno == ffmpeg::util::error::EAGAIN => {}
+            result => result?,
+        }
+        let mut decoded = Video::empty();
+        // 完全なフレームがデコードされたか確認
+        if video_player_non_send.decoder.receive_frame(&mut decoded).is_ok() {
+            let mut rgb_frame = Video::empty();
+            // フレームをスケーラーに通してRGBAに変換
+            video_player_non_send.scaler_context.run(&decoded, &mut rgb_frame)?;
+            match images.get_mut(&video_player.image_handle).and_then(|image| image.data.as_mut()) {
+                Some(data) => copy_frame(&rgb_frame, data),
+                None => error!("Image data is None"),
             }
-            other => other.unwrap(), // その他のエラーはパニック
+            return Ok(());
+        }
+    }
+    // フレームが受信できなかった場合（ファイルの終端など）
+    // デコーダーに再生の終了を通知
+    match video_player_non_send.decoder.send_eof() {
+        // 必要であれば、ここで動画のループ再生や停止などのロジックを追加
+        Ok(()) | Err(ffmpeg::Error::Eof) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// RGBA のフレームを画像に写す。ffmpeg の行の幅 (stride) は width*4 より長いことがあるので 1 行ずつ写す
+fn copy_frame(frame: &Video, data: &mut [u8]) {
+    let row = frame.width() as usize * 4;
+    if row == 0 {
+        return;
+    }
+    let stride = frame.stride(0);
+    let source = frame.data(0);
+    for (y, target) in data.chunks_exact_mut(row).take(frame.height() as usize).enumerate() {
+        if let Some(line) = source.get(y * stride..y * stride + row) {
+            target.copy_from_slice(line);
         }
     }
 }
@@ -183,7 +287,7 @@ pub fn play_video(
 pub fn ffmpeg_window(
     mut contexts: EguiContexts,
     show_ffmpeg_window: Res<ShowFfmpegWindow>,
-    video_player_query: Query<(&VideoPlayer, Entity)>,
+    video_player_query: Query<(&VideoPlayer, Entity), Without<crate::PageViewer>>,
     images_assets: Res<Assets<Image>>,
 ) {
     // Collect `TextureId` and `Vec2` data before the `show` closure.
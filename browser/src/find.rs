@@ -0,0 +1,285 @@
+//! Find in page: the Ctrl+F bar of the page view. Searches the text as it
+//! is laid out, or the source in the source view, highlights every match
+//! and steps through them, plain or as a regular expression.
+
+use std::ops::Range;
+
+use bevy_egui::egui::{self, vec2, Color32, Rect};
+use regex::{Regex, RegexBuilder};
+
+use crate::layout::{DisplayItem, LayoutHost, PageLayout};
+
+/// Matches looked for at most, so that `.` on a large page stays quick.
+const MAX_MATCHES: usize = 10_000;
+
+fn match_color() -> Color32 {
+    Color32::from_rgba_unmultiplied(255, 230, 0, 110)
+}
+
+fn current_color() -> Color32 {
+    Color32::from_rgba_unmultiplied(255, 140, 0, 170)
+}
+
+/// State of the find bar.
+#[derive(Default)]
+pub struct FindBar {
+    pub open: bool,
+    pub query: String,
+    pub case_sensitive: bool,
+    pub regex: bool,
+    /// Index of the match stepped to.
+    current: usize,
+    /// Matches found by the view drawn this frame.
+    count: usize,
+    /// Scroll the current match into view on the next draw.
+    scroll: bool,
+    /// Put the keyboard focus in the text box on the next draw.
+    focus: bool,
+    /// The query compiled, with the text and modes it was compiled from.
+    compiled: Option<(String, bool, bool, Result<Regex, String>)>,
+}
+
+impl FindBar {
+    pub fn show(&mut self) {
+        self.open = true;
+        self.focus = true;
+        self.scroll = true;
+    }
+
+    /// Moves to the next match, or the previous one, wrapping around.
+    pub fn step(&mut self, forward: bool) {
+        if self.count == 0 {
+            return;
+        }
+        self.current = if forward { (self.current + 1) % self.count } else { (self.current + self.count - 1) % self.count };
+        self.scroll = true;
+    }
+
+    fn restart(&mut self) {
+        self.current = 0;
+        self.scroll = true;
+    }
+
+    fn matcher(&mut self) -> Option<&Result<Regex, String>> {
+        if self.query.is_empty() {
+            return None;
+        }
+        let stale = !matches!(&self.compiled, Some((query, case_sensitive, regex, _))
+            if *query == self.query && *case_sensitive == self.case_sensitive && *regex == self.regex);
+        if stale {
+            // 正規表現でなければ文字どおりに探す
+            let pattern = if self.regex { self.query.clone() } else { regex::escape(&self.query) };
+            let compiled = RegexBuilder::new(&pattern)
+                .case_insensitive(!self.case_sensitive)
+                .build()
+                .map_err(|e| e.to_string());
+            self.compiled = Some((self.query.clone(), self.case_sensitive, self.regex, compiled));
+        }
+        self.compiled.as_ref().map(|(.., compiled)| compiled)
+    }
+
+    /// Byte ranges of the matches in `text`. Empty matches are skipped.
+    fn find(&mut self, text: &str) -> Vec<Range<usize>> {
+        let matches: Vec<Range<usize>> = match self.matcher() {
+            Some(Ok(regex)) => regex.find_iter(text).filter(|m| !m.is_empty()).map(|m| m.range()).take(MAX_MATCHES).collect(),
+            _ => Vec::new(),
+        };
+        self.count = matches.len();
+        if self.current >= self.count {
+            self.current = 0;
+        }
+        matches
+    }
+
+    /// Ctrl+F opens the bar; F3 and Shift+F3 step while it is open.
+    pub fn shortcuts(&mut self, ctx: &egui::Context) {
+        let (open, next, previous) = ctx.input_mut(|input| {
+            (
+                input.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::F)),
+                input.consume_key(egui::Modifiers::NONE, egui::Key::F3),
+                input.consume_key(egui::Modifiers::SHIFT, egui::Key::F3),
+            )
+        });
+        if open {
+            self.show();
+        }
+        if self.open && next {
+            self.step(true);
+        }
+        if self.open && previous {
+            self.step(false);
+        }
+    }
+
+    /// Draws the bar. Call before the view it searches, which updates the count.
+    pub fn bar_ui(&mut self, ui: &mut egui::Ui) {
+        if !self.open {
+            return;
+        }
+        // 数は前のフレームで描いたビューが数えたもの
+        let count = std::mem::take(&mut self.count);
+        ui.horizontal(|ui| {
+            ui.label("Find:");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.query).id(egui::Id::new("find_bar")).desired_width(200.0));
+            if std::mem::take(&mut self.focus) {
+                response.request_focus();
+            }
+            if response.changed() {
+                self.restart();
+            }
+            if response.lost_focus() {
+                if ui.input(|input| input.key_pressed(egui::Key::Escape)) {
+                    self.open = false;
+                } else if ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                    // Enter で次へ、Shift+Enter で前へ。続けて押せるようにフォーカスを戻す
+                    self.count = count;
+                    self.step(!ui.input(|input| input.modifiers.shift));
+                    response.request_focus();
+                }
+            }
+            if ui.add_enabled(count > 0, egui::Button::new("⏶")).on_hover_text("Previous match (Shift+F3)").clicked() {
+                self.count = count;
+                self.step(false);
+            }
+            if ui.add_enabled(count > 0, egui::Button::new("⏷")).on_hover_text("Next match (F3)").clicked() {
+                self.count = count;
+                self.step(true);
+            }
+            if ui.checkbox(&mut self.case_sensitive, "Match case").changed() | ui.checkbox(&mut self.regex, "Regex").changed() {
+                self.restart();
+            }
+            match self.matcher() {
+                Some(Err(error)) => {
+                    let error = error.clone();
+                    ui.colored_label(ui.visuals().error_fg_color, "Invalid regex").on_hover_text(egui::RichText::new(error).monospace());
+                }
+                Some(Ok(_)) if count == 0 => {
+                    ui.colored_label(ui.visuals().warn_fg_color, "No matches");
+                }
+                Some(Ok(_)) if count >= MAX_MATCHES => {
+                    ui.label(format!("{} of {}+ matches", self.current + 1, MAX_MATCHES));
+                }
+                Some(Ok(_)) => {
+                    ui.label(format!("{} of {} matches", self.current + 1, count));
+                }
+                None => {}
+            }
+            if ui.button("✕").on_hover_text("Close (Esc)").clicked() {
+                self.open = false;
+            }
+        });
+    }
+
+    /// Boxes in page coordinates of the matches in the laid-out page that
+    /// fall in `visible`, and always of the current one (flagged `true`).
+    pub fn page_matches(&mut self, layout: &PageLayout, host: &dyn LayoutHost, visible: Rect) -> Vec<(Rect, bool)> {
+        if !self.open {
+            return Vec::new();
+        }
+        let (text, segments) = rendered_text(layout);
+        let matches = self.find(&text);
+        let mut boxes = Vec::new();
+        for (index, range) in matches.iter().enumerate() {
+            let current = index == self.current;
+            for rect in match_rects(layout, &segments, range, host, (!current).then_some(visible)) {
+                boxes.push((rect, current));
+            }
+        }
+        boxes
+    }
+
+    /// Paints the boxes from [`FindBar::page_matches`] over the page painted
+    /// at `origin`, and scrolls the current match into view after a step.
+    pub fn paint_page_matches(&mut self, ui: &egui::Ui, origin: egui::Pos2, boxes: &[(Rect, bool)]) {
+        let painter = ui.painter();
+        for &(rect, current) in boxes {
+            painter.rect_filled(rect.translate(origin.to_vec2()), 2.0, if current { current_color() } else { match_color() });
+        }
+        if std::mem::take(&mut self.scroll)
+            && let Some((rect, _)) = boxes.iter().find(|(_, current)| *current)
+        {
+            ui.scroll_to_rect(rect.translate(origin.to_vec2()), Some(egui::Align::Center));
+        }
+    }
+
+    /// Draws the page source with the matches highlighted.
+    pub fn source_ui(&mut self, ui: &mut egui::Ui, source: &str) {
+        let matches = if self.open { self.find(source) } else { Vec::new() };
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let color = ui.visuals().text_color();
+        let format = |background| egui::TextFormat { font_id: font_id.clone(), color, background, ..Default::default() };
+        let mut job = egui::text::LayoutJob::default();
+        job.wrap.max_width = ui.available_width();
+        let mut last = 0;
+        for (index, range) in matches.iter().enumerate() {
+            job.append(&source[last..range.start], 0.0, format(Color32::TRANSPARENT));
+            job.append(&source[range.clone()], 0.0, format(if index == self.current { current_color() } else { match_color() }));
+            last = range.end;
+        }
+        job.append(&source[last..], 0.0, format(Color32::TRANSPARENT));
+        let galley = ui.fonts(|fonts| fonts.layout_job(job));
+        let (rect, _) = ui.allocate_exact_size(galley.size(), egui::Sense::hover());
+        if std::mem::take(&mut self.scroll)
+            && let Some(range) = matches.get(self.current)
+        {
+            let cursor = egui::text::CCursor::new(source[..range.start].chars().count());
+            ui.scroll_to_rect(galley.pos_from_ccursor(cursor).translate(rect.min.to_vec2()), Some(egui::Align::Center));
+        }
+        ui.painter().galley(rect.min, galley, color);
+    }
+}
+
+/// The text of the page as laid out, and where in it each text item starts
+/// (item index, byte offset).
+fn rendered_text(layout: &PageLayout) -> (String, Vec<(usize, usize)>) {
+    let mut text = String::new();
+    let mut segments = Vec::new();
+    let mut previous: Option<Rect> = None;
+    for (index, item) in layout.items.iter().enumerate() {
+        let DisplayItem::Text { rect, text: run, .. } = item else {
+            continue;
+        };
+        // 同じ行で隣り合う文字列はそのまま繋ぎ、行やブロックが変わったら空白で区切る
+        if let Some(previous) = previous {
+            let adjacent = (rect.min.x - previous.max.x).abs() < 1.0 && rect.y_range().intersects(previous.y_range());
+            if !adjacent && !text.ends_with(char::is_whitespace) && !run.starts_with(char::is_whitespace) {
+                text.push(' ');
+            }
+        }
+        segments.push((index, text.len()));
+        text.push_str(run);
+        previous = Some(*rect);
+    }
+    (text, segments)
+}
+
+/// Boxes in page coordinates covering `range` of the rendered text, one
+/// per text item it spans, leaving out items outside `visible` if given.
+fn match_rects(
+    layout: &PageLayout,
+    segments: &[(usize, usize)],
+    range: &Range<usize>,
+    host: &dyn LayoutHost,
+    visible: Option<Rect>,
+) -> Vec<Rect> {
+    let first = segments.partition_point(|&(_, start)| start <= range.start).saturating_sub(1);
+    let mut rects = Vec::new();
+    for &(index, start) in &segments[first..] {
+        if start >= range.end {
+            break;
+        }
+        let DisplayItem::Text { rect, text, font, .. } = &layout.items[index] else {
+            continue;
+        };
+        let end = start + text.len();
+        if end <= range.start || visible.is_some_and(|visible| !rect.intersects(visible)) {
+            continue;
+        }
+        let local = range.start.max(start) - start..range.end.min(end) - start;
+        let x = host.text_width(&text[..local.start], font);
+        let width = host.text_width(&text[local], font);
+        rects.push(Rect::from_min_size(rect.min + vec2(x, 0.0), vec2(width, rect.height())));
+    }
+    rects
+}
+
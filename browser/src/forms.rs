@@ -0,0 +1,752 @@
+//! HTML forms: the values of a page's controls, egui widgets bound to them,
+//! and submission as a GET or POST navigation with the body encoded as
+//! `application/x-www-form-urlencoded`, `multipart/form-data` or
+//! `text/plain`.
+//!
+//! Values live in a [`FormState`] next to the document rather than in the
+//! DOM itself, so typing does not make the page restyle. A control the user
+//! has not touched still has the value its markup gives it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use encoding_rs::Encoding;
+
+use crate::dom::{Document, NodeId};
+use crate::history::{Navigate, NavigationAction};
+use crate::http_client::PostData;
+use crate::{CurrentDocument, DocumentEncoding, PageForms, ResponseBody};
+
+/// What kind of control an element is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlKind {
+    /// A single-line text field (`text`, `search`, `email`, `number`, ...).
+    Text,
+    Password,
+    TextArea,
+    Checkbox,
+    Radio,
+    Select { multiple: bool },
+    Submit,
+    /// `<input type=image>`: a submit button sending the click position.
+    Image,
+    Reset,
+    /// A button that does nothing without scripts.
+    Button,
+    Hidden,
+    /// `<input type=file>`, entered as a path since there is no file dialog.
+    File,
+}
+
+/// The kind of control `node` is, or `None` if it is not one.
+pub fn control_kind(doc: &Document, node: NodeId) -> Option<ControlKind> {
+    let element = doc.element(node)?;
+    let kind = match element.name.as_str() {
+        "input" => match element.attr("type").unwrap_or("text").trim().to_ascii_lowercase().as_str() {
+            "password" => ControlKind::Password,
+            "checkbox" => ControlKind::Checkbox,
+            "radio" => ControlKind::Radio,
+            "submit" => ControlKind::Submit,
+            "image" => ControlKind::Image,
+            "reset" => ControlKind::Reset,
+            "button" => ControlKind::Button,
+            "hidden" => ControlKind::Hidden,
+            "file" => ControlKind::File,
+            _ => ControlKind::Text,
+        },
+        "textarea" => ControlKind::TextArea,
+        "select" => ControlKind::Select {
+            multiple: element.has_attr("multiple"),
+        },
+        "button" => match element.attr("type").unwrap_or("submit").trim().to_ascii_lowercase().as_str() {
+            "reset" => ControlKind::Reset,
+            "button" => ControlKind::Button,
+            _ => ControlKind::Submit,
+        },
+        _ => return None,
+    };
+    Some(kind)
+}
+
+/// A value the user gave a control.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ControlValue {
+    Text(String),
+    Checked(bool),
+    /// The selected `<option>`s of a `<select>`.
+    Selected(Vec<NodeId>),
+}
+
+/// Values of the controls on a page that the user has changed.
+#[derive(Clone, Debug, Default)]
+pub struct FormState {
+    values: HashMap<NodeId, ControlValue>,
+}
+
+impl FormState {
+    /// Text of a text field, text area, hidden or file input. A file input
+    /// is empty until the user enters a path; its `value` attribute is ignored.
+    pub fn text(&self, doc: &Document, node: NodeId) -> String {
+        match self.values.get(&node) {
+            Some(ControlValue::Text(text)) => text.clone(),
+            // ファイルはユーザーが選んだものだけ。ページの value で指定させない
+            _ if control_kind(doc, node) == Some(ControlKind::File) => String::new(),
+            _ if doc.is_element_named(node, "textarea") => {
+                // 開始タグ直後の改行は値に含めない
+                let text = doc.text_content(node);
+                text.strip_prefix('\n').map(str::to_string).unwrap_or(text)
+            }
+            _ => doc.element(node).and_then(|e| e.attr("value")).unwrap_or("").to_string(),
+        }
+    }
+
+    /// Whether a checkbox or radio button is checked.
+    pub fn checked(&self, doc: &Document, node: NodeId) -> bool {
+        match self.values.get(&node) {
+            Some(ControlValue::Checked(checked)) => *checked,
+            _ => doc.element(node).is_some_and(|e| e.has_attr("checked")),
+        }
+    }
+
+    /// The selected options of a `<select>`. Without a `selected` option, a
+    /// drop-down shows its first enabled option.
+    pub fn selected(&self, doc: &Document, node: NodeId) -> Vec<NodeId> {
+        if let Some(ControlValue::Selected(selected)) = self.values.get(&node) {
+            return selected.clone();
+        }
+        let options = options(doc, node);
+        let selected: Vec<NodeId> = options.iter().copied().filter(|o| doc.element(*o).is_some_and(|e| e.has_attr("selected"))).collect();
+        if !selected.is_empty() || matches!(control_kind(doc, node), Some(ControlKind::Select { multiple: true })) {
+            return selected;
+        }
+        options.into_iter().find(|o| !is_disabled(doc, *o)).into_iter().collect()
+    }
+
+    pub fn set(&mut self, node: NodeId, value: ControlValue) {
+        self.values.insert(node, value);
+    }
+
+    /// Checks a radio button and unchecks the others of its group.
+    pub fn check_radio(&mut self, doc: &Document, node: NodeId) {
+        let name = doc.element(node).and_then(|e| e.attr("name")).unwrap_or("");
+        if !name.is_empty() {
+            let owner = form_owner(doc, node);
+            for other in doc.descendants(doc.root()) {
+                if other != node
+                    && control_kind(doc, other) == Some(ControlKind::Radio)
+                    && doc.element(other).and_then(|e| e.attr("name")) == Some(name)
+                    && form_owner(doc, other) == owner
+                {
+                    self.set(other, ControlValue::Checked(false));
+                }
+            }
+        }
+        self.set(node, ControlValue::Checked(true));
+    }
+
+    /// Puts the controls of `form` back to the values in the markup.
+    pub fn reset(&mut self, doc: &Document, form: NodeId) {
+        for control in form_controls(doc, form) {
+            self.values.remove(&control);
+        }
+    }
+}
+
+/// The form a control belongs to: the one its `form` attribute names, else
+/// the nearest enclosing `<form>`.
+pub fn form_owner(doc: &Document, node: NodeId) -> Option<NodeId> {
+    if let Some(id) = doc.element(node)?.attr("form") {
+        return doc
+            .elements_by_tag_name("form")
+            .into_iter()
+            .find(|f| doc.element(*f).and_then(|e| e.id()) == Some(id));
+    }
+    doc.ancestors(node).find(|n| doc.is_element_named(*n, "form"))
+}
+
+/// The controls of `form`, in tree order.
+pub fn form_controls(doc: &Document, form: NodeId) -> Vec<NodeId> {
+    doc.descendants(doc.root())
+        .into_iter()
+        .filter(|n| control_kind(doc, *n).is_some() && form_owner(doc, *n) == Some(form))
+        .collect()
+}
+
+/// Whether a control or option is disabled, itself or by a disabled
+/// `<fieldset>` or `<optgroup>` around it.
+pub fn is_disabled(doc: &Document, node: NodeId) -> bool {
+    std::iter::once(node)
+        .chain(doc.ancestors(node))
+        .filter(|n| *n == node || doc.is_element_named(*n, "fieldset") || doc.is_element_named(*n, "optgroup"))
+        .any(|n| doc.element(n).is_some_and(|e| e.has_attr("disabled")))
+}
+
+/// The `<option>`s of a `<select>`, in tree order.
+fn options(doc: &Document, select: NodeId) -> Vec<NodeId> {
+    doc.descendants(select).into_iter().filter(|n| doc.is_element_named(*n, "option")).collect()
+}
+
+fn option_label(doc: &Document, option: NodeId) -> String {
+    match doc.element(option).and_then(|e| e.attr("label")) {
+        Some(label) => label.to_string(),
+        None => collapse_whitespace(&doc.text_content(option)),
+    }
+}
+
+fn option_value(doc: &Document, option: NodeId) -> String {
+    match doc.element(option).and_then(|e| e.attr("value")) {
+        Some(value) => value.to_string(),
+        None => collapse_whitespace(&doc.text_content(option)),
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// ---- widgets -------------------------------------------------------------------
+
+/// Draws the control `node` as an egui widget over `rect` (screen
+/// coordinates) and keeps what the user enters in `state`. Returns the form
+/// to submit, with the button that submits it, when the user submits one.
+pub fn control_ui(ui: &mut egui::Ui, doc: &Document, state: &mut FormState, node: NodeId, rect: egui::Rect) -> Option<(NodeId, Option<NodeId>)> {
+    let kind = control_kind(doc, node)?;
+    let element = doc.element(node)?;
+    let attr = |name: &str| element.attr(name);
+    let font = egui::FontId::proportional((rect.height() * 0.75).clamp(8.0, 16.0));
+    let owner = form_owner(doc, node);
+    let mut submit = None;
+    // 画面外の部品を飛ばしても ID が変わらないよう、部品ごとに ID を固定する
+    ui.scope_builder(egui::UiBuilder::new().id_salt(("form-control", node)).max_rect(rect), |ui| {
+        if is_disabled(doc, node) {
+            ui.disable();
+        }
+        match kind {
+            ControlKind::Text | ControlKind::Password | ControlKind::File => {
+                let mut text = state.text(doc, node);
+                let hint = match kind {
+                    ControlKind::File => "Path of a file to send",
+                    _ => attr("placeholder").unwrap_or(""),
+                };
+                let mut edit = egui::TextEdit::singleline(&mut text)
+                    .font(font)
+                    .margin(egui::Margin::symmetric(2, 0))
+                    .desired_width(rect.width())
+                    .password(kind == ControlKind::Password)
+                    .interactive(!element.has_attr("readonly"))
+                    .hint_text(hint);
+                if let Some(limit) = attr("maxlength").and_then(|m| m.trim().parse().ok()) {
+                    edit = edit.char_limit(limit);
+                }
+                let response = ui.put(rect, edit);
+                if response.changed() {
+                    state.set(node, ControlValue::Text(text));
+                }
+                // テキスト欄で Enter を押すとフォームを送る
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    submit = owner.map(|form| (form, None));
+                }
+            }
+            ControlKind::TextArea => {
+                let mut text = state.text(doc, node);
+                let response = egui::ScrollArea::vertical()
+                    .max_height(rect.height())
+                    .show(ui, |ui| {
+                        ui.add_sized(
+                            rect.size(),
+                            egui::TextEdit::multiline(&mut text)
+                                .font(font)
+                                .interactive(!element.has_attr("readonly"))
+                                .hint_text(attr("placeholder").unwrap_or("")),
+                        )
+                    })
+                    .inner;
+                if response.changed() {
+                    state.set(node, ControlValue::Text(text));
+                }
+            }
+            ControlKind::Checkbox => {
+                let mut checked = state.checked(doc, node);
+                if ui.put(rect, egui::Checkbox::without_text(&mut checked)).changed() {
+                    state.set(node, ControlValue::Checked(checked));
+                }
+            }
+            ControlKind::Radio => {
+                let checked = state.checked(doc, node);
+                if ui.put(rect, egui::RadioButton::new(checked, "")).clicked() && !checked {
+                    state.check_radio(doc, node);
+                }
+            }
+            ControlKind::Select { multiple } => select_ui(ui, doc, state, node, rect, multiple, font),
+            ControlKind::Submit | ControlKind::Image | ControlKind::Reset | ControlKind::Button => {
+                let label = match kind {
+                    ControlKind::Image => attr("alt").unwrap_or("Submit"),
+                    ControlKind::Reset => attr("value").unwrap_or("Reset"),
+                    ControlKind::Submit => attr("value").unwrap_or("Submit"),
+                    _ => attr("value").unwrap_or(""),
+                };
+                if ui.put(rect, egui::Button::new(egui::RichText::new(label).font(font))).clicked() {
+                    match kind {
+                        ControlKind::Reset => {
+                            if let Some(form) = owner {
+                                state.reset(doc, form);
+                            }
+                        }
+                        ControlKind::Button => {}
+                        _ => submit = owner.map(|form| (form, Some(node))),
+                    }
+                }
+            }
+            ControlKind::Hidden => {}
+        }
+    });
+    submit
+}
+
+fn select_ui(ui: &mut egui::Ui, doc: &Document, state: &mut FormState, node: NodeId, rect: egui::Rect, multiple: bool, font: egui::FontId) {
+    let mut selected = state.selected(doc, node);
+    let text = selected.first().map(|o| option_label(doc, *o)).unwrap_or_default();
+    let mut changed = false;
+    // optgroup の見出しと option を木の順に並べる
+    let mut entries = |ui: &mut egui::Ui| {
+        for child in doc.descendants(node) {
+            if doc.is_element_named(child, "optgroup") {
+                let label = doc.element(child).and_then(|e| e.attr("label")).unwrap_or("");
+                ui.label(egui::RichText::new(label).strong());
+            } else if doc.is_element_named(child, "option") {
+                let is_selected = selected.contains(&child);
+                let option = egui::SelectableLabel::new(is_selected, egui::RichText::new(option_label(doc, child)).font(font.clone()));
+                if ui.add_enabled(!is_disabled(doc, child), option).clicked() {
+                    if !multiple {
+                        selected.clear();
+                        selected.push(child);
+                    } else if is_selected {
+                        selected.retain(|o| *o != child);
+                    } else {
+                        selected.push(child);
+                    }
+                    changed = true;
+                }
+            }
+        }
+    };
+    if multiple || select_size(doc, node) > 1 {
+        egui::Frame::group(ui.style()).inner_margin(1.0).show(ui, |ui| {
+            egui::ScrollArea::vertical().max_height(rect.height() - 4.0).auto_shrink([false, false]).show(ui, |ui| entries(ui));
+        });
+    } else {
+        egui::ComboBox::from_id_salt("select")
+            .width(rect.width())
+            .selected_text(egui::RichText::new(text).font(font.clone()))
+            .show_ui(ui, |ui| entries(ui));
+    }
+    if changed {
+        // 選択順ではなく木の順に並べておく
+        let order = options(doc, node);
+        selected.sort_by_key(|o| order.iter().position(|n| n == o));
+        state.set(node, ControlValue::Selected(selected));
+    }
+}
+
+/// Rows a `<select>` shows: its `size`, or 4 for a multiple selection.
+pub fn select_size(doc: &Document, node: NodeId) -> usize {
+    let element = doc.element(node);
+    let default = if element.is_some_and(|e| e.has_attr("multiple")) { 4 } else { 1 };
+    element.and_then(|e| e.attr("size")).and_then(|s| s.trim().parse().ok()).filter(|s| *s > 0).unwrap_or(default)
+}
+
+/// Handles a click on page content at `node` that is not a widget: a
+/// `<button>` submits or resets its form and a `<label>` toggles the
+/// checkbox or radio button it labels. Returns the form to submit, if any.
+pub fn activate(doc: &Document, state: &mut FormState, node: NodeId) -> Option<(NodeId, Option<NodeId>)> {
+    for target in std::iter::once(node).chain(doc.ancestors(node)) {
+        if doc.is_element_named(target, "button") {
+            if is_disabled(doc, target) {
+                return None;
+            }
+            let form = form_owner(doc, target)?;
+            return match control_kind(doc, target) {
+                Some(ControlKind::Submit) => Some((form, Some(target))),
+                Some(ControlKind::Reset) => {
+                    state.reset(doc, form);
+                    None
+                }
+                _ => None,
+            };
+        }
+        if doc.is_element_named(target, "label") {
+            let control = labeled_control(doc, target)?;
+            if is_disabled(doc, control) {
+                return None;
+            }
+            match control_kind(doc, control) {
+                Some(ControlKind::Checkbox) => {
+                    let checked = state.checked(doc, control);
+                    state.set(control, ControlValue::Checked(!checked));
+                }
+                Some(ControlKind::Radio) => state.check_radio(doc, control),
+                _ => {}
+            }
+            return None;
+        }
+    }
+    None
+}
+
+// <label for> の指す部品、なければ中にある最初の部品
+fn labeled_control(doc: &Document, label: NodeId) -> Option<NodeId> {
+    match doc.element(label)?.attr("for") {
+        Some(id) => doc
+            .descendants(doc.root())
+            .into_iter()
+            .find(|n| doc.element(*n).and_then(|e| e.id()) == Some(id) && control_kind(doc, *n).is_some()),
+        None => doc.descendants(label).into_iter().find(|n| control_kind(doc, *n).is_some()),
+    }
+}
+
+// ---- submission ------------------------------------------------------------------
+
+/// A request to submit a form of a tab, with the button that submitted it.
+#[derive(Event, Clone, Debug)]
+pub struct SubmitForm {
+    pub tab: Entity,
+    pub form: NodeId,
+    pub submitter: Option<NodeId>,
+}
+
+/// One entry of the form data set.
+#[derive(Clone, Debug, PartialEq)]
+enum Entry {
+    Text(String),
+    /// The path the user entered in a file input, if any.
+    File(Option<PathBuf>),
+}
+
+/// A file read for a `multipart/form-data` body.
+struct Upload {
+    filename: String,
+    content_type: String,
+    bytes: Vec<u8>,
+}
+
+impl Upload {
+    /// What an empty or unreadable file input sends.
+    fn empty() -> Self {
+        Upload {
+            filename: String::new(),
+            content_type: "application/octet-stream".to_string(),
+            bytes: Vec::new(),
+        }
+    }
+}
+
+/// The body of a form sent by POST. Files are only read when the request
+/// is sent, in the fetch task, so a large one does not stall the frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormBody(BodyKind);
+
+#[derive(Clone, Debug, PartialEq)]
+enum BodyKind {
+    Encoded(PostData),
+    Multipart { entries: Vec<(String, Entry)>, encoding: &'static Encoding },
+}
+
+impl FormBody {
+    pub fn content_type(&self) -> &str {
+        match &self.0 {
+            BodyKind::Encoded(data) => &data.content_type,
+            BodyKind::Multipart { .. } => "multipart/form-data",
+        }
+    }
+
+    /// Reads the files of the form and encodes the request body.
+    pub async fn into_post_data(self) -> PostData {
+        match self.0 {
+            BodyKind::Encoded(data) => data,
+            BodyKind::Multipart { entries, encoding } => {
+                let mut uploads = Vec::new();
+                for (_, entry) in &entries {
+                    if let Entry::File(path) = entry {
+                        uploads.push(read_upload(path.as_deref()).await);
+                    }
+                }
+                multipart(&entries, uploads, encoding)
+            }
+        }
+    }
+}
+
+/// Where a form goes and how.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Submission {
+    Get(String),
+    Post { url: String, data: FormBody },
+}
+
+/// Builds the submission of `form` by `submitter` (the default button when
+/// `None`): its action URL resolved against the document base URL, and the
+/// form data set in the method and encoding type the form asks for.
+pub fn submission(
+    doc: &Document,
+    document_url: &str,
+    document_encoding: &'static Encoding,
+    state: &FormState,
+    form: NodeId,
+    submitter: Option<NodeId>,
+) -> Option<Submission> {
+    // Enter で送ったときは最初の送信ボタンが押されたことにする
+    let submitter = submitter.or_else(|| {
+        form_controls(doc, form)
+            .into_iter()
+            .find(|c| matches!(control_kind(doc, *c), Some(ControlKind::Submit | ControlKind::Image)))
+    });
+    // 送信ボタンの formaction などはフォームの指定より優先する
+    let setting = |form_attr: &str, button_attr: &str| {
+        submitter
+            .and_then(|s| doc.element(s)?.attr(button_attr))
+            .or_else(|| doc.element(form)?.attr(form_attr))
+            .map(str::trim)
+    };
+    let action = setting("action", "formaction").filter(|a| !a.is_empty()).unwrap_or(document_url);
+    let mut url = reqwest::Url::parse(&crate::links::resolve(doc, document_url, action)?).ok()?;
+    let method = setting("method", "formmethod").unwrap_or("get").to_ascii_lowercase();
+    let enctype = setting("enctype", "formenctype").unwrap_or("").to_ascii_lowercase();
+    let encoding = form_encoding(doc, form, document_encoding);
+    let entries = form_data(doc, state, form, submitter, encoding);
+
+    match method.as_str() {
+        "post" => {
+            let data = match enctype.as_str() {
+                "multipart/form-data" => BodyKind::Multipart { entries, encoding },
+                "text/plain" => BodyKind::Encoded(PostData {
+                    content_type: "text/plain".to_string(),
+                    body: text_plain(&entries, encoding),
+                }),
+                _ => BodyKind::Encoded(PostData {
+                    content_type: "application/x-www-form-urlencoded".to_string(),
+                    body: urlencoded(&entries, encoding).into_bytes(),
+                }),
+            };
+            let data = FormBody(data);
+            Some(Submission::Post { url: url.to_string(), data })
+        }
+        "dialog" => None,
+        _ => {
+            // GET はクエリを置き換える (フラグメントはそのまま)
+            url.set_query(Some(&urlencoded(&entries, encoding)));
+            Some(Submission::Get(url.to_string()))
+        }
+    }
+}
+
+// accept-charset に書かれた最初の使える文字コード、なければ文書の文字コード
+fn form_encoding(doc: &Document, form: NodeId, document_encoding: &'static Encoding) -> &'static Encoding {
+    doc.element(form)
+        .and_then(|e| e.attr("accept-charset"))
+        .and_then(|labels| labels.split_ascii_whitespace().find_map(|label| Encoding::for_label(label.as_bytes())))
+        .unwrap_or(document_encoding)
+        .output_encoding()
+}
+
+// フォームデータ集合を作る (HTML の "constructing the entry list")
+fn form_data(doc: &Document, state: &FormState, form: NodeId, submitter: Option<NodeId>, encoding: &'static Encoding) -> Vec<(String, Entry)> {
+    let mut entries = Vec::new();
+    for control in form_controls(doc, form) {
+        let (Some(kind), Some(element)) = (control_kind(doc, control), doc.element(control)) else {
+            continue;
+        };
+        if is_disabled(doc, control) || doc.ancestors(control).any(|n| doc.is_element_named(n, "datalist")) {
+            continue;
+        }
+        let name = element.attr("name").unwrap_or("");
+        match kind {
+            ControlKind::Submit | ControlKind::Image if Some(control) != submitter => continue,
+            ControlKind::Reset | ControlKind::Button => continue,
+            ControlKind::Image => {
+                // クリック位置は取っていないので左上を押したことにする
+                let prefix = if name.is_empty() { String::new() } else { format!("{}.", name) };
+                entries.push((format!("{}x", prefix), Entry::Text("0".to_string())));
+                entries.push((format!("{}y", prefix), Entry::Text("0".to_string())));
+                continue;
+            }
+            _ => {}
+        }
+        if name.is_empty() {
+            continue;
+        }
+        let name = name.to_string();
+        match kind {
+            ControlKind::Checkbox | ControlKind::Radio => {
+                if state.checked(doc, control) {
+                    entries.push((name, Entry::Text(element.attr("value").unwrap_or("on").to_string())));
+                }
+            }
+            ControlKind::Select { .. } => {
+                for option in state.selected(doc, control) {
+                    entries.push((name.clone(), Entry::Text(option_value(doc, option))));
+                }
+            }
+            ControlKind::File => {
+                let path = state.text(doc, control);
+                let path = path.trim();
+                entries.push((name, Entry::File((!path.is_empty()).then(|| PathBuf::from(path)))));
+            }
+            ControlKind::Hidden if name.eq_ignore_ascii_case("_charset_") => entries.push((name, Entry::Text(encoding.name().to_string()))),
+            _ => entries.push((name, Entry::Text(state.text(doc, control)))),
+        }
+    }
+    entries
+}
+
+// ファイル欄に書かれたパスのファイルを読む。空や読めないときは空のファイルとして送る
+async fn read_upload(path: Option<&Path>) -> Upload {
+    let Some(path) = path else {
+        return Upload::empty();
+    };
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Upload {
+            filename: file_name(path),
+            content_type: crate::schemes::content_type_for(path).to_string(),
+            bytes,
+        },
+        Err(e) => {
+            warn!("Could not read {} for the form: {}", path.display(), e);
+            Upload::empty()
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// 改行はすべて CRLF にして送る
+fn normalize_newlines(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\r', '\n']) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n").replace('\n', "\r\n"))
+}
+
+fn encode<'a>(encoding: &'static Encoding, text: &'a str) -> Cow<'a, [u8]> {
+    // 文字コードで表せない文字は &#...; になる
+    encoding.encode(text).0
+}
+
+fn urlencoded(entries: &[(String, Entry)], encoding: &'static Encoding) -> String {
+    let encode_override: &dyn Fn(&str) -> Cow<'_, [u8]> = &|text| encode(encoding, text);
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    serializer.encoding_override(Some(encode_override));
+    for (name, entry) in entries {
+        let value = match entry {
+            Entry::Text(text) => normalize_newlines(text),
+            Entry::File(path) => Cow::Owned(path.as_deref().map(file_name).unwrap_or_default()),
+        };
+        serializer.append_pair(&normalize_newlines(name), &value);
+    }
+    serializer.finish()
+}
+
+fn text_plain(entries: &[(String, Entry)], encoding: &'static Encoding) -> Vec<u8> {
+    let mut body = String::new();
+    for (name, entry) in entries {
+        let value = match entry {
+            Entry::Text(text) => normalize_newlines(text),
+            Entry::File(path) => Cow::Owned(path.as_deref().map(file_name).unwrap_or_default()),
+        };
+        body.push_str(&format!("{}={}\r\n", normalize_newlines(name), value));
+    }
+    encode(encoding, &body).into_owned()
+}
+
+/// `uploads` are the files of the `Entry::File` entries, in order.
+fn multipart(entries: &[(String, Entry)], uploads: Vec<Upload>, encoding: &'static Encoding) -> PostData {
+    let boundary = multipart_boundary();
+    // 名前とファイル名の中の " と改行はパーセントエンコードする
+    let escape = |text: &str| text.replace('"', "%22").replace('\r', "%0D").replace('\n', "%0A");
+    let mut body = Vec::new();
+    let mut uploads = uploads.into_iter();
+    for (name, entry) in entries {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        body.extend_from_slice(b"Content-Disposition: form-data; name=\"");
+        body.extend_from_slice(&encode(encoding, &escape(&normalize_newlines(name))));
+        body.push(b'"');
+        match entry {
+            Entry::Text(text) => {
+                body.extend_from_slice(b"\r\n\r\n");
+                body.extend_from_slice(&encode(encoding, &normalize_newlines(text)));
+            }
+            Entry::File(_) => {
+                let Upload { filename, content_type, bytes } = uploads.next().unwrap_or_else(Upload::empty);
+                body.extend_from_slice(b"; filename=\"");
+                body.extend_from_slice(&encode(encoding, &escape(&filename)));
+                body.extend_from_slice(format!("\"\r\nContent-Type: {}\r\n\r\n", content_type).as_bytes());
+                body.extend_from_slice(&bytes);
+            }
+        }
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    PostData {
+        content_type: format!("multipart/form-data; boundary={}", boundary),
+        body,
+    }
+}
+
+fn multipart_boundary() -> String {
+    use ring::rand::SecureRandom;
+    let mut random = [0u8; 12];
+    // 乱数が取れなければ時刻で代える
+    if ring::rand::SystemRandom::new().fill(&mut random).is_err() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        random.copy_from_slice(&nanos.to_le_bytes()[..12]);
+    }
+    let hex: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("----BrowserFormBoundary{}", hex)
+}
+
+// ---- systems ---------------------------------------------------------------------
+
+// 新しい文書になったら入力途中の値を捨てるシステム
+pub fn reset_form_state(mut pages: Query<&mut PageForms, Changed<CurrentDocument>>) {
+    for mut forms in &mut pages {
+        forms.0 = FormState::default();
+    }
+}
+
+// SubmitForm イベントを受けてフォームの内容を組み立て、GET/POST のナビゲーションにするシステム
+pub fn submit_forms(
+    mut events: EventReader<SubmitForm>,
+    pages: Query<(&CurrentDocument, &ResponseBody, &DocumentEncoding, &PageForms)>,
+    mut navigate: EventWriter<Navigate>,
+) {
+    for event in events.read() {
+        let Ok((document, body, document_encoding, forms)) = pages.get(event.tab) else {
+            continue;
+        };
+        let Some(submission) = submission(&document.0, &body.url, document_encoding.encoding, &forms.0, event.form, event.submitter) else {
+            continue;
+        };
+        let action = match submission {
+            Submission::Get(url) => {
+                info!("Submitting form by GET: {}", url);
+                NavigationAction::Link {
+                    url,
+                    initiator: body.url.clone(),
+                }
+            }
+            Submission::Post { url, data } => {
+                info!("Submitting form by POST: {} ({})", url, data.content_type());
+                NavigationAction::Post {
+                    url,
+                    data,
+                    initiator: body.url.clone(),
+                }
+            }
+        };
+        navigate.write(Navigate { tab: event.tab, action });
+    }
+}
@@ -0,0 +1,325 @@
+//! The Gopher protocol (RFC 1436): a selector line over TCP, and menus of
+//! typed items that link to other selectors.
+//!
+//! URLs follow RFC 4266: `gopher://host:port/<type><selector>`, with the
+//! search string of a type 7 item after `%09`.
+
+use reqwest::Url;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::debug;
+
+use crate::http_client::{body_too_large, FetchError, ProgressSender, Response, ResponseSource, DEFAULT_CONNECT_TIMEOUT, MAX_BODY_SIZE};
+
+pub const DEFAULT_PORT: u16 = 70;
+/// Content type given to menus (item types 1 and 7), which are drawn natively.
+pub const MENU_CONTENT_TYPE: &str = "text/x-gopher-menu";
+
+/// One line of a menu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    /// Item type: `0` text, `1` menu, `7` search, `i` information, ...
+    pub kind: char,
+    pub display: String,
+    pub selector: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Item {
+    /// Where the item points, or `None` for information lines and errors.
+    pub fn url(&self) -> Option<String> {
+        match self.kind {
+            'i' | '3' => None,
+            // "URL:" で始まるセレクタは他のプロトコルへのリンク
+            'h' if self.selector.starts_with("URL:") => Some(self.selector["URL:".len()..].to_string()),
+            '8' | 'T' => Some(format!("telnet://{}:{}", self.host, self.port)),
+            kind => Some(item_url(&self.host, self.port, kind, &self.selector, None)),
+        }
+    }
+
+    /// The URL of a search (type 7) item for this query.
+    pub fn search_url(&self, query: &str) -> String {
+        item_url(&self.host, self.port, self.kind, &self.selector, Some(query))
+    }
+
+    /// Short label for the item type, shown in front of links.
+    pub fn kind_label(&self) -> &'static str {
+        match self.kind {
+            '0' => "TXT",
+            '1' => "DIR",
+            '7' => "?",
+            '2' | '8' | 'T' => "TEL",
+            '4' | '5' | '6' | '9' => "BIN",
+            'g' | 'I' | 'p' => "IMG",
+            's' | '<' => "SND",
+            ';' => "VID",
+            'h' => "WEB",
+            'd' => "DOC",
+            '3' => "ERR",
+            _ => "",
+        }
+    }
+}
+
+/// Characters escaped in the selector part of a URL.
+const SELECTOR: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+fn item_url(host: &str, port: u16, kind: char, selector: &str, search: Option<&str>) -> String {
+    let port = if port == DEFAULT_PORT { String::new() } else { format!(":{}", port) };
+    let mut url = format!("gopher://{}{}/{}{}", host, port, kind, percent_encoding::utf8_percent_encode(selector, SELECTOR));
+    if let Some(search) = search {
+        url.push_str("%09");
+        url.push_str(&percent_encoding::utf8_percent_encode(search, SELECTOR).to_string());
+    }
+    url
+}
+
+/// Fetches a `gopher://` URL.
+pub async fn fetch(url: &Url, progress: Option<&ProgressSender>) -> Result<Response, FetchError> {
+    let host = url.host_str().ok_or_else(|| FetchError::InvalidUrl(format!("{}: no host", url)))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port().unwrap_or(DEFAULT_PORT);
+    // パスの先頭 1 文字が項目の種類で、残りがセレクタ (空ならトップのメニュー)
+    let path = percent_encoding::percent_decode_str(url.path()).collect::<Vec<u8>>();
+    let path = path.strip_prefix(b"/").unwrap_or(&path);
+    let (kind, selector) = match path.split_first() {
+        Some((&kind, selector)) => (kind as char, selector),
+        None => ('1', &[][..]),
+    };
+    // 検索語はセレクタとタブで区切って送る
+    let mut request = selector.to_vec();
+    if let Some(query) = url.query().filter(|_| kind == '7' && !selector.contains(&b'\t')) {
+        request.push(b'\t');
+        request.extend(percent_encoding::percent_decode_str(query));
+    }
+    // 改行を入れられると好きな行を送れてしまう (gopher での SSRF)。タブは検索語の区切りだけ許す
+    let tabs = request.iter().filter(|&&b| b == b'\t').count();
+    if request.contains(&b'\r') || request.contains(&b'\n') || tabs > usize::from(kind == '7') {
+        return Err(FetchError::InvalidUrl(format!("{}: control characters in the selector", url)));
+    }
+    request.extend_from_slice(b"\r\n");
+
+    let connect = tokio::net::TcpStream::connect((host, port));
+    let mut stream = tokio::time::timeout(DEFAULT_CONNECT_TIMEOUT, connect)
+        .await
+        .map_err(|_| FetchError::Timeout)?
+        .map_err(|e| if e.to_string().contains("lookup") { FetchError::Dns(host.to_string()) } else { FetchError::Connect(e.to_string()) })?;
+    stream.write_all(&request).await.map_err(|e| FetchError::Connect(e.to_string()))?;
+
+    let mut body = Vec::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let n = stream.read(&mut buf).await.map_err(|e| FetchError::Connect(e.to_string()))?;
+        if n == 0 {
+            break;
+        }
+        if body.len() + n > MAX_BODY_SIZE {
+            return Err(body_too_large());
+        }
+        body.extend_from_slice(&buf[..n]);
+        if let Some(progress) = progress {
+            progress.send_modify(|p| {
+                p.url = url.to_string();
+                p.received = body.len() as u64;
+            });
+        }
+    }
+    debug!("Gopher {} ({}): {} bytes", url, kind, body.len());
+
+    let content_type = match kind {
+        '1' | '7' => {
+            strip_terminator(&mut body);
+            MENU_CONTENT_TYPE
+        }
+        '0' => {
+            strip_terminator(&mut body);
+            "text/plain"
+        }
+        'h' => "text/html",
+        'g' => "image/gif",
+        // それ以外はセレクタの拡張子から推す
+        _ => crate::schemes::content_type_for(std::path::Path::new(&*String::from_utf8_lossy(selector))),
+    };
+    let mut response = Response::local(url.clone(), content_type, body);
+    response.source = ResponseSource::Network;
+    Ok(response)
+}
+
+// テキストとメニューの終わりを示す "." の行を取り除く
+fn strip_terminator(body: &mut Vec<u8>) {
+    for terminator in [&b"\r\n.\r\n"[..], b"\n.\n", b"\r\n.", b"\n."] {
+        if body.ends_with(terminator) {
+            body.truncate(body.len() - terminator.len() + terminator.iter().position(|&b| b == b'.').unwrap_or(0));
+            return;
+        }
+    }
+    if body.as_slice() == b".\r\n" || body.as_slice() == b"." {
+        body.clear();
+    }
+}
+
+/// Parses a menu: tab-separated `<type><display>`, selector, host and port
+/// on each line.
+pub fn parse_menu(text: &str) -> Vec<Item> {
+    text.lines()
+        .filter(|line| !line.is_empty() && *line != ".")
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let first = fields.next().unwrap_or("");
+            let mut chars = first.chars();
+            let kind = chars.next().unwrap_or('i');
+            Item {
+                kind,
+                display: chars.as_str().to_string(),
+                selector: fields.next().unwrap_or("").to_string(),
+                host: fields.next().unwrap_or("").to_string(),
+                port: fields.next().and_then(|p| p.trim().parse().ok()).unwrap_or(DEFAULT_PORT),
+            }
+        })
+        .collect()
+}
+
+/// The same menu as HTML, for the DOM view and the tab label.
+pub fn menu_to_html(items: &[Item], url: &str) -> String {
+    let mut html = format!("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head>\n<body><pre>\n", escape(url));
+    for item in items {
+        match item.url() {
+            Some(href) => html.push_str(&format!("{:>3} <a href=\"{}\">{}</a>\n", item.kind_label(), escape(&href), escape(&item.display))),
+            None => html.push_str(&format!("    {}\n", escape(&item.display))),
+        }
+    }
+    html.push_str("</pre></body></html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// A local Gopher server answering each request with `respond(request)`,
+    /// where the request is its selector line without the CRLF. Returns its
+    /// base URL and the requests it has seen.
+    async fn fixture_server(respond: fn(&str) -> Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base = format!("gopher://{}", listener.local_addr().expect("address"));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut line = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !line.ends_with(b"\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => line.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&line).trim_end_matches("\r\n").to_string();
+                let response = respond(&request);
+                log.lock().unwrap().push(request);
+                let _ = stream.write_all(&response).await;
+            }
+        });
+        (base, seen)
+    }
+
+    async fn get(url: &str) -> Result<Response, FetchError> {
+        fetch(&Url::parse(url).expect("url"), None).await
+    }
+
+    fn content_type(response: &Response) -> &str {
+        response.headers[reqwest::header::CONTENT_TYPE].to_str().expect("content type")
+    }
+
+    #[test]
+    fn menu_lines_are_split_into_items() {
+        let items = parse_menu("1Docs\t/docs\texample.org\t70\r\niWelcome\t\terror.host\t1\r\n7Search\t/find\texample.org\t7070\r\n.\r\n");
+        assert_eq!(
+            items,
+            vec![
+                Item { kind: '1', display: "Docs".to_string(), selector: "/docs".to_string(), host: "example.org".to_string(), port: 70 },
+                Item { kind: 'i', display: "Welcome".to_string(), selector: String::new(), host: "error.host".to_string(), port: 1 },
+                Item { kind: '7', display: "Search".to_string(), selector: "/find".to_string(), host: "example.org".to_string(), port: 7070 },
+            ]
+        );
+        // 欠けた欄は既定値で埋める
+        assert_eq!(parse_menu("0Short")[0], Item { kind: '0', display: "Short".to_string(), selector: String::new(), host: String::new(), port: DEFAULT_PORT });
+    }
+
+    #[test]
+    fn item_urls_follow_rfc_4266() {
+        let item = |kind, selector: &str, port| Item { kind, display: String::new(), selector: selector.to_string(), host: "example.org".to_string(), port };
+        assert_eq!(item('0', "/a b?", 70).url().as_deref(), Some("gopher://example.org/0/a%20b%3F"));
+        assert_eq!(item('1', "", 7070).url().as_deref(), Some("gopher://example.org:7070/1"));
+        assert_eq!(item('h', "URL:https://example.com/", 70).url().as_deref(), Some("https://example.com/"));
+        assert_eq!(item('8', "", 23).url().as_deref(), Some("telnet://example.org:23"));
+        assert_eq!(item('i', "", 70).url(), None);
+        assert_eq!(item('3', "", 70).url(), None);
+        assert_eq!(item('7', "/find", 70).search_url("two words"), "gopher://example.org/7/find%09two%20words");
+    }
+
+    #[test]
+    fn menu_html_links_items_and_escapes_text() {
+        let html = menu_to_html(&parse_menu("iHello <world>\r\n1Docs\t/docs\texample.org\t70\r\n"), "gopher://example.org/");
+        assert!(html.contains("    Hello &lt;world&gt;\n"));
+        assert!(html.contains("DIR <a href=\"gopher://example.org/1/docs\">Docs</a>\n"));
+    }
+
+    #[tokio::test]
+    async fn menus_and_text_are_fetched_without_the_terminator() {
+        let (base, seen) = fixture_server(|request| match request {
+            "" => b"1Docs\t/docs\texample.org\t70\r\n.\r\n".to_vec(),
+            _ => b"Hello\r\n.\r\n".to_vec(),
+        })
+        .await;
+        let menu = get(&base).await.expect("menu");
+        assert_eq!(content_type(&menu), MENU_CONTENT_TYPE);
+        assert_eq!(menu.body, b"1Docs\t/docs\texample.org\t70\r\n");
+        assert_eq!(menu.source, ResponseSource::Network);
+        let text = get(&format!("{}/0/notes%20today.txt", base)).await.expect("text");
+        assert_eq!(content_type(&text), "text/plain");
+        assert_eq!(text.body, b"Hello\r\n");
+        assert_eq!(*seen.lock().unwrap(), vec!["".to_string(), "/notes today.txt".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn search_queries_are_sent_after_a_tab() {
+        let (base, seen) = fixture_server(|_| b"iNo results\t\t\t0\r\n.\r\n".to_vec()).await;
+        get(&format!("{}/7/find%09two%20words", base)).await.expect("search in path");
+        get(&format!("{}/7/find?two%20words", base)).await.expect("search in query");
+        assert_eq!(*seen.lock().unwrap(), vec!["/find\ttwo words".to_string(), "/find\ttwo words".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn line_breaks_and_stray_tabs_in_selectors_are_refused() {
+        let (base, seen) = fixture_server(|_| Vec::new()).await;
+        for path in ["/0/a%0D%0Ab", "/0/a%0Ab", "/0/a%09b", "/7/find%09a%09b"] {
+            let error = get(&format!("{}{}", base, path)).await.unwrap_err();
+            assert!(matches!(error, FetchError::InvalidUrl(_)), "{}: {:?}", path, error);
+        }
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_refused() {
+        let (base, _) = fixture_server(|_| vec![b'a'; MAX_BODY_SIZE + 1]).await;
+        let error = get(&format!("{}/9/big.bin", base)).await.unwrap_err();
+        assert_eq!(error, body_too_large());
+    }
+}
@@ -0,0 +1,346 @@
+//! Session history: the back/forward stack of visited pages.
+//!
+//! Each tab has its own stack. Every navigation goes through a [`Navigate`]
+//! event so the URL bar, the shortcut keys and the history window all move
+//! through the same code.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+use serde::{Deserialize, Serialize};
+
+use crate::cookies::RequestContext;
+use crate::forms::FormBody;
+use crate::http_client::FetchProgress;
+use crate::menu::Fetcher;
+use crate::schemes::AboutSources;
+use crate::{AsyncComputeTaskPool, BrowsingHistory, CurrentDocument, CurrentUrl, FetchHtmlTask, LoadState, PageIndex, ResponseBody, ScrollTarget, ShowHistoryWindow, Tabs};
+
+/// One visited page.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub url: String,
+    /// `<title>` of the page, once it has loaded.
+    pub title: Option<String>,
+    /// Last time the entry was shown, in seconds since the Unix epoch.
+    pub visited_at: u64,
+}
+
+/// The back/forward stack. `current` indexes `entries`; loading a new page
+/// drops everything after it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SessionHistory {
+    pub entries: Vec<HistoryEntry>,
+    pub current: Option<usize>,
+}
+
+impl SessionHistory {
+    pub fn current_entry(&self) -> Option<&HistoryEntry> {
+        self.current.and_then(|i| self.entries.get(i))
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.current.is_some_and(|i| i > 0)
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.current.is_some_and(|i| i + 1 < self.entries.len())
+    }
+
+    /// Adds `url` after the current entry, discarding the forward entries.
+    /// Loading the current URL again only refreshes its visit time.
+    pub fn push(&mut self, url: String) {
+        if let Some(index) = self.current.filter(|i| self.entries[*i].url == url) {
+            self.go_to(index);
+            return;
+        }
+        let keep = self.current.map_or(0, |i| i + 1);
+        self.entries.truncate(keep);
+        self.entries.push(HistoryEntry {
+            url,
+            title: None,
+            visited_at: now(),
+        });
+        self.current = Some(self.entries.len() - 1);
+    }
+
+    /// Moves to entry `index` and returns its URL.
+    pub fn go_to(&mut self, index: usize) -> Option<&str> {
+        let entry = self.entries.get_mut(index)?;
+        entry.visited_at = now();
+        self.current = Some(index);
+        Some(&entry.url)
+    }
+
+    /// Drops the current entry, for a URL that turned out to be a download,
+    /// and goes back to the entry before it.
+    pub fn discard_current(&mut self) {
+        let Some(index) = self.current else {
+            return;
+        };
+        self.entries.remove(index);
+        self.current = match index {
+            _ if self.entries.is_empty() => None,
+            0 => Some(0),
+            _ => Some(index - 1),
+        };
+    }
+
+    pub fn set_current_title(&mut self, title: Option<String>) {
+        if let Some(entry) = self.current.and_then(|i| self.entries.get_mut(i)) {
+            entry.title = title;
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Formats Unix seconds as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // 日数 → 年月日 (グレゴリオ暦, 3 月始まりで計算する)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        rem / 3_600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// A request to move one tab through its history.
+#[derive(Event, Clone, Debug)]
+pub struct Navigate {
+    pub tab: Entity,
+    pub action: NavigationAction,
+}
+
+#[derive(Clone, Debug)]
+pub enum NavigationAction {
+    /// Load a new URL, as if typed into the URL bar.
+    To(String),
+    /// Follow a link, or submit a form by GET, on the page at `initiator`.
+    /// The initiator decides which cookies are sent and whether local files
+    /// may be opened.
+    Link { url: String, initiator: String },
+    /// Submit a form by POST from the page at `initiator`. The history entry
+    /// keeps only the URL, so reloading it loads the URL with a GET.
+    Post {
+        url: String,
+        data: FormBody,
+        initiator: String,
+    },
+    Back,
+    Forward,
+    Reload,
+    /// Jump to an entry picked in the history window.
+    Entry(usize),
+    /// Cancel the load in progress.
+    Stop,
+}
+
+/// A tab's history and URL as navigation moves them, and the page it shows.
+type NavigatedPage<'a> = (&'a mut BrowsingHistory, &'a mut CurrentUrl, &'a mut LoadState, &'a ResponseBody, &'a mut ScrollTarget);
+
+// Navigate イベントを受けてタブの履歴を動かし、ページを取りに行くシステム
+pub fn navigate_system(
+    mut commands: Commands,
+    mut events: EventReader<Navigate>,
+    mut pages: Query<NavigatedPage>,
+    fetcher: Fetcher,
+    pending: Query<(Entity, &FetchHtmlTask)>,
+    search_index: Res<PageIndex>,
+) {
+    // 同じフレームに同じタブへ複数来たら最後の行き先だけ読み込む
+    let mut loads: Vec<(Entity, String, RequestContext, Option<FormBody>)> = Vec::new();
+    let mut stops: Vec<Entity> = Vec::new();
+    for event in events.read() {
+        let Ok((mut history, mut current_url, load_state, body, mut scroll_target)) = pages.get_mut(event.tab) else {
+            continue;
+        };
+        if let NavigationAction::Stop = event.action {
+            loads.retain(|(tab, ..)| *tab != event.tab);
+            stops.push(event.tab);
+            continue;
+        }
+        let history = &mut history.0;
+        let url = match &event.action {
+            NavigationAction::To(url) | NavigationAction::Link { url, .. } | NavigationAction::Post { url, .. } => {
+                history.push(url.clone());
+                Some(url.clone())
+            }
+            NavigationAction::Back if history.can_go_back() => {
+                let index = history.current.unwrap_or(0) - 1;
+                history.go_to(index).map(str::to_string)
+            }
+            NavigationAction::Forward if history.can_go_forward() => {
+                let index = history.current.unwrap_or(0) + 1;
+                history.go_to(index).map(str::to_string)
+            }
+            NavigationAction::Reload => history.current_entry().map(|e| e.url.clone()),
+            NavigationAction::Entry(index) => history.go_to(*index).map(str::to_string),
+            _ => None,
+        };
+        if let Some(url) = url {
+            current_url.0 = url.clone();
+            // 表示中の文書の中のフラグメントへ移るだけなら、取り直さずにスクロールする
+            let same_document = match event.action {
+                NavigationAction::To(_) | NavigationAction::Link { .. } => crate::links::is_same_document(&body.url, &url),
+                NavigationAction::Reload | NavigationAction::Post { .. } => false,
+                _ => crate::links::same_resource(&body.url, &url),
+            };
+            if same_document && !matches!(*load_state, LoadState::Failed { .. }) {
+                scroll_target.0 = Some(crate::links::fragment(&url).unwrap_or_default());
+                loads.retain(|(tab, ..)| *tab != event.tab);
+                stops.push(event.tab);
+                continue;
+            }
+            // 再読み込みはキャッシュを確認し直す
+            let (context, post) = match &event.action {
+                NavigationAction::Reload => (RequestContext::reload(), None),
+                // about: のページはブラウザが作ったものなので、そこのリンクはユーザーの操作と同じ扱い
+                NavigationAction::Link { initiator, .. } if !initiator.starts_with("about:") => (
+                    RequestContext {
+                        initiator: Some(initiator.clone()),
+                        ..RequestContext::navigation()
+                    },
+                    None,
+                ),
+                NavigationAction::Post { data, initiator, .. } => (
+                    RequestContext {
+                        initiator: Some(initiator.clone()),
+                        ..RequestContext::navigation()
+                    },
+                    Some(data.clone()),
+                ),
+                _ => (RequestContext::navigation(), None),
+            };
+            loads.retain(|(tab, ..)| *tab != event.tab);
+            loads.push((event.tab, url, context, post));
+        }
+    }
+
+    // 読み込み中の前のページは捨てる (後から届いて上書きしないように)
+    // タスクを捨てると Tokio 側のリクエストも止まる
+    for tab in stops.iter().chain(loads.iter().map(|(tab, ..)| tab)) {
+        for (entity, fetch) in &pending {
+            if fetch.tab == *tab {
+                commands.entity(entity).despawn();
+            }
+        }
+        if let Ok((_, _, mut load_state, _, _)) = pages.get_mut(*tab) {
+            *load_state = LoadState::Idle;
+        }
+    }
+    for (tab, url, context, post) in loads {
+        info!("Navigating tab {:?} to: {}", tab, url);
+        let (progress, receiver) = tokio::sync::watch::channel(FetchProgress::default());
+        let Ok((history, _, mut load_state, _, _)) = pages.get_mut(tab) else {
+            continue;
+        };
+        *load_state = LoadState::Loading(FetchProgress::default());
+        // about: のページはブラウザの状態から作るので、ここで組み立てて渡す
+        let about = url.starts_with("about:").then(|| {
+            let sources = AboutSources {
+                history: &history.0,
+                client: fetcher.client(),
+                loads: pending.iter().map(|(_, fetch)| fetch.progress.borrow().clone()).collect(),
+                p2p: fetcher.p2p(),
+                search: &search_index.0,
+            };
+            crate::schemes::about_page(&url, &sources)
+        });
+        let task = match (about.flatten(), post) {
+            (Some(page), _) => AsyncComputeTaskPool::get().spawn(async move { Ok(page) }),
+            (None, Some(data)) => fetcher.spawn_post(url.clone(), data, context, Some(progress)),
+            (None, None) => fetcher.spawn(url.clone(), context, Some(progress)),
+        };
+        commands.spawn(FetchHtmlTask {
+            tab,
+            task,
+            url,
+            progress: receiver,
+        });
+    }
+}
+
+// マウスの戻る/進むボタンと Alt+←/→ で選択中のタブの履歴を移動し、Esc で読み込みを止めるシステム
+pub fn history_shortcut_system(
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    tabs: Res<Tabs>,
+    mut events: EventWriter<Navigate>,
+    mut contexts: EguiContexts,
+) {
+    // 入力欄に文字を打っている間のキーはその欄のもの (マウスのボタンは効かせる)
+    let typing = contexts.ctx_mut().wants_keyboard_input();
+    let alt = !typing && keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]);
+    let tab = tabs.active;
+    if mouse.just_pressed(MouseButton::Back) || (alt && keys.just_pressed(KeyCode::ArrowLeft)) {
+        events.write(Navigate { tab, action: NavigationAction::Back });
+    }
+    if mouse.just_pressed(MouseButton::Forward) || (alt && keys.just_pressed(KeyCode::ArrowRight)) {
+        events.write(Navigate { tab, action: NavigationAction::Forward });
+    }
+    if !typing && keys.just_pressed(KeyCode::Escape) {
+        events.write(Navigate { tab, action: NavigationAction::Stop });
+    }
+}
+
+// 読み込んだページのタイトルをそのタブの現在の履歴項目に記録するシステム
+pub fn record_page_title(mut pages: Query<(&CurrentDocument, &mut BrowsingHistory), Changed<CurrentDocument>>) {
+    for (document, mut history) in &mut pages {
+        history.0.set_current_title(document.0.title());
+    }
+}
+
+pub fn history_window(
+    mut contexts: EguiContexts,
+    mut show_history_window: ResMut<ShowHistoryWindow>,
+    tabs: Res<Tabs>,
+    pages: Query<&BrowsingHistory>,
+    mut events: EventWriter<Navigate>,
+) {
+    let Ok(history) = pages.get(tabs.active) else {
+        return;
+    };
+    let ctx = contexts.ctx_mut();
+    egui::Window::new("History")
+        .open(&mut show_history_window.0)
+        .default_size(egui::vec2(500.0, 300.0))
+        .show(ctx, |ui| {
+            if history.0.entries.is_empty() {
+                ui.label("No pages visited yet.");
+                return;
+            }
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("history_grid").striped(true).show(ui, |ui| {
+                    // 新しいものを上に並べる
+                    for (index, entry) in history.0.entries.iter().enumerate().rev() {
+                        let title = entry.title.as_deref().unwrap_or(&entry.url);
+                        let current = history.0.current == Some(index);
+                        if ui.selectable_label(current, title).on_hover_text(&entry.url).clicked() && !current {
+                            events.write(Navigate {
+                                tab: tabs.active,
+                                action: NavigationAction::Entry(index),
+                            });
+                        }
+                        ui.label(format_timestamp(entry.visited_at));
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+}
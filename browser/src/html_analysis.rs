@@ -0,0 +1,1209 @@
+//! Port of the nana HTML analyzer (`cpp_parse/html_element.*` and
+//! `cpp_parse/html_analysys.*`).
+//!
+//! Unlike [`crate::html_parser`], this does not build a spec-compliant DOM.
+//! It splits the markup into parts (tags, text, comments, declarations) and
+//! then matches start and end tags as written, so broken nesting such as
+//! `<form><div></form></div>` stays visible in the result. That makes it
+//! useful for checking markup rather than rendering it; [`MarkupCheck`]
+//! shows the checks in the Markup tab of the HTML view.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bevy_egui::egui;
+
+/// Returns `true` when `target` starts with `search`.
+///
+/// The C++ version only returned `true` for identical strings; this is the
+/// prefix match its name and callers expect.
+pub fn forward_match(target: &str, search: &str) -> bool {
+    target.starts_with(search)
+}
+
+/// Returns `true` when `target` ends with `search`.
+///
+/// Like [`forward_match`], the C++ version degenerated into an equality check.
+pub fn backward_match(target: &str, search: &str) -> bool {
+    target.ends_with(search)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WildcardToken {
+    AnySequence,
+    AnyChar,
+    Literal(char),
+}
+
+/// Wildcard match where `*` matches any sequence, `?` matches one character
+/// and `\` escapes the next character. The whole of `text` must match.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => WildcardToken::AnySequence,
+            '?' => WildcardToken::AnyChar,
+            // 末尾の `\` は何にもマッチしない (C++ 版と同じ)
+            '\\' => match chars.next() {
+                Some(escaped) => WildcardToken::Literal(escaped),
+                None => break,
+            },
+            other => WildcardToken::Literal(other),
+        });
+    }
+    let text: Vec<char> = text.chars().collect();
+
+    // `*` の位置を覚えておき、失敗したらそこからやり直す
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(WildcardToken::AnySequence) => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(WildcardToken::AnyChar) => {
+                p += 1;
+                t += 1;
+            }
+            Some(WildcardToken::Literal(c)) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[p..].iter().all(|t| *t == WildcardToken::AnySequence)
+}
+
+/// nana treats only space, tab and newline as HTML whitespace.
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n')
+}
+
+/// Kind of an [`HtmlPart`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HtmlPartType {
+    Text,
+    Tag,
+    Comment,
+    /// `<!doctype ...>`, `<?xml ...?>` and the like.
+    Declaration,
+    /// A `<` whose closing `>` never appeared.
+    NotEnd,
+}
+
+impl HtmlPartType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HtmlPartType::Text => "TEXT",
+            HtmlPartType::Tag => "TAG",
+            HtmlPartType::Comment => "COMMENT",
+            HtmlPartType::Declaration => "DECLARATION",
+            HtmlPartType::NotEnd => "?",
+        }
+    }
+}
+
+/// One piece of the markup: a start tag, an end tag, text, a comment or a
+/// declaration.
+#[derive(Clone, Debug)]
+pub struct HtmlPart {
+    pub kind: HtmlPartType,
+    /// The raw markup of this part.
+    pub content: String,
+    /// 1-based line where the part starts.
+    pub line: usize,
+    /// Byte offset where the part starts.
+    pub pos: usize,
+    tag_name: String,
+    attrs: BTreeMap<String, Vec<String>>,
+}
+
+impl HtmlPart {
+    fn new(kind: HtmlPartType, content: String, line: usize, pos: usize) -> Self {
+        let mut part = Self {
+            kind,
+            content,
+            line,
+            pos,
+            tag_name: String::new(),
+            attrs: BTreeMap::new(),
+        };
+        if kind == HtmlPartType::Tag {
+            part.parse_tag();
+        }
+        part
+    }
+
+    /// Lower-cased tag name. End tags keep their slash (`/div`); non-tag
+    /// parts return an empty string.
+    pub fn tag_name(&self) -> &str {
+        &self.tag_name
+    }
+
+    /// Value of the `index`-th attribute called `key`, or `""`.
+    pub fn attr(&self, key: &str, index: usize) -> &str {
+        self.attrs
+            .get(key)
+            .and_then(|values| values.get(index))
+            .map(String::as_str)
+            .unwrap_or("")
+    }
+
+    pub fn has_attr(&self, key: &str, index: usize) -> bool {
+        self.attrs.get(key).is_some_and(|values| index < values.len())
+    }
+
+    /// Attribute names in sorted order.
+    pub fn attr_names(&self) -> impl Iterator<Item = &str> {
+        self.attrs.keys().map(String::as_str)
+    }
+
+    /// `<br/>` style tags.
+    fn is_self_closing(&self) -> bool {
+        let bytes = self.content.as_bytes();
+        bytes.len() >= 2 && bytes[bytes.len() - 2] == b'/'
+    }
+
+    fn parse_tag(&mut self) {
+        const NOT_KEY: &[u8] = b" \n\t=>/";
+        let s = self.content.as_bytes();
+        let find_from = |start: usize, stop: &dyn Fn(u8) -> bool| {
+            (start..s.len()).find(|i| stop(s[*i])).unwrap_or(s.len())
+        };
+        let skip_space = |start: usize| find_from(start, &|c| !is_space(c));
+
+        // タグ名は 3 文字目から探す (`</div>` の `/` を含めるため)
+        let name_end = find_from(2.min(s.len()), &|c| NOT_KEY.contains(&c));
+        self.tag_name = String::from_utf8_lossy(&s[1.min(name_end)..name_end]).to_ascii_lowercase();
+
+        let mut i = name_end;
+        loop {
+            i = skip_space(i);
+            if i >= s.len() {
+                break;
+            }
+            if s[i] == b'>' || s[i] == b'/' {
+                i += 1;
+                continue;
+            }
+            // 属性名
+            let key_start = i;
+            i = find_from(i, &|c| NOT_KEY.contains(&c));
+            if i == key_start {
+                // `=` が名前の位置に来た場合は読み飛ばす
+                i += 1;
+                continue;
+            }
+            let key = String::from_utf8_lossy(&s[key_start..i]).to_ascii_lowercase();
+            i = skip_space(i);
+            if i >= s.len() || s[i] != b'=' {
+                self.attrs.entry(key).or_default().push(String::new());
+                continue;
+            }
+            // 属性値
+            i = skip_space(i + 1);
+            let value = match s.get(i) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let end = find_from(i + 1, &|c| c == quote);
+                    let value = &s[i + 1..end];
+                    i = end + 1;
+                    value
+                }
+                Some(_) => {
+                    // C++ 版は `/` でも値を打ち切っていたが、URL が壊れるので空白と `>` だけで区切る
+                    let end = find_from(i, &|c| is_space(c) || c == b'>');
+                    let value = &s[i..end];
+                    i = end;
+                    value
+                }
+                None => &s[0..0],
+            };
+            self.attrs
+                .entry(key)
+                .or_default()
+                .push(String::from_utf8_lossy(value).into_owned());
+        }
+    }
+}
+
+/// Splits `input` into [`HtmlPart`]s (the nana SAX parser).
+///
+/// `<` followed by whitespace is treated as text, comments run until `-->`,
+/// and a `<` without a matching `>` becomes a [`HtmlPartType::NotEnd`] part.
+pub fn parse_parts(input: &str) -> Vec<HtmlPart> {
+    let s = input.as_bytes();
+    let mut parts = Vec::new();
+    let line_at = |pos: usize| 1 + s[..pos].iter().filter(|c| **c == b'\n').count();
+    let mut text_start = 0;
+    let mut i = 0;
+
+    let flush_text = |parts: &mut Vec<HtmlPart>, start: usize, end: usize| {
+        if start < end {
+            parts.push(HtmlPart::new(
+                HtmlPartType::Text,
+                input[start..end].to_string(),
+                line_at(start),
+                start,
+            ));
+        }
+    };
+
+    while i < s.len() {
+        if s[i] != b'<' || i + 1 >= s.len() || is_space(s[i + 1]) {
+            i += if s[i] == b'<' && i + 1 < s.len() { 2 } else { 1 };
+            continue;
+        }
+        flush_text(&mut parts, text_start, i);
+        let start = i;
+        // `<` の次の文字は必ずタグに含める (`<>` は次の `>` まで続く)
+        let close = s[start + 2..].iter().position(|c| *c == b'>').map(|p| start + 2 + p);
+        let Some(mut end) = close.map(|p| p + 1) else {
+            parts.push(HtmlPart::new(
+                HtmlPartType::NotEnd,
+                input[start..].to_string(),
+                line_at(start),
+                start,
+            ));
+            text_start = s.len();
+            break;
+        };
+        let mut kind = HtmlPartType::Tag;
+        if end - start >= 6 && s[start..].starts_with(b"<!--") {
+            kind = HtmlPartType::Comment;
+            while !s[..end].ends_with(b"-->") {
+                match s[end..].iter().position(|c| *c == b'>') {
+                    Some(p) => end += p + 1,
+                    None => {
+                        end = s.len();
+                        kind = HtmlPartType::NotEnd;
+                        break;
+                    }
+                }
+            }
+        } else if end - start >= 10
+            && (s[start + 1] == b'?'
+                || (s[start..start + 9].eq_ignore_ascii_case(b"<!doctype") && is_space(s[start + 9])))
+        {
+            kind = HtmlPartType::Declaration;
+        }
+        parts.push(HtmlPart::new(
+            kind,
+            input[start..end].to_string(),
+            line_at(start),
+            start,
+        ));
+        i = end;
+        text_start = end;
+    }
+    flush_text(&mut parts, text_start, s.len());
+    parts
+}
+
+/// Tags that never get an end tag.
+fn is_not_closed(tag_name: &str) -> bool {
+    matches!(
+        tag_name,
+        "<!--" | "<!doctype" | "br" | "img" | "hr" | "meta" | "input" | "embed" | "area"
+            | "base" | "col" | "keygen" | "link" | "param" | "source"
+    )
+}
+
+/// A matched pair of start and end tags. Either side may be missing when the
+/// markup is broken. Text is not part of the node tree; use
+/// [`HtmlDocument::range`] to get at it.
+#[derive(Clone, Debug, Default)]
+pub struct HtmlNode {
+    /// Index of the start tag in [`HtmlDocument::parts`].
+    pub start_tag: Option<usize>,
+    /// Index of the end tag in [`HtmlDocument::parts`].
+    pub end_tag: Option<usize>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+impl HtmlNode {
+    /// Both the start and the end tag are present.
+    pub fn is_closed(&self) -> bool {
+        self.start_tag.is_some() && self.end_tag.is_some()
+    }
+}
+
+/// Result of analyzing a document: the flat list of parts plus the tag tree.
+/// Node 0 is the root, which has neither a start nor an end tag.
+#[derive(Clone, Debug)]
+pub struct HtmlDocument {
+    pub parts: Vec<HtmlPart>,
+    pub nodes: Vec<HtmlNode>,
+}
+
+impl HtmlDocument {
+    /// Parses `input` and matches each start tag against the end tag that
+    /// closes its own level (hierarchical matching). For
+    /// `<html><form><div></form></div></html>` this gives
+    ///
+    /// ```text
+    /// html (not closed)
+    ///   form (not closed)
+    ///     div (closed)
+    ///       /form (end tag only)
+    ///     /html (end tag only)
+    /// ```
+    pub fn parse(input: &str) -> Self {
+        let mut doc = Self::from_parts(parse_parts(input));
+        let mut i = 0;
+        doc.analyze(0, "", &mut i);
+        doc
+    }
+
+    /// Parses `input` and matches each end tag against the most recent open
+    /// start tag with the same name, wherever it is in the tree. For
+    /// `<html><form><div></form></div></html>` all three nodes end up closed.
+    pub fn parse_by_same_tag_match(input: &str) -> Self {
+        let mut doc = Self::from_parts(parse_parts(input));
+        let mut i = 0;
+        let mut stock = HashMap::new();
+        doc.analyze_by_same_tag_match(0, "", &mut i, &mut stock);
+        doc
+    }
+
+    fn from_parts(parts: Vec<HtmlPart>) -> Self {
+        Self {
+            parts,
+            nodes: vec![HtmlNode::default()],
+        }
+    }
+
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    pub fn node(&self, id: usize) -> &HtmlNode {
+        &self.nodes[id]
+    }
+
+    fn append_node(&mut self, parent: usize, start_tag: Option<usize>, end_tag: Option<usize>) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(HtmlNode {
+            start_tag,
+            end_tag,
+            parent: Some(parent),
+            children: Vec::new(),
+        });
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    /// Tag name of a node. Unclosed end-tag-only nodes keep the slash,
+    /// `[nullptr]` is the root and `[err]` a start tag without `>`.
+    pub fn tag_name(&self, id: usize) -> &str {
+        let node = &self.nodes[id];
+        match (node.start_tag, node.end_tag) {
+            (None, None) => "[nullptr]",
+            (None, Some(end)) => self.parts[end].tag_name(),
+            (Some(start), _) if self.parts[start].kind == HtmlPartType::NotEnd => "[err]",
+            (Some(start), _) => self.parts[start].tag_name(),
+        }
+    }
+
+    /// Path of the node from the root, e.g. `/html/body/div`. Paths deeper
+    /// than 20 levels are shortened with `...`.
+    pub fn path_str(&self, id: usize) -> String {
+        let Some(mut parent) = self.nodes[id].parent else {
+            return "/".to_string();
+        };
+        let mut path = self.tag_name(id).to_string();
+        for _ in 0..20 {
+            match self.nodes[parent].parent {
+                None => return format!("/{}", path),
+                Some(grand) => {
+                    path = format!("{}/{}", self.tag_name(parent), path);
+                    parent = grand;
+                }
+            }
+        }
+        format!(".../{}", path)
+    }
+
+    /// `(start,end)` tag names of a node, for debugging.
+    pub fn tag_str(&self, id: usize) -> String {
+        let node = &self.nodes[id];
+        let name = |part: Option<usize>| part.map(|p| self.parts[p].tag_name()).unwrap_or("");
+        format!("({},{})", name(node.start_tag), name(node.end_tag))
+    }
+
+    /// Parts from `start` up to and including `end`, in document order.
+    pub fn range(&self, start: usize, end: usize) -> &[HtmlPart] {
+        let end = (end + 1).min(self.parts.len());
+        &self.parts[start.min(end)..end]
+    }
+
+    fn analyze(&mut self, current: usize, start_tag_name: &str, i: &mut usize) -> Option<usize> {
+        let close_name = if start_tag_name.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", start_tag_name)
+        };
+        while *i < self.parts.len() {
+            let index = *i;
+            *i += 1;
+            let part = &self.parts[index];
+            match part.kind {
+                HtmlPartType::NotEnd => {
+                    self.append_node(current, Some(index), None);
+                    continue;
+                }
+                HtmlPartType::Tag => {}
+                _ => continue,
+            }
+            if part.is_self_closing() || is_not_closed(part.tag_name()) {
+                self.append_node(current, Some(index), Some(index));
+                continue;
+            }
+            if part.tag_name().starts_with('/') {
+                if !close_name.is_empty() && part.tag_name() == close_name {
+                    return Some(index);
+                }
+                // 探していない終了タグは終了タグだけのノードにする
+                self.append_node(current, None, Some(index));
+                continue;
+            }
+            // 開始タグ: 1 つ下の階層を走査する
+            let name = part.tag_name().to_string();
+            let child = self.append_node(current, Some(index), None);
+            let end_tag = self.analyze(child, &name, i);
+            self.nodes[child].end_tag = end_tag;
+        }
+        None
+    }
+
+    fn analyze_by_same_tag_match(
+        &mut self,
+        current: usize,
+        start_tag_name: &str,
+        i: &mut usize,
+        stock: &mut HashMap<String, Vec<usize>>,
+    ) {
+        while *i < self.parts.len() {
+            let index = *i;
+            *i += 1;
+            let part = &self.parts[index];
+            match part.kind {
+                HtmlPartType::NotEnd => {
+                    self.append_node(current, Some(index), None);
+                    continue;
+                }
+                HtmlPartType::Tag => {}
+                _ => continue,
+            }
+            if part.is_self_closing() || is_not_closed(part.tag_name()) {
+                self.append_node(current, Some(index), Some(index));
+                continue;
+            }
+            if let Some(name) = part.tag_name().strip_prefix('/') {
+                let name = name.to_string();
+                match stock.get_mut(&name).and_then(|open| open.pop()) {
+                    Some(start_node) => {
+                        // 同名の開始タグのうち一番最近のものを閉じる
+                        self.nodes[start_node].end_tag = Some(index);
+                        if start_tag_name == name {
+                            return;
+                        }
+                    }
+                    None => {
+                        self.append_node(current, None, Some(index));
+                    }
+                }
+                continue;
+            }
+            let name = part.tag_name().to_string();
+            let child = self.append_node(current, Some(index), None);
+            stock.entry(name.clone()).or_default().push(child);
+            self.analyze_by_same_tag_match(child, &name, i, stock);
+        }
+    }
+}
+
+/// Something that inspects every node of an [`HtmlDocument`].
+pub trait HtmlNodeAccessor {
+    /// Called once before a visit starts.
+    fn init(&mut self);
+    fn access(&mut self, doc: &HtmlDocument, node: usize);
+}
+
+/// Visits every node except the root in document order, handing each one to
+/// all `accessors`.
+pub fn visit(doc: &HtmlDocument, accessors: &mut [&mut dyn HtmlNodeAccessor]) {
+    for accessor in accessors.iter_mut() {
+        accessor.init();
+    }
+    let mut stack = vec![doc.root()];
+    while let Some(node) = stack.pop() {
+        if node != doc.root() {
+            for accessor in accessors.iter_mut() {
+                accessor.access(doc, node);
+            }
+        }
+        stack.extend(doc.node(node).children.iter().rev());
+    }
+}
+
+/// Collects start tags that were never closed, stray end tags, and end tags
+/// that close an element across another one (alternated tags).
+#[derive(Default)]
+pub struct EndTagAccessor {
+    non_closed: Vec<usize>,
+    alternated: Vec<usize>,
+    stock: BTreeMap<String, Vec<usize>>,
+}
+
+impl EndTagAccessor {
+    /// Unclosed and unmatched nodes, including start tags still left open at
+    /// the end of the visit.
+    pub fn non_closed_result(&self) -> Vec<usize> {
+        let mut result = self.non_closed.clone();
+        for open in self.stock.values() {
+            result.extend(open);
+        }
+        result
+    }
+
+    /// End-tag-only nodes that match an earlier open start tag.
+    pub fn alternated_result(&self) -> &[usize] {
+        &self.alternated
+    }
+}
+
+impl HtmlNodeAccessor for EndTagAccessor {
+    fn init(&mut self) {
+        *self = Self::default();
+    }
+
+    fn access(&mut self, doc: &HtmlDocument, node: usize) {
+        let html_node = doc.node(node);
+        if html_node.is_closed() {
+            return;
+        }
+        match html_node.start_tag {
+            None => {
+                let name = doc.tag_name(node).trim_start_matches('/');
+                match self.stock.get_mut(name).and_then(|open| open.pop()) {
+                    None => self.non_closed.push(node),
+                    Some(_) => {
+                        // 親が閉じているのに外側の開始タグを閉じている = 入れ違い
+                        let parent_closed = html_node.parent.is_none_or(|p| doc.node(p).is_closed());
+                        if parent_closed {
+                            self.alternated.push(node);
+                        }
+                    }
+                }
+            }
+            Some(start) if doc.parts[start].kind == HtmlPartType::NotEnd => {
+                self.non_closed.push(node);
+            }
+            Some(_) => {
+                self.stock
+                    .entry(doc.tag_name(node).to_string())
+                    .or_default()
+                    .push(node);
+            }
+        }
+    }
+}
+
+/// Collects elements that are obsolete in HTML5.
+#[derive(Default)]
+pub struct DeprecatedInHtml5Accessor {
+    result: Vec<usize>,
+}
+
+impl DeprecatedInHtml5Accessor {
+    const DEPRECATED_TAGS: &'static [&'static str] = &[
+        "center", "font", "blink", "strike", "s", "u", "bgsound", "marquee", "applet", "acronym",
+        "dir", "frame", "frameset", "noframes", "isindex", "listing", "xmp", "noembed",
+        "plaintext", "rb", "basefont", "big", "spacer", "tt",
+    ];
+
+    pub fn result(&self) -> &[usize] {
+        &self.result
+    }
+}
+
+impl HtmlNodeAccessor for DeprecatedInHtml5Accessor {
+    fn init(&mut self) {
+        self.result.clear();
+    }
+
+    fn access(&mut self, doc: &HtmlDocument, node: usize) {
+        if Self::DEPRECATED_TAGS.contains(&doc.tag_name(node)) {
+            self.result.push(node);
+        }
+    }
+}
+
+/// Collects `<img>` tags without an `alt` attribute, an important
+/// accessibility check.
+#[derive(Default)]
+pub struct ImgAltAccessor {
+    result: Vec<usize>,
+}
+
+impl ImgAltAccessor {
+    pub fn result(&self) -> &[usize] {
+        &self.result
+    }
+}
+
+impl HtmlNodeAccessor for ImgAltAccessor {
+    fn init(&mut self) {
+        self.result.clear();
+    }
+
+    fn access(&mut self, doc: &HtmlDocument, node: usize) {
+        if doc.tag_name(node) != "img" {
+            return;
+        }
+        let Some(start) = doc.node(node).start_tag else {
+            return;
+        };
+        if !doc.parts[start].has_attr("alt", 0) {
+            self.result.push(node);
+        }
+    }
+}
+
+/// XPath-like queries over an [`HtmlDocument`].
+pub mod path {
+    use super::{backward_match, forward_match, wildcard_match, HashSet, HtmlDocument};
+
+    /// One step of a path.
+    #[derive(Clone, Debug)]
+    pub enum HtmlPath {
+        /// `/tag[pred]...`: children whose tag name matches the wildcard,
+        /// filtered by the predicates per parent.
+        Path { tag_name: String, predicates: Vec<HtmlPath> },
+        /// `[n]`: the n-th (0-based) node.
+        Position(usize),
+        /// `[@name="value"]`: both sides are wildcards; empty means "any".
+        Attributes { name: String, value: String },
+        /// `//`: every node and its descendants.
+        Descendants,
+    }
+
+    impl HtmlPath {
+        pub fn filter(&self, doc: &HtmlDocument, nodes: &[usize]) -> Vec<usize> {
+            match self {
+                HtmlPath::Path { tag_name, predicates } => {
+                    let mut result = Vec::new();
+                    for node in nodes {
+                        let mut matched: Vec<usize> = doc
+                            .node(*node)
+                            .children
+                            .iter()
+                            .copied()
+                            .filter(|c| wildcard_match(tag_name, doc.tag_name(*c)))
+                            .collect();
+                        for predicate in predicates {
+                            matched = predicate.filter(doc, &matched);
+                        }
+                        result.extend(matched);
+                    }
+                    result
+                }
+                HtmlPath::Position(position) => nodes.get(*position).copied().into_iter().collect(),
+                HtmlPath::Attributes { name, value } => nodes
+                    .iter()
+                    .copied()
+                    .filter(|node| {
+                        let Some(start) = doc.node(*node).start_tag else {
+                            return false;
+                        };
+                        let part = &doc.parts[start];
+                        part.attr_names().any(|key| {
+                            (name.is_empty() || wildcard_match(name, key))
+                                && (value.is_empty() || wildcard_match(value, part.attr(key, 0)))
+                        })
+                    })
+                    .collect(),
+                HtmlPath::Descendants => {
+                    let mut result = Vec::new();
+                    let mut seen = HashSet::new();
+                    for node in nodes {
+                        let mut stack = vec![*node];
+                        while let Some(next) = stack.pop() {
+                            if seen.insert(next) {
+                                result.push(next);
+                            }
+                            stack.extend(doc.node(next).children.iter().rev());
+                        }
+                    }
+                    result
+                }
+            }
+        }
+    }
+
+    /// Builds and runs a path, e.g. `//form/input[@type="hidden"]` is
+    /// `HtmlPathExecutor::default().slash2().tag("form").tag("input").pred_attr("type", "hidden")`.
+    #[derive(Default)]
+    pub struct HtmlPathExecutor {
+        paths: Vec<HtmlPath>,
+    }
+
+    impl HtmlPathExecutor {
+        /// Runs the path starting from a single node (usually the root).
+        pub fn exec(&self, doc: &HtmlDocument, node: usize) -> Vec<usize> {
+            self.exec_nodes(doc, &[node])
+        }
+
+        /// Runs the path starting from several nodes, e.g. to refine an
+        /// earlier result.
+        pub fn exec_nodes(&self, doc: &HtmlDocument, nodes: &[usize]) -> Vec<usize> {
+            let mut result = nodes.to_vec();
+            for path in &self.paths {
+                result = path.filter(doc, &result);
+            }
+            result
+        }
+
+        pub fn add(&mut self, path: HtmlPath) -> &mut Self {
+            self.paths.push(path);
+            self
+        }
+
+        /// `//` (descendant-or-self).
+        pub fn slash2(&mut self) -> &mut Self {
+            self.add(HtmlPath::Descendants)
+        }
+
+        /// `/tag`, where `tag_name` may contain wildcards.
+        pub fn tag(&mut self, tag_name: &str) -> &mut Self {
+            self.add(HtmlPath::Path {
+                tag_name: tag_name.to_string(),
+                predicates: Vec::new(),
+            })
+        }
+
+        /// `[@name="value"]` on the preceding [`tag`](Self::tag).
+        pub fn pred_attr(&mut self, name: &str, value: &str) -> Result<&mut Self, String> {
+            self.push_predicate(HtmlPath::Attributes {
+                name: name.to_string(),
+                value: value.to_string(),
+            })
+        }
+
+        /// `[n]` on the preceding [`tag`](Self::tag).
+        pub fn pred_pos(&mut self, index: usize) -> Result<&mut Self, String> {
+            self.push_predicate(HtmlPath::Position(index))
+        }
+
+        fn push_predicate(&mut self, predicate: HtmlPath) -> Result<&mut Self, String> {
+            match self.paths.last_mut() {
+                Some(HtmlPath::Path { predicates, .. }) => {
+                    predicates.push(predicate);
+                    Ok(self)
+                }
+                _ => Err("predicate is specified, but previous element is not tag()".to_string()),
+            }
+        }
+    }
+
+    /// Parses a path written as text, e.g. `//form/input[@type="hidden"][0]`,
+    /// into the executor the builder methods would make. Tag names, attribute
+    /// names and values may use the wildcards of [`wildcard_match`].
+    pub fn parse_path(query: &str) -> Result<HtmlPathExecutor, String> {
+        let mut executor = HtmlPathExecutor::default();
+        let mut rest = query.trim();
+        if rest.is_empty() {
+            return Err("empty path".to_string());
+        }
+        while !rest.is_empty() {
+            if forward_match(rest, "//") {
+                executor.slash2();
+                rest = &rest[2..];
+            } else if forward_match(rest, "/") {
+                rest = &rest[1..];
+            } else {
+                return Err(format!("expected / before {}", rest));
+            }
+            let end = rest.find(['/', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err("missing tag name".to_string());
+            }
+            executor.tag(&rest[..end].to_ascii_lowercase());
+            rest = &rest[end..];
+            while let Some(inner) = rest.strip_prefix('[') {
+                let close = inner.find(']').ok_or_else(|| "missing ]".to_string())?;
+                let predicate = inner[..close].trim();
+                rest = &inner[close + 1..];
+                if let Some(attr) = predicate.strip_prefix('@') {
+                    let (name, value) = attr.split_once('=').unwrap_or((attr, ""));
+                    let value = value.trim();
+                    let quoted = value.len() >= 2
+                        && ((forward_match(value, "\"") && backward_match(value, "\""))
+                            || (forward_match(value, "'") && backward_match(value, "'")));
+                    let value = if quoted { &value[1..value.len() - 1] } else { value };
+                    executor.pred_attr(&name.trim().to_ascii_lowercase(), value)?;
+                } else {
+                    let index = predicate
+                        .parse()
+                        .map_err(|_| format!("[{}] is neither an @attribute nor a position", predicate))?;
+                    executor.pred_pos(index)?;
+                }
+            }
+        }
+        Ok(executor)
+    }
+}
+
+/// Results of the checks on one page source.
+struct MarkupReport {
+    source: String,
+    same_tag: bool,
+    doc: HtmlDocument,
+    non_closed: Vec<usize>,
+    alternated: Vec<usize>,
+    deprecated: Vec<usize>,
+    img_without_alt: Vec<usize>,
+}
+
+impl MarkupReport {
+    fn new(source: &str, same_tag: bool) -> Self {
+        let doc = if same_tag { HtmlDocument::parse_by_same_tag_match(source) } else { HtmlDocument::parse(source) };
+        let mut end_tags = EndTagAccessor::default();
+        let mut deprecated = DeprecatedInHtml5Accessor::default();
+        let mut img_alt = ImgAltAccessor::default();
+        visit(&doc, &mut [&mut end_tags, &mut deprecated, &mut img_alt]);
+        // 開いたまま残ったものは名前ごとに集まっているので、文書の順に並べ直す
+        let mut non_closed = end_tags.non_closed_result();
+        non_closed.sort_unstable();
+        Self {
+            source: source.to_string(),
+            same_tag,
+            non_closed,
+            alternated: end_tags.alternated_result().to_vec(),
+            deprecated: deprecated.result().to_vec(),
+            img_without_alt: img_alt.result().to_vec(),
+            doc,
+        }
+    }
+}
+
+/// Rows listed per section at most; big broken pages can have thousands.
+const MAX_ROWS: usize = 500;
+
+/// State of the Markup tab of the HTML view: runs the nana checks on the
+/// page source and queries it with [`path::parse_path`].
+#[derive(Default)]
+pub struct MarkupCheck {
+    /// Match end tags by name ([`HtmlDocument::parse_by_same_tag_match`])
+    /// rather than by level.
+    pub same_tag: bool,
+    pub query: String,
+    /// The checks for the source drawn last; redone when it changes.
+    report: Option<MarkupReport>,
+    /// Nodes the query found, or why it could not be parsed.
+    found: Option<Result<Vec<usize>, String>>,
+}
+
+impl MarkupCheck {
+    pub fn ui(&mut self, ui: &mut egui::Ui, source: &str) {
+        ui.horizontal(|ui| {
+            ui.label("End tags:");
+            ui.radio_value(&mut self.same_tag, false, "By level")
+                .on_hover_text("Close only the element open at the same level, so crossed tags show up");
+            ui.radio_value(&mut self.same_tag, true, "By name").on_hover_text("Close the latest open element of the same name");
+        });
+        if !self.report.as_ref().is_some_and(|report| report.same_tag == self.same_tag && report.source == source) {
+            self.report = Some(MarkupReport::new(source, self.same_tag));
+            self.found = None;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Path:");
+            let edit = egui::TextEdit::singleline(&mut self.query).hint_text("//form/input[@type=\"hidden\"]").desired_width(300.0);
+            if ui.add(edit).changed() {
+                self.found = None;
+            }
+        });
+        let Self { query, report: Some(report), found, .. } = self else {
+            return;
+        };
+        let doc = &report.doc;
+        if found.is_none() && !query.trim().is_empty() {
+            *found = Some(path::parse_path(query).map(|executor| executor.exec(doc, doc.root())));
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            match found {
+                Some(Err(error)) => {
+                    ui.colored_label(ui.visuals().error_fg_color, format!("Invalid path: {}", error));
+                }
+                Some(Ok(nodes)) => nodes_ui(ui, doc, "Matches", nodes),
+                None => {}
+            }
+            nodes_ui(ui, doc, "Unclosed or stray tags", &report.non_closed);
+            nodes_ui(ui, doc, "Crossed end tags", &report.alternated);
+            nodes_ui(ui, doc, "Obsolete in HTML5", &report.deprecated);
+            nodes_ui(ui, doc, "<img> without alt", &report.img_without_alt);
+        });
+    }
+}
+
+// 見つかったノードを 1 行ずつ「行番号: パス」で並べ、ポインタを合わせるとその部分のマークアップを出す
+fn nodes_ui(ui: &mut egui::Ui, doc: &HtmlDocument, title: &str, nodes: &[usize]) {
+    egui::CollapsingHeader::new(format!("{} ({})", title, nodes.len()))
+        .id_salt(title)
+        .default_open(true)
+        .show(ui, |ui| {
+            for &node in nodes.iter().take(MAX_ROWS) {
+                let html_node = doc.node(node);
+                let Some(first) = html_node.start_tag.or(html_node.end_tag) else {
+                    continue;
+                };
+                let part = &doc.parts[first];
+                ui.label(egui::RichText::new(format!("{}: {}", part.line, doc.path_str(node))).monospace())
+                    .on_hover_ui(|ui| {
+                        ui.label(format!("{} {} at byte {}", part.kind.as_str(), doc.tag_str(node), part.pos));
+                        let last = html_node.end_tag.unwrap_or(first);
+                        let markup: String = doc.range(first, last).iter().map(|part| part.content.as_str()).collect();
+                        let mut shown: String = markup.chars().take(400).collect();
+                        if shown.len() < markup.len() {
+                            shown.push('…');
+                        }
+                        ui.label(egui::RichText::new(shown).monospace());
+                    });
+            }
+            if nodes.len() > MAX_ROWS {
+                ui.label(egui::RichText::new(format!("… and {} more", nodes.len() - MAX_ROWS)).weak());
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::path::HtmlPathExecutor;
+    use super::*;
+
+    /// Tag tree as `name(start?,end?)` strings, indented by depth.
+    fn outline(doc: &HtmlDocument) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut stack: Vec<(usize, usize)> = doc.node(doc.root()).children.iter().rev().map(|c| (*c, 0)).collect();
+        while let Some((node, depth)) = stack.pop() {
+            let n = doc.node(node);
+            let state = match (n.start_tag, n.end_tag) {
+                (Some(_), Some(_)) => "closed",
+                (Some(_), None) => "open",
+                (None, _) => "end-only",
+            };
+            out.push(format!("{}{} {}", "  ".repeat(depth), doc.tag_name(node), state));
+            stack.extend(n.children.iter().rev().map(|c| (*c, depth + 1)));
+        }
+        out
+    }
+
+    #[test]
+    fn sax_splits_sample_into_parts() {
+        let parts = parse_parts("<html><form><div>サンプル</div></form></html>");
+        let summary: Vec<(HtmlPartType, &str, &str)> = parts
+            .iter()
+            .map(|p| (p.kind, p.content.as_str(), p.tag_name()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (HtmlPartType::Tag, "<html>", "html"),
+                (HtmlPartType::Tag, "<form>", "form"),
+                (HtmlPartType::Tag, "<div>", "div"),
+                (HtmlPartType::Text, "サンプル", ""),
+                (HtmlPartType::Tag, "</div>", "/div"),
+                (HtmlPartType::Tag, "</form>", "/form"),
+                (HtmlPartType::Tag, "</html>", "/html"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sax_handles_comments_declarations_and_unterminated_tags() {
+        let parts = parse_parts("<!DOCTYPE html>\n<!-- a > b -->a < b<p");
+        let kinds: Vec<HtmlPartType> = parts.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                HtmlPartType::Declaration,
+                HtmlPartType::Text,
+                HtmlPartType::Comment,
+                HtmlPartType::Text,
+                HtmlPartType::NotEnd,
+            ]
+        );
+        assert_eq!(parts[2].content, "<!-- a > b -->");
+        assert_eq!(parts[2].line, 2);
+        assert_eq!(parts[3].content, "a < b");
+    }
+
+    #[test]
+    fn tag_attributes_are_parsed() {
+        let parts = parse_parts("<A HREF=\"x.html\" target='_blank' checked data=http://e.com/a>");
+        let tag = &parts[0];
+        assert_eq!(tag.tag_name(), "a");
+        assert_eq!(tag.attr("href", 0), "x.html");
+        assert_eq!(tag.attr("target", 0), "_blank");
+        assert!(tag.has_attr("checked", 0));
+        assert_eq!(tag.attr("checked", 0), "");
+        assert_eq!(tag.attr("data", 0), "http://e.com/a");
+        assert!(!tag.has_attr("href", 1));
+        assert_eq!(tag.attr_names().collect::<Vec<_>>(), vec!["checked", "data", "href", "target"]);
+    }
+
+    #[test]
+    fn hierarchical_match_of_well_formed_sample() {
+        let doc = HtmlDocument::parse("<html><form><div>サンプル</div></form></html>");
+        assert_eq!(outline(&doc), vec!["html closed", "  form closed", "    div closed"]);
+        let div = doc.node(doc.node(doc.node(doc.root()).children[0]).children[0]).children[0];
+        assert_eq!(doc.path_str(div), "/html/form/div");
+        let node = doc.node(div);
+        let text: Vec<&str> = doc
+            .range(node.start_tag.unwrap(), node.end_tag.unwrap())
+            .iter()
+            .map(|p| p.content.as_str())
+            .collect();
+        assert_eq!(text, vec!["<div>", "サンプル", "</div>"]);
+    }
+
+    #[test]
+    fn hierarchical_match_keeps_broken_nesting_visible() {
+        let doc = HtmlDocument::parse("<html><form><div></form></div></html>");
+        assert_eq!(
+            outline(&doc),
+            vec![
+                "html open",
+                "  form open",
+                "    div closed",
+                "      /form end-only",
+                "    /html end-only",
+            ]
+        );
+    }
+
+    #[test]
+    fn same_tag_match_closes_every_element() {
+        let doc = HtmlDocument::parse_by_same_tag_match("<html><form><div></form></div></html>");
+        assert_eq!(outline(&doc), vec!["html closed", "  form closed", "    div closed"]);
+    }
+
+    #[test]
+    fn void_and_self_closing_tags_have_no_children() {
+        let doc = HtmlDocument::parse("<p>a<br>b<img src=x.png/><input type=text></p>");
+        assert_eq!(
+            outline(&doc),
+            vec!["p closed", "  br closed", "  img closed", "  input closed"]
+        );
+    }
+
+    #[test]
+    fn end_tag_accessor_reports_unclosed_and_alternated_tags() {
+        let doc = HtmlDocument::parse("<html><form><div></form></div></html>");
+        let mut end_tags = EndTagAccessor::default();
+        visit(&doc, &mut [&mut end_tags]);
+        let non_closed: Vec<&str> = end_tags.non_closed_result().iter().map(|n| doc.tag_name(*n)).collect();
+        let alternated: Vec<&str> = end_tags.alternated_result().iter().map(|n| doc.tag_name(*n)).collect();
+        assert_eq!(alternated, vec!["/form"]);
+        // `</html>` が form の中で html と対になるので、未閉じとしては数えない
+        assert!(non_closed.is_empty());
+
+        let doc = HtmlDocument::parse("<div></span><p");
+        visit(&doc, &mut [&mut end_tags]);
+        let non_closed: Vec<&str> = end_tags.non_closed_result().iter().map(|n| doc.tag_name(*n)).collect();
+        assert_eq!(non_closed, vec!["/span", "[err]", "div"]);
+    }
+
+    #[test]
+    fn deprecated_and_img_alt_accessors() {
+        let doc = HtmlDocument::parse("<center><font>x</font><img src=a><img src=b alt=''></center>");
+        let mut deprecated = DeprecatedInHtml5Accessor::default();
+        let mut img_alt = ImgAltAccessor::default();
+        visit(&doc, &mut [&mut deprecated, &mut img_alt]);
+        let names: Vec<&str> = deprecated.result().iter().map(|n| doc.tag_name(*n)).collect();
+        assert_eq!(names, vec!["center", "font"]);
+        assert_eq!(img_alt.result().len(), 1);
+        let start = doc.node(img_alt.result()[0]).start_tag.unwrap();
+        assert_eq!(doc.parts[start].attr("src", 0), "a");
+    }
+
+    #[test]
+    fn path_executor_queries() {
+        let doc = HtmlDocument::parse(
+            "<html><body><form id=f1><input type=hidden name=a><input type=text name=b></form>\
+             <div><form id=f2><input type=hidden name=c></form></div></body></html>",
+        );
+        let names = |nodes: Vec<usize>| -> Vec<String> {
+            nodes
+                .iter()
+                .map(|n| doc.parts[doc.node(*n).start_tag.unwrap()].attr("name", 0).to_string())
+                .collect()
+        };
+
+        let mut exec = HtmlPathExecutor::default();
+        exec.slash2().tag("form").tag("input").pred_attr("type", "hidden").unwrap();
+        assert_eq!(names(exec.exec(&doc, doc.root())), vec!["a", "c"]);
+
+        let mut exec = HtmlPathExecutor::default();
+        exec.tag("html").tag("body").tag("form").tag("in*").pred_pos(1).unwrap();
+        assert_eq!(names(exec.exec(&doc, doc.root())), vec!["b"]);
+
+        let mut exec = HtmlPathExecutor::default();
+        exec.slash2().tag("form").pred_attr("id", "f?").unwrap();
+        assert_eq!(exec.exec(&doc, doc.root()).len(), 2);
+
+        let mut exec = HtmlPathExecutor::default();
+        assert!(exec.slash2().pred_pos(0).is_err());
+    }
+
+    #[test]
+    fn parsed_paths_match_built_ones() {
+        let doc = HtmlDocument::parse(
+            "<html><body><form id=f1><input type=hidden name=a><input type=text name=b></form>\
+             <div><form id=f2><input type=hidden name=c></form></div></body></html>",
+        );
+        let names = |query: &str| -> Vec<String> {
+            path::parse_path(query)
+                .unwrap()
+                .exec(&doc, doc.root())
+                .iter()
+                .map(|n| doc.parts[doc.node(*n).start_tag.unwrap()].attr("name", 0).to_string())
+                .collect()
+        };
+        assert_eq!(names("//form/input[@type=\"hidden\"]"), vec!["a", "c"]);
+        assert_eq!(names("/HTML/body/form/in*[1]"), vec!["b"]);
+        assert_eq!(names("//form[@id='f2']/input"), vec!["c"]);
+        assert!(path::parse_path("//form[").is_err());
+        assert!(path::parse_path("//form[x]").is_err());
+        assert!(path::parse_path("form").is_err());
+        assert!(path::parse_path("//").is_err());
+    }
+
+    #[test]
+    fn wildcard_and_prefix_matching() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("d?v", "div"));
+        assert!(wildcard_match("h*", "html"));
+        assert!(wildcard_match("*ml", "html"));
+        assert!(!wildcard_match("h?", "html"));
+        assert!(wildcard_match("a\\*b", "a*b"));
+        assert!(!wildcard_match("a\\*b", "axb"));
+        assert!(wildcard_match("*a*b*c", "xxaxxbxxc"));
+        assert!(wildcard_match("サ*ル", "サンプル"));
+
+        assert!(forward_match("http://example.com", "http://"));
+        assert!(!forward_match("http", "http://"));
+        assert!(backward_match("index.html", ".html"));
+        assert!(!backward_match("index.htm", ".html"));
+    }
+}
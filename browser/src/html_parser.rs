@@ -0,0 +1,2303 @@
+//! HTML tree construction (WHATWG HTML standard, section 13.2.6).
+//!
+//! The builder implements the insertion modes a browser needs for ordinary
+//! documents: implied `<html>/<head>/<body>`, auto-closing of `<p>`, `<li>`,
+//! `<dd>/<dt>` and `<option>`, the list of active formatting elements with the
+//! adoption agency algorithm, table handling with foster parenting, `<select>`
+//! and framesets. `<template>` contents are parsed with the "in template"
+//! insertion mode and kept as children of the template element, which
+//! [`Document::descendants`] treats as inert. `<svg>` and `<math>` subtrees
+//! are parsed with the rules for foreign content into their own namespaces.
+
+use crate::dom::{Attribute, Document, Namespace, NodeData, NodeId, QuirksMode};
+use crate::html_tokenizer::{TextState, Token, Tokenizer};
+
+/// Parses `input` into a DOM tree, recovering from malformed markup the same
+/// way a browser would. `forced_mode`, when set, replaces the document mode
+/// the doctype would select.
+pub fn parse_document(input: &str, forced_mode: Option<QuirksMode>) -> Document {
+    let mut builder = TreeBuilder::new(input);
+    builder.forced_mode = forced_mode;
+    builder.run();
+    let mut doc = builder.doc;
+    let mut errors = builder.tokenizer.errors;
+    errors.append(&mut builder.errors);
+    doc.parse_errors = errors;
+    doc
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InsertionMode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    InHeadNoscript,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    InFrameset,
+    AfterBody,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+    InTemplate,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+#[derive(Clone, Debug)]
+enum FormattingEntry {
+    Marker,
+    Element {
+        node: NodeId,
+        name: String,
+        attrs: Vec<Attribute>,
+    },
+}
+
+struct TreeBuilder {
+    tokenizer: Tokenizer,
+    doc: Document,
+    mode: InsertionMode,
+    original_mode: InsertionMode,
+    open: Vec<NodeId>,
+    formatting: Vec<FormattingEntry>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    /// "stack of template insertion modes", one per open `<template>`.
+    template_modes: Vec<InsertionMode>,
+    frameset_ok: bool,
+    foster_parenting: bool,
+    /// Set after `<pre>`, `<listing>` and `<textarea>`: a leading newline is dropped.
+    ignore_lf: bool,
+    pending_table_text: String,
+    stopped: bool,
+    errors: Vec<String>,
+    /// Mode chosen by the user; overrides what the doctype selects.
+    forced_mode: Option<QuirksMode>,
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0C' | '\r' | ' ')
+}
+
+fn split_leading_whitespace(text: &str) -> (&str, &str) {
+    let idx = text.find(|c: char| !is_whitespace(c)).unwrap_or(text.len());
+    text.split_at(idx)
+}
+
+/// Public identifier prefixes that put a document into quirks mode.
+const QUIRKS_PUBLIC_PREFIXES: &[&str] = &[
+    "+//silmaril//dtd html pro v0r11 19970101//",
+    "-//as//dtd html 3.0 aswedit + extensions//",
+    "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+    "-//ietf//dtd html 2.0 level 1//",
+    "-//ietf//dtd html 2.0 level 2//",
+    "-//ietf//dtd html 2.0 strict level 1//",
+    "-//ietf//dtd html 2.0 strict level 2//",
+    "-//ietf//dtd html 2.0 strict//",
+    "-//ietf//dtd html 2.0//",
+    "-//ietf//dtd html 2.1e//",
+    "-//ietf//dtd html 3.0//",
+    "-//ietf//dtd html 3.2 final//",
+    "-//ietf//dtd html 3.2//",
+    "-//ietf//dtd html 3//",
+    "-//ietf//dtd html level 0//",
+    "-//ietf//dtd html level 1//",
+    "-//ietf//dtd html level 2//",
+    "-//ietf//dtd html level 3//",
+    "-//ietf//dtd html strict level 0//",
+    "-//ietf//dtd html strict level 1//",
+    "-//ietf//dtd html strict level 2//",
+    "-//ietf//dtd html strict level 3//",
+    "-//ietf//dtd html strict//",
+    "-//ietf//dtd html//",
+    "-//metrius//dtd metrius presentational//",
+    "-//microsoft//dtd internet explorer 2.0 html strict//",
+    "-//microsoft//dtd internet explorer 2.0 html//",
+    "-//microsoft//dtd internet explorer 2.0 tables//",
+    "-//microsoft//dtd internet explorer 3.0 html strict//",
+    "-//microsoft//dtd internet explorer 3.0 html//",
+    "-//microsoft//dtd internet explorer 3.0 tables//",
+    "-//netscape comm. corp.//dtd html//",
+    "-//netscape comm. corp.//dtd strict html//",
+    "-//o'reilly and associates//dtd html 2.0//",
+    "-//o'reilly and associates//dtd html extended 1.0//",
+    "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+    "-//sq//dtd html 2.0 hotmetal + extensions//",
+    "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+    "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+    "-//spyglass//dtd html 2.0 extended//",
+    "-//sun microsystems corp.//dtd hotjava html//",
+    "-//sun microsystems corp.//dtd hotjava strict html//",
+    "-//w3c//dtd html 3 1995-03-24//",
+    "-//w3c//dtd html 3.2 draft//",
+    "-//w3c//dtd html 3.2 final//",
+    "-//w3c//dtd html 3.2//",
+    "-//w3c//dtd html 3.2s draft//",
+    "-//w3c//dtd html 4.0 frameset//",
+    "-//w3c//dtd html 4.0 transitional//",
+    "-//w3c//dtd html experimental 19960712//",
+    "-//w3c//dtd html experimental 970421//",
+    "-//w3c//dtd w3 html//",
+    "-//w3o//dtd w3 html 3.0//",
+    "-//webtechs//dtd mozilla html 2.0//",
+    "-//webtechs//dtd mozilla html//",
+];
+
+/// Picks the document mode for a doctype token (WHATWG "initial" insertion
+/// mode). Identifiers are compared ASCII case-insensitively.
+pub fn quirks_mode_for_doctype(
+    name: Option<&str>,
+    public_id: Option<&str>,
+    system_id: Option<&str>,
+    force_quirks: bool,
+) -> QuirksMode {
+    let public = public_id.map(|s| s.to_ascii_lowercase());
+    let system = system_id.map(|s| s.to_ascii_lowercase());
+    let public = public.as_deref();
+    let system = system.as_deref();
+    let public_starts = |prefixes: &[&str]| public.is_some_and(|p| prefixes.iter().any(|x| p.starts_with(x)));
+    let html4_frameset_or_transitional = ["-//w3c//dtd html 4.01 frameset//", "-//w3c//dtd html 4.01 transitional//"];
+
+    if force_quirks
+        || !name.is_some_and(|n| n.eq_ignore_ascii_case("html"))
+        || matches!(public, Some("-//w3o//dtd w3 html strict 3.0//en//" | "-/w3c/dtd html 4.0 transitional/en" | "html"))
+        || system == Some("http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd")
+        || public_starts(QUIRKS_PUBLIC_PREFIXES)
+        || (system.is_none() && public_starts(&html4_frameset_or_transitional))
+    {
+        QuirksMode::Quirks
+    } else if public_starts(&["-//w3c//dtd xhtml 1.0 frameset//", "-//w3c//dtd xhtml 1.0 transitional//"])
+        || (system.is_some() && public_starts(&html4_frameset_or_transitional))
+    {
+        QuirksMode::LimitedQuirks
+    } else {
+        QuirksMode::NoQuirks
+    }
+}
+
+fn is_special(name: &str) -> bool {
+    matches!(
+        name,
+        "address" | "applet" | "area" | "article" | "aside" | "base" | "basefont" | "bgsound"
+            | "blockquote" | "body" | "br" | "button" | "caption" | "center" | "col" | "colgroup"
+            | "dd" | "details" | "dir" | "div" | "dl" | "dt" | "embed" | "fieldset"
+            | "figcaption" | "figure" | "footer" | "form" | "frame" | "frameset" | "h1" | "h2"
+            | "h3" | "h4" | "h5" | "h6" | "head" | "header" | "hgroup" | "hr" | "html"
+            | "iframe" | "img" | "input" | "keygen" | "li" | "link" | "listing" | "main"
+            | "marquee" | "menu" | "meta" | "nav" | "noembed" | "noframes" | "noscript"
+            | "object" | "ol" | "p" | "param" | "plaintext" | "pre" | "script" | "search"
+            | "section" | "select" | "source" | "style" | "summary" | "table" | "tbody" | "td"
+            | "template" | "textarea" | "tfoot" | "th" | "thead" | "title" | "tr" | "track"
+            | "ul" | "wbr" | "xmp"
+    )
+}
+
+/// HTML start tags that end SVG/MathML content and go back to HTML parsing.
+fn breaks_out_of_foreign_content(name: &str, attrs: &[Attribute]) -> bool {
+    matches!(
+        name,
+        "b" | "big" | "blockquote" | "body" | "br" | "center" | "code" | "dd" | "div" | "dl" | "dt"
+            | "em" | "embed" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "head" | "hr" | "i" | "img"
+            | "li" | "listing" | "menu" | "meta" | "nobr" | "ol" | "p" | "pre" | "ruby" | "s"
+            | "small" | "span" | "strong" | "strike" | "sub" | "sup" | "table" | "tt" | "u" | "ul"
+            | "var"
+    ) || (name == "font" && attrs.iter().any(|a| matches!(a.name.as_str(), "color" | "face" | "size")))
+}
+
+/// SVG element names whose case the tokenizer's lower-casing lost.
+const SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion", "animateTransform",
+    "clipPath", "feBlend", "feColorMatrix", "feComponentTransfer", "feComposite", "feConvolveMatrix",
+    "feDiffuseLighting", "feDisplacementMap", "feDistantLight", "feDropShadow", "feFlood", "feFuncA",
+    "feFuncB", "feFuncG", "feFuncR", "feGaussianBlur", "feImage", "feMerge", "feMergeNode",
+    "feMorphology", "feOffset", "fePointLight", "feSpecularLighting", "feSpotLight", "feTile",
+    "feTurbulence", "foreignObject", "glyphRef", "linearGradient", "radialGradient", "textPath",
+];
+
+/// SVG attribute names whose case the tokenizer's lower-casing lost.
+const SVG_ATTRIBUTE_NAMES: &[&str] = &[
+    "attributeName", "attributeType", "baseFrequency", "baseProfile", "calcMode", "clipPathUnits",
+    "diffuseConstant", "edgeMode", "filterUnits", "glyphRef", "gradientTransform", "gradientUnits",
+    "kernelMatrix", "kernelUnitLength", "keyPoints", "keySplines", "keyTimes", "lengthAdjust",
+    "limitingConeAngle", "markerHeight", "markerUnits", "markerWidth", "maskContentUnits", "maskUnits",
+    "numOctaves", "pathLength", "patternContentUnits", "patternTransform", "patternUnits", "pointsAtX",
+    "pointsAtY", "pointsAtZ", "preserveAlpha", "preserveAspectRatio", "primitiveUnits", "refX", "refY",
+    "repeatCount", "repeatDur", "requiredExtensions", "requiredFeatures", "specularConstant",
+    "specularExponent", "spreadMethod", "startOffset", "stdDeviation", "stitchTiles", "surfaceScale",
+    "systemLanguage", "tableValues", "targetX", "targetY", "textLength", "viewBox", "viewTarget",
+    "xChannelSelector", "yChannelSelector", "zoomAndPan",
+];
+
+/// "adjust SVG attributes" / "adjust MathML attributes" and the SVG tag
+/// name fix-up for an element created in foreign content.
+fn adjust_foreign_names(name: &str, attrs: &mut [Attribute], namespace: Namespace) -> String {
+    let restore = |table: &[&str], lower: &str| {
+        table.iter().find(|n| n.eq_ignore_ascii_case(lower)).map(|n| n.to_string())
+    };
+    for attr in attrs.iter_mut() {
+        let adjusted = match namespace {
+            Namespace::Svg => restore(SVG_ATTRIBUTE_NAMES, &attr.name),
+            Namespace::MathMl if attr.name == "definitionurl" => Some("definitionURL".to_string()),
+            _ => None,
+        };
+        if let Some(adjusted) = adjusted {
+            attr.name = adjusted;
+        }
+    }
+    match namespace {
+        Namespace::Svg => restore(SVG_TAG_NAMES, name).unwrap_or_else(|| name.to_string()),
+        _ => name.to_string(),
+    }
+}
+
+fn is_heading(name: &str) -> bool {
+    matches!(name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+}
+
+fn is_formatting(name: &str) -> bool {
+    matches!(
+        name,
+        "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small" | "strike"
+            | "strong" | "tt" | "u"
+    )
+}
+
+fn has_implied_end_tag(name: &str) -> bool {
+    matches!(
+        name,
+        "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc"
+    )
+}
+
+impl TreeBuilder {
+    fn new(input: &str) -> Self {
+        Self {
+            tokenizer: Tokenizer::new(input),
+            doc: Document::new(),
+            mode: InsertionMode::Initial,
+            original_mode: InsertionMode::Initial,
+            open: Vec::new(),
+            formatting: Vec::new(),
+            head: None,
+            form: None,
+            template_modes: Vec::new(),
+            frameset_ok: true,
+            foster_parenting: false,
+            ignore_lf: false,
+            pending_table_text: String::new(),
+            stopped: false,
+            errors: Vec::new(),
+            forced_mode: None,
+        }
+    }
+
+    fn run(&mut self) {
+        while !self.stopped {
+            let token = self.tokenizer.next_token();
+            let is_eof = token == Token::Eof;
+            self.process(token);
+            if is_eof {
+                break;
+            }
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors.push(format!("{} (mode {:?})", message, self.mode));
+    }
+
+    // ---- 木の操作 ----------------------------------------------------------
+
+    fn current(&self) -> NodeId {
+        *self.open.last().unwrap_or(&self.doc.root())
+    }
+
+    fn current_name(&self) -> &str {
+        self.doc.tag_name(self.current()).unwrap_or("")
+    }
+
+    fn name_of(&self, id: NodeId) -> &str {
+        self.doc.tag_name(id).unwrap_or("")
+    }
+
+    fn namespace_of(&self, id: NodeId) -> Namespace {
+        self.doc.element(id).map_or(Namespace::Html, |e| e.namespace)
+    }
+
+    /// Element in the "special" category, which stops scans down the stack.
+    fn is_special_node(&self, id: NodeId) -> bool {
+        let name = self.name_of(id);
+        match self.namespace_of(id) {
+            Namespace::Html => is_special(name),
+            Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+            Namespace::MathMl => matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"),
+        }
+    }
+
+    fn is_mathml_text_integration_point(&self, id: NodeId) -> bool {
+        self.namespace_of(id) == Namespace::MathMl && matches!(self.name_of(id), "mi" | "mo" | "mn" | "ms" | "mtext")
+    }
+
+    fn is_html_integration_point(&self, id: NodeId) -> bool {
+        let Some(e) = self.doc.element(id) else {
+            return false;
+        };
+        match e.namespace {
+            Namespace::Html => false,
+            Namespace::Svg => matches!(e.name.as_str(), "foreignObject" | "desc" | "title"),
+            Namespace::MathMl => {
+                e.name == "annotation-xml"
+                    && e.attr("encoding").is_some_and(|v| {
+                        v.eq_ignore_ascii_case("text/html") || v.eq_ignore_ascii_case("application/xhtml+xml")
+                    })
+            }
+        }
+    }
+
+    /// "appropriate place for inserting a node", including foster parenting.
+    fn insertion_place(&self) -> (NodeId, Option<NodeId>) {
+        let target = self.current();
+        if self.foster_parenting
+            && matches!(self.name_of(target), "table" | "tbody" | "tfoot" | "thead" | "tr")
+        {
+            if let Some(table_index) = self.open.iter().rposition(|n| self.name_of(*n) == "table") {
+                let table = self.open[table_index];
+                if let Some(parent) = self.doc.parent(table) {
+                    return (parent, Some(table));
+                }
+                return (self.open[table_index.saturating_sub(1)], None);
+            }
+            return (self.open[0], None);
+        }
+        (target, None)
+    }
+
+    fn insert_node(&mut self, node: NodeId) {
+        match self.insertion_place() {
+            (parent, Some(before)) => self.doc.insert_before(parent, node, before),
+            (parent, None) => self.doc.append_child(parent, node),
+        }
+    }
+
+    fn insert_element(&mut self, name: &str, attrs: Vec<Attribute>) -> NodeId {
+        let node = self.doc.create_element(name, attrs);
+        self.insert_node(node);
+        self.open.push(node);
+        node
+    }
+
+    /// "insert a foreign element"; self-closing tags are acknowledged.
+    fn insert_foreign_element(&mut self, name: &str, mut attrs: Vec<Attribute>, namespace: Namespace, self_closing: bool) {
+        let name = adjust_foreign_names(name, &mut attrs, namespace);
+        let node = self.doc.create_element_ns(&name, attrs, namespace);
+        self.insert_node(node);
+        if !self_closing {
+            self.open.push(node);
+        }
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.insertion_place() {
+            (parent, Some(before)) => self.doc.insert_text_before(parent, text, before),
+            (parent, None) => {
+                if parent != self.doc.root() {
+                    self.doc.append_text(parent, text);
+                }
+            }
+        }
+    }
+
+    fn insert_comment(&mut self, text: String) {
+        let node = self.doc.create_node(NodeData::Comment(text));
+        self.insert_node(node);
+    }
+
+    fn append_comment_to(&mut self, parent: NodeId, text: String) {
+        let node = self.doc.create_node(NodeData::Comment(text));
+        self.doc.append_child(parent, node);
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        self.open.pop()
+    }
+
+    fn pop_until(&mut self, name: &str) {
+        while let Some(node) = self.open.pop() {
+            if self.name_of(node) == name {
+                break;
+            }
+        }
+    }
+
+    fn pop_until_one_of(&mut self, names: fn(&str) -> bool) {
+        while let Some(node) = self.open.pop() {
+            if names(self.name_of(node)) {
+                break;
+            }
+        }
+    }
+
+    fn pop_until_node(&mut self, target: NodeId) {
+        while let Some(node) = self.open.pop() {
+            if node == target {
+                break;
+            }
+        }
+    }
+
+    fn remove_from_stack(&mut self, node: NodeId) {
+        self.open.retain(|n| *n != node);
+    }
+
+    fn merge_attrs(&mut self, node: NodeId, attrs: Vec<Attribute>) {
+        if let NodeData::Element(e) = &mut self.doc.node_mut(node).data {
+            for attr in attrs {
+                if !e.has_attr(&attr.name) {
+                    e.attrs.push(attr);
+                }
+            }
+        }
+    }
+
+    fn in_scope_where(&self, matches: impl Fn(&str) -> bool, scope: Scope) -> bool {
+        for node in self.open.iter().rev() {
+            let name = self.name_of(*node);
+            if self.namespace_of(*node) != Namespace::Html {
+                // SVG・MathML の要素は名前が同じでも HTML の要素としては数えない
+                if scope != Scope::Select && scope != Scope::Table && self.is_special_node(*node) {
+                    return false;
+                }
+                if scope == Scope::Select {
+                    return false;
+                }
+                continue;
+            }
+            if matches(name) {
+                return true;
+            }
+            let boundary = match scope {
+                Scope::Select => !matches!(name, "optgroup" | "option"),
+                Scope::Table => matches!(name, "html" | "table" | "template"),
+                _ => {
+                    matches!(
+                        name,
+                        "applet" | "caption" | "html" | "table" | "td" | "th" | "marquee"
+                            | "object" | "template"
+                    ) || (scope == Scope::ListItem && matches!(name, "ol" | "ul"))
+                        || (scope == Scope::Button && name == "button")
+                }
+            };
+            if boundary {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn in_scope(&self, name: &str, scope: Scope) -> bool {
+        self.in_scope_where(|n| n == name, scope)
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        loop {
+            let name = self.current_name();
+            if has_implied_end_tag(name) && Some(name) != except {
+                self.pop();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn close_p_if_in_button_scope(&mut self) {
+        if self.in_scope("p", Scope::Button) {
+            self.close_p();
+        }
+    }
+
+    fn close_p(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        if self.current_name() != "p" {
+            self.error("unexpected-open-element-while-closing-p");
+        }
+        self.pop_until("p");
+    }
+
+    // ---- 書式要素 (active formatting elements) ----------------------------
+
+    fn push_formatting(&mut self, node: NodeId, name: &str, attrs: &[Attribute]) {
+        // Noah's Ark: 同じ要素が 3 つ以上あれば一番古いものを消す
+        let mut same = Vec::new();
+        for (i, entry) in self.formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element { name: n, attrs: a, .. } => {
+                    if n == name && a.len() == attrs.len() && a.iter().all(|x| attrs.contains(x)) {
+                        same.push(i);
+                    }
+                }
+            }
+        }
+        if same.len() >= 3 {
+            self.formatting.remove(*same.last().unwrap());
+        }
+        self.formatting.push(FormattingEntry::Element {
+            node,
+            name: name.to_string(),
+            attrs: attrs.to_vec(),
+        });
+    }
+
+    fn formatting_index_of(&self, node: NodeId) -> Option<usize> {
+        self.formatting.iter().position(
+            |e| matches!(e, FormattingEntry::Element { node: n, .. } if *n == node),
+        )
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.formatting.pop() {
+            if matches!(entry, FormattingEntry::Marker) {
+                break;
+            }
+        }
+    }
+
+    fn reconstruct_formatting(&mut self) {
+        let Some(last) = self.formatting.last() else {
+            return;
+        };
+        match last {
+            FormattingEntry::Marker => return,
+            FormattingEntry::Element { node, .. } if self.open.contains(node) => return,
+            _ => {}
+        }
+        let mut index = self.formatting.len() - 1;
+        while index > 0 {
+            match &self.formatting[index - 1] {
+                FormattingEntry::Marker => break,
+                FormattingEntry::Element { node, .. } if self.open.contains(node) => break,
+                _ => index -= 1,
+            }
+        }
+        for i in index..self.formatting.len() {
+            let FormattingEntry::Element { name, attrs, .. } = self.formatting[i].clone() else {
+                continue;
+            };
+            let node = self.insert_element(&name, attrs.clone());
+            self.formatting[i] = FormattingEntry::Element { node, name, attrs };
+        }
+    }
+
+    /// The adoption agency algorithm. Returns `true` when the caller should
+    /// fall back to "any other end tag".
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        let current = self.current();
+        if self.name_of(current) == subject && self.formatting_index_of(current).is_none() {
+            self.pop();
+            return false;
+        }
+        for _ in 0..8 {
+            // 最後の marker 以降で subject と同名の書式要素を探す
+            let mut fe_index = None;
+            for (i, entry) in self.formatting.iter().enumerate().rev() {
+                match entry {
+                    FormattingEntry::Marker => break,
+                    FormattingEntry::Element { name, .. } if name == subject => {
+                        fe_index = Some(i);
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let Some(fe_index) = fe_index else {
+                return true;
+            };
+            let FormattingEntry::Element {
+                node: formatting_element,
+                name: fe_name,
+                attrs: fe_attrs,
+            } = self.formatting[fe_index].clone()
+            else {
+                return true;
+            };
+            let Some(fe_stack_index) = self.open.iter().position(|n| *n == formatting_element) else {
+                self.error("adoption-agency-formatting-element-not-open");
+                self.formatting.remove(fe_index);
+                return false;
+            };
+            if !self.element_in_scope(formatting_element) {
+                self.error("adoption-agency-formatting-element-not-in-scope");
+                return false;
+            }
+            if formatting_element != self.current() {
+                self.error("adoption-agency-misnested-formatting-element");
+            }
+            let furthest_block = self.open[fe_stack_index + 1..]
+                .iter()
+                .copied()
+                .find(|n| self.is_special_node(*n));
+            let Some(furthest_block) = furthest_block else {
+                self.pop_until_node(formatting_element);
+                self.formatting.remove(fe_index);
+                return false;
+            };
+            let common_ancestor = self.open[fe_stack_index - 1];
+            let mut bookmark = fe_index;
+            let mut node_index = self.open.iter().position(|n| *n == furthest_block).unwrap();
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let mut fmt_index = self.formatting_index_of(node);
+                if let Some(i) = fmt_index.filter(|_| inner > 3) {
+                    self.formatting.remove(i);
+                    if i < bookmark {
+                        bookmark -= 1;
+                    }
+                    fmt_index = None;
+                }
+                let Some(fmt_index) = fmt_index else {
+                    self.open.remove(node_index);
+                    continue;
+                };
+                let FormattingEntry::Element { name, attrs, .. } = self.formatting[fmt_index].clone() else {
+                    break;
+                };
+                let new_node = self.doc.create_element(&name, attrs.clone());
+                self.formatting[fmt_index] = FormattingEntry::Element { node: new_node, name, attrs };
+                self.open[node_index] = new_node;
+                if last_node == furthest_block {
+                    bookmark = fmt_index + 1;
+                }
+                self.doc.append_child(new_node, last_node);
+                last_node = new_node;
+            }
+            // common ancestor に last node を挿入 (必要なら foster parenting)
+            if self.foster_parenting
+                && matches!(self.name_of(common_ancestor), "table" | "tbody" | "tfoot" | "thead" | "tr")
+            {
+                let saved = self.open.clone();
+                self.open.truncate(fe_stack_index);
+                self.insert_node(last_node);
+                self.open = saved;
+            } else {
+                self.doc.append_child(common_ancestor, last_node);
+            }
+            let new_element = self.doc.create_element(&fe_name, fe_attrs.clone());
+            let children: Vec<NodeId> = self.doc.children(furthest_block).to_vec();
+            for child in children {
+                self.doc.append_child(new_element, child);
+            }
+            self.doc.append_child(furthest_block, new_element);
+            let entry = FormattingEntry::Element {
+                node: new_element,
+                name: fe_name,
+                attrs: fe_attrs,
+            };
+            if let Some(old) = self.formatting_index_of(formatting_element) {
+                self.formatting.remove(old);
+                if old < bookmark {
+                    bookmark -= 1;
+                }
+            }
+            self.formatting.insert(bookmark.min(self.formatting.len()), entry);
+            self.remove_from_stack(formatting_element);
+            let fb_index = self.open.iter().position(|n| *n == furthest_block).unwrap();
+            self.open.insert(fb_index + 1, new_element);
+        }
+        false
+    }
+
+    fn element_in_scope(&self, target: NodeId) -> bool {
+        for node in self.open.iter().rev() {
+            if *node == target {
+                return true;
+            }
+            if matches!(
+                self.name_of(*node),
+                "applet" | "caption" | "html" | "table" | "td" | "th" | "marquee" | "object" | "template"
+            ) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn any_other_end_tag(&mut self, name: &str) {
+        for i in (0..self.open.len()).rev() {
+            let node = self.open[i];
+            let node_name = self.name_of(node).to_string();
+            if node_name == name && self.namespace_of(node) == Namespace::Html {
+                self.generate_implied_end_tags(Some(name));
+                if self.current() != node {
+                    self.error("end-tag-closes-unclosed-elements");
+                }
+                self.open.truncate(i);
+                return;
+            }
+            if self.is_special_node(node) {
+                self.error("unexpected-end-tag");
+                return;
+            }
+        }
+    }
+
+    fn reset_insertion_mode(&mut self) {
+        for i in (0..self.open.len()).rev() {
+            let last = i == 0;
+            let name = self.name_of(self.open[i]).to_string();
+            self.mode = match name.as_str() {
+                "select" => {
+                    let in_table = self.open[..i]
+                        .iter()
+                        .rev()
+                        .any(|n| self.name_of(*n) == "table");
+                    if in_table {
+                        InsertionMode::InSelectInTable
+                    } else {
+                        InsertionMode::InSelect
+                    }
+                }
+                "td" | "th" if !last => InsertionMode::InCell,
+                "tr" => InsertionMode::InRow,
+                "tbody" | "thead" | "tfoot" => InsertionMode::InTableBody,
+                "caption" => InsertionMode::InCaption,
+                "colgroup" => InsertionMode::InColumnGroup,
+                "table" => InsertionMode::InTable,
+                "template" => self.template_modes.last().copied().unwrap_or(InsertionMode::InTemplate),
+                "head" if !last => InsertionMode::InHead,
+                "body" => InsertionMode::InBody,
+                "frameset" => InsertionMode::InFrameset,
+                "html" => {
+                    if self.head.is_none() {
+                        InsertionMode::BeforeHead
+                    } else {
+                        InsertionMode::AfterHead
+                    }
+                }
+                _ if last => InsertionMode::InBody,
+                _ => continue,
+            };
+            return;
+        }
+        self.mode = InsertionMode::InBody;
+    }
+
+    /// "generic raw text / RCDATA element parsing algorithm"
+    fn parse_raw_text(&mut self, name: &str, attrs: Vec<Attribute>, state: TextState) {
+        self.insert_element(name, attrs);
+        self.tokenizer.switch_to(state);
+        self.original_mode = self.mode;
+        self.mode = InsertionMode::Text;
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while !names.contains(&self.current_name()) && self.open.len() > 1 {
+            self.pop();
+        }
+    }
+
+    // ---- トークン処理 ------------------------------------------------------
+
+    /// The tree construction dispatcher: tokens inside SVG/MathML go to the
+    /// rules for foreign content, the rest to the current insertion mode.
+    fn process(&mut self, token: Token) {
+        if self.in_foreign_content(&token) {
+            self.foreign_content(token);
+        } else {
+            self.process_in(self.mode, token);
+        }
+    }
+
+    fn in_foreign_content(&self, token: &Token) -> bool {
+        let Some(&node) = self.open.last() else {
+            return false;
+        };
+        if self.namespace_of(node) == Namespace::Html || *token == Token::Eof {
+            return false;
+        }
+        let start = match token {
+            Token::StartTag { name, .. } => Some(name.as_str()),
+            _ => None,
+        };
+        let characters = matches!(token, Token::Characters(_));
+        if self.is_mathml_text_integration_point(node)
+            && (characters || start.is_some_and(|name| name != "mglyph" && name != "malignmark"))
+        {
+            return false;
+        }
+        if self.namespace_of(node) == Namespace::MathMl && self.name_of(node) == "annotation-xml" && start == Some("svg") {
+            return false;
+        }
+        !(self.is_html_integration_point(node) && (characters || start.is_some()))
+    }
+
+    /// "rules for parsing tokens in foreign content"
+    fn foreign_content(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                if !text.chars().all(is_whitespace) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(&text.replace('\0', "\u{FFFD}"));
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } if breaks_out_of_foreign_content(&name, &attrs) => {
+                self.error("unexpected-html-element-in-foreign-content");
+                // HTML の要素か統合ポイントまで閉じてから HTML として処理し直す
+                while let Some(&node) = self.open.last()
+                    && self.namespace_of(node) != Namespace::Html
+                    && !self.is_mathml_text_integration_point(node)
+                    && !self.is_html_integration_point(node)
+                {
+                    self.pop();
+                }
+                self.process(Token::StartTag { name, attrs, self_closing });
+            }
+            Token::StartTag { name, attrs, self_closing } => {
+                let namespace = self.namespace_of(self.current());
+                self.insert_foreign_element(&name, attrs, namespace, self_closing);
+            }
+            Token::EndTag { name } => {
+                let mut index = self.open.len() - 1;
+                if !self.name_of(self.open[index]).eq_ignore_ascii_case(&name) {
+                    self.error("unexpected-end-tag");
+                }
+                while index > 0 {
+                    if self.name_of(self.open[index]).eq_ignore_ascii_case(&name) {
+                        self.open.truncate(index);
+                        return;
+                    }
+                    index -= 1;
+                    if self.namespace_of(self.open[index]) == Namespace::Html {
+                        self.process_in(self.mode, Token::EndTag { name });
+                        return;
+                    }
+                }
+            }
+            Token::Eof => self.process_in(self.mode, Token::Eof),
+        }
+    }
+
+    /// Handles `token` with the rules of `mode` without switching the current
+    /// insertion mode ("process the token using the rules for ...").
+    fn process_in(&mut self, mode: InsertionMode, token: Token) {
+        match mode {
+            InsertionMode::Initial => self.initial(token),
+            InsertionMode::BeforeHtml => self.before_html(token),
+            InsertionMode::BeforeHead => self.before_head(token),
+            InsertionMode::InHead => self.in_head(token),
+            InsertionMode::InHeadNoscript => self.in_head_noscript(token),
+            InsertionMode::AfterHead => self.after_head(token),
+            InsertionMode::InBody => self.in_body(token),
+            InsertionMode::Text => self.text(token),
+            InsertionMode::InTable => self.in_table(token),
+            InsertionMode::InTableText => self.in_table_text(token),
+            InsertionMode::InCaption => self.in_caption(token),
+            InsertionMode::InColumnGroup => self.in_column_group(token),
+            InsertionMode::InTableBody => self.in_table_body(token),
+            InsertionMode::InRow => self.in_row(token),
+            InsertionMode::InCell => self.in_cell(token),
+            InsertionMode::InSelect => self.in_select(token),
+            InsertionMode::InSelectInTable => self.in_select_in_table(token),
+            InsertionMode::InFrameset => self.in_frameset(token),
+            InsertionMode::AfterBody => self.after_body(token),
+            InsertionMode::AfterFrameset => self.after_frameset(token),
+            InsertionMode::AfterAfterBody => self.after_after_body(token),
+            InsertionMode::AfterAfterFrameset => self.after_after_frameset(token),
+            InsertionMode::InTemplate => self.in_template(token),
+        }
+    }
+
+    fn initial(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let (_, rest) = split_leading_whitespace(&text);
+                if !rest.is_empty() {
+                    self.mode = InsertionMode::BeforeHtml;
+                    self.process(Token::Characters(rest.to_string()));
+                }
+            }
+            Token::Comment(text) => {
+                let root = self.doc.root();
+                self.append_comment_to(root, text);
+            }
+            Token::Doctype {
+                name,
+                public_id,
+                system_id,
+                force_quirks,
+            } => {
+                let detected = quirks_mode_for_doctype(name.as_deref(), public_id.as_deref(), system_id.as_deref(), force_quirks);
+                self.doc.mode = self.forced_mode.unwrap_or(detected);
+                let node = self.doc.create_node(NodeData::Doctype {
+                    name: name.unwrap_or_default(),
+                    public_id: public_id.unwrap_or_default(),
+                    system_id: system_id.unwrap_or_default(),
+                });
+                let root = self.doc.root();
+                self.doc.append_child(root, node);
+                self.mode = InsertionMode::BeforeHtml;
+            }
+            other => {
+                self.error("missing-doctype");
+                // DOCTYPE が無い古いページは互換モードで扱う
+                self.doc.mode = self.forced_mode.unwrap_or(QuirksMode::Quirks);
+                self.mode = InsertionMode::BeforeHtml;
+                self.process(other);
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token) {
+        match token {
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::Comment(text) => {
+                let root = self.doc.root();
+                self.append_comment_to(root, text);
+            }
+            Token::Characters(text) => {
+                let (_, rest) = split_leading_whitespace(&text);
+                if !rest.is_empty() {
+                    self.insert_html_element(Vec::new());
+                    self.process(Token::Characters(rest.to_string()));
+                }
+            }
+            Token::StartTag { ref name, ref attrs, .. } if name == "html" => {
+                self.insert_html_element(attrs.clone());
+            }
+            Token::EndTag { ref name } if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag");
+            }
+            other => {
+                self.insert_html_element(Vec::new());
+                self.process(other);
+            }
+        }
+    }
+
+    fn insert_html_element(&mut self, attrs: Vec<Attribute>) {
+        let html = self.doc.create_element("html", attrs);
+        let root = self.doc.root();
+        self.doc.append_child(root, html);
+        self.open.push(html);
+        self.mode = InsertionMode::BeforeHead;
+    }
+
+    fn before_head(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let (_, rest) = split_leading_whitespace(&text);
+                if !rest.is_empty() {
+                    self.insert_head(Vec::new());
+                    self.process(Token::Characters(rest.to_string()));
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { ref name, .. } if name == "html" => self.process_in(InsertionMode::InBody, token),
+            Token::StartTag { ref name, ref attrs, .. } if name == "head" => {
+                self.insert_head(attrs.clone());
+            }
+            Token::EndTag { ref name } if !matches!(name.as_str(), "head" | "body" | "html" | "br") => {
+                self.error("unexpected-end-tag");
+            }
+            other => {
+                self.insert_head(Vec::new());
+                self.process(other);
+            }
+        }
+    }
+
+    fn insert_head(&mut self, attrs: Vec<Attribute>) {
+        let head = self.insert_element("head", attrs);
+        self.head = Some(head);
+        self.mode = InsertionMode::InHead;
+    }
+
+    fn in_head(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let (ws, rest) = split_leading_whitespace(&text);
+                self.insert_text(ws);
+                if !rest.is_empty() {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(Token::Characters(rest.to_string()));
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } => match name.as_str() {
+                "html" => self.process_in(
+                    InsertionMode::InBody,
+                    Token::StartTag { name, attrs, self_closing },
+                ),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_element(&name, attrs);
+                    self.pop();
+                }
+                "title" => self.parse_raw_text(&name, attrs, TextState::Rcdata),
+                "noframes" | "style" => self.parse_raw_text(&name, attrs, TextState::Rawtext),
+                "noscript" => {
+                    // スクリプトは実行しないので scripting flag は常に off
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InHeadNoscript;
+                }
+                "script" => self.parse_raw_text(&name, attrs, TextState::ScriptData),
+                "template" => {
+                    self.insert_element(&name, attrs);
+                    self.formatting.push(FormattingEntry::Marker);
+                    self.frameset_ok = false;
+                    self.mode = InsertionMode::InTemplate;
+                    self.template_modes.push(InsertionMode::InTemplate);
+                }
+                "head" => self.error("unexpected-head"),
+                _ => {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(Token::StartTag { name, attrs, self_closing });
+                }
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "head" => {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                }
+                "template" => {
+                    if self.open.iter().any(|n| self.name_of(*n) == "template") {
+                        self.generate_implied_end_tags(None);
+                        if self.current_name() != "template" {
+                            self.error("end-tag-closes-unclosed-elements");
+                        }
+                        self.pop_until("template");
+                        self.clear_formatting_to_marker();
+                        self.template_modes.pop();
+                        self.reset_insertion_mode();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "body" | "html" | "br" => {
+                    self.pop();
+                    self.mode = InsertionMode::AfterHead;
+                    self.process(Token::EndTag { name });
+                }
+                _ => self.error("unexpected-end-tag"),
+            },
+            Token::Eof => {
+                self.pop();
+                self.mode = InsertionMode::AfterHead;
+                self.process(Token::Eof);
+            }
+        }
+    }
+
+    fn in_head_noscript(&mut self, token: Token) {
+        match token {
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { ref name, .. } if name == "html" => self.process_in(InsertionMode::InBody, token),
+            Token::EndTag { ref name } if name == "noscript" => {
+                self.pop();
+                self.mode = InsertionMode::InHead;
+            }
+            Token::Comment(_) => self.process_in(InsertionMode::InHead, token),
+            Token::Characters(ref text) if text.chars().all(is_whitespace) => {
+                self.process_in(InsertionMode::InHead, token)
+            }
+            Token::StartTag { ref name, .. }
+                if matches!(name.as_str(), "basefont" | "bgsound" | "link" | "meta" | "noframes" | "style") =>
+            {
+                self.process_in(InsertionMode::InHead, token)
+            }
+            Token::StartTag { ref name, .. } if matches!(name.as_str(), "head" | "noscript") => {
+                self.error("unexpected-start-tag");
+            }
+            Token::EndTag { ref name } if name != "br" => self.error("unexpected-end-tag"),
+            other => {
+                self.error("unexpected-token-in-head-noscript");
+                self.pop();
+                self.mode = InsertionMode::InHead;
+                self.process(other);
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let (ws, rest) = split_leading_whitespace(&text);
+                self.insert_text(ws);
+                if !rest.is_empty() {
+                    self.insert_body(Vec::new());
+                    self.process(Token::Characters(rest.to_string()));
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } => match name.as_str() {
+                "html" => self.process_in(
+                    InsertionMode::InBody,
+                    Token::StartTag { name, attrs, self_closing },
+                ),
+                "body" => {
+                    self.insert_body(attrs);
+                    self.frameset_ok = false;
+                }
+                "frameset" => {
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InFrameset;
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    self.error("head-element-after-head");
+                    let Some(head) = self.head else {
+                        return;
+                    };
+                    self.open.push(head);
+                    self.process_in(
+                        InsertionMode::InHead,
+                        Token::StartTag { name, attrs, self_closing },
+                    );
+                    self.remove_from_stack(head);
+                }
+                "head" => self.error("unexpected-head"),
+                _ => {
+                    self.insert_body(Vec::new());
+                    self.process(Token::StartTag { name, attrs, self_closing });
+                }
+            },
+            Token::EndTag { ref name } if !matches!(name.as_str(), "body" | "html" | "br" | "template") => {
+                self.error("unexpected-end-tag");
+            }
+            other => {
+                self.insert_body(Vec::new());
+                self.process(other);
+            }
+        }
+    }
+
+    fn insert_body(&mut self, attrs: Vec<Attribute>) {
+        self.insert_element("body", attrs);
+        self.mode = InsertionMode::InBody;
+    }
+
+    fn in_body(&mut self, token: Token) {
+        match token {
+            Token::Characters(mut text) => {
+                if std::mem::take(&mut self.ignore_lf) && text.starts_with('\n') {
+                    text.remove(0);
+                }
+                if text.is_empty() {
+                    return;
+                }
+                self.reconstruct_formatting();
+                self.insert_text(&text);
+                if !text.chars().all(is_whitespace) {
+                    self.frameset_ok = false;
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } => {
+                self.ignore_lf = false;
+                self.in_body_start_tag(name, attrs, self_closing);
+            }
+            Token::EndTag { name } => {
+                self.ignore_lf = false;
+                self.in_body_end_tag(name);
+            }
+            Token::Eof if !self.template_modes.is_empty() => self.in_template(Token::Eof),
+            Token::Eof => self.stopped = true,
+        }
+    }
+
+    fn in_body_start_tag(&mut self, name: String, attrs: Vec<Attribute>, self_closing: bool) {
+        match name.as_str() {
+            "html" => {
+                self.error("unexpected-html-start-tag");
+                if let Some(&html) = self.open.first() {
+                    self.merge_attrs(html, attrs);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => {
+                self.process_in(InsertionMode::InHead, Token::StartTag { name, attrs, self_closing });
+            }
+            "body" => {
+                self.error("unexpected-body-start-tag");
+                if let Some(&body) = self.open.get(1).filter(|b| self.name_of(**b) == "body") {
+                    self.frameset_ok = false;
+                    self.merge_attrs(body, attrs);
+                }
+            }
+            "frameset" => {
+                self.error("unexpected-frameset-start-tag");
+                let body = self.open.get(1).copied();
+                if let Some(body) = body.filter(|b| self.name_of(*b) == "body" && self.frameset_ok) {
+                    self.doc.detach(body);
+                    self.open.truncate(1);
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InFrameset;
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section" | "summary"
+            | "ul" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&name, attrs);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_if_in_button_scope();
+                if is_heading(self.current_name()) {
+                    self.error("nested-heading");
+                    self.pop();
+                }
+                self.insert_element(&name, attrs);
+            }
+            "pre" | "listing" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&name, attrs);
+                self.ignore_lf = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                if self.form.is_some() {
+                    self.error("nested-form");
+                    return;
+                }
+                self.close_p_if_in_button_scope();
+                let form = self.insert_element(&name, attrs);
+                self.form = Some(form);
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                for i in (0..self.open.len()).rev() {
+                    let node = self.open[i];
+                    let node_name = self.name_of(node).to_string();
+                    let closes = if name == "li" {
+                        node_name == "li"
+                    } else {
+                        node_name == "dd" || node_name == "dt"
+                    };
+                    if closes {
+                        self.generate_implied_end_tags(Some(&node_name));
+                        self.pop_until(&node_name);
+                        break;
+                    }
+                    if self.is_special_node(node) && !matches!(node_name.as_str(), "address" | "div" | "p") {
+                        break;
+                    }
+                }
+                self.close_p_if_in_button_scope();
+                self.insert_element(&name, attrs);
+            }
+            "plaintext" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&name, attrs);
+                self.tokenizer.switch_to(TextState::Plaintext);
+            }
+            "button" => {
+                if self.in_scope("button", Scope::Default) {
+                    self.error("nested-button");
+                    self.generate_implied_end_tags(None);
+                    self.pop_until("button");
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let open_a = self.formatting.iter().rev().find_map(|e| match e {
+                    FormattingEntry::Marker => Some(None),
+                    FormattingEntry::Element { node, name, .. } if name == "a" => Some(Some(*node)),
+                    _ => None,
+                });
+                if let Some(Some(a)) = open_a {
+                    self.error("nested-a");
+                    self.adoption_agency("a");
+                    if let Some(i) = self.formatting_index_of(a) {
+                        self.formatting.remove(i);
+                    }
+                    self.remove_from_stack(a);
+                }
+                self.reconstruct_formatting();
+                let node = self.insert_element(&name, attrs.clone());
+                self.push_formatting(node, &name, &attrs);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_formatting();
+                let node = self.insert_element(&name, attrs.clone());
+                self.push_formatting(node, &name, &attrs);
+            }
+            "nobr" => {
+                self.reconstruct_formatting();
+                if self.in_scope("nobr", Scope::Default) {
+                    self.error("nested-nobr");
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+                let node = self.insert_element(&name, attrs.clone());
+                self.push_formatting(node, &name, &attrs);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+                self.formatting.push(FormattingEntry::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if self.doc.mode != QuirksMode::Quirks {
+                    self.close_p_if_in_button_scope();
+                }
+                self.insert_element(&name, attrs);
+                self.frameset_ok = false;
+                self.mode = InsertionMode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_formatting();
+                let hidden = attrs
+                    .iter()
+                    .any(|a| a.name == "type" && a.value.eq_ignore_ascii_case("hidden"));
+                self.insert_element(&name, attrs);
+                self.pop();
+                if !hidden {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => {
+                self.insert_element(&name, attrs);
+                self.pop();
+            }
+            "hr" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&name, attrs);
+                self.pop();
+                self.frameset_ok = false;
+            }
+            "image" => {
+                self.error("image-start-tag");
+                self.in_body_start_tag("img".to_string(), attrs, self_closing);
+            }
+            "textarea" => {
+                self.insert_element(&name, attrs);
+                self.ignore_lf = true;
+                self.tokenizer.switch_to(TextState::Rcdata);
+                self.original_mode = self.mode;
+                self.frameset_ok = false;
+                self.mode = InsertionMode::Text;
+            }
+            "xmp" => {
+                self.close_p_if_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.parse_raw_text(&name, attrs, TextState::Rawtext);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_raw_text(&name, attrs, TextState::Rawtext);
+            }
+            "noembed" => self.parse_raw_text(&name, attrs, TextState::Rawtext),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    InsertionMode::InTable
+                    | InsertionMode::InCaption
+                    | InsertionMode::InTableBody
+                    | InsertionMode::InRow
+                    | InsertionMode::InCell => InsertionMode::InSelectInTable,
+                    _ => InsertionMode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_name() == "option" {
+                    self.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+            }
+            "rb" | "rtc" => {
+                if self.in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_element(&name, attrs);
+            }
+            "rp" | "rt" => {
+                if self.in_scope("ruby", Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_element(&name, attrs);
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {
+                self.error("unexpected-start-tag-in-body");
+            }
+            "math" | "svg" => {
+                self.reconstruct_formatting();
+                let namespace = if name == "svg" { Namespace::Svg } else { Namespace::MathMl };
+                self.insert_foreign_element(&name, attrs, namespace, self_closing);
+            }
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&name, attrs);
+            }
+        }
+    }
+
+    fn in_body_end_tag(&mut self, name: String) {
+        match name.as_str() {
+            "template" => self.process_in(InsertionMode::InHead, Token::EndTag { name }),
+            "body" | "html" => {
+                if !self.in_scope("body", Scope::Default) {
+                    self.error("unexpected-body-end-tag");
+                    return;
+                }
+                self.mode = InsertionMode::AfterBody;
+                if name == "html" {
+                    self.process(Token::EndTag { name });
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                if !self.in_scope(&name, Scope::Default) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if self.current_name() != name {
+                    self.error("end-tag-closes-unclosed-elements");
+                }
+                self.pop_until(&name);
+            }
+            "form" => {
+                let node = self.form.take();
+                match node {
+                    Some(form) if self.element_in_scope(form) => {
+                        self.generate_implied_end_tags(None);
+                        if self.current() != form {
+                            self.error("end-tag-closes-unclosed-elements");
+                        }
+                        self.remove_from_stack(form);
+                    }
+                    _ => self.error("unexpected-form-end-tag"),
+                }
+            }
+            "p" => {
+                if !self.in_scope("p", Scope::Button) {
+                    self.error("p-end-tag-without-p");
+                    self.insert_element("p", Vec::new());
+                }
+                self.close_p();
+            }
+            "li" => {
+                if !self.in_scope("li", Scope::ListItem) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(Some("li"));
+                self.pop_until("li");
+            }
+            "dd" | "dt" => {
+                if !self.in_scope(&name, Scope::Default) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(Some(&name));
+                self.pop_until(&name);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if !self.in_scope_where(is_heading, Scope::Default) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until_one_of(is_heading);
+            }
+            _ if is_formatting(&name) => {
+                if self.adoption_agency(&name) {
+                    self.any_other_end_tag(&name);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                if !self.in_scope(&name, Scope::Default) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                self.pop_until(&name);
+                self.clear_formatting_to_marker();
+            }
+            "br" => {
+                self.error("br-end-tag");
+                self.in_body_start_tag("br".to_string(), Vec::new(), false);
+            }
+            _ => self.any_other_end_tag(&name),
+        }
+    }
+
+    fn text(&mut self, token: Token) {
+        match token {
+            Token::Characters(mut text) => {
+                if std::mem::take(&mut self.ignore_lf) && text.starts_with('\n') {
+                    text.remove(0);
+                }
+                self.insert_text(&text);
+            }
+            Token::Eof => {
+                self.error("eof-in-text");
+                self.pop();
+                self.mode = self.original_mode;
+                self.process(Token::Eof);
+            }
+            Token::EndTag { .. } => {
+                self.ignore_lf = false;
+                self.tokenizer.switch_to(TextState::Data);
+                self.pop();
+                self.mode = self.original_mode;
+            }
+            _ => {}
+        }
+    }
+
+    fn in_table(&mut self, token: Token) {
+        match token {
+            Token::Characters(_)
+                if matches!(self.current_name(), "table" | "tbody" | "tfoot" | "thead" | "tr") =>
+            {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = InsertionMode::InTableText;
+                self.process(token);
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } => match name.as_str() {
+                "caption" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.formatting.push(FormattingEntry::Marker);
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InCaption;
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InColumnGroup;
+                }
+                "col" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element("colgroup", Vec::new());
+                    self.mode = InsertionMode::InColumnGroup;
+                    self.process(Token::StartTag { name, attrs, self_closing });
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element(&name, attrs);
+                    self.mode = InsertionMode::InTableBody;
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to(&["table", "template", "html"]);
+                    self.insert_element("tbody", Vec::new());
+                    self.mode = InsertionMode::InTableBody;
+                    self.process(Token::StartTag { name, attrs, self_closing });
+                }
+                "table" => {
+                    self.error("nested-table");
+                    if self.in_scope("table", Scope::Table) {
+                        self.pop_until("table");
+                        self.reset_insertion_mode();
+                        self.process(Token::StartTag { name, attrs, self_closing });
+                    }
+                }
+                "style" | "script" | "template" => {
+                    self.process_in(InsertionMode::InHead, Token::StartTag { name, attrs, self_closing });
+                }
+                "input"
+                    if attrs
+                        .iter()
+                        .any(|a| a.name == "type" && a.value.eq_ignore_ascii_case("hidden")) =>
+                {
+                    self.error("input-in-table");
+                    self.insert_element(&name, attrs);
+                    self.pop();
+                }
+                "form" => {
+                    self.error("form-in-table");
+                    if self.form.is_none() {
+                        let form = self.insert_element(&name, attrs);
+                        self.form = Some(form);
+                        self.pop();
+                    }
+                }
+                _ => self.in_table_anything_else(Token::StartTag { name, attrs, self_closing }),
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "table" => {
+                    if !self.in_scope("table", Scope::Table) {
+                        self.error("unexpected-end-tag");
+                        return;
+                    }
+                    self.pop_until("table");
+                    self.reset_insertion_mode();
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th"
+                | "thead" | "tr" => self.error("unexpected-end-tag"),
+                "template" => self.process_in(InsertionMode::InHead, Token::EndTag { name }),
+                _ => self.in_table_anything_else(Token::EndTag { name }),
+            },
+            Token::Eof => self.in_body(Token::Eof),
+            other => self.in_table_anything_else(other),
+        }
+    }
+
+    fn in_table_anything_else(&mut self, token: Token) {
+        self.error("foster-parented-content");
+        self.foster_parenting = true;
+        self.process_in(InsertionMode::InBody, token);
+        self.foster_parenting = false;
+    }
+
+    fn in_table_text(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => self.pending_table_text.push_str(&text),
+            other => {
+                let text = std::mem::take(&mut self.pending_table_text);
+                self.mode = self.original_mode;
+                if text.chars().all(is_whitespace) {
+                    self.insert_text(&text);
+                } else {
+                    self.in_table_anything_else(Token::Characters(text));
+                }
+                self.process(other);
+            }
+        }
+    }
+
+    fn in_caption(&mut self, token: Token) {
+        match &token {
+            Token::EndTag { name } if name == "caption" => {
+                self.close_caption();
+            }
+            Token::StartTag { name, .. }
+                if matches!(
+                    name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if self.close_caption() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag { name } if name == "table" => {
+                if self.close_caption() {
+                    self.process(token);
+                }
+            }
+            Token::EndTag { name }
+                if matches!(
+                    name.as_str(),
+                    "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag");
+            }
+            _ => self.process_in(InsertionMode::InBody, token),
+        }
+    }
+
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope("caption", Scope::Table) {
+            self.error("unexpected-caption-end");
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        self.pop_until("caption");
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token) {
+        match token {
+            Token::Characters(ref text) => {
+                let (ws, rest) = split_leading_whitespace(text);
+                let rest = rest.to_string();
+                self.insert_text(ws);
+                if !rest.is_empty() {
+                    self.column_group_anything_else(Token::Characters(rest));
+                }
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { ref name, ref attrs, .. } if name == "col" => {
+                self.insert_element(name, attrs.clone());
+                self.pop();
+            }
+            Token::StartTag { ref name, .. } if name == "html" => self.process_in(InsertionMode::InBody, token),
+            Token::StartTag { ref name, .. } | Token::EndTag { ref name } if name == "template" => {
+                self.process_in(InsertionMode::InHead, token)
+            }
+            Token::EndTag { ref name } if name == "colgroup" => {
+                if self.current_name() == "colgroup" {
+                    self.pop();
+                    self.mode = InsertionMode::InTable;
+                } else {
+                    self.error("unexpected-end-tag");
+                }
+            }
+            Token::EndTag { ref name } if name == "col" => self.error("unexpected-end-tag"),
+            Token::Eof => self.in_body(Token::Eof),
+            other => self.column_group_anything_else(other),
+        }
+    }
+
+    fn column_group_anything_else(&mut self, token: Token) {
+        if self.current_name() != "colgroup" {
+            self.error("unexpected-token-in-colgroup");
+            return;
+        }
+        self.pop();
+        self.mode = InsertionMode::InTable;
+        self.process(token);
+    }
+
+    fn in_table_body(&mut self, token: Token) {
+        const CONTEXT: &[&str] = &["tbody", "tfoot", "thead", "template", "html"];
+        match &token {
+            Token::StartTag { name, attrs, .. } if name == "tr" => {
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_element(name, attrs.clone());
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag { name, .. } if name == "th" || name == "td" => {
+                self.error("cell-outside-row");
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_element("tr", Vec::new());
+                self.mode = InsertionMode::InRow;
+                self.process(token);
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(name, Scope::Table) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.clear_stack_back_to(CONTEXT);
+                self.pop();
+                self.mode = InsertionMode::InTable;
+            }
+            Token::StartTag { name, .. }
+                if matches!(name.as_str(), "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead") =>
+            {
+                self.leave_table_body(token);
+            }
+            Token::EndTag { name } if name == "table" => self.leave_table_body(token),
+            Token::EndTag { name }
+                if matches!(
+                    name.as_str(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                self.error("unexpected-end-tag");
+            }
+            _ => self.in_table(token),
+        }
+    }
+
+    fn leave_table_body(&mut self, token: Token) {
+        if !self.in_scope_where(|n| matches!(n, "tbody" | "thead" | "tfoot"), Scope::Table) {
+            self.error("unexpected-token-in-table-body");
+            return;
+        }
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead", "template", "html"]);
+        self.pop();
+        self.mode = InsertionMode::InTable;
+        self.process(token);
+    }
+
+    fn in_row(&mut self, token: Token) {
+        const CONTEXT: &[&str] = &["tr", "template", "html"];
+        match &token {
+            Token::StartTag { name, attrs, .. } if name == "th" || name == "td" => {
+                self.clear_stack_back_to(CONTEXT);
+                self.insert_element(name, attrs.clone());
+                self.mode = InsertionMode::InCell;
+                self.formatting.push(FormattingEntry::Marker);
+            }
+            Token::EndTag { name } if name == "tr" => {
+                if !self.in_scope("tr", Scope::Table) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.clear_stack_back_to(CONTEXT);
+                self.pop();
+                self.mode = InsertionMode::InTableBody;
+            }
+            Token::StartTag { name, .. }
+                if matches!(
+                    name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                self.leave_row(token);
+            }
+            Token::EndTag { name } if name == "table" => self.leave_row(token),
+            Token::EndTag { name } if matches!(name.as_str(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(name, Scope::Table) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.leave_row(token);
+            }
+            Token::EndTag { name }
+                if matches!(name.as_str(), "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th") =>
+            {
+                self.error("unexpected-end-tag");
+            }
+            _ => self.in_table(token),
+        }
+    }
+
+    fn leave_row(&mut self, token: Token) {
+        if !self.in_scope("tr", Scope::Table) {
+            self.error("unexpected-token-in-row");
+            return;
+        }
+        self.clear_stack_back_to(&["tr", "template", "html"]);
+        self.pop();
+        self.mode = InsertionMode::InTableBody;
+        self.process(token);
+    }
+
+    fn in_cell(&mut self, token: Token) {
+        match &token {
+            Token::EndTag { name } if name == "td" || name == "th" => {
+                if !self.in_scope(name, Scope::Table) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.generate_implied_end_tags(None);
+                if self.current_name() != name {
+                    self.error("end-tag-closes-unclosed-elements");
+                }
+                self.pop_until(name);
+                self.clear_formatting_to_marker();
+                self.mode = InsertionMode::InRow;
+            }
+            Token::StartTag { name, .. }
+                if matches!(
+                    name.as_str(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if !self.in_scope_where(|n| n == "td" || n == "th", Scope::Table) {
+                    self.error("unexpected-start-tag");
+                    return;
+                }
+                self.close_cell();
+                self.process(token);
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "body" | "caption" | "col" | "colgroup" | "html") => {
+                self.error("unexpected-end-tag");
+            }
+            Token::EndTag { name } if matches!(name.as_str(), "table" | "tbody" | "tfoot" | "thead" | "tr") => {
+                if !self.in_scope(name, Scope::Table) {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.close_cell();
+                self.process(token);
+            }
+            _ => self.process_in(InsertionMode::InBody, token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        self.pop_until_one_of(|n| n == "td" || n == "th");
+        self.clear_formatting_to_marker();
+        self.mode = InsertionMode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => self.insert_text(&text),
+            Token::Comment(text) => self.insert_comment(text),
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { name, attrs, self_closing } => match name.as_str() {
+                "html" => self.process_in(InsertionMode::InBody, Token::StartTag { name, attrs, self_closing }),
+                "option" => {
+                    if self.current_name() == "option" {
+                        self.pop();
+                    }
+                    self.insert_element(&name, attrs);
+                }
+                "optgroup" => {
+                    if self.current_name() == "option" {
+                        self.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.pop();
+                    }
+                    self.insert_element(&name, attrs);
+                }
+                "hr" => {
+                    if self.current_name() == "option" {
+                        self.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.pop();
+                    }
+                    self.insert_element(&name, attrs);
+                    self.pop();
+                }
+                "select" => {
+                    self.error("nested-select");
+                    if self.in_scope("select", Scope::Select) {
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                    }
+                }
+                "input" | "keygen" | "textarea" => {
+                    self.error("unexpected-start-tag-in-select");
+                    if self.in_scope("select", Scope::Select) {
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                        self.process(Token::StartTag { name, attrs, self_closing });
+                    }
+                }
+                "script" | "template" => {
+                    self.process_in(InsertionMode::InHead, Token::StartTag { name, attrs, self_closing })
+                }
+                _ => self.error("unexpected-start-tag-in-select"),
+            },
+            Token::EndTag { name } => match name.as_str() {
+                "optgroup" => {
+                    let len = self.open.len();
+                    if self.current_name() == "option"
+                        && len >= 2
+                        && self.name_of(self.open[len - 2]) == "optgroup"
+                    {
+                        self.pop();
+                    }
+                    if self.current_name() == "optgroup" {
+                        self.pop();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "option" => {
+                    if self.current_name() == "option" {
+                        self.pop();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "select" => {
+                    if self.in_scope("select", Scope::Select) {
+                        self.pop_until("select");
+                        self.reset_insertion_mode();
+                    } else {
+                        self.error("unexpected-end-tag");
+                    }
+                }
+                "template" => self.process_in(InsertionMode::InHead, Token::EndTag { name }),
+                _ => self.error("unexpected-end-tag-in-select"),
+            },
+            Token::Eof => self.in_body(Token::Eof),
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token) {
+        let table_tag = |name: &str| {
+            matches!(
+                name,
+                "caption" | "table" | "tbody" | "tfoot" | "thead" | "tr" | "td" | "th"
+            )
+        };
+        match &token {
+            Token::StartTag { name, .. } if table_tag(name) => {
+                self.error("table-tag-in-select");
+                self.pop_until("select");
+                self.reset_insertion_mode();
+                self.process(token);
+            }
+            Token::EndTag { name } if table_tag(name) => {
+                self.error("table-tag-in-select");
+                if self.in_scope(name, Scope::Table) {
+                    self.pop_until("select");
+                    self.reset_insertion_mode();
+                    self.process(token);
+                }
+            }
+            _ => self.in_select(token),
+        }
+    }
+
+    fn in_frameset(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let ws: String = text.chars().filter(|c| is_whitespace(*c)).collect();
+                self.insert_text(&ws);
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::StartTag { name, attrs, self_closing } => match name.as_str() {
+                "html" => self.process_in(InsertionMode::InBody, Token::StartTag { name, attrs, self_closing }),
+                "frameset" => {
+                    self.insert_element(&name, attrs);
+                }
+                "frame" => {
+                    self.insert_element(&name, attrs);
+                    self.pop();
+                }
+                "noframes" => self.process_in(InsertionMode::InHead, Token::StartTag { name, attrs, self_closing }),
+                _ => self.error("unexpected-start-tag-in-frameset"),
+            },
+            Token::EndTag { name } if name == "frameset" => {
+                if self.current_name() == "html" {
+                    self.error("unexpected-end-tag");
+                    return;
+                }
+                self.pop();
+                if self.current_name() != "frameset" {
+                    self.mode = InsertionMode::AfterFrameset;
+                }
+            }
+            Token::Eof => self.stopped = true,
+            _ => self.error("unexpected-token-in-frameset"),
+        }
+    }
+
+    fn after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Characters(text) => {
+                let ws: String = text.chars().filter(|c| is_whitespace(*c)).collect();
+                self.insert_text(&ws);
+            }
+            Token::Comment(text) => self.insert_comment(text),
+            Token::EndTag { ref name } if name == "html" => self.mode = InsertionMode::AfterAfterFrameset,
+            Token::StartTag { ref name, .. } if name == "noframes" => self.process_in(InsertionMode::InHead, token),
+            Token::Eof => self.stopped = true,
+            _ => self.error("unexpected-token-after-frameset"),
+        }
+    }
+
+    fn after_body(&mut self, token: Token) {
+        match token {
+            Token::Characters(ref text) if text.chars().all(is_whitespace) => {
+                self.process_in(InsertionMode::InBody, token)
+            }
+            Token::Comment(text) => {
+                let html = self.open.first().copied().unwrap_or(self.doc.root());
+                self.append_comment_to(html, text);
+            }
+            Token::Doctype { .. } => self.error("unexpected-doctype"),
+            Token::StartTag { ref name, .. } if name == "html" => self.process_in(InsertionMode::InBody, token),
+            Token::EndTag { ref name } if name == "html" => self.mode = InsertionMode::AfterAfterBody,
+            Token::Eof => self.stopped = true,
+            other => {
+                self.error("content-after-body");
+                self.mode = InsertionMode::InBody;
+                self.process(other);
+            }
+        }
+    }
+
+    fn after_after_body(&mut self, token: Token) {
+        match token {
+            Token::Comment(text) => {
+                let root = self.doc.root();
+                self.append_comment_to(root, text);
+            }
+            Token::Doctype { .. } => self.process_in(InsertionMode::InBody, token),
+            Token::Characters(ref text) if text.chars().all(is_whitespace) => {
+                self.process_in(InsertionMode::InBody, token)
+            }
+            Token::StartTag { ref name, .. } if name == "html" => self.process_in(InsertionMode::InBody, token),
+            Token::Eof => self.stopped = true,
+            other => {
+                self.error("content-after-html");
+                self.mode = InsertionMode::InBody;
+                self.process(other);
+            }
+        }
+    }
+
+    /// "in template": the contents of `<template>` are parsed as if they
+    /// were in whatever context their first element implies.
+    fn in_template(&mut self, token: Token) {
+        match token {
+            Token::Characters(_) | Token::Comment(_) | Token::Doctype { .. } => {
+                self.process_in(InsertionMode::InBody, token)
+            }
+            Token::StartTag { ref name, .. }
+                if matches!(
+                    name.as_str(),
+                    "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style" | "template" | "title"
+                ) =>
+            {
+                self.process_in(InsertionMode::InHead, token)
+            }
+            Token::EndTag { ref name } if name == "template" => self.process_in(InsertionMode::InHead, token),
+            Token::StartTag { ref name, .. } => {
+                // 最初の要素から、テンプレートの中身がどの文脈のものかを決める
+                let mode = match name.as_str() {
+                    "caption" | "colgroup" | "tbody" | "tfoot" | "thead" => InsertionMode::InTable,
+                    "col" => InsertionMode::InColumnGroup,
+                    "tr" => InsertionMode::InTableBody,
+                    "td" | "th" => InsertionMode::InRow,
+                    _ => InsertionMode::InBody,
+                };
+                self.template_modes.pop();
+                self.template_modes.push(mode);
+                self.mode = mode;
+                self.process(token);
+            }
+            Token::EndTag { .. } => self.error("unexpected-end-tag"),
+            Token::Eof => {
+                if !self.open.iter().any(|n| self.name_of(*n) == "template") {
+                    self.stopped = true;
+                    return;
+                }
+                self.error("eof-in-template");
+                self.pop_until("template");
+                self.clear_formatting_to_marker();
+                self.template_modes.pop();
+                self.reset_insertion_mode();
+                self.process(Token::Eof);
+            }
+        }
+    }
+
+    fn after_after_frameset(&mut self, token: Token) {
+        match token {
+            Token::Comment(text) => {
+                let root = self.doc.root();
+                self.append_comment_to(root, text);
+            }
+            Token::StartTag { ref name, .. } if name == "noframes" => self.process_in(InsertionMode::InHead, token),
+            Token::Eof => self.stopped = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Element names below `id` as `name(child child ...)`, skipping text.
+    fn outline(doc: &Document, id: NodeId) -> String {
+        let children: Vec<String> = doc
+            .children(id)
+            .iter()
+            .filter(|c| doc.element(**c).is_some())
+            .map(|c| outline(doc, *c))
+            .collect();
+        let name = doc.tag_name(id).unwrap_or("#document");
+        if children.is_empty() { name.to_string() } else { format!("{}({})", name, children.join(" ")) }
+    }
+
+    #[test]
+    fn template_in_head_keeps_body_out_of_head() {
+        let doc = parse_document("<head><template><div>t</div></template></head><body><p>x", None);
+        assert_eq!(outline(&doc, doc.root()), "#document(html(head(template(div)) body(p)))");
+        let body = doc.body().expect("body");
+        assert_eq!(doc.text_content(body), "x");
+    }
+
+    #[test]
+    fn template_contents_are_inert() {
+        let doc = parse_document("<body><template><img src=a.png><title>no</title></template><title>yes</title>", None);
+        assert_eq!(doc.elements_by_tag_name("img").len(), 0);
+        assert_eq!(doc.title().as_deref(), Some("yes"));
+        let template = doc.elements_by_tag_name("template")[0];
+        assert_eq!(doc.descendants(template).len(), 3);
+    }
+
+    #[test]
+    fn template_table_contents_use_table_modes() {
+        let doc = parse_document("<template><tr><td>a</td></tr></template><p>b", None);
+        assert_eq!(outline(&doc, doc.root()), "#document(html(head(template(tr(td))) body(p)))");
+    }
+
+    #[test]
+    fn self_closing_svg_children_are_siblings() {
+        let doc = parse_document("<body><svg><path/><rect/></svg><p>after", None);
+        assert_eq!(outline(&doc, doc.root()), "#document(html(head body(svg(path rect) p)))");
+        let rect = doc.elements_by_tag_name("rect")[0];
+        assert_eq!(doc.element(rect).map(|e| e.namespace), Some(Namespace::Svg));
+    }
+
+    #[test]
+    fn svg_names_keep_their_case() {
+        let doc = parse_document("<svg viewbox='0 0 1 1'><clippath/><foreignobject><div>x</div></foreignobject></svg>", None);
+        assert_eq!(outline(&doc, doc.root()), "#document(html(head body(svg(clipPath foreignObject(div)))))");
+        let svg = doc.elements_by_tag_name("svg")[0];
+        assert_eq!(doc.element(svg).and_then(|e| e.attr("viewBox")), Some("0 0 1 1"));
+        let div = doc.elements_by_tag_name("div")[0];
+        assert_eq!(doc.element(div).map(|e| e.namespace), Some(Namespace::Html));
+    }
+
+    #[test]
+    fn html_start_tag_breaks_out_of_svg() {
+        let doc = parse_document("<svg><g><p>x</p></svg><math><mi/><mo>+</mo></math>", None);
+        assert_eq!(outline(&doc, doc.root()), "#document(html(head body(svg(g) p math(mi mo))))");
+    }
+
+    #[test]
+    fn svg_title_is_not_the_document_title() {
+        let doc = parse_document("<svg><title>icon</title></svg><title>page</title>", None);
+        assert_eq!(doc.title().as_deref(), Some("page"));
+    }
+
+    #[test]
+    fn raw_text_ending_in_a_less_than_sign_terminates() {
+        let doc = parse_document("<title><", None);
+        assert_eq!(doc.title().as_deref(), Some("<"));
+        let doc = parse_document("<script></scr", None);
+        let script = doc.elements_by_tag_name("script")[0];
+        assert_eq!(doc.text_content(script), "</scr");
+        let doc = parse_document("<textarea></", None);
+        let textarea = doc.elements_by_tag_name("textarea")[0];
+        assert_eq!(doc.text_content(textarea), "</");
+    }
+}
@@ -0,0 +1,1040 @@
+//! HTML tokenizer following the state machine of the WHATWG HTML standard
+//! (section 13.2.5). The tree builder in `html_parser` drives it one token at a
+//! time and switches it into RCDATA/RAWTEXT/script/PLAINTEXT as needed.
+
+use std::collections::VecDeque;
+
+use crate::dom::Attribute;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Token {
+    Doctype {
+        name: Option<String>,
+        public_id: Option<String>,
+        system_id: Option<String>,
+        force_quirks: bool,
+    },
+    StartTag {
+        name: String,
+        attrs: Vec<Attribute>,
+        self_closing: bool,
+    },
+    EndTag {
+        name: String,
+    },
+    Comment(String),
+    /// A run of character data. Consecutive characters are merged.
+    Characters(String),
+    Eof,
+}
+
+/// Tokenizer states the tree builder is allowed to switch into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextState {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Data,
+    Rcdata,
+    Rawtext,
+    ScriptData,
+    Plaintext,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    /// `<` seen inside RCDATA/RAWTEXT/script data.
+    RawLessThan(TextState),
+    RawEndTagOpen(TextState),
+    RawEndTagName(TextState),
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    BeforeDoctypeName,
+    DoctypeName,
+    AfterDoctypeName,
+    BeforeDoctypePublicIdentifier,
+    DoctypePublicIdentifierDoubleQuoted,
+    DoctypePublicIdentifierSingleQuoted,
+    AfterDoctypePublicIdentifier,
+    BeforeDoctypeSystemIdentifier,
+    DoctypeSystemIdentifierDoubleQuoted,
+    DoctypeSystemIdentifierSingleQuoted,
+    AfterDoctypeSystemIdentifier,
+    BogusDoctype,
+    CdataSection,
+}
+
+#[derive(Default)]
+struct TagBuilder {
+    name: String,
+    is_end: bool,
+    self_closing: bool,
+    attrs: Vec<Attribute>,
+    attr_name: String,
+    attr_value: String,
+    in_attr: bool,
+}
+
+#[derive(Default)]
+struct DoctypeBuilder {
+    name: Option<String>,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    force_quirks: bool,
+}
+
+pub struct Tokenizer {
+    input: Vec<char>,
+    pos: usize,
+    state: State,
+    tag: TagBuilder,
+    doctype: DoctypeBuilder,
+    comment: String,
+    /// Buffer for the `</name` we are checking against the last start tag.
+    temp: String,
+    last_start_tag: String,
+    chars: String,
+    pending: VecDeque<Token>,
+    eof_emitted: bool,
+    pub errors: Vec<String>,
+}
+
+impl Tokenizer {
+    pub fn new(input: &str) -> Self {
+        // 13.2.3.5: CRLF と単独の CR は LF に正規化する
+        let normalized = input.replace("\r\n", "\n").replace('\r', "\n");
+        Self {
+            input: normalized.chars().collect(),
+            pos: 0,
+            state: State::Data,
+            tag: TagBuilder::default(),
+            doctype: DoctypeBuilder::default(),
+            comment: String::new(),
+            temp: String::new(),
+            last_start_tag: String::new(),
+            chars: String::new(),
+            pending: VecDeque::new(),
+            eof_emitted: false,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Called by the tree builder after inserting `<title>`, `<style>`, ...
+    pub fn switch_to(&mut self, state: TextState) {
+        self.state = match state {
+            TextState::Data => State::Data,
+            TextState::Rcdata => State::Rcdata,
+            TextState::Rawtext => State::Rawtext,
+            TextState::ScriptData => State::ScriptData,
+            TextState::Plaintext => State::Plaintext,
+        };
+    }
+
+    pub fn next_token(&mut self) -> Token {
+        loop {
+            if let Some(token) = self.pending.pop_front() {
+                return token;
+            }
+            if self.eof_emitted {
+                return Token::Eof;
+            }
+            self.step();
+        }
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors.push(format!("{} at offset {}", message, self.pos));
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        let c = self.input.get(self.pos).copied();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn reconsume(&mut self, state: State) {
+        self.pos -= 1;
+        self.state = state;
+    }
+
+    fn lookahead_matches(&self, text: &str, case_insensitive: bool) -> bool {
+        text.chars().enumerate().all(|(offset, expected)| match self.input.get(self.pos + offset) {
+            Some(c) => *c == expected || (case_insensitive && c.eq_ignore_ascii_case(&expected)),
+            None => false,
+        })
+    }
+
+    fn emit_char(&mut self, c: char) {
+        self.chars.push(c);
+    }
+
+    fn emit_str(&mut self, s: &str) {
+        self.chars.push_str(s);
+    }
+
+    fn flush_chars(&mut self) {
+        if !self.chars.is_empty() {
+            let text = std::mem::take(&mut self.chars);
+            self.pending.push_back(Token::Characters(text));
+        }
+    }
+
+    fn emit(&mut self, token: Token) {
+        self.flush_chars();
+        self.pending.push_back(token);
+    }
+
+    fn emit_eof(&mut self) {
+        self.flush_chars();
+        self.pending.push_back(Token::Eof);
+        self.eof_emitted = true;
+    }
+
+    fn start_tag(&mut self, is_end: bool) {
+        self.tag = TagBuilder {
+            is_end,
+            ..TagBuilder::default()
+        };
+    }
+
+    fn start_attr(&mut self) {
+        self.finish_attr();
+        self.tag.in_attr = true;
+    }
+
+    fn finish_attr(&mut self) {
+        if !self.tag.in_attr {
+            return;
+        }
+        self.tag.in_attr = false;
+        let name = std::mem::take(&mut self.tag.attr_name);
+        let value = std::mem::take(&mut self.tag.attr_value);
+        if self.tag.attrs.iter().any(|a| a.name == name) {
+            self.error("duplicate-attribute");
+        } else {
+            self.tag.attrs.push(Attribute { name, value });
+        }
+    }
+
+    fn emit_tag(&mut self) {
+        self.finish_attr();
+        let tag = std::mem::take(&mut self.tag);
+        if tag.is_end {
+            if !tag.attrs.is_empty() {
+                self.error("end-tag-with-attributes");
+            }
+            self.emit(Token::EndTag { name: tag.name });
+        } else {
+            self.last_start_tag = tag.name.clone();
+            self.emit(Token::StartTag {
+                name: tag.name,
+                attrs: tag.attrs,
+                self_closing: tag.self_closing,
+            });
+        }
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        self.emit(Token::Comment(comment));
+    }
+
+    fn emit_doctype(&mut self) {
+        let d = std::mem::take(&mut self.doctype);
+        self.emit(Token::Doctype {
+            name: d.name,
+            public_id: d.public_id,
+            system_id: d.system_id,
+            force_quirks: d.force_quirks,
+        });
+    }
+
+    fn is_appropriate_end_tag(&self) -> bool {
+        !self.last_start_tag.is_empty() && self.tag.name == self.last_start_tag
+    }
+
+    fn step(&mut self) {
+        let c = self.consume();
+        match self.state {
+            State::Data => match c {
+                Some('&') => {
+                    let decoded = self.consume_char_ref(false);
+                    self.emit_str(&decoded);
+                }
+                Some('<') => self.state = State::TagOpen,
+                Some('\0') => self.error("unexpected-null-character"),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rcdata => match c {
+                Some('&') => {
+                    let decoded = self.consume_char_ref(false);
+                    self.emit_str(&decoded);
+                }
+                Some('<') => self.state = State::RawLessThan(TextState::Rcdata),
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::Rawtext | State::ScriptData => {
+                let text_state = if self.state == State::Rawtext {
+                    TextState::Rawtext
+                } else {
+                    TextState::ScriptData
+                };
+                match c {
+                    Some('<') => self.state = State::RawLessThan(text_state),
+                    Some('\0') => self.emit_char('\u{FFFD}'),
+                    Some(c) => self.emit_char(c),
+                    None => self.emit_eof(),
+                }
+            }
+            State::Plaintext => match c {
+                Some('\0') => self.emit_char('\u{FFFD}'),
+                Some(c) => self.emit_char(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(false);
+                    self.reconsume(State::TagName);
+                }
+                Some('?') => {
+                    self.error("unexpected-question-mark-instead-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(State::BogusComment);
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.emit_char('<');
+                    self.reconsume(State::Data);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_char('<');
+                    self.emit_eof();
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(true);
+                    self.reconsume(State::TagName);
+                }
+                Some('>') => {
+                    self.error("missing-end-tag-name");
+                    self.state = State::Data;
+                }
+                Some(_) => {
+                    self.error("invalid-first-character-of-tag-name");
+                    self.comment.clear();
+                    self.reconsume(State::BogusComment);
+                }
+                None => {
+                    self.error("eof-before-tag-name");
+                    self.emit_str("</");
+                    self.emit_eof();
+                }
+            },
+            State::TagName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.tag.name.push('\u{FFFD}'),
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::RawLessThan(text_state) => match c {
+                Some('/') => {
+                    self.temp.clear();
+                    self.state = State::RawEndTagOpen(text_state);
+                }
+                _ => {
+                    self.emit_char('<');
+                    self.reconsume_or_eof(Self::text_state(text_state), c);
+                }
+            },
+            State::RawEndTagOpen(text_state) => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(true);
+                    self.reconsume(State::RawEndTagName(text_state));
+                }
+                _ => {
+                    self.emit_str("</");
+                    self.reconsume_or_eof(Self::text_state(text_state), c);
+                }
+            },
+            State::RawEndTagName(text_state) => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') if self.is_appropriate_end_tag() => {
+                    self.state = State::BeforeAttributeName;
+                }
+                Some('/') if self.is_appropriate_end_tag() => {
+                    self.state = State::SelfClosingStartTag;
+                }
+                Some('>') if self.is_appropriate_end_tag() => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.tag.name.push(c.to_ascii_lowercase());
+                    self.temp.push(c);
+                }
+                _ => {
+                    let temp = std::mem::take(&mut self.temp);
+                    self.emit_str("</");
+                    self.emit_str(&temp);
+                    self.reconsume_or_eof(Self::text_state(text_state), c);
+                }
+            },
+            State::BeforeAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/' | '>') | None => self.reconsume_or_eof(State::AfterAttributeName, c),
+                Some('=') => {
+                    self.error("unexpected-equals-sign-before-attribute-name");
+                    self.start_attr();
+                    self.tag.attr_name.push('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attr();
+                    self.reconsume(State::AttributeName);
+                }
+            },
+            State::AttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ' | '/' | '>') | None => {
+                    self.reconsume_or_eof(State::AfterAttributeName, c)
+                }
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => self.tag.attr_name.push('\u{FFFD}'),
+                Some(c) => {
+                    if matches!(c, '"' | '\'' | '<') {
+                        self.error("unexpected-character-in-attribute-name");
+                    }
+                    self.tag.attr_name.push(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.start_attr();
+                    self.reconsume(State::AttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error("missing-attribute-value");
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                _ => self.reconsume_or_eof(State::AttributeValueUnquoted, c),
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = if self.state == State::AttributeValueDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(q) if q == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => {
+                        let decoded = self.consume_char_ref(true);
+                        self.tag.attr_value.push_str(&decoded);
+                    }
+                    Some('\0') => self.tag.attr_value.push('\u{FFFD}'),
+                    Some(c) => self.tag.attr_value.push(c),
+                    None => {
+                        self.error("eof-in-tag");
+                        self.emit_eof();
+                    }
+                }
+            }
+            State::AttributeValueUnquoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('&') => {
+                    let decoded = self.consume_char_ref(true);
+                    self.tag.attr_value.push_str(&decoded);
+                }
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some('\0') => self.tag.attr_value.push('\u{FFFD}'),
+                Some(c) => self.tag.attr_value.push(c),
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.error("missing-whitespace-between-attributes");
+                    self.reconsume(State::BeforeAttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.state = State::Data;
+                    self.emit_tag();
+                }
+                Some(_) => {
+                    self.error("unexpected-solidus-in-tag");
+                    self.reconsume(State::BeforeAttributeName);
+                }
+                None => {
+                    self.error("eof-in-tag");
+                    self.emit_eof();
+                }
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('\0') => self.comment.push('\u{FFFD}'),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => {
+                // consume() 済みの 1 文字を戻してから先読みする
+                self.pos -= usize::from(c.is_some());
+                if self.lookahead_matches("--", false) {
+                    self.pos += 2;
+                    self.comment.clear();
+                    self.state = State::CommentStart;
+                } else if self.lookahead_matches("DOCTYPE", true) {
+                    self.pos += 7;
+                    self.state = State::Doctype;
+                } else if self.lookahead_matches("[CDATA[", false) {
+                    // HTML 名前空間では CDATA は bogus comment 扱い
+                    self.error("cdata-in-html-content");
+                    self.comment.clear();
+                    self.comment.push_str("[CDATA[");
+                    self.pos += 7;
+                    self.state = State::CdataSection;
+                } else {
+                    self.error("incorrectly-opened-comment");
+                    self.comment.clear();
+                    self.state = State::BogusComment;
+                }
+            }
+            State::CdataSection => {
+                self.pos -= usize::from(c.is_some());
+                if self.lookahead_matches("]]>", false) {
+                    self.pos += 3;
+                    self.comment.push_str("]]");
+                    self.state = State::Data;
+                    self.emit_comment();
+                } else {
+                    match self.consume() {
+                        Some(c) => self.comment.push(c),
+                        None => {
+                            self.emit_comment();
+                            self.emit_eof();
+                        }
+                    }
+                }
+            }
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => self.reconsume_or_eof(State::Comment, c),
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt-closing-of-empty-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::Comment => match c {
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => self.comment.push('\u{FFFD}'),
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly-closed-comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume(State::Comment);
+                }
+                None => {
+                    self.error("eof-in-comment");
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::Doctype => {
+                self.doctype = DoctypeBuilder::default();
+                match c {
+                    Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeDoctypeName,
+                    Some('>') => self.reconsume(State::BeforeDoctypeName),
+                    Some(_) => {
+                        self.error("missing-whitespace-before-doctype-name");
+                        self.reconsume(State::BeforeDoctypeName);
+                    }
+                    None => {
+                        self.error("eof-in-doctype");
+                        self.doctype.force_quirks = true;
+                        self.emit_doctype();
+                        self.emit_eof();
+                    }
+                }
+            }
+            State::BeforeDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.error("missing-doctype-name");
+                    self.doctype.force_quirks = true;
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(c) => {
+                    let c = if c == '\0' { '\u{FFFD}' } else { c.to_ascii_lowercase() };
+                    self.doctype.name = Some(c.to_string());
+                    self.state = State::DoctypeName;
+                }
+                None => {
+                    self.error("eof-in-doctype");
+                    self.doctype.force_quirks = true;
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::DoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::AfterDoctypeName,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(c) => {
+                    let c = if c == '\0' { '\u{FFFD}' } else { c.to_ascii_lowercase() };
+                    self.doctype.name.get_or_insert_with(String::new).push(c);
+                }
+                None => {
+                    self.error("eof-in-doctype");
+                    self.doctype.force_quirks = true;
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+            },
+            State::AfterDoctypeName => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => {
+                    self.error("eof-in-doctype");
+                    self.doctype.force_quirks = true;
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.pos -= 1;
+                    if self.lookahead_matches("PUBLIC", true) {
+                        self.pos += 6;
+                        self.state = State::BeforeDoctypePublicIdentifier;
+                    } else if self.lookahead_matches("SYSTEM", true) {
+                        self.pos += 6;
+                        self.state = State::BeforeDoctypeSystemIdentifier;
+                    } else {
+                        self.error("invalid-character-sequence-after-doctype-name");
+                        self.doctype.force_quirks = true;
+                        self.state = State::BogusDoctype;
+                    }
+                }
+            },
+            State::BeforeDoctypePublicIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('"') => {
+                    self.doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.doctype.public_id = Some(String::new());
+                    self.state = State::DoctypePublicIdentifierSingleQuoted;
+                }
+                _ => self.bogus_doctype(c),
+            },
+            State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypePublicIdentifierDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(q) if q == quote => self.state = State::AfterDoctypePublicIdentifier,
+                    Some('>') => {
+                        self.error("abrupt-doctype-public-identifier");
+                        self.doctype.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    }
+                    Some(c) => self.doctype.public_id.get_or_insert_with(String::new).push(c),
+                    None => self.bogus_doctype(None),
+                }
+            }
+            State::AfterDoctypePublicIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => self.state = State::BeforeDoctypeSystemIdentifier,
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                _ => self.bogus_doctype(c),
+            },
+            State::BeforeDoctypeSystemIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') if self.doctype.public_id.is_some() => {
+                    // "between public and system identifiers" で '>' が来た場合
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some('"') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierDoubleQuoted;
+                }
+                Some('\'') => {
+                    self.doctype.system_id = Some(String::new());
+                    self.state = State::DoctypeSystemIdentifierSingleQuoted;
+                }
+                _ => self.bogus_doctype(c),
+            },
+            State::DoctypeSystemIdentifierDoubleQuoted | State::DoctypeSystemIdentifierSingleQuoted => {
+                let quote = if self.state == State::DoctypeSystemIdentifierDoubleQuoted { '"' } else { '\'' };
+                match c {
+                    Some(q) if q == quote => self.state = State::AfterDoctypeSystemIdentifier,
+                    Some('>') => {
+                        self.error("abrupt-doctype-system-identifier");
+                        self.doctype.force_quirks = true;
+                        self.state = State::Data;
+                        self.emit_doctype();
+                    }
+                    Some(c) => self.doctype.system_id.get_or_insert_with(String::new).push(c),
+                    None => self.bogus_doctype(None),
+                }
+            }
+            State::AfterDoctypeSystemIdentifier => match c {
+                Some('\t' | '\n' | '\x0C' | ' ') => {}
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                None => self.bogus_doctype(None),
+                Some(_) => {
+                    // force-quirks は立てない (仕様どおり)
+                    self.error("unexpected-character-after-doctype-system-identifier");
+                    self.state = State::BogusDoctype;
+                }
+            },
+            State::BogusDoctype => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_doctype();
+                }
+                Some(_) => {}
+                None => {
+                    self.emit_doctype();
+                    self.emit_eof();
+                }
+            },
+        }
+    }
+
+    fn text_state(state: TextState) -> State {
+        match state {
+            TextState::Data => State::Data,
+            TextState::Rcdata => State::Rcdata,
+            TextState::Rawtext => State::Rawtext,
+            TextState::ScriptData => State::ScriptData,
+            TextState::Plaintext => State::Plaintext,
+        }
+    }
+
+    fn reconsume_or_eof(&mut self, state: State, c: Option<char>) {
+        if c.is_some() {
+            self.reconsume(state);
+        } else {
+            self.state = state;
+        }
+    }
+
+    /// Missing quote / unexpected character in a DOCTYPE: force quirks and
+    /// skip to the closing `>`.
+    fn bogus_doctype(&mut self, c: Option<char>) {
+        self.doctype.force_quirks = true;
+        match c {
+            Some('>') => {
+                self.error("missing-doctype-identifier");
+                self.state = State::Data;
+                self.emit_doctype();
+            }
+            Some(_) => {
+                self.error("missing-quote-before-doctype-identifier");
+                self.state = State::BogusDoctype;
+            }
+            None => {
+                self.error("eof-in-doctype");
+                self.emit_doctype();
+                self.emit_eof();
+            }
+        }
+    }
+
+    /// Consumes a character reference after `&` and returns the replacement
+    /// text. When nothing matches, `&` is returned unchanged.
+    fn consume_char_ref(&mut self, in_attribute: bool) -> String {
+        match self.peek() {
+            Some('#') => {
+                let start = self.pos;
+                self.pos += 1;
+                let hex = matches!(self.peek(), Some('x' | 'X'));
+                if hex {
+                    self.pos += 1;
+                }
+                let digits_start = self.pos;
+                let radix = if hex { 16 } else { 10 };
+                while self.peek().is_some_and(|c| c.is_digit(radix)) {
+                    self.pos += 1;
+                }
+                if self.pos == digits_start {
+                    self.error("absence-of-digits-in-numeric-character-reference");
+                    self.pos = start;
+                    return "&".to_string();
+                }
+                let digits: String = self.input[digits_start..self.pos].iter().collect();
+                if self.peek() == Some(';') {
+                    self.pos += 1;
+                } else {
+                    self.error("missing-semicolon-after-character-reference");
+                }
+                let code = u32::from_str_radix(&digits, radix).unwrap_or(0x110000);
+                numeric_char_ref(code).to_string()
+            }
+            Some(c) if c.is_ascii_alphanumeric() => {
+                // 名前付き文字参照は最長一致
+                let mut best: Option<(usize, &str)> = None;
+                for (name, value) in NAMED_CHAR_REFS {
+                    if self.lookahead_matches(name, false)
+                        && best.is_none_or(|(len, _)| name.len() > len)
+                    {
+                        best = Some((name.len(), value));
+                    }
+                }
+                let Some((len, value)) = best else {
+                    return "&".to_string();
+                };
+                let ends_with_semicolon = self.input.get(self.pos + len - 1) == Some(&';');
+                if in_attribute && !ends_with_semicolon {
+                    let next = self.input.get(self.pos + len).copied();
+                    if next.is_some_and(|n| n == '=' || n.is_ascii_alphanumeric()) {
+                        return "&".to_string();
+                    }
+                }
+                if !ends_with_semicolon {
+                    self.error("missing-semicolon-after-character-reference");
+                }
+                self.pos += len;
+                value.to_string()
+            }
+            _ => "&".to_string(),
+        }
+    }
+}
+
+/// 13.2.5.80 numeric character reference end state.
+fn numeric_char_ref(code: u32) -> char {
+    match code {
+        0 => '\u{FFFD}',
+        0x80 => '\u{20AC}',
+        0x82 => '\u{201A}',
+        0x83 => '\u{0192}',
+        0x84 => '\u{201E}',
+        0x85 => '\u{2026}',
+        0x86 => '\u{2020}',
+        0x87 => '\u{2021}',
+        0x88 => '\u{02C6}',
+        0x89 => '\u{2030}',
+        0x8A => '\u{0160}',
+        0x8B => '\u{2039}',
+        0x8C => '\u{0152}',
+        0x8E => '\u{017D}',
+        0x91 => '\u{2018}',
+        0x92 => '\u{2019}',
+        0x93 => '\u{201C}',
+        0x94 => '\u{201D}',
+        0x95 => '\u{2022}',
+        0x96 => '\u{2013}',
+        0x97 => '\u{2014}',
+        0x98 => '\u{02DC}',
+        0x99 => '\u{2122}',
+        0x9A => '\u{0161}',
+        0x9B => '\u{203A}',
+        0x9C => '\u{0153}',
+        0x9E => '\u{017E}',
+        0x9F => '\u{0178}',
+        _ => char::from_u32(code).unwrap_or('\u{FFFD}'),
+    }
+}
+
+/// Named character references. Entries without a trailing `;` are the legacy
+/// forms that browsers still accept.
+static NAMED_CHAR_REFS: &[(&str, &str)] = &[
+    ("amp;", "&"), ("amp", "&"), ("AMP;", "&"), ("AMP", "&"),
+    ("lt;", "<"), ("lt", "<"), ("LT;", "<"), ("LT", "<"),
+    ("gt;", ">"), ("gt", ">"), ("GT;", ">"), ("GT", ">"),
+    ("quot;", "\""), ("quot", "\""), ("QUOT;", "\""), ("QUOT", "\""),
+    ("apos;", "'"),
+    ("nbsp;", "\u{A0}"), ("nbsp", "\u{A0}"),
+    ("copy;", "\u{A9}"), ("copy", "\u{A9}"), ("COPY;", "\u{A9}"),
+    ("reg;", "\u{AE}"), ("reg", "\u{AE}"), ("REG;", "\u{AE}"),
+    ("trade;", "\u{2122}"),
+    ("iexcl;", "\u{A1}"), ("cent;", "\u{A2}"), ("pound;", "\u{A3}"), ("curren;", "\u{A4}"),
+    ("yen;", "\u{A5}"), ("yen", "\u{A5}"), ("brvbar;", "\u{A6}"), ("sect;", "\u{A7}"),
+    ("uml;", "\u{A8}"), ("ordf;", "\u{AA}"), ("laquo;", "\u{AB}"), ("laquo", "\u{AB}"),
+    ("not;", "\u{AC}"), ("not", "\u{AC}"), ("notin;", "\u{2209}"), ("shy;", "\u{AD}"), ("macr;", "\u{AF}"), ("deg;", "\u{B0}"),
+    ("plusmn;", "\u{B1}"), ("sup2;", "\u{B2}"), ("sup3;", "\u{B3}"), ("acute;", "\u{B4}"),
+    ("micro;", "\u{B5}"), ("para;", "\u{B6}"), ("middot;", "\u{B7}"), ("cedil;", "\u{B8}"),
+    ("sup1;", "\u{B9}"), ("ordm;", "\u{BA}"), ("raquo;", "\u{BB}"), ("raquo", "\u{BB}"),
+    ("frac14;", "\u{BC}"), ("frac12;", "\u{BD}"), ("frac34;", "\u{BE}"), ("iquest;", "\u{BF}"),
+    ("times;", "\u{D7}"), ("divide;", "\u{F7}"),
+    ("Agrave;", "\u{C0}"), ("Aacute;", "\u{C1}"), ("Auml;", "\u{C4}"), ("Ccedil;", "\u{C7}"),
+    ("Eacute;", "\u{C9}"), ("Ouml;", "\u{D6}"), ("Uuml;", "\u{DC}"), ("szlig;", "\u{DF}"),
+    ("agrave;", "\u{E0}"), ("aacute;", "\u{E1}"), ("auml;", "\u{E4}"), ("ccedil;", "\u{E7}"),
+    ("egrave;", "\u{E8}"), ("eacute;", "\u{E9}"), ("ecirc;", "\u{EA}"), ("iacute;", "\u{ED}"),
+    ("ntilde;", "\u{F1}"), ("oacute;", "\u{F3}"), ("ouml;", "\u{F6}"), ("uacute;", "\u{FA}"),
+    ("uuml;", "\u{FC}"),
+    ("ensp;", "\u{2002}"), ("emsp;", "\u{2003}"), ("thinsp;", "\u{2009}"),
+    ("zwnj;", "\u{200C}"), ("zwj;", "\u{200D}"),
+    ("ndash;", "\u{2013}"), ("mdash;", "\u{2014}"),
+    ("lsquo;", "\u{2018}"), ("rsquo;", "\u{2019}"), ("sbquo;", "\u{201A}"),
+    ("ldquo;", "\u{201C}"), ("rdquo;", "\u{201D}"), ("bdquo;", "\u{201E}"),
+    ("dagger;", "\u{2020}"), ("Dagger;", "\u{2021}"), ("bull;", "\u{2022}"),
+    ("hellip;", "\u{2026}"), ("permil;", "\u{2030}"), ("prime;", "\u{2032}"),
+    ("lsaquo;", "\u{2039}"), ("rsaquo;", "\u{203A}"), ("euro;", "\u{20AC}"),
+    ("larr;", "\u{2190}"), ("uarr;", "\u{2191}"), ("rarr;", "\u{2192}"), ("darr;", "\u{2193}"),
+    ("harr;", "\u{2194}"), ("lArr;", "\u{21D0}"), ("rArr;", "\u{21D2}"), ("hArr;", "\u{21D4}"),
+    ("minus;", "\u{2212}"), ("infin;", "\u{221E}"), ("ne;", "\u{2260}"), ("le;", "\u{2264}"),
+    ("ge;", "\u{2265}"), ("asymp;", "\u{2248}"), ("equiv;", "\u{2261}"),
+    ("alpha;", "\u{3B1}"), ("beta;", "\u{3B2}"), ("gamma;", "\u{3B3}"), ("delta;", "\u{3B4}"),
+    ("pi;", "\u{3C0}"), ("sigma;", "\u{3C3}"), ("omega;", "\u{3C9}"), ("mu;", "\u{3BC}"),
+    ("loz;", "\u{25CA}"), ("spades;", "\u{2660}"), ("clubs;", "\u{2663}"),
+    ("hearts;", "\u{2665}"), ("diams;", "\u{2666}"), ("star;", "\u{2606}"),
+    ("check;", "\u{2713}"), ("cross;", "\u{2717}"),
+];
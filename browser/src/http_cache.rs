@@ -0,0 +1,489 @@
+//! Private HTTP cache on disk (RFC 9111).
+//!
+//! Each response is kept as two files named after a hash of its URL:
+//! `<hash>.meta` (URL, status, time stored and the response headers) and
+//! `<hash>.body`. Freshness is worked out from the stored headers when the
+//! entry is looked up, so nothing derived is written to disk.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+
+/// Directory the browser keeps its cache in, relative to the working directory.
+pub const DEFAULT_DIR: &str = "http_cache";
+
+/// Upper bound for heuristic freshness (10% of the time since Last-Modified).
+const MAX_HEURISTIC_LIFETIME: u64 = 7 * 24 * 60 * 60;
+
+/// Headers that describe the connection rather than the response.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "set-cookie",
+];
+
+/// A stored response, without its body.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub url: String,
+    pub status: u16,
+    /// Unix seconds when the response was received (or last revalidated).
+    pub stored_at: u64,
+    pub headers: Vec<(String, String)>,
+    pub body_size: u64,
+}
+
+impl CacheEntry {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    pub fn header_map(&self) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                map.append(name, value);
+            }
+        }
+        map
+    }
+
+    fn cache_control(&self) -> CacheControl {
+        CacheControl::parse(self.headers.iter().filter(|(n, _)| n.eq_ignore_ascii_case("cache-control")).map(|(_, v)| v.as_str()))
+    }
+
+    /// How long the response stays fresh after `stored_at` (RFC 9111 4.2.1).
+    pub fn freshness_lifetime(&self) -> u64 {
+        let cache_control = self.cache_control();
+        if let Some(max_age) = cache_control.max_age {
+            return max_age;
+        }
+        let date = self.header("date").and_then(crate::http_client::parse_http_date).unwrap_or(self.stored_at);
+        if let Some(expires) = self.header("expires") {
+            // 解釈できない Expires は期限切れとして扱う
+            return crate::http_client::parse_http_date(expires).map_or(0, |e| e.saturating_sub(date));
+        }
+        match self.header("last-modified").and_then(crate::http_client::parse_http_date) {
+            Some(modified) if is_heuristically_cacheable(self.status) => {
+                (date.saturating_sub(modified) / 10).min(MAX_HEURISTIC_LIFETIME)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Seconds since the response was generated, counting its `Age` header.
+    pub fn age(&self, now: u64) -> u64 {
+        let initial = self.header("age").and_then(|a| a.trim().parse::<u64>().ok()).unwrap_or(0);
+        initial + now.saturating_sub(self.stored_at)
+    }
+
+    /// Whether the entry can be used without asking the server.
+    pub fn is_fresh(&self, now: u64) -> bool {
+        !self.cache_control().no_cache && self.age(now) < self.freshness_lifetime()
+    }
+
+    /// Whether a stale copy may be shown when the server can't be reached.
+    pub fn allows_stale(&self) -> bool {
+        !self.cache_control().must_revalidate
+    }
+
+    pub fn has_validators(&self) -> bool {
+        self.header("etag").is_some() || self.header("last-modified").is_some()
+    }
+}
+
+/// The Cache-Control directives a private cache cares about.
+#[derive(Debug, Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    must_revalidate: bool,
+    max_age: Option<u64>,
+}
+
+impl CacheControl {
+    fn parse<'a>(values: impl Iterator<Item = &'a str>) -> Self {
+        let mut cc = CacheControl::default();
+        for directive in values.flat_map(|v| v.split(',')) {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "must-revalidate" | "proxy-revalidate" => cc.must_revalidate = true,
+                "max-age" => cc.max_age = value.trim().trim_matches('"').parse().ok(),
+                _ => {}
+            }
+        }
+        cc
+    }
+}
+
+/// Statuses that may be cached without explicit freshness (RFC 9110 15.1).
+fn is_heuristically_cacheable(status: u16) -> bool {
+    matches!(status, 200 | 203 | 204 | 206 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// FNV-1a; stable across runs and Rust versions, unlike `DefaultHasher`.
+fn file_stem(url: &str) -> String {
+    let hash = url.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3));
+    format!("{:016x}", hash)
+}
+
+pub struct HttpCache {
+    dir: PathBuf,
+    /// Metadata of every entry on disk, by URL.
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl HttpCache {
+    /// Opens (creating if needed) the cache in `dir` and reads its index.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut entries = HashMap::new();
+        let listing = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        for path in listing.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().is_some_and(|x| x == "meta")
+                && let Some(entry) = std::fs::read_to_string(&path).ok().and_then(|t| parse_meta(&t))
+            {
+                entries.insert(entry.url.clone(), entry);
+            }
+        }
+        Ok(HttpCache {
+            dir: dir.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn lookup(&self, url: &str) -> Option<CacheEntry> {
+        self.entries.lock().unwrap().get(url).cloned()
+    }
+
+    pub fn body(&self, url: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.path(url, "body")).ok()
+    }
+
+    /// Stores a response if the headers allow it. Returns whether it was stored.
+    pub fn store(&self, url: &str, status: StatusCode, headers: &HeaderMap, body: &[u8]) -> bool {
+        let cache_control = CacheControl::parse(headers.get_all("cache-control").iter().filter_map(|v| v.to_str().ok()));
+        let vary = headers.get_all("vary").iter().filter_map(|v| v.to_str().ok()).collect::<Vec<_>>().join(",");
+        // クッキーやすべてで変わる応答は保存しない
+        let varies = vary.split(',').any(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "*" | "cookie"));
+        // 304 は本文のない応答なので、それ自体は保存しない
+        if cache_control.no_store || varies || status == StatusCode::NOT_MODIFIED {
+            self.remove(url);
+            return false;
+        }
+        let entry = CacheEntry {
+            url: url.to_string(),
+            status: status.as_u16(),
+            stored_at: now(),
+            headers: headers
+                .iter()
+                .filter(|(name, _)| !HOP_BY_HOP.contains(&name.as_str()))
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            body_size: body.len() as u64,
+        };
+        let explicit = cache_control.max_age.is_some() || entry.header("expires").is_some();
+        if !(explicit || is_heuristically_cacheable(entry.status)) || (entry.freshness_lifetime() == 0 && !entry.has_validators()) {
+            self.remove(url);
+            return false;
+        }
+        if let Err(e) = std::fs::write(self.path(url, "body"), body).and_then(|_| std::fs::write(self.path(url, "meta"), format_meta(&entry))) {
+            tracing::warn!("Failed to write cache entry for {}: {}", url, e);
+            return false;
+        }
+        self.entries.lock().unwrap().insert(url.to_string(), entry);
+        true
+    }
+
+    /// Merges the headers of a 304 response into the entry and restarts its
+    /// freshness clock (RFC 9111 4.3.4). Returns the updated entry.
+    pub fn refresh(&self, url: &str, headers: &HeaderMap) -> Option<CacheEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(url)?;
+        for (name, value) in headers {
+            let name = name.as_str();
+            if HOP_BY_HOP.contains(&name) || name == "content-length" {
+                continue;
+            }
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            entry.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+            entry.headers.push((name.to_string(), value.to_string()));
+        }
+        entry.stored_at = now();
+        let _ = std::fs::write(self.path(url, "meta"), format_meta(entry));
+        Some(entry.clone())
+    }
+
+    pub fn remove(&self, url: &str) {
+        if self.entries.lock().unwrap().remove(url).is_some() {
+            let _ = std::fs::remove_file(self.path(url, "meta"));
+            let _ = std::fs::remove_file(self.path(url, "body"));
+        }
+    }
+
+    /// Deletes every entry.
+    pub fn clear(&self) {
+        let urls: Vec<String> = self.entries.lock().unwrap().keys().cloned().collect();
+        for url in urls {
+            self.remove(&url);
+        }
+    }
+
+    /// All entries, sorted by URL, for the inspector.
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let mut entries: Vec<CacheEntry> = self.entries.lock().unwrap().values().cloned().collect();
+        entries.sort_by(|a, b| a.url.cmp(&b.url));
+        entries
+    }
+
+    fn path(&self, url: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", file_stem(url), extension))
+    }
+}
+
+pub fn is_fresh_now(entry: &CacheEntry) -> bool {
+    entry.is_fresh(now())
+}
+
+fn format_meta(entry: &CacheEntry) -> String {
+    let mut out = format!("{}\n{}\n{}\n{}\n", entry.url, entry.status, entry.stored_at, entry.body_size);
+    for (name, value) in &entry.headers {
+        out.push_str(&format!("{}: {}\n", name, value));
+    }
+    out
+}
+
+fn parse_meta(text: &str) -> Option<CacheEntry> {
+    let mut lines = text.lines();
+    let url = lines.next()?.to_string();
+    let status = lines.next()?.parse().ok()?;
+    let stored_at = lines.next()?.parse().ok()?;
+    let body_size = lines.next()?.parse().ok()?;
+    let headers = lines
+        .filter_map(|line| line.split_once(": "))
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    Some(CacheEntry {
+        url,
+        status,
+        stored_at,
+        headers,
+        body_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::cookies::RequestContext;
+    use crate::http_client::{Client, CurlConfig, ResponseSource};
+
+    // Sun, 06 Nov 1994 08:49:37 GMT
+    const DATE: u64 = 784_111_777;
+
+    fn stored(headers: &[(&str, &str)]) -> CacheEntry {
+        CacheEntry {
+            url: "http://example.com/".to_string(),
+            status: 200,
+            stored_at: DATE,
+            headers: headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+            body_size: 0,
+        }
+    }
+
+    fn header_map(headers: &[(&str, &str)]) -> HeaderMap {
+        stored(headers).header_map()
+    }
+
+    /// An empty cache in its own directory under the system temp directory.
+    fn temp_cache(name: &str) -> HttpCache {
+        let dir = std::env::temp_dir().join(format!("browser-http-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        HttpCache::open(&dir).expect("cache dir")
+    }
+
+    /// A local HTTP server answering each request with `respond(request)`,
+    /// where the request is its lowercased head. Returns its base URL and
+    /// the requests it has seen.
+    async fn fixture_server(respond: fn(&str) -> String) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let base = format!("http://{}/", listener.local_addr().expect("address"));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&head).to_ascii_lowercase();
+                let response = respond(&request);
+                log.lock().unwrap().push(request);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (base, seen)
+    }
+
+    fn ok(headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}",
+            body.len(),
+            headers,
+            body
+        )
+    }
+
+    fn not_modified(headers: &str) -> String {
+        format!("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n{}\r\n", headers)
+    }
+
+    fn subresource() -> RequestContext {
+        RequestContext {
+            initiator: None,
+            navigation: false,
+            reload: false,
+        }
+    }
+
+    #[test]
+    fn max_age_wins_over_expires() {
+        let entry = stored(&[("cache-control", "max-age=60"), ("date", "Sun, 06 Nov 1994 08:49:37 GMT"), ("expires", "Sun, 06 Nov 1994 09:49:37 GMT")]);
+        assert_eq!(entry.freshness_lifetime(), 60);
+        assert!(entry.is_fresh(DATE + 59));
+        assert!(!entry.is_fresh(DATE + 60));
+    }
+
+    #[test]
+    fn expires_counts_from_date_and_bad_expires_is_stale() {
+        let entry = stored(&[("date", "Sun, 06 Nov 1994 08:49:37 GMT"), ("expires", "Sun, 06 Nov 1994 08:59:37 GMT")]);
+        assert_eq!(entry.freshness_lifetime(), 600);
+        let bad = stored(&[("expires", "0")]);
+        assert_eq!(bad.freshness_lifetime(), 0);
+    }
+
+    #[test]
+    fn heuristic_freshness_is_a_tenth_of_the_time_since_last_modified() {
+        let entry = stored(&[("date", "Sun, 06 Nov 1994 08:49:37 GMT"), ("last-modified", "Sat, 05 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(entry.freshness_lifetime(), 8_640);
+        let old = stored(&[("date", "Sun, 06 Nov 1994 08:49:37 GMT"), ("last-modified", "Sun, 06 Nov 1904 08:49:37 GMT")]);
+        assert_eq!(old.freshness_lifetime(), MAX_HEURISTIC_LIFETIME);
+    }
+
+    #[test]
+    fn age_header_and_no_cache_make_entries_stale() {
+        let aged = stored(&[("cache-control", "max-age=60"), ("age", "50")]);
+        assert!(aged.is_fresh(DATE + 9));
+        assert!(!aged.is_fresh(DATE + 10));
+        let no_cache = stored(&[("cache-control", "no-cache, max-age=60")]);
+        assert!(!no_cache.is_fresh(DATE));
+    }
+
+    #[test]
+    fn no_store_is_not_stored_but_private_is() {
+        let cache = temp_cache("no-store");
+        let url = "http://example.com/a";
+        assert!(!cache.store(url, StatusCode::OK, &header_map(&[("cache-control", "no-store, max-age=60")]), b"a"));
+        assert!(cache.lookup(url).is_none());
+        // ブラウザのキャッシュは private なので private も保存してよい
+        assert!(cache.store(url, StatusCode::OK, &header_map(&[("cache-control", "private, max-age=60")]), b"a"));
+        assert_eq!(cache.body(url).as_deref(), Some(&b"a"[..]));
+        // 後から no-store で返ってきたら消す
+        assert!(!cache.store(url, StatusCode::OK, &header_map(&[("cache-control", "no-store")]), b"b"));
+        assert!(cache.lookup(url).is_none());
+    }
+
+    #[test]
+    fn refresh_merges_304_headers_and_restarts_the_clock() {
+        let cache = temp_cache("refresh");
+        let url = "http://example.com/r";
+        assert!(cache.store(url, StatusCode::OK, &header_map(&[("etag", "\"v1\""), ("x-version", "1")]), b"body"));
+        let entry = cache.refresh(url, &header_map(&[("x-version", "2"), ("content-length", "0")])).expect("entry");
+        assert_eq!(entry.header("x-version"), Some("2"));
+        assert_eq!(entry.header("etag"), Some("\"v1\""));
+        assert_eq!(entry.header("content-length"), None);
+        assert_eq!(cache.body(url).as_deref(), Some(&b"body"[..]));
+        assert!(cache.refresh("http://example.com/missing", &HeaderMap::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn fresh_entries_are_served_without_asking_the_server() {
+        let (base, seen) = fixture_server(|_| ok("Cache-Control: max-age=60\r\n", "hello")).await;
+        let client = Client::new(&CurlConfig::default()).expect("client").with_cache(temp_cache("fresh"));
+        let first = client.get(&base, &subresource(), None).await.expect("first");
+        assert_eq!(first.source, ResponseSource::Network);
+        let second = client.get(&base, &subresource(), None).await.expect("second");
+        assert_eq!(second.source, ResponseSource::Cache);
+        assert_eq!(second.body, b"hello");
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stale_entries_are_revalidated_with_etag_and_refreshed_by_304() {
+        let (base, seen) = fixture_server(|request| {
+            if request.contains("if-none-match: \"v1\"") {
+                not_modified("X-Checked: yes\r\n")
+            } else {
+                ok("Cache-Control: no-cache\r\nETag: \"v1\"\r\n", "hello")
+            }
+        })
+        .await;
+        let client = Client::new(&CurlConfig::default()).expect("client").with_cache(temp_cache("etag"));
+        client.get(&base, &subresource(), None).await.expect("first");
+        let second = client.get(&base, &subresource(), None).await.expect("second");
+        assert_eq!(second.source, ResponseSource::Revalidated);
+        assert_eq!(second.status, StatusCode::OK);
+        assert_eq!(second.body, b"hello");
+        assert_eq!(seen.lock().unwrap().len(), 2);
+        let entry = client.cache().and_then(|cache| cache.lookup(&base)).expect("entry");
+        assert_eq!(entry.header("x-checked"), Some("yes"));
+    }
+
+    #[tokio::test]
+    async fn last_modified_is_sent_back_as_if_modified_since() {
+        let (base, seen) = fixture_server(|request| {
+            if request.contains("if-modified-since: sun, 06 nov 1994 08:49:37 gmt") {
+                not_modified("")
+            } else {
+                ok("Cache-Control: max-age=0\r\nLast-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n", "hello")
+            }
+        })
+        .await;
+        let client = Client::new(&CurlConfig::default()).expect("client").with_cache(temp_cache("last-modified"));
+        client.get(&base, &subresource(), None).await.expect("first");
+        let second = client.get(&base, &subresource(), None).await.expect("second");
+        assert_eq!(second.source, ResponseSource::Revalidated);
+        assert_eq!(second.body, b"hello");
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+}
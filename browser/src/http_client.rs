@@ -0,0 +1,801 @@
+//! The shared HTTP client, configured from a curlrc-style file.
+//!
+//! Only the options that make sense for a browser are honored (headers,
+//! user agent, timeouts, `-k`, `-u`, `-L`, proxies and cookies); output
+//! options such as `-s` and `-o` are accepted and ignored so the same file
+//! works for both curl and the browser. See `curl --config` for the syntax.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{StatusCode, Url};
+
+use crate::cookies::{CookieJar, RequestContext};
+use crate::http_cache::{self, CacheEntry, HttpCache};
+
+/// curl's default limit for `-L`.
+pub const DEFAULT_MAX_REDIRS: usize = 50;
+/// Used when the curlrc has no `--connect-timeout` (curl waits 300 s).
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest response body read into memory; bigger pages fail instead of
+/// exhausting memory. Downloads stream to disk and are not limited.
+pub const MAX_BODY_SIZE: usize = 64 << 20;
+
+/// Settings read from a curlrc file.
+#[derive(Clone, Debug, Default)]
+pub struct CurlConfig {
+    /// `-H`; an empty value removes a default header (curl's `-H "Name:"`).
+    pub headers: Vec<(String, String)>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    pub connect_timeout: Option<Duration>,
+    pub max_time: Option<Duration>,
+    /// `-k`: accept invalid TLS certificates.
+    pub insecure: bool,
+    /// `-u user[:password]`.
+    pub user: Option<(String, Option<String>)>,
+    /// `-L`: follow redirects.
+    pub location: bool,
+    pub max_redirs: Option<usize>,
+    pub proxy: Option<String>,
+    pub proxy_user: Option<(String, Option<String>)>,
+    pub noproxy: Option<String>,
+    /// `-b`: a cookie file to read, or literal `name=value` pairs.
+    pub cookie: Option<String>,
+    /// `-c`: where cookies are written back.
+    pub cookie_jar: Option<PathBuf>,
+}
+
+impl CurlConfig {
+    /// What the browser uses when there is no curlrc: follow redirects like
+    /// any other browser, keep cookies in `cookies.txt` and leave everything
+    /// else at reqwest's defaults.
+    pub fn browser_default() -> Self {
+        CurlConfig {
+            location: true,
+            cookie: Some("cookies.txt".to_string()),
+            cookie_jar: Some(PathBuf::from("cookies.txt")),
+            ..Default::default()
+        }
+    }
+
+    /// Reads and parses `path`. Unknown options are logged and skipped.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (config, warnings) = parse_curlrc(&text);
+        for warning in warnings {
+            tracing::warn!("{}: {}", path.display(), warning);
+        }
+        Ok(config)
+    }
+
+    /// The file curl itself would read: `$CURL_HOME/.curlrc`, then `$HOME/.curlrc`.
+    pub fn default_path() -> Option<PathBuf> {
+        ["CURL_HOME", "HOME"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .map(|dir| Path::new(&dir).join(".curlrc"))
+            .find(|path| path.is_file())
+    }
+
+    /// `-b` names a file unless it looks like `name=value` pairs, as in curl.
+    pub fn cookie_file(&self) -> Option<PathBuf> {
+        self.cookie.as_ref().filter(|c| !c.contains('=')).map(PathBuf::from)
+    }
+}
+
+/// Splits `user[:password]`.
+fn split_credentials(value: &str) -> (String, Option<String>) {
+    match value.split_once(':') {
+        Some((user, password)) => (user.to_string(), Some(password.to_string())),
+        None => (value.to_string(), None),
+    }
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    value.parse::<f64>().ok().filter(|s| *s >= 0.0).map(Duration::from_secs_f64)
+}
+
+/// Long option name for a short one, and whether it takes a parameter.
+fn option_spec(name: &str) -> Option<(&'static str, bool)> {
+    Some(match name {
+        "H" | "header" => ("header", true),
+        "A" | "user-agent" => ("user-agent", true),
+        "e" | "referer" => ("referer", true),
+        "connect-timeout" => ("connect-timeout", true),
+        "m" | "max-time" => ("max-time", true),
+        "k" | "insecure" => ("insecure", false),
+        "u" | "user" => ("user", true),
+        "L" | "location" => ("location", false),
+        "max-redirs" => ("max-redirs", true),
+        "x" | "proxy" => ("proxy", true),
+        "U" | "proxy-user" => ("proxy-user", true),
+        "noproxy" => ("noproxy", true),
+        "b" | "cookie" => ("cookie", true),
+        "c" | "cookie-jar" => ("cookie-jar", true),
+        // ブラウザには関係ない出力系のオプション
+        "s" | "silent" | "S" | "show-error" | "v" | "verbose" | "i" | "include" | "f" | "fail" | "compressed" => {
+            ("ignored", false)
+        }
+        "o" | "output" | "w" | "write-out" | "D" | "dump-header" | "retry" => ("ignored", true),
+        _ => return None,
+    })
+}
+
+/// Parses curlrc text. Returns the settings and a message for each line that
+/// could not be understood.
+pub fn parse_curlrc(text: &str) -> (CurlConfig, Vec<String>) {
+    let mut config = CurlConfig::default();
+    let mut warnings = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, rest) = split_option(line);
+        let Some((option, takes_value)) = option_spec(name) else {
+            warnings.push(format!("line {}: unsupported option `{}`", number + 1, name));
+            continue;
+        };
+        let value = if takes_value {
+            match parse_parameter(rest) {
+                Some(value) => value,
+                None => {
+                    warnings.push(format!("line {}: `{}` needs a parameter", number + 1, name));
+                    continue;
+                }
+            }
+        } else {
+            String::new()
+        };
+        if let Err(message) = apply_option(&mut config, option, value) {
+            warnings.push(format!("line {}: {}", number + 1, message));
+        }
+    }
+    (config, warnings)
+}
+
+/// Splits a line into the option name (without dashes) and the text after it.
+/// Options without leading dashes may use `=` or `:` as the separator.
+fn split_option(line: &str) -> (&str, &str) {
+    if let Some(rest) = line.strip_prefix('-') {
+        let rest = rest.strip_prefix('-').unwrap_or(rest);
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        return (&rest[..end], &rest[end..]);
+    }
+    let end = line.find(|c: char| c.is_whitespace() || c == '=' || c == ':').unwrap_or(line.len());
+    let rest = line[end..].trim_start();
+    let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest);
+    (&line[..end], rest)
+}
+
+/// The parameter: a double-quoted string with curl's escapes, or the next word.
+fn parse_parameter(rest: &str) -> Option<String> {
+    let rest = rest.trim_start();
+    let Some(quoted) = rest.strip_prefix('"') else {
+        let word = rest.split_whitespace().next()?;
+        return Some(word.to_string());
+    };
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                't' => value.push('\t'),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                'v' => value.push('\x0B'),
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    // 閉じ引用符が無くても行末までを値として扱う
+    Some(value)
+}
+
+fn apply_option(config: &mut CurlConfig, option: &str, value: String) -> Result<(), String> {
+    match option {
+        "header" => {
+            let (name, header_value) = value.split_once(':').ok_or(format!("bad header `{}`", value))?;
+            config.headers.push((name.trim().to_string(), header_value.trim().to_string()));
+        }
+        "user-agent" => config.user_agent = Some(value),
+        "referer" => config.referer = Some(value),
+        "connect-timeout" => config.connect_timeout = Some(parse_seconds(&value).ok_or(format!("bad timeout `{}`", value))?),
+        "max-time" => config.max_time = Some(parse_seconds(&value).ok_or(format!("bad timeout `{}`", value))?),
+        "insecure" => config.insecure = true,
+        "user" => config.user = Some(split_credentials(&value)),
+        "location" => config.location = true,
+        "max-redirs" => config.max_redirs = Some(value.parse().map_err(|_| format!("bad count `{}`", value))?),
+        "proxy" => config.proxy = Some(value),
+        "proxy-user" => config.proxy_user = Some(split_credentials(&value)),
+        "noproxy" => config.noproxy = Some(value),
+        "cookie" => config.cookie = Some(value),
+        "cookie-jar" => config.cookie_jar = Some(PathBuf::from(value)),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A configured client. Cheap to clone; clones share connections and the
+/// cookie jar.
+#[derive(Clone)]
+pub struct Client {
+    inner: reqwest::Client,
+    /// `-u`; only sent to the host the request started at, like curl.
+    basic_auth: Option<(String, Option<String>)>,
+    /// `-b name=value`: sent along with the jar's cookies.
+    extra_cookies: Option<String>,
+    /// Redirect limit when `-L` is on.
+    max_redirects: Option<usize>,
+    cookies: Arc<Mutex<CookieJar>>,
+    /// `-c`: the jar is written here whenever it changes.
+    jar_path: Option<PathBuf>,
+    cache: Option<Arc<HttpCache>>,
+    /// The settings the client was built from, for about:network.
+    config: Arc<CurlConfig>,
+}
+
+impl Client {
+    pub fn new(config: &CurlConfig) -> Result<Self, String> {
+        let mut headers = HeaderMap::new();
+        if let Some(referer) = &config.referer {
+            headers.insert(reqwest::header::REFERER, header_value(referer)?);
+        }
+        let mut user_agent = config.user_agent.clone();
+        for (name, value) in &config.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| format!("header `{}`: {}", name, e))?;
+            // User-Agent はクライアント側の設定として扱う
+            if name == reqwest::header::USER_AGENT {
+                user_agent = Some(value.clone());
+            } else if value.is_empty() {
+                headers.remove(&name);
+            } else {
+                headers.append(name, header_value(value)?);
+            }
+        }
+
+        // リダイレクトはクッキーを 1 回ごとに処理するため自前で辿る
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
+            .danger_accept_invalid_certs(config.insecure)
+            .redirect(reqwest::redirect::Policy::none());
+        if let Some(user_agent) = user_agent.filter(|ua| !ua.is_empty()) {
+            builder = builder.user_agent(user_agent);
+        }
+        builder = builder.connect_timeout(config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT));
+        if let Some(timeout) = config.max_time {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &config.proxy {
+            // curl と同じく、スキームが無ければ http:// とみなす
+            let url = if proxy.contains("://") { proxy.clone() } else { format!("http://{}", proxy) };
+            let mut proxy = reqwest::Proxy::all(&url).map_err(|e| format!("proxy `{}`: {}", url, e))?;
+            if let Some((user, password)) = &config.proxy_user {
+                proxy = proxy.basic_auth(user, password.as_deref().unwrap_or(""));
+            }
+            proxy = proxy.no_proxy(config.noproxy.as_deref().and_then(reqwest::NoProxy::from_string));
+            builder = builder.proxy(proxy);
+        }
+        let inner = builder.build().map_err(|e| format!("HTTP client: {}", e))?;
+
+        let jar = match config.cookie_file() {
+            Some(path) => CookieJar::load(&path)?,
+            None => CookieJar::default(),
+        };
+        Ok(Client {
+            inner,
+            basic_auth: config.user.clone(),
+            extra_cookies: config.cookie.clone().filter(|c| c.contains('=')),
+            max_redirects: config.location.then(|| config.max_redirs.unwrap_or(DEFAULT_MAX_REDIRS)),
+            cookies: Arc::new(Mutex::new(jar)),
+            jar_path: config.cookie_jar.clone(),
+            cache: None,
+            config: Arc::new(config.clone()),
+        })
+    }
+
+    /// The shared cookie jar.
+    pub fn cookie_jar(&self) -> &Arc<Mutex<CookieJar>> {
+        &self.cookies
+    }
+
+    /// Writes the jar to the `-c` file, if there is one.
+    pub fn save_cookies(&self) {
+        let Some(path) = &self.jar_path else {
+            return;
+        };
+        if let Err(e) = self.cookies.lock().unwrap().save(path) {
+            tracing::warn!("Failed to save cookies: {}", e);
+        }
+    }
+
+    /// Keeps responses in `cache` from now on.
+    pub fn with_cache(mut self, cache: HttpCache) -> Self {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    pub fn cache(&self) -> Option<&HttpCache> {
+        self.cache.as_deref()
+    }
+
+    pub fn config(&self) -> &CurlConfig {
+        &self.config
+    }
+
+    /// GETs `url`, following redirects if `-L` is on. Cookies are sent and
+    /// stored, and the cache consulted, at every hop. Progress is published
+    /// on `progress` if given.
+    pub async fn get(&self, url: &str, context: &RequestContext, progress: Option<&ProgressSender>) -> Result<Response, FetchError> {
+        self.request(url, None, context, progress).await
+    }
+
+    /// POSTs `data` to `url` (a form submission). A 301, 302 or 303 redirect
+    /// is followed with a GET; 307 and 308 send the body again. The cache is
+    /// neither consulted nor filled for the POST itself.
+    pub async fn post(&self, url: &str, data: &PostData, context: &RequestContext, progress: Option<&ProgressSender>) -> Result<Response, FetchError> {
+        self.request(url, Some(data), context, progress).await
+    }
+
+    async fn request(&self, url: &str, mut post: Option<&PostData>, context: &RequestContext, progress: Option<&ProgressSender>) -> Result<Response, FetchError> {
+        let mut url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(format!("{}: {}", url, e)))?;
+        let origin_host = url.host_str().map(str::to_string);
+        let mut redirects = 0;
+        loop {
+            if let Some(progress) = progress {
+                progress.send_replace(FetchProgress {
+                    url: url.to_string(),
+                    redirects,
+                    ..FetchProgress::default()
+                });
+            }
+            let response = self.send_once(&url, post, origin_host.as_deref(), context, progress).await?;
+            let location = response.headers.get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok());
+            match (self.max_redirects, location) {
+                (Some(max), Some(location)) if is_redirect(response.status) => {
+                    if redirects >= max {
+                        return Err(FetchError::TooManyRedirects(max));
+                    }
+                    redirects += 1;
+                    // 307/308 以外は GET でリダイレクト先を取りに行く
+                    if !matches!(response.status, StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT) {
+                        post = None;
+                    }
+                    url = url.join(location).map_err(|e| FetchError::InvalidUrl(format!("{}: {}", location, e)))?;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    /// One hop: answers a GET from the cache when the stored copy is fresh,
+    /// otherwise asks the server (conditionally, if there is a stored copy).
+    async fn send_once(
+        &self,
+        url: &Url,
+        post: Option<&PostData>,
+        origin_host: Option<&str>,
+        context: &RequestContext,
+        progress: Option<&ProgressSender>,
+    ) -> Result<Response, FetchError> {
+        let cached = match post {
+            Some(_) => None,
+            None => self.cache().and_then(|cache| Some((cache.lookup(url.as_str())?, cache.body(url.as_str())?))),
+        };
+        if let Some((entry, body)) = &cached
+            && !context.reload
+            && http_cache::is_fresh_now(entry)
+        {
+            return Ok(Response::from_cache(url, entry, body.clone(), ResponseSource::Cache));
+        }
+
+        let mut request = match post {
+            Some(data) => {
+                let mut request = self
+                    .inner
+                    .post(url.clone())
+                    .header(reqwest::header::CONTENT_TYPE, data.content_type.as_str())
+                    .body(data.body.clone());
+                // CSRF 対策で Origin を見るサーバーのために送信元を付ける
+                if let Some(origin) = context.initiator.as_deref().and_then(|i| Url::parse(i).ok()).map(|i| i.origin())
+                    && origin.is_tuple()
+                {
+                    request = request.header(reqwest::header::ORIGIN, origin.ascii_serialization());
+                }
+                request
+            }
+            None => self.inner.get(url.clone()),
+        };
+        request = self.with_credentials(request, url, origin_host, context, post.is_some());
+        if let Some((entry, _)) = &cached {
+            if let Some(etag) = entry.header("etag") {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(modified) = entry.header("last-modified") {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, modified);
+            }
+        }
+
+        let mut response = match request.send().await {
+            Ok(response) => response,
+            // オフラインなどで繋がらなければ保存済みのものを出す
+            Err(e) => match cached {
+                Some((entry, body)) if entry.allows_stale() => {
+                    tracing::info!("Serving {} from cache: {}", url, e);
+                    return Ok(Response::from_cache(url, &entry, body, ResponseSource::Offline));
+                }
+                _ => return Err(FetchError::from_reqwest(&e, url)),
+            },
+        };
+        self.store_cookies(url, response.headers());
+
+        if response.status() == StatusCode::NOT_MODIFIED
+            && let Some((entry, body)) = cached
+        {
+            // 保存し直せなくても、手元の写しがまだ正しいことには変わりない
+            let entry = self.cache().and_then(|cache| cache.refresh(url.as_str(), response.headers())).unwrap_or(entry);
+            return Ok(Response::from_cache(url, &entry, body, ResponseSource::Revalidated));
+        }
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        // ダウンロードになるページは本文を読まず、ダウンロードマネージャーに取り直させる
+        if context.navigation && post.is_none() && status.is_success() && crate::downloads::is_download(&headers, None) {
+            return Ok(Response {
+                url: url.clone(),
+                status,
+                headers,
+                body: Vec::new(),
+                source: ResponseSource::Download,
+            });
+        }
+        let total = response.content_length();
+        let mut body = Vec::with_capacity(total.unwrap_or(0).min(1 << 24) as usize);
+        while let Some(chunk) = response.chunk().await.map_err(|e| FetchError::from_reqwest(&e, url))? {
+            if body.len() + chunk.len() > MAX_BODY_SIZE {
+                return Err(body_too_large());
+            }
+            body.extend_from_slice(&chunk);
+            if let Some(progress) = progress {
+                progress.send_modify(|p| {
+                    p.received = body.len() as u64;
+                    p.total = total;
+                });
+            }
+        }
+        if let Some(cache) = self.cache()
+            && post.is_none()
+        {
+            cache.store(url.as_str(), status, &headers, &body);
+        }
+        Ok(Response {
+            url: url.clone(),
+            status,
+            headers,
+            body,
+            source: ResponseSource::Network,
+        })
+    }
+
+    /// Starts a download of `url` from byte `offset` on, with a `Range`
+    /// request when `offset` is not 0. `if_range` is the validator (ETag or
+    /// Last-Modified) of the part already on disk, so a file that changed
+    /// since comes back whole with a 200. Redirects are followed as for
+    /// [`Client::get`]; the body is left for the caller to stream.
+    pub async fn open_download(&self, url: &str, offset: u64, if_range: Option<&str>, context: &RequestContext) -> Result<reqwest::Response, FetchError> {
+        let mut url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(format!("{}: {}", url, e)))?;
+        let origin_host = url.host_str().map(str::to_string);
+        let mut redirects = 0;
+        loop {
+            let mut request = self.with_credentials(self.inner.get(url.clone()), &url, origin_host.as_deref(), context, false);
+            if offset > 0 {
+                request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+                if let Some(validator) = if_range {
+                    request = request.header(reqwest::header::IF_RANGE, validator);
+                }
+            }
+            let response = request.send().await.map_err(|e| FetchError::from_reqwest(&e, &url))?;
+            self.store_cookies(&url, response.headers());
+            let location = response.headers().get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok());
+            match (self.max_redirects, location) {
+                (Some(max), Some(location)) if is_redirect(response.status()) => {
+                    if redirects >= max {
+                        return Err(FetchError::TooManyRedirects(max));
+                    }
+                    redirects += 1;
+                    url = url.join(location).map_err(|e| FetchError::InvalidUrl(format!("{}: {}", location, e)))?;
+                }
+                _ if !response.status().is_success() => return Err(FetchError::Http(response.status())),
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    /// Adds the `-u` credentials (on the starting host only) and the cookies
+    /// for `url` to a request.
+    fn with_credentials(
+        &self,
+        mut request: reqwest::RequestBuilder,
+        url: &Url,
+        origin_host: Option<&str>,
+        context: &RequestContext,
+        post: bool,
+    ) -> reqwest::RequestBuilder {
+        if let Some((user, password)) = &self.basic_auth
+            && url.host_str() == origin_host
+        {
+            request = request.basic_auth(user, password.as_deref());
+        }
+        // POST のナビゲーションでは SameSite=Lax のクッキーは同じサイトからしか送らない
+        let jar_cookies = if post {
+            self.cookies.lock().unwrap().header_for(url, &RequestContext { navigation: false, ..context.clone() })
+        } else {
+            self.cookies.lock().unwrap().header_for(url, context)
+        };
+        let cookie_header = match (&self.extra_cookies, jar_cookies) {
+            (Some(extra), Some(jar)) => Some(format!("{}; {}", extra, jar)),
+            (extra, jar) => extra.clone().or(jar),
+        };
+        if let Some(cookie_header) = cookie_header {
+            request = request.header(reqwest::header::COOKIE, cookie_header);
+        }
+        request
+    }
+
+    /// Keeps the cookies a response to `url` sets.
+    fn store_cookies(&self, url: &Url, headers: &HeaderMap) {
+        let set_cookies = headers.get_all(reqwest::header::SET_COOKIE).iter().filter_map(|v| v.to_str().ok());
+        if self.cookies.lock().unwrap().store_response(url, set_cookies) {
+            self.save_cookies();
+        }
+    }
+}
+
+fn is_redirect(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER | StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+    )
+}
+
+/// The body of a POST request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PostData {
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+/// How far a fetch has got, published while it runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchProgress {
+    /// URL of the current hop.
+    pub url: String,
+    pub redirects: usize,
+    /// Body bytes received so far on this hop.
+    pub received: u64,
+    /// Content-Length, if the server sent one.
+    pub total: Option<u64>,
+}
+
+pub type ProgressSender = tokio::sync::watch::Sender<FetchProgress>;
+
+/// Why a fetch failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FetchError {
+    InvalidUrl(String),
+    /// The host name could not be resolved.
+    Dns(String),
+    /// No connection (refused, unreachable, reset, proxy failure).
+    Connect(String),
+    /// The TLS handshake failed, usually over the certificate.
+    Tls(String),
+    Timeout,
+    TooManyRedirects(usize),
+    /// The server answered with an error status.
+    Http(StatusCode),
+    /// A Gemini server answered with a failure (4x/5x) or asked for a
+    /// client certificate (6x).
+    Gemini { status: u8, meta: String },
+    /// A file: URL could not be read.
+    File(String),
+    /// No handler for the URL's scheme (or about: page of that name).
+    UnsupportedScheme(String),
+    Other(String),
+}
+
+/// The error for a response over [`MAX_BODY_SIZE`].
+pub fn body_too_large() -> FetchError {
+    FetchError::Other(format!("The response is larger than {} MiB", MAX_BODY_SIZE >> 20))
+}
+
+impl FetchError {
+    /// Sorts a reqwest error for a request to `url` by looking through its
+    /// chain of causes.
+    pub fn from_reqwest(error: &reqwest::Error, url: &Url) -> Self {
+        let mut causes = Vec::new();
+        let mut source: Option<&dyn std::error::Error> = Some(error);
+        while let Some(cause) = source {
+            causes.push(cause.to_string());
+            source = cause.source();
+        }
+        let detail = causes.last().cloned().unwrap_or_default();
+        let mentions = |needles: &[&str]| causes.iter().any(|c| needles.iter().any(|n| c.to_ascii_lowercase().contains(n)));
+        if error.is_timeout() || mentions(&["timed out"]) {
+            FetchError::Timeout
+        } else if mentions(&["dns error", "failed to lookup address", "name or service not known", "no such host"]) {
+            FetchError::Dns(url.host_str().unwrap_or_default().to_string())
+        } else if mentions(&["certificate", "tls", "handshake"]) {
+            FetchError::Tls(detail)
+        } else if error.is_connect() || mentions(&["connection refused", "connection reset"]) {
+            FetchError::Connect(detail)
+        } else {
+            FetchError::Other(detail)
+        }
+    }
+
+    /// Heading for the error page.
+    pub fn title(&self) -> &'static str {
+        match self {
+            FetchError::InvalidUrl(_) => "Invalid address",
+            FetchError::Dns(_) => "Server not found",
+            FetchError::Connect(_) => "Unable to connect",
+            FetchError::Tls(_) => "Secure connection failed",
+            FetchError::Timeout => "The connection has timed out",
+            FetchError::TooManyRedirects(_) => "The page isn't redirecting properly",
+            FetchError::Http(_) => "The server returned an error",
+            FetchError::Gemini { status: 40..=49, .. } => "Temporary failure",
+            FetchError::Gemini { status: 51, .. } => "Not found",
+            FetchError::Gemini { status: 60..=69, .. } => "Client certificate required",
+            FetchError::Gemini { .. } => "Permanent failure",
+            FetchError::File(_) => "File not found",
+            FetchError::UnsupportedScheme(_) => "The address wasn't understood",
+            FetchError::Other(_) => "Problem loading page",
+        }
+    }
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::InvalidUrl(detail) => write!(f, "Invalid URL {}", detail),
+            FetchError::Dns(host) => write!(f, "Could not resolve host {}", host),
+            FetchError::Connect(detail) => write!(f, "Could not connect: {}", detail),
+            FetchError::Tls(detail) => write!(f, "TLS error: {}", detail),
+            FetchError::Timeout => write!(f, "Timed out"),
+            FetchError::TooManyRedirects(max) => write!(f, "Too many redirects (more than {})", max),
+            FetchError::Http(status) => write!(f, "HTTP Error: {}", status),
+            FetchError::Gemini { status, meta } => write!(f, "Gemini status {}: {}", status, meta),
+            FetchError::File(detail) => write!(f, "{}", detail),
+            FetchError::UnsupportedScheme(scheme) => write!(f, "No handler for {}", scheme),
+            FetchError::Other(detail) => write!(f, "{}", detail),
+        }
+    }
+}
+
+/// Where a [`Response`] came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseSource {
+    Network,
+    /// Fresh copy from the cache; the server was not asked.
+    Cache,
+    /// The server answered 304 Not Modified to a conditional request.
+    Revalidated,
+    /// The server could not be reached; possibly stale copy from the cache.
+    Offline,
+    /// Not fetched over the network (file:, data:, about:).
+    Local,
+    /// A page load that turned out to be a download: the headers only, the
+    /// body is left for the download manager to fetch.
+    Download,
+}
+
+/// A complete response (body already read).
+#[derive(Clone, Debug)]
+pub struct Response {
+    /// URL after redirects.
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub source: ResponseSource,
+}
+
+impl Response {
+    /// A response made up locally, for the non-HTTP schemes.
+    pub fn local(url: Url, content_type: &str, body: Vec<u8>) -> Self {
+        let mut headers = HeaderMap::new();
+        if let Ok(value) = HeaderValue::from_str(content_type) {
+            headers.insert(reqwest::header::CONTENT_TYPE, value);
+        }
+        Response {
+            url,
+            status: StatusCode::OK,
+            headers,
+            body,
+            source: ResponseSource::Local,
+        }
+    }
+
+    fn from_cache(url: &Url, entry: &CacheEntry, body: Vec<u8>, source: ResponseSource) -> Self {
+        Response {
+            url: url.clone(),
+            status: StatusCode::from_u16(entry.status).unwrap_or(StatusCode::OK),
+            headers: entry.header_map(),
+            body,
+            source,
+        }
+    }
+
+    /// The body decoded as UTF-8, with invalid bytes replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|e| format!("header value `{}`: {}", value, e))
+}
+
+/// Parses an HTTP date (IMF-fixdate, RFC 850 or asctime form) into Unix
+/// seconds, using the lenient algorithm of RFC 6265 5.1.1. Dates before 1970
+/// come out as 0.
+pub fn parse_http_date(text: &str) -> Option<u64> {
+    let mut time = None;
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let tokens = text.split(|c: char| !(c.is_ascii_alphanumeric() || c == ':')).filter(|t| !t.is_empty());
+    for token in tokens {
+        let digits = token.bytes().take_while(u8::is_ascii_digit).count();
+        if time.is_none()
+            && let Some(t) = parse_clock(token)
+        {
+            time = Some(t);
+        } else if day.is_none() && (1..=2).contains(&digits) && digits == token.len() {
+            day = token.parse::<i64>().ok();
+        } else if month.is_none()
+            && let Some(m) = MONTHS.iter().position(|m| token.get(..3).is_some_and(|t| t.eq_ignore_ascii_case(m)))
+        {
+            month = Some(m as i64 + 1);
+        } else if year.is_none() && (2..=4).contains(&digits) && digits == token.len() {
+            year = token.parse::<i64>().ok();
+        }
+    }
+    let (hour, minute, second) = time?;
+    let (day, month) = (day?, month?);
+    let year = match year? {
+        y @ 70..=99 => y + 1900,
+        y @ 0..=69 => y + 2000,
+        y => y,
+    };
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(seconds.max(0) as u64)
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// `hh:mm:ss` with one or two digits per field.
+fn parse_clock(token: &str) -> Option<(i64, i64, i64)> {
+    let mut fields = token.split(':').map(|f| (1..=2).contains(&f.len()).then(|| f.parse::<i64>().ok()).flatten());
+    let clock = (fields.next()??, fields.next()??, fields.next()??);
+    fields.next().is_none().then_some(clock)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,248 @@
+//! Images of a page: `<img>` elements (with `srcset`) and CSS background
+//! images are fetched on the Tokio runtime, decoded on the compute pool into
+//! Bevy [`Image`] assets and handed to egui as textures for the page view.
+
+use std::collections::HashMap;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_egui::egui;
+use futures_lite::future;
+
+use crate::cookies::RequestContext;
+use crate::dom::{Document, NodeId};
+use crate::http_client::FetchError;
+use crate::layout::ImageTextures;
+use crate::menu::Fetcher;
+use crate::style::StyleMap;
+use crate::{AsyncComputeTaskPool, CurrentDocument, CurrentStyles, FetchImageTask, PageImages, ResponseBody};
+
+/// Longest side an image is kept at; larger ones are scaled down to stay
+/// within what every GPU can hold as one texture.
+const MAX_TEXTURE_SIDE: u32 = 4096;
+
+/// Where one image of the page is.
+#[derive(Clone, Debug)]
+pub enum ImageState {
+    Loading,
+    Loaded { handle: Handle<Image>, size: Vec2 },
+    Failed(String),
+}
+
+/// The images a page uses, by URL, and which elements show them.
+#[derive(Clone, Debug, Default)]
+pub struct ImageSet {
+    pub images: HashMap<String, ImageState>,
+    /// URL chosen for each `<img>` from `src` and `srcset`.
+    pub sources: HashMap<NodeId, String>,
+    /// URL of the CSS background image of each element that has one.
+    pub backgrounds: HashMap<NodeId, String>,
+}
+
+impl ImageSet {
+    /// Handle and natural size of the loaded image of `node`.
+    pub fn loaded(&self, node: NodeId, background: bool) -> Option<(&Handle<Image>, Vec2)> {
+        let urls = if background { &self.backgrounds } else { &self.sources };
+        match self.images.get(urls.get(&node)?)? {
+            ImageState::Loaded { handle, size } => Some((handle, *size)),
+            _ => None,
+        }
+    }
+
+    /// Number of images still loading.
+    pub fn pending(&self) -> usize {
+        self.images.values().filter(|state| matches!(state, ImageState::Loading)).count()
+    }
+}
+
+/// The textures egui has registered for the loaded images of one page.
+pub struct PageTextures<'a> {
+    pub images: &'a ImageSet,
+    pub ids: HashMap<AssetId<Image>, egui::TextureId>,
+}
+
+impl ImageTextures for PageTextures<'_> {
+    fn texture(&self, node: NodeId, background: bool) -> Option<(egui::TextureId, egui::Vec2)> {
+        let (handle, size) = self.images.loaded(node, background)?;
+        Some((*self.ids.get(&handle.id())?, egui::vec2(size.x, size.y)))
+    }
+}
+
+/// A tab whose styles were just computed, with what its images are found from.
+type StyledPage<'a> = (Entity, Ref<'a, CurrentDocument>, &'a CurrentStyles, &'a ResponseBody, &'a mut PageImages);
+
+// スタイルが決まったら (新しいページ・スタイルシートの到着) 画像を集め、まだのものを取りに行くシステム
+pub fn fetch_page_images(
+    mut commands: Commands,
+    mut pages: Query<StyledPage, Changed<CurrentStyles>>,
+    stale_tasks: Query<(Entity, &FetchImageTask)>,
+    fetcher: Fetcher,
+) {
+    for (tab, current_document, current_styles, body, mut page_images) in &mut pages {
+        // 別のページになったら前のページの画像は捨てる
+        if current_document.is_changed() {
+            page_images.0 = ImageSet::default();
+            for (stale, fetch) in &stale_tasks {
+                if fetch.tab == tab {
+                    commands.entity(stale).despawn();
+                }
+            }
+        }
+        let doc = &current_document.0;
+        let base = crate::links::base_url(doc, &body.url);
+        let set = &mut page_images.0;
+        set.sources = image_sources(doc, base.as_ref());
+        set.backgrounds = background_images(doc, &current_styles.0, base.as_ref());
+        let wanted: Vec<String> = set.sources.values().chain(set.backgrounds.values()).cloned().collect();
+        for url in wanted {
+            if set.images.contains_key(&url) {
+                continue;
+            }
+            set.images.insert(url.clone(), ImageState::Loading);
+            // 取得は Tokio、デコードは計算用のスレッドプールで行う
+            let fetch = fetcher.spawn(url.clone(), RequestContext::subresource(&body.url), None);
+            let task = AsyncComputeTaskPool::get().spawn(async move { decode_image(&fetch.await?.body) });
+            commands.spawn(FetchImageTask { tab, url, task });
+        }
+    }
+}
+
+// 画像の取得・デコードの完了を監視し、Bevy の Image アセットにしてタブに渡すシステム
+pub fn poll_fetch_image_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut FetchImageTask)>,
+    mut pages: Query<&mut PageImages>,
+    mut assets: ResMut<Assets<Image>>,
+) {
+    for (entity, mut fetch) in &mut tasks {
+        let Some(result) = future::block_on(future::poll_once(&mut fetch.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        let Ok(mut page_images) = pages.get_mut(fetch.tab) else {
+            continue;
+        };
+        let state = match result {
+            Ok(image) => {
+                let size = image.size_f32();
+                debug!("Image loaded: {} ({}x{})", fetch.url, size.x, size.y);
+                ImageState::Loaded { handle: assets.add(image), size }
+            }
+            Err(e) => {
+                warn!("Image failed for {}: {}", fetch.url, e);
+                ImageState::Failed(e.to_string())
+            }
+        };
+        page_images.0.images.insert(fetch.url.clone(), state);
+    }
+}
+
+/// Decodes a fetched image into an RGBA texture.
+pub fn decode_image(bytes: &[u8]) -> Result<Image, FetchError> {
+    let decoded = image::load_from_memory(bytes).map_err(|e| FetchError::Other(format!("Could not decode image: {}", e)))?;
+    let decoded = if decoded.width().max(decoded.height()) > MAX_TEXTURE_SIDE {
+        decoded.resize(MAX_TEXTURE_SIDE, MAX_TEXTURE_SIDE, image::imageops::FilterType::Triangle)
+    } else {
+        decoded
+    };
+    let rgba = decoded.into_rgba8();
+    let size = Extent3d {
+        width: rgba.width(),
+        height: rgba.height(),
+        depth_or_array_layers: 1,
+    };
+    Ok(Image::new(size, TextureDimension::D2, rgba.into_raw(), TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::RENDER_WORLD))
+}
+
+// <img> ごとに src / srcset から使う URL を選ぶ
+fn image_sources(doc: &Document, base: Option<&reqwest::Url>) -> HashMap<NodeId, String> {
+    let mut sources = HashMap::new();
+    for node in doc.descendants(doc.root()) {
+        if !doc.is_element_named(node, "img") {
+            continue;
+        }
+        let Some(element) = doc.element(node) else {
+            continue;
+        };
+        let chosen = choose_source(element.attr("src"), element.attr("srcset"), crate::css::MediaContext::default().width);
+        if let Some(url) = chosen.and_then(|href| resolve(base, &href)) {
+            sources.insert(node, url);
+        }
+    }
+    sources
+}
+
+// background-image を持つ要素を集める (外部シートの url() は読み込み時に絶対 URL にしてある)
+fn background_images(doc: &Document, styles: &StyleMap, base: Option<&reqwest::Url>) -> HashMap<NodeId, String> {
+    doc.descendants(doc.root())
+        .into_iter()
+        .filter_map(|node| {
+            let href = styles.get(node)?.background_image.as_deref()?;
+            Some((node, resolve(base, href)?))
+        })
+        .collect()
+}
+
+fn resolve(base: Option<&reqwest::Url>, href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() {
+        return None;
+    }
+    match base {
+        Some(base) => base.join(href).ok().map(String::from),
+        None => reqwest::Url::parse(href).ok().map(String::from),
+    }
+}
+
+/// Picks the image candidate of `srcset` (with `src` as the `1x` fallback)
+/// for a 1x display, taking `w` descriptors as widths in a viewport
+/// `viewport_width` pixels wide (as if `sizes` were `100vw`).
+pub fn choose_source(src: Option<&str>, srcset: Option<&str>, viewport_width: f32) -> Option<String> {
+    let mut candidates = srcset.map(|set| parse_srcset(set, viewport_width)).unwrap_or_default();
+    if let Some(src) = src.filter(|s| !s.trim().is_empty())
+        && !candidates.iter().any(|(_, density)| *density == 1.0)
+    {
+        candidates.push((src.trim().to_string(), 1.0));
+    }
+    // 1x 以上で一番小さいもの、なければ一番大きいもの
+    let at_least = candidates.iter().filter(|(_, d)| *d >= 1.0).min_by(|a, b| a.1.total_cmp(&b.1));
+    at_least.or_else(|| candidates.iter().max_by(|a, b| a.1.total_cmp(&b.1))).map(|(url, _)| url.clone())
+}
+
+/// Parses a `srcset` into (URL, pixel density) pairs.
+fn parse_srcset(set: &str, viewport_width: f32) -> Vec<(String, f32)> {
+    let mut candidates = Vec::new();
+    let mut rest = set;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        let end = rest.find(|c: char| c.is_ascii_whitespace()).unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        // URL の直後のカンマは記述子なしの区切り
+        let (url, descriptors, next) = if url.ends_with(',') {
+            (url.trim_end_matches(','), "", after)
+        } else {
+            let end = after.find(',').unwrap_or(after.len());
+            (url, &after[..end], &after[end..])
+        };
+        rest = next;
+        let mut density = Some(1.0);
+        for descriptor in descriptors.split_ascii_whitespace() {
+            density = if let Some(x) = descriptor.strip_suffix('x') {
+                x.parse().ok()
+            } else if let Some(w) = descriptor.strip_suffix('w') {
+                w.parse::<f32>().ok().map(|w| w / viewport_width)
+            } else {
+                // h 記述子は幅が分からないと使えないので無視する
+                density
+            };
+        }
+        if let Some(density) = density.filter(|d: &f32| *d > 0.0) {
+            candidates.push((url.to_string(), density));
+        }
+    }
+    candidates
+}
@@ -0,0 +1,1533 @@
+//! Block and inline layout.
+//!
+//! [`layout_document`] turns a styled [`Document`] into a flat list of
+//! [`DisplayItem`]s in page coordinates (origin at the top-left of the
+//! canvas). The box tree is built first, then laid out top to bottom:
+//! block boxes stack vertically with sibling margins collapsed, inline
+//! content is broken into lines, and tables get a simple auto layout.
+
+use std::collections::HashMap;
+
+use bevy_egui::egui::{self, pos2, vec2, Color32, FontFamily, FontId, Rect, Stroke, Vec2};
+
+use crate::css::Color;
+use crate::dom::{Document, NodeData, NodeId, QuirksMode};
+use crate::style::{
+    BorderStyle, ComputedStyle, Dimension, Display, ListStyleType, StyleMap, TextAlign, TextDecoration,
+    VerticalAlign,
+};
+
+/// Font parameters that affect text measurement.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontSpec {
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+    pub monospace: bool,
+}
+
+impl FontSpec {
+    pub fn from_style(style: &ComputedStyle) -> Self {
+        Self {
+            size: style.font_size,
+            bold: style.is_bold(),
+            italic: style.font_italic,
+            monospace: style.is_monospace(),
+        }
+    }
+}
+
+/// What the layout engine needs from the outside world.
+pub trait LayoutHost {
+    fn text_width(&self, text: &str, font: &FontSpec) -> f32;
+
+    /// Natural size of a loaded image, if any.
+    fn image_size(&self, _node: NodeId) -> Option<Vec2> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisplayItem {
+    /// A filled background.
+    Rect { rect: Rect, color: Color },
+    /// The four borders of `rect` (its border box).
+    Border {
+        rect: Rect,
+        widths: [f32; 4],
+        styles: [BorderStyle; 4],
+        colors: [Color; 4],
+    },
+    /// A run of text; `rect` is its line-relative box, `node` the text node
+    /// (or the list item for markers).
+    Text {
+        rect: Rect,
+        text: String,
+        node: NodeId,
+        font: FontSpec,
+        color: Color,
+        decoration: TextDecoration,
+    },
+    /// An `<img>`. Painted as a frame with the alt text until the image is loaded.
+    Image { rect: Rect, node: NodeId, alt: String },
+    /// The CSS `background-image` of `node`, tiled over its border box.
+    BackgroundImage { rect: Rect, node: NodeId },
+    /// A form control or embedded content, drawn as a labelled box.
+    Replaced { rect: Rect, node: NodeId, label: String },
+}
+
+impl DisplayItem {
+    pub fn rect(&self) -> Rect {
+        match self {
+            DisplayItem::Rect { rect, .. }
+            | DisplayItem::Border { rect, .. }
+            | DisplayItem::Text { rect, .. }
+            | DisplayItem::Image { rect, .. }
+            | DisplayItem::BackgroundImage { rect, .. }
+            | DisplayItem::Replaced { rect, .. } => *rect,
+        }
+    }
+
+    /// The node the item was made for, if it has one.
+    pub fn node(&self) -> Option<NodeId> {
+        match self {
+            DisplayItem::Rect { .. } | DisplayItem::Border { .. } => None,
+            DisplayItem::Text { node, .. }
+            | DisplayItem::Image { node, .. }
+            | DisplayItem::BackgroundImage { node, .. }
+            | DisplayItem::Replaced { node, .. } => Some(*node),
+        }
+    }
+}
+
+/// Result of laying out a document at a given width.
+#[derive(Clone, Debug, Default)]
+pub struct PageLayout {
+    pub items: Vec<DisplayItem>,
+    /// Size of the whole canvas.
+    pub size: Vec2,
+    /// Viewport width the layout was computed for.
+    pub viewport_width: f32,
+    /// Top of the first box of each element, for scrolling to fragments.
+    pub anchors: HashMap<NodeId, f32>,
+}
+
+impl PageLayout {
+    /// Topmost item under `pos`, in paint order.
+    pub fn hit_test(&self, pos: egui::Pos2) -> Option<&DisplayItem> {
+        self.items.iter().rev().find(|item| {
+            !matches!(item, DisplayItem::Rect { .. } | DisplayItem::Border { .. }) && item.rect().contains(pos)
+        })
+    }
+}
+
+// ---- box tree ----------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BoxKind {
+    Block,
+    ListItem,
+    Table,
+    TableRow,
+    TableCell,
+    InlineBlock,
+    Replaced,
+}
+
+struct LayoutBox {
+    /// `None` for anonymous blocks.
+    node: Option<NodeId>,
+    style: ComputedStyle,
+    kind: BoxKind,
+    content: Content,
+}
+
+enum Content {
+    Blocks(Vec<LayoutBox>),
+    Inline(Vec<InlineItem>),
+}
+
+enum InlineItem {
+    Text(NodeId),
+    Open(NodeId),
+    Close(NodeId),
+    Break,
+    Atomic(Box<LayoutBox>),
+}
+
+fn is_replaced_element(name: &str) -> bool {
+    matches!(name, "img" | "input" | "textarea" | "select" | "video" | "iframe" | "canvas" | "embed" | "object")
+}
+
+struct BoxBuilder<'a> {
+    doc: &'a Document,
+    styles: &'a StyleMap,
+}
+
+impl BoxBuilder<'_> {
+    fn build(&self, node: NodeId, style: &ComputedStyle) -> LayoutBox {
+        let name = self.doc.tag_name(node).unwrap_or("");
+        let kind = if is_replaced_element(name) {
+            BoxKind::Replaced
+        } else {
+            match style.display {
+                Display::ListItem => BoxKind::ListItem,
+                Display::Table => BoxKind::Table,
+                Display::TableRow => BoxKind::TableRow,
+                Display::TableCell => BoxKind::TableCell,
+                Display::InlineBlock => BoxKind::InlineBlock,
+                _ => BoxKind::Block,
+            }
+        };
+        let content = match kind {
+            BoxKind::Replaced => Content::Blocks(Vec::new()),
+            BoxKind::Table => {
+                let mut rows = Vec::new();
+                self.collect_rows(node, style, &mut rows);
+                Content::Blocks(rows)
+            }
+            BoxKind::TableRow => Content::Blocks(self.collect_cells(node, style)),
+            _ => self.block_content(node, style),
+        };
+        LayoutBox {
+            node: Some(node),
+            style: style.clone(),
+            kind,
+            content,
+        }
+    }
+
+    fn block_content(&self, node: NodeId, style: &ComputedStyle) -> Content {
+        let mut blocks = Vec::new();
+        let mut inline = Vec::new();
+        self.collect(node, style, &mut blocks, &mut inline);
+        if blocks.is_empty() {
+            return Content::Inline(inline);
+        }
+        self.flush_inline(style, &mut blocks, &mut inline);
+        Content::Blocks(blocks)
+    }
+
+    fn collect(&self, parent: NodeId, parent_style: &ComputedStyle, blocks: &mut Vec<LayoutBox>, inline: &mut Vec<InlineItem>) {
+        for &child in self.doc.children(parent) {
+            match &self.doc.node(child).data {
+                NodeData::Text(_) => inline.push(InlineItem::Text(child)),
+                NodeData::Element(element) => {
+                    let Some(style) = self.styles.get(child) else {
+                        continue;
+                    };
+                    match style.display {
+                        Display::None => {}
+                        Display::Contents => self.collect(child, parent_style, blocks, inline),
+                        Display::Inline if element.name == "br" => inline.push(InlineItem::Break),
+                        Display::Inline | Display::InlineBlock
+                            if style.display == Display::InlineBlock || is_replaced_element(&element.name) =>
+                        {
+                            inline.push(InlineItem::Atomic(Box::new(self.build(child, style))));
+                        }
+                        Display::Inline if !self.has_block_content(child) => {
+                            inline.push(InlineItem::Open(child));
+                            self.collect(child, parent_style, blocks, inline);
+                            inline.push(InlineItem::Close(child));
+                        }
+                        _ => {
+                            // ブロックの前までのインライン内容は匿名ブロックに包む
+                            self.flush_inline(parent_style, blocks, inline);
+                            blocks.push(self.build(child, style));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether an inline element contains block-level boxes. Such elements are
+    /// laid out as blocks instead of being split around their block children.
+    fn has_block_content(&self, node: NodeId) -> bool {
+        self.doc.children(node).iter().any(|&child| match self.styles.get(child) {
+            Some(style) if style.display == Display::Inline || style.display == Display::Contents => {
+                self.has_block_content(child)
+            }
+            Some(style) => style.display.is_block_level(),
+            None => false,
+        })
+    }
+
+    fn flush_inline(&self, parent_style: &ComputedStyle, blocks: &mut Vec<LayoutBox>, inline: &mut Vec<InlineItem>) {
+        let items = std::mem::take(inline);
+        let meaningful = items.iter().any(|item| match item {
+            InlineItem::Text(node) => match &self.doc.node(*node).data {
+                NodeData::Text(text) => {
+                    let preserved = self
+                        .styles
+                        .for_node(self.doc, *node)
+                        .is_some_and(|s| !s.white_space.collapses_spaces());
+                    preserved || text.chars().any(|c| !c.is_ascii_whitespace())
+                }
+                _ => false,
+            },
+            InlineItem::Break | InlineItem::Atomic(_) => true,
+            InlineItem::Open(_) | InlineItem::Close(_) => false,
+        });
+        if meaningful {
+            let mut style = ComputedStyle::inherit_from(parent_style);
+            style.display = Display::Block;
+            blocks.push(LayoutBox {
+                node: None,
+                style,
+                kind: BoxKind::Block,
+                content: Content::Inline(items),
+            });
+        }
+    }
+
+    /// Rows (and captions) of a table, looking through row groups.
+    fn collect_rows(&self, parent: NodeId, table_style: &ComputedStyle, out: &mut Vec<LayoutBox>) {
+        for &child in self.doc.children(parent) {
+            let Some(style) = self.styles.get(child) else {
+                continue;
+            };
+            match style.display {
+                Display::TableRowGroup | Display::TableHeaderGroup | Display::TableFooterGroup | Display::Contents => {
+                    self.collect_rows(child, table_style, out);
+                }
+                Display::TableRow => out.push(self.build(child, style)),
+                Display::TableCaption => {
+                    let mut caption = self.build(child, style);
+                    caption.kind = BoxKind::Block;
+                    out.push(caption);
+                }
+                Display::TableCell => {
+                    // 行の外に置かれたセルは匿名の行に入れる
+                    let mut row_style = ComputedStyle::inherit_from(table_style);
+                    row_style.display = Display::TableRow;
+                    out.push(LayoutBox {
+                        node: None,
+                        style: row_style,
+                        kind: BoxKind::TableRow,
+                        content: Content::Blocks(vec![self.build(child, style)]),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn collect_cells(&self, row: NodeId, row_style: &ComputedStyle) -> Vec<LayoutBox> {
+        let mut cells = Vec::new();
+        for &child in self.doc.children(row) {
+            let Some(style) = self.styles.get(child) else {
+                continue;
+            };
+            match style.display {
+                Display::None => {}
+                Display::TableCell => cells.push(self.build(child, style)),
+                _ => {
+                    let mut cell_style = ComputedStyle::inherit_from(row_style);
+                    cell_style.display = Display::TableCell;
+                    let mut cell = self.build(child, style);
+                    cell.kind = BoxKind::Block;
+                    cells.push(LayoutBox {
+                        node: None,
+                        style: cell_style,
+                        kind: BoxKind::TableCell,
+                        content: Content::Blocks(vec![cell]),
+                    });
+                }
+            }
+        }
+        cells
+    }
+}
+
+// ---- inline pieces -----------------------------------------------------------
+
+/// Inline content split at every possible line break.
+enum Piece<'b> {
+    Text {
+        node: NodeId,
+        text: String,
+        width: f32,
+        /// A collapsible or preserved space; trimmed at line ends when collapsible.
+        space: bool,
+        /// A line may break after this piece.
+        break_after: bool,
+    },
+    /// Start of an inline element; `width` is its left margin, border and padding.
+    Open { node: NodeId, width: f32 },
+    Close { node: NodeId, width: f32 },
+    Atomic { b: &'b LayoutBox, min: f32, max: f32 },
+    Break,
+}
+
+impl Piece<'_> {
+    fn has_content(&self) -> bool {
+        match self {
+            Piece::Text { space, .. } => !space,
+            Piece::Atomic { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xF900..=0xFAFF | 0xFF00..=0xFFEF | 0x3000..=0x303F | 0xAC00..=0xD7AF)
+}
+
+struct LayoutCtx<'a> {
+    doc: &'a Document,
+    styles: &'a StyleMap,
+    host: &'a dyn LayoutHost,
+    items: Vec<DisplayItem>,
+    anchors: HashMap<NodeId, f32>,
+}
+
+impl<'a> LayoutCtx<'a> {
+    fn style_of(&self, node: NodeId) -> &'a ComputedStyle {
+        static DEFAULT: std::sync::OnceLock<ComputedStyle> = std::sync::OnceLock::new();
+        self.styles
+            .for_node(self.doc, node)
+            .unwrap_or_else(|| DEFAULT.get_or_init(ComputedStyle::default))
+    }
+
+    fn pieces<'b>(&self, items: &'b [InlineItem], containing: f32) -> Vec<Piece<'b>> {
+        let mut pieces = Vec::new();
+        // 行頭と直前が空白のときは空白を詰める
+        let mut after_space = true;
+        for item in items {
+            match item {
+                InlineItem::Text(node) => {
+                    let NodeData::Text(text) = &self.doc.node(*node).data else {
+                        continue;
+                    };
+                    self.text_pieces(*node, text, &mut after_space, &mut pieces);
+                }
+                InlineItem::Open(node) => {
+                    let style = self.style_of(*node);
+                    let width = style.margin[3].resolve_or_zero(containing)
+                        + style.used_border_width(3)
+                        + style.padding[3].resolve_or_zero(containing);
+                    pieces.push(Piece::Open { node: *node, width });
+                }
+                InlineItem::Close(node) => {
+                    let style = self.style_of(*node);
+                    let width = style.margin[1].resolve_or_zero(containing)
+                        + style.used_border_width(1)
+                        + style.padding[1].resolve_or_zero(containing);
+                    pieces.push(Piece::Close { node: *node, width });
+                }
+                InlineItem::Break => {
+                    pieces.push(Piece::Break);
+                    after_space = true;
+                }
+                InlineItem::Atomic(b) => {
+                    let (min, max) = self.intrinsic_widths(b);
+                    let (ml, mr) = (
+                        b.style.margin[3].resolve_or_zero(containing),
+                        b.style.margin[1].resolve_or_zero(containing),
+                    );
+                    pieces.push(Piece::Atomic { b, min: min + ml + mr, max: max + ml + mr });
+                    after_space = false;
+                }
+            }
+        }
+        pieces
+    }
+
+    fn text_pieces(&self, node: NodeId, text: &str, after_space: &mut bool, pieces: &mut Vec<Piece<'_>>) {
+        let style = self.style_of(node);
+        let font = FontSpec::from_style(style);
+        let white_space = style.white_space;
+        let wraps = white_space.wraps();
+        let mut word = String::new();
+        let push_word = |word: &mut String, pieces: &mut Vec<Piece<'_>>, break_after: bool| {
+            if !word.is_empty() {
+                let text = std::mem::take(word);
+                pieces.push(Piece::Text {
+                    node,
+                    width: self.host.text_width(&text, &font),
+                    text,
+                    space: false,
+                    break_after,
+                });
+            }
+        };
+        let space_piece = |text: &str| Piece::Text {
+            node,
+            text: text.to_string(),
+            width: self.host.text_width(text, &font),
+            space: true,
+            break_after: wraps,
+        };
+        for c in text.chars() {
+            match c {
+                '\n' if white_space.preserves_newlines() => {
+                    push_word(&mut word, pieces, false);
+                    pieces.push(Piece::Break);
+                    *after_space = true;
+                }
+                ' ' | '\t' | '\n' | '\r' | '\x0C' if white_space.collapses_spaces() => {
+                    push_word(&mut word, pieces, false);
+                    if !*after_space {
+                        pieces.push(space_piece(" "));
+                        *after_space = true;
+                    }
+                }
+                ' ' | '\t' => {
+                    push_word(&mut word, pieces, false);
+                    pieces.push(space_piece(if c == '\t' { "        " } else { " " }));
+                    *after_space = false;
+                }
+                c if wraps && is_cjk(c) => {
+                    // 日本語などは文字ごとに改行できる
+                    push_word(&mut word, pieces, true);
+                    word.push(c);
+                    push_word(&mut word, pieces, true);
+                    *after_space = false;
+                }
+                '\r' => {}
+                c => {
+                    word.push(c);
+                    *after_space = false;
+                }
+            }
+        }
+        push_word(&mut word, pieces, false);
+    }
+
+    // ---- intrinsic sizes -----------------------------------------------------
+
+    /// Min-content and max-content widths of `b`'s border box.
+    fn intrinsic_widths(&self, b: &LayoutBox) -> (f32, f32) {
+        let style = &b.style;
+        let horizontal = style.padding[1].resolve_or_zero(0.0)
+            + style.padding[3].resolve_or_zero(0.0)
+            + style.used_border_width(1)
+            + style.used_border_width(3);
+        if let Dimension::Px(width) = style.width {
+            return (width + horizontal, width + horizontal);
+        }
+        let (min, max) = match (&b.kind, &b.content) {
+            (BoxKind::Replaced, _) => {
+                let size = self.replaced_size(b);
+                (size.x, size.x)
+            }
+            (BoxKind::Table, Content::Blocks(rows)) => {
+                let (columns, caption) = self.column_widths(rows);
+                let spacing = style.border_spacing * (columns.len() + 1) as f32;
+                let min = columns.iter().map(|c| c.0).sum::<f32>() + spacing;
+                let max = columns.iter().map(|c| c.1).sum::<f32>() + spacing;
+                (min.max(caption.0), max.max(caption.1))
+            }
+            (_, Content::Blocks(children)) => children.iter().fold((0.0_f32, 0.0_f32), |(min, max), child| {
+                let (child_min, child_max) = self.intrinsic_widths(child);
+                let margins = child.style.margin[1].resolve_or_zero(0.0) + child.style.margin[3].resolve_or_zero(0.0);
+                (min.max(child_min + margins), max.max(child_max + margins))
+            }),
+            (_, Content::Inline(items)) => {
+                let pieces = self.pieces(items, 0.0);
+                let mut min = 0.0_f32;
+                let mut max = 0.0_f32;
+                let mut run = 0.0;
+                let mut line = 0.0;
+                let mut trailing_space = 0.0;
+                for piece in &pieces {
+                    match piece {
+                        Piece::Break => {
+                            max = max.max(line - trailing_space);
+                            line = 0.0;
+                            run = 0.0;
+                            trailing_space = 0.0;
+                        }
+                        Piece::Text { width, space, break_after, .. } => {
+                            line += width;
+                            trailing_space = if *space { *width } else { 0.0 };
+                            if !*space {
+                                run += width;
+                                min = min.max(run);
+                            }
+                            if *break_after {
+                                run = 0.0;
+                            }
+                        }
+                        Piece::Open { width, .. } | Piece::Close { width, .. } => {
+                            line += width;
+                            run += width;
+                        }
+                        Piece::Atomic { min: atomic_min, max: atomic_max, .. } => {
+                            min = min.max(*atomic_min);
+                            line += atomic_max;
+                            run = 0.0;
+                            trailing_space = 0.0;
+                        }
+                    }
+                }
+                (min, max.max(line - trailing_space))
+            }
+        };
+        let min = clamp_width(style, min, 0.0);
+        let max = clamp_width(style, max, 0.0);
+        (min + horizontal, max.max(min) + horizontal)
+    }
+
+    /// Content size of a replaced element.
+    fn replaced_size(&self, b: &LayoutBox) -> Vec2 {
+        let Some(node) = b.node else {
+            return Vec2::ZERO;
+        };
+        let element = self.doc.element(node);
+        let attr = |name: &str| element.and_then(|e| e.attr(name));
+        let style = &b.style;
+        let font = FontSpec::from_style(style);
+        let line = style.line_height_px();
+        let natural = match self.doc.tag_name(node).unwrap_or("") {
+            "img" => self.host.image_size(node).unwrap_or_else(|| {
+                match attr("alt").filter(|alt| !alt.is_empty()) {
+                    Some(alt) => vec2(self.host.text_width(alt, &font) + 4.0, line + 4.0),
+                    None => vec2(16.0, 16.0),
+                }
+            }),
+            "input" => match attr("type").unwrap_or("text").to_ascii_lowercase().as_str() {
+                "checkbox" | "radio" => vec2(13.0, 13.0),
+                "submit" | "button" | "reset" => {
+                    let label = attr("value").unwrap_or(match attr("type") {
+                        Some("reset") => "Reset",
+                        _ => "Submit",
+                    });
+                    vec2(self.host.text_width(label, &font) + 12.0, line)
+                }
+                "image" => vec2(16.0, 16.0),
+                _ => {
+                    let size = attr("size").and_then(|s| s.trim().parse::<f32>().ok()).unwrap_or(20.0);
+                    vec2(size * style.font_size * 0.5, line)
+                }
+            },
+            "textarea" => {
+                let cols = attr("cols").and_then(|s| s.trim().parse::<f32>().ok()).unwrap_or(20.0);
+                let rows = attr("rows").and_then(|s| s.trim().parse::<f32>().ok()).unwrap_or(2.0);
+                vec2(cols * style.font_size * 0.5, rows * line)
+            }
+            "select" => {
+                let widest = self
+                    .doc
+                    .descendants(node)
+                    .into_iter()
+                    .filter(|n| self.doc.is_element_named(*n, "option"))
+                    .map(|n| self.host.text_width(self.doc.text_content(n).trim(), &font))
+                    .fold(0.0, f32::max);
+                vec2(widest + 24.0, line * crate::forms::select_size(self.doc, node) as f32)
+            }
+            _ => vec2(300.0, 150.0),
+        };
+        // width/height のどちらかだけ指定されたら縦横比を保つ
+        match (style.width, style.height) {
+            (Dimension::Px(w), Dimension::Px(h)) => vec2(w, h),
+            (Dimension::Px(w), _) if natural.x > 0.0 => vec2(w, natural.y * w / natural.x),
+            (_, Dimension::Px(h)) if natural.y > 0.0 => vec2(natural.x * h / natural.y, h),
+            _ => natural,
+        }
+    }
+
+    // ---- block layout --------------------------------------------------------
+
+    /// Used margins and border-box width of a block-level box inside a
+    /// containing block of `containing` pixels.
+    fn horizontal_metrics(&self, b: &LayoutBox, containing: f32) -> (f32, f32, f32) {
+        let style = &b.style;
+        let horizontal = style.padding[1].resolve_or_zero(containing)
+            + style.padding[3].resolve_or_zero(containing)
+            + style.used_border_width(1)
+            + style.used_border_width(3);
+        let margin_left = style.margin[3].resolve(containing);
+        let margin_right = style.margin[1].resolve(containing);
+        let specified = match b.kind {
+            BoxKind::Replaced => Some(self.replaced_size(b).x),
+            _ => style.width.resolve(containing),
+        };
+        let width = match specified {
+            Some(width) => clamp_width(style, width, containing),
+            None if matches!(b.kind, BoxKind::Table | BoxKind::InlineBlock) => {
+                // 縮小して内容に合わせる (shrink-to-fit)
+                let available = containing - margin_left.unwrap_or(0.0) - margin_right.unwrap_or(0.0);
+                let (min, max) = self.intrinsic_widths(b);
+                clamp_width(style, max.min(available).max(min) - horizontal, containing)
+            }
+            None => {
+                let available = containing - margin_left.unwrap_or(0.0) - margin_right.unwrap_or(0.0) - horizontal;
+                clamp_width(style, available, containing)
+            }
+        };
+        let remaining = containing - width - horizontal;
+        let (left, right) = match (margin_left, margin_right) {
+            (None, None) => (remaining.max(0.0) / 2.0, remaining.max(0.0) / 2.0),
+            (None, Some(right)) => (remaining - right, right),
+            (Some(left), _) => (left, remaining - left),
+        };
+        (left, right, width + horizontal)
+    }
+
+    /// Lays out `b` with its border box at `(x, y)` and returns the border-box height.
+    fn layout_block(&mut self, b: &LayoutBox, x: f32, y: f32, width: f32, min_height: Option<f32>) -> f32 {
+        let style = &b.style;
+        let containing = width;
+        let padding = style.padding.map(|p| p.resolve_or_zero(containing));
+        let border = [0, 1, 2, 3].map(|side| style.used_border_width(side));
+        let content_x = x + border[3] + padding[3];
+        let content_y = y + border[0] + padding[0];
+        let content_width = (width - border[1] - border[3] - padding[1] - padding[3]).max(0.0);
+        // 仮配置で測ったあとに本配置するので、後から来た位置で上書きする
+        if let Some(node) = b.node {
+            self.anchors.insert(node, y);
+        }
+
+        let background_index = self.items.len();
+        let paints = style.visible && b.node.is_some();
+        if paints && !style.background_color.is_transparent() {
+            self.items.push(DisplayItem::Rect {
+                rect: Rect::NOTHING,
+                color: style.background_color,
+            });
+        }
+        let background_image_index = self.items.len();
+        if paints
+            && style.background_image.is_some()
+            && let Some(node) = b.node
+        {
+            self.items.push(DisplayItem::BackgroundImage { rect: Rect::NOTHING, node });
+        }
+        let border_index = self.items.len();
+        if paints && border.iter().any(|w| *w > 0.0) {
+            self.items.push(DisplayItem::Border {
+                rect: Rect::NOTHING,
+                widths: border,
+                styles: style.border_style,
+                colors: [0, 1, 2, 3].map(|side| style.used_border_color(side)),
+            });
+        }
+
+        let content_height = match (&b.kind, &b.content) {
+            (BoxKind::Replaced, _) => {
+                let size = vec2(content_width, self.replaced_size(b).y);
+                self.push_replaced(b, Rect::from_min_size(pos2(content_x, content_y), size));
+                size.y
+            }
+            (BoxKind::Table, Content::Blocks(rows)) => self.layout_table(b, rows, content_x, content_y, content_width),
+            (_, Content::Blocks(children)) => self.layout_children(children, content_x, content_y, content_width),
+            (_, Content::Inline(items)) => self.layout_inline(items, style, content_x, content_y, content_width),
+        };
+        let mut height = match style.height {
+            Dimension::Px(h) if b.kind != BoxKind::Replaced => h,
+            _ => content_height,
+        };
+        if let Dimension::Px(min) = style.min_height {
+            height = height.max(min);
+        }
+        if let Dimension::Px(max) = style.max_height {
+            height = height.min(max);
+        }
+        let mut border_height = height + padding[0] + padding[2] + border[0] + border[2];
+        if let Some(min_height) = min_height {
+            border_height = border_height.max(min_height);
+        }
+
+        let rect = Rect::from_min_size(pos2(x, y), vec2(width, border_height));
+        for index in [background_index, background_image_index, border_index] {
+            match self.items.get_mut(index) {
+                Some(DisplayItem::Rect { rect: r, .. } | DisplayItem::BackgroundImage { rect: r, .. } | DisplayItem::Border { rect: r, .. })
+                    if *r == Rect::NOTHING =>
+                {
+                    *r = rect
+                }
+                _ => {}
+            }
+        }
+        if b.kind == BoxKind::ListItem
+            && style.list_style_type != ListStyleType::None
+            && let Some(node) = b.node
+        {
+            self.push_marker(node, style, content_x, content_y);
+        }
+        border_height
+    }
+
+    fn push_replaced(&mut self, b: &LayoutBox, rect: Rect) {
+        let Some(node) = b.node else {
+            return;
+        };
+        if !b.style.visible {
+            return;
+        }
+        let element = self.doc.element(node);
+        let attr = |name: &str| element.and_then(|e| e.attr(name)).unwrap_or("").to_string();
+        let item = match self.doc.tag_name(node).unwrap_or("") {
+            "img" => DisplayItem::Image { rect, node, alt: attr("alt") },
+            "input" => {
+                let value = attr("value");
+                let label = if value.is_empty() { attr("placeholder") } else { value };
+                DisplayItem::Replaced { rect, node, label }
+            }
+            "textarea" => DisplayItem::Replaced { rect, node, label: self.doc.text_content(node) },
+            "select" => {
+                let options: Vec<NodeId> = self
+                    .doc
+                    .descendants(node)
+                    .into_iter()
+                    .filter(|n| self.doc.is_element_named(*n, "option"))
+                    .collect();
+                let selected = options
+                    .iter()
+                    .find(|n| self.doc.element(**n).is_some_and(|e| e.has_attr("selected")))
+                    .or(options.first());
+                let label = selected.map(|n| self.doc.text_content(*n).trim().to_string()).unwrap_or_default();
+                DisplayItem::Replaced { rect, node, label }
+            }
+            name => DisplayItem::Replaced { rect, node, label: format!("<{}>", name) },
+        };
+        self.items.push(item);
+    }
+
+    fn push_marker(&mut self, node: NodeId, style: &ComputedStyle, content_x: f32, content_y: f32) {
+        let text = match style.list_style_type {
+            ListStyleType::Disc => "•".to_string(),
+            ListStyleType::Circle => "◦".to_string(),
+            ListStyleType::Square => "▪".to_string(),
+            ListStyleType::None => return,
+            list => {
+                let index = self.list_index(node);
+                let label = match list {
+                    ListStyleType::LowerAlpha => alpha_label(index).to_lowercase(),
+                    ListStyleType::UpperAlpha => alpha_label(index),
+                    ListStyleType::LowerRoman => roman_label(index).to_lowercase(),
+                    ListStyleType::UpperRoman => roman_label(index),
+                    _ => index.to_string(),
+                };
+                format!("{}.", label)
+            }
+        };
+        let font = FontSpec::from_style(style);
+        let width = self.host.text_width(&text, &font);
+        let line = style.line_height_px();
+        let top = content_y + (line - style.font_size) / 2.0;
+        self.items.push(DisplayItem::Text {
+            rect: Rect::from_min_size(pos2(content_x - width - 8.0, top), vec2(width, style.font_size)),
+            text,
+            node,
+            font,
+            color: style.color,
+            decoration: TextDecoration::default(),
+        });
+    }
+
+    /// 1-based ordinal of a list item, honouring `<ol start>` and `<li value>`.
+    fn list_index(&self, node: NodeId) -> i64 {
+        let Some(parent) = self.doc.parent(node) else {
+            return 1;
+        };
+        let parent_element = self.doc.element(parent);
+        let reversed = parent_element.is_some_and(|e| e.has_attr("reversed"));
+        let items: Vec<NodeId> = self
+            .doc
+            .children(parent)
+            .iter()
+            .copied()
+            .filter(|c| self.styles.get(*c).is_some_and(|s| s.display == Display::ListItem))
+            .collect();
+        let start = parent_element
+            .and_then(|e| e.attr("start"))
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or(if reversed { items.len() as i64 } else { 1 });
+        let mut value = start;
+        for item in items {
+            if let Some(explicit) = self.doc.element(item).and_then(|e| e.attr("value")).and_then(|v| v.trim().parse().ok()) {
+                value = explicit;
+            }
+            if item == node {
+                return value;
+            }
+            value += if reversed { -1 } else { 1 };
+        }
+        value
+    }
+
+    /// Stacks block children vertically, collapsing adjacent sibling margins.
+    fn layout_children(&mut self, children: &[LayoutBox], x: f32, y: f32, width: f32) -> f32 {
+        let mut cursor = y;
+        let mut previous_margin: Option<f32> = None;
+        for child in children {
+            let (margin_left, _, border_width) = self.horizontal_metrics(child, width);
+            let margin_top = child.style.margin[0].resolve_or_zero(width);
+            let margin_bottom = child.style.margin[2].resolve_or_zero(width);
+            cursor += match previous_margin {
+                Some(previous) => collapse_margins(previous, margin_top),
+                None => margin_top,
+            };
+            let height = self.layout_block(child, x + margin_left, cursor, border_width, None);
+            if height == 0.0 && matches!(child.content, Content::Inline(_)) && child.node.is_none() {
+                // 空の匿名ブロックはマージンを分断しない
+                cursor -= margin_top;
+                continue;
+            }
+            cursor += height;
+            previous_margin = Some(margin_bottom);
+        }
+        cursor += previous_margin.unwrap_or(0.0);
+        cursor - y
+    }
+
+    // ---- tables --------------------------------------------------------------
+
+    /// Per-column (min, max) widths and the caption's (min, max).
+    fn column_widths(&self, rows: &[LayoutBox]) -> (Vec<(f32, f32)>, (f32, f32)) {
+        let mut columns: Vec<(f32, f32)> = Vec::new();
+        let mut caption = (0.0_f32, 0.0_f32);
+        let mut spans = Vec::new();
+        for row in rows {
+            let Content::Blocks(cells) = &row.content else {
+                continue;
+            };
+            if row.kind != BoxKind::TableRow {
+                let (min, max) = self.intrinsic_widths(row);
+                caption = (caption.0.max(min), caption.1.max(max));
+                continue;
+            }
+            let mut column = 0;
+            for cell in cells {
+                let span = cell_span(self.doc, cell);
+                let widths = self.intrinsic_widths(cell);
+                if columns.len() < column + span {
+                    columns.resize(column + span, (0.0, 0.0));
+                }
+                if span == 1 {
+                    columns[column].0 = columns[column].0.max(widths.0);
+                    columns[column].1 = columns[column].1.max(widths.1);
+                } else {
+                    spans.push((column, span, widths));
+                }
+                column += span;
+            }
+        }
+        // 複数列にまたがるセルは、足りない分を列に均等に配る
+        for (start, span, (min, max)) in spans {
+            let range = start..start + span;
+            let current_min: f32 = columns[range.clone()].iter().map(|c| c.0).sum();
+            let current_max: f32 = columns[range.clone()].iter().map(|c| c.1).sum();
+            for column in &mut columns[range] {
+                column.0 += (min - current_min).max(0.0) / span as f32;
+                column.1 += (max - current_max).max(0.0) / span as f32;
+            }
+        }
+        (columns, caption)
+    }
+
+    fn layout_table(&mut self, table: &LayoutBox, rows: &[LayoutBox], x: f32, y: f32, width: f32) -> f32 {
+        let spacing = table.style.border_spacing;
+        let (columns, _) = self.column_widths(rows);
+        let count = columns.len();
+        let available = (width - spacing * (count + 1) as f32).max(0.0);
+        let min_total: f32 = columns.iter().map(|c| c.0).sum();
+        let max_total: f32 = columns.iter().map(|c| c.1).sum();
+        let widths: Vec<f32> = if max_total <= available {
+            // 余った幅は最大幅の比で配る
+            let extra = available - max_total;
+            columns
+                .iter()
+                .map(|c| c.1 + if max_total > 0.0 { extra * c.1 / max_total } else { extra / count as f32 })
+                .collect()
+        } else if min_total >= available {
+            columns.iter().map(|c| c.0).collect()
+        } else {
+            let flexible = max_total - min_total;
+            columns
+                .iter()
+                .map(|c| c.0 + (available - min_total) * (c.1 - c.0) / flexible.max(f32::EPSILON))
+                .collect()
+        };
+
+        let mut cursor = y;
+        let mut started = false;
+        for row in rows {
+            if row.kind != BoxKind::TableRow {
+                // キャプションは表の上に置く
+                let (margin_left, _, border_width) = self.horizontal_metrics(row, width);
+                cursor += self.layout_block(row, x + margin_left, cursor, border_width, None);
+                continue;
+            }
+            if !started {
+                cursor += spacing;
+                started = true;
+            }
+            let Content::Blocks(cells) = &row.content else {
+                continue;
+            };
+            // 行の高さを決めるため、まず仮に配置して測る
+            let mark = self.items.len();
+            let mut row_height = match row.style.height {
+                Dimension::Px(h) => h,
+                _ => 0.0,
+            };
+            let mut column = 0;
+            for cell in cells {
+                let span = cell_span(self.doc, cell);
+                let cell_width = cell_width(&widths, column, span, spacing);
+                row_height = row_height.max(self.layout_block(cell, 0.0, 0.0, cell_width, None));
+                column += span;
+            }
+            self.items.truncate(mark);
+
+            let row_width = widths.iter().sum::<f32>() + spacing * (count.saturating_sub(1)) as f32;
+            if row.node.is_some() && row.style.visible && !row.style.background_color.is_transparent() {
+                self.items.push(DisplayItem::Rect {
+                    rect: Rect::from_min_size(pos2(x + spacing, cursor), vec2(row_width, row_height)),
+                    color: row.style.background_color,
+                });
+            }
+            let mut column = 0;
+            let mut cell_x = x + spacing;
+            for cell in cells {
+                let span = cell_span(self.doc, cell);
+                let cell_width = cell_width(&widths, column, span, spacing);
+                self.layout_block(cell, cell_x, cursor, cell_width, Some(row_height));
+                cell_x += cell_width + spacing;
+                column += span;
+            }
+            cursor += row_height + spacing;
+        }
+        cursor - y
+    }
+
+    // ---- inline layout -------------------------------------------------------
+
+    /// Breaks inline content into lines and returns the height of all lines.
+    fn layout_inline(&mut self, items: &[InlineItem], block_style: &ComputedStyle, x: f32, y: f32, width: f32) -> f32 {
+        let pieces = self.pieces(items, width);
+        // インラインブロックや画像は先に大きさを決めておく
+        let atomic_sizes: Vec<Option<(Vec2, f32, f32)>> = pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Atomic { b, min, max } => {
+                    let margin_left = b.style.margin[3].resolve_or_zero(width);
+                    let margin_right = b.style.margin[1].resolve_or_zero(width);
+                    let outer = max.min(width).max(*min);
+                    let border_width = outer - margin_left - margin_right;
+                    let mark = self.items.len();
+                    let height = self.layout_block(b, 0.0, 0.0, border_width, None);
+                    self.items.truncate(mark);
+                    let margin_top = b.style.margin[0].resolve_or_zero(width);
+                    let margin_bottom = b.style.margin[2].resolve_or_zero(width);
+                    Some((vec2(outer, height + margin_top + margin_bottom), margin_left, margin_top))
+                }
+                _ => None,
+            })
+            .collect();
+        let piece_width = |index: usize| match &pieces[index] {
+            Piece::Text { width, .. } | Piece::Open { width, .. } | Piece::Close { width, .. } => *width,
+            Piece::Atomic { .. } => atomic_sizes[index].map_or(0.0, |s| s.0.x),
+            Piece::Break => 0.0,
+        };
+        let breakable_after = |index: usize| match &pieces[index] {
+            Piece::Text { break_after, .. } => *break_after,
+            Piece::Atomic { .. } => true,
+            _ => false,
+        };
+
+        // 行分割
+        let indent = block_style.text_indent.resolve_or_zero(width);
+        let mut lines: Vec<(Vec<usize>, bool)> = Vec::new();
+        let mut current: Vec<usize> = Vec::new();
+        let mut current_width = 0.0;
+        let mut last_opportunity: Option<usize> = None;
+        for index in 0..pieces.len() {
+            if matches!(pieces[index], Piece::Break) {
+                lines.push((std::mem::take(&mut current), true));
+                current_width = 0.0;
+                last_opportunity = None;
+                continue;
+            }
+            let is_collapsible_space = matches!(&pieces[index], Piece::Text { space: true, node, .. }
+                if self.style_of(*node).white_space.collapses_spaces());
+            let has_content = current.iter().any(|i| pieces[*i].has_content());
+            if is_collapsible_space && !has_content {
+                continue;
+            }
+            let available = if lines.is_empty() { width - indent } else { width };
+            let w = piece_width(index);
+            let opportunity_before = current.last().is_some_and(|last| breakable_after(*last))
+                || matches!(pieces[index], Piece::Atomic { .. });
+            if current_width + w > available && has_content && !is_collapsible_space {
+                if opportunity_before {
+                    lines.push((std::mem::take(&mut current), false));
+                    current_width = 0.0;
+                    last_opportunity = None;
+                } else if let Some(split) = last_opportunity {
+                    let rest = current.split_off(split);
+                    lines.push((std::mem::replace(&mut current, rest), false));
+                    // 次の行の先頭の空白は捨てる
+                    current.retain(|i| !matches!(&pieces[*i], Piece::Text { space: true, node, .. }
+                        if self.style_of(*node).white_space.collapses_spaces()));
+                    current_width = current.iter().map(|i| piece_width(*i)).sum();
+                    last_opportunity = None;
+                }
+            }
+            current.push(index);
+            current_width += w;
+            if breakable_after(index) {
+                last_opportunity = Some(current.len());
+            }
+        }
+        if !current.is_empty() {
+            lines.push((current, false));
+        }
+
+        // 行の配置
+        let strut_line = block_style.line_height_px();
+        let strut_ascent = (strut_line - block_style.font_size) / 2.0 + block_style.font_size * 0.8;
+        let mut cursor = y;
+        let mut open: Vec<(NodeId, f32)> = Vec::new();
+        for (line_index, (line, forced)) in lines.iter().enumerate() {
+            let mut line: Vec<usize> = line.clone();
+            // 行末の空白は幅に数えない
+            while let Some(&last) = line.iter().rev().find(|i| !matches!(pieces[**i], Piece::Close { .. })) {
+                let trailing = matches!(&pieces[last], Piece::Text { space: true, node, .. }
+                    if self.style_of(*node).white_space.collapses_spaces());
+                if !trailing {
+                    break;
+                }
+                line.retain(|i| *i != last);
+            }
+            let has_content = line.iter().any(|i| pieces[*i].has_content())
+                || line.iter().any(|i| matches!(pieces[*i], Piece::Text { .. }));
+            if !has_content && !*forced {
+                // 空白しかない行は高さを持たない
+                for &index in &line {
+                    match &pieces[index] {
+                        Piece::Open { node, .. } => {
+                            self.anchors.insert(*node, cursor);
+                            open.push((*node, x));
+                        }
+                        Piece::Close { node, .. } => open.retain(|(n, _)| n != node),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+
+            // 互換モードでは文字の無い行 (画像だけの行など) に strut を入れない
+            let text_less = !line.iter().any(|i| matches!(pieces[*i], Piece::Text { .. }))
+                && line.iter().any(|i| matches!(pieces[*i], Piece::Atomic { .. }));
+            let (mut ascent, mut descent) = if text_less && self.doc.mode != QuirksMode::NoQuirks {
+                (0.0, 0.0)
+            } else {
+                (strut_ascent, strut_line - strut_ascent)
+            };
+            for &index in &line {
+                match &pieces[index] {
+                    Piece::Text { node, .. } => {
+                        let style = self.style_of(*node);
+                        let line_height = style.line_height_px();
+                        let a = (line_height - style.font_size) / 2.0 + style.font_size * 0.8;
+                        let shift = baseline_shift(style);
+                        ascent = ascent.max(a - shift);
+                        descent = descent.max(line_height - a + shift);
+                    }
+                    Piece::Atomic { .. } => {
+                        ascent = ascent.max(atomic_sizes[index].map_or(0.0, |s| s.0.y));
+                    }
+                    _ => {}
+                }
+            }
+            let line_height = ascent + descent;
+            let baseline = cursor + ascent;
+
+            let line_width: f32 = line.iter().map(|i| piece_width(*i)).sum();
+            let start_x = x + if line_index == 0 { indent } else { 0.0 };
+            let available = width - (start_x - x);
+            let offset = match block_style.text_align {
+                TextAlign::Center => ((available - line_width) / 2.0).max(0.0),
+                TextAlign::Right => (available - line_width).max(0.0),
+                TextAlign::Left | TextAlign::Justify => 0.0,
+            };
+
+            let line_start = self.items.len();
+            let mut fragments: Vec<DisplayItem> = Vec::new();
+            let mut pen = start_x + offset;
+            for entry in open.iter_mut() {
+                entry.1 = pen;
+            }
+            let mut run: Option<(NodeId, String, f32, f32)> = None;
+            for &index in &line {
+                let w = piece_width(index);
+                match &pieces[index] {
+                    Piece::Text { node, text, .. } => {
+                        match &mut run {
+                            Some((run_node, run_text, _, run_width)) if run_node == node => {
+                                run_text.push_str(text);
+                                *run_width += w;
+                            }
+                            _ => {
+                                self.flush_run(run.take(), baseline);
+                                run = Some((*node, text.clone(), pen, w));
+                            }
+                        }
+                    }
+                    Piece::Open { node, .. } => {
+                        self.flush_run(run.take(), baseline);
+                        self.anchors.insert(*node, cursor);
+                        open.push((*node, pen));
+                    }
+                    Piece::Close { node, .. } => {
+                        self.flush_run(run.take(), baseline);
+                        if let Some(position) = open.iter().rposition(|(n, _)| n == node) {
+                            let (_, start) = open.remove(position);
+                            self.inline_fragment(*node, start, pen + w, baseline, true, &mut fragments);
+                        }
+                    }
+                    Piece::Atomic { b, .. } => {
+                        self.flush_run(run.take(), baseline);
+                        if let Some((size, margin_left, margin_top)) = atomic_sizes[index] {
+                            let margin_right = b.style.margin[1].resolve_or_zero(width);
+                            let border_width = size.x - margin_left - margin_right;
+                            self.layout_block(b, pen + margin_left, baseline - size.y + margin_top, border_width, None);
+                        }
+                    }
+                    Piece::Break => {}
+                }
+                pen += w;
+            }
+            self.flush_run(run.take(), baseline);
+            // 行をまたぐインライン要素はここで一旦切る
+            for (node, start) in &open {
+                self.inline_fragment(*node, *start, pen, baseline, false, &mut fragments);
+            }
+            self.items.splice(line_start..line_start, fragments);
+            cursor += line_height;
+        }
+        cursor - y
+    }
+
+    fn flush_run(&mut self, run: Option<(NodeId, String, f32, f32)>, baseline: f32) {
+        let Some((node, text, x, width)) = run else {
+            return;
+        };
+        let style = self.style_of(node);
+        if !style.visible {
+            return;
+        }
+        let top = baseline - style.font_size * 0.8 + baseline_shift(style);
+        self.items.push(DisplayItem::Text {
+            rect: Rect::from_min_size(pos2(x, top), vec2(width, style.font_size)),
+            text,
+            node,
+            font: FontSpec::from_style(style),
+            color: style.color,
+            decoration: style.text_decoration,
+        });
+    }
+
+    /// Background and borders of one line's worth of an inline element.
+    fn inline_fragment(&self, node: NodeId, start: f32, end: f32, baseline: f32, closes: bool, out: &mut Vec<DisplayItem>) {
+        let style = self.style_of(node);
+        if !style.visible {
+            return;
+        }
+        let padding_top = style.padding[0].resolve_or_zero(0.0);
+        let padding_bottom = style.padding[2].resolve_or_zero(0.0);
+        let top = baseline - style.font_size * 0.8 - padding_top - style.used_border_width(0);
+        let bottom = baseline + style.font_size * 0.25 + padding_bottom + style.used_border_width(2);
+        let margin_left = style.margin[3].resolve_or_zero(0.0);
+        let margin_right = if closes { style.margin[1].resolve_or_zero(0.0) } else { 0.0 };
+        let rect = Rect::from_min_max(pos2(start + margin_left, top), pos2((end - margin_right).max(start + margin_left), bottom));
+        if !style.background_color.is_transparent() {
+            out.push(DisplayItem::Rect { rect, color: style.background_color });
+        }
+        let widths = [0, 1, 2, 3].map(|side| style.used_border_width(side));
+        if widths.iter().any(|w| *w > 0.0) {
+            out.push(DisplayItem::Border {
+                rect,
+                widths,
+                styles: style.border_style,
+                colors: [0, 1, 2, 3].map(|side| style.used_border_color(side)),
+            });
+        }
+    }
+}
+
+fn collapse_margins(a: f32, b: f32) -> f32 {
+    match (a >= 0.0, b >= 0.0) {
+        (true, true) => a.max(b),
+        (false, false) => a.min(b),
+        _ => a + b,
+    }
+}
+
+fn clamp_width(style: &ComputedStyle, width: f32, containing: f32) -> f32 {
+    let mut width = width;
+    if let Some(max) = style.max_width.resolve(containing) {
+        width = width.min(max);
+    }
+    width.max(style.min_width.resolve_or_zero(containing)).max(0.0)
+}
+
+fn baseline_shift(style: &ComputedStyle) -> f32 {
+    match style.vertical_align {
+        VerticalAlign::Sub => style.font_size * 0.25,
+        VerticalAlign::Super => -style.font_size * 0.4,
+        _ => 0.0,
+    }
+}
+
+fn cell_span(doc: &Document, cell: &LayoutBox) -> usize {
+    cell.node
+        .and_then(|n| doc.element(n))
+        .and_then(|e| e.attr("colspan"))
+        .and_then(|s| s.trim().parse::<usize>().ok())
+        .unwrap_or(1)
+        .clamp(1, 1000)
+}
+
+fn cell_width(widths: &[f32], column: usize, span: usize, spacing: f32) -> f32 {
+    let end = (column + span).min(widths.len());
+    widths[column.min(end)..end].iter().sum::<f32>() + spacing * (span.saturating_sub(1)) as f32
+}
+
+fn alpha_label(mut index: i64) -> String {
+    if index <= 0 {
+        return index.to_string();
+    }
+    let mut label = Vec::new();
+    while index > 0 {
+        index -= 1;
+        label.push((b'A' + (index % 26) as u8) as char);
+        index /= 26;
+    }
+    label.iter().rev().collect()
+}
+
+fn roman_label(mut index: i64) -> String {
+    if !(1..4000).contains(&index) {
+        return index.to_string();
+    }
+    let table = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+    ];
+    let mut out = String::new();
+    for (value, numeral) in table {
+        while index >= value {
+            out.push_str(numeral);
+            index -= value;
+        }
+    }
+    out
+}
+
+/// Lays out `doc` for a viewport `viewport_width` pixels wide.
+pub fn layout_document(doc: &Document, styles: &StyleMap, host: &dyn LayoutHost, viewport_width: f32) -> PageLayout {
+    let mut layout = PageLayout {
+        viewport_width,
+        ..Default::default()
+    };
+    let Some(root) = doc.document_element() else {
+        return layout;
+    };
+    let Some(root_style) = styles.get(root) else {
+        return layout;
+    };
+    let builder = BoxBuilder { doc, styles };
+    let root_box = builder.build(root, root_style);
+    let mut ctx = LayoutCtx {
+        doc,
+        styles,
+        host,
+        items: Vec::new(),
+        anchors: HashMap::new(),
+    };
+    // キャンバスの背景はルート要素、透明なら body の背景色を使う
+    let canvas = Some(root_style.background_color)
+        .filter(|c| !c.is_transparent())
+        .or_else(|| doc.body().and_then(|b| styles.get(b)).map(|s| s.background_color).filter(|c| !c.is_transparent()))
+        .unwrap_or(Color::WHITE);
+    ctx.items.push(DisplayItem::Rect {
+        rect: Rect::NOTHING,
+        color: canvas,
+    });
+    let (margin_left, _, border_width) = ctx.horizontal_metrics(&root_box, viewport_width);
+    let margin_top = root_box.style.margin[0].resolve_or_zero(viewport_width);
+    let margin_bottom = root_box.style.margin[2].resolve_or_zero(viewport_width);
+    let height = ctx.layout_block(&root_box, margin_left, margin_top, border_width, None);
+
+    let right = ctx
+        .items
+        .iter()
+        .skip(1)
+        .map(|item| item.rect().max.x)
+        .filter(|x| x.is_finite())
+        .fold(viewport_width, f32::max);
+    layout.size = vec2(right, margin_top + height + margin_bottom);
+    ctx.items[0] = DisplayItem::Rect {
+        rect: Rect::from_min_size(pos2(0.0, 0.0), layout.size),
+        color: canvas,
+    };
+    layout.items = ctx.items;
+    layout.anchors = ctx.anchors;
+    layout
+}
+
+// ---- egui ----------------------------------------------------------------------
+
+fn font_id(font: &FontSpec) -> FontId {
+    let family = if font.monospace {
+        FontFamily::Monospace
+    } else {
+        FontFamily::Proportional
+    };
+    FontId::new(font.size, family)
+}
+
+fn color32(color: Color) -> Color32 {
+    Color32::from_rgba_unmultiplied(color.r, color.g, color.b, color.a)
+}
+
+/// Loaded images of a page, as egui textures.
+pub trait ImageTextures {
+    /// Texture and natural size of the `<img>` `node`, or with `background`
+    /// of its CSS background image.
+    fn texture(&self, node: NodeId, background: bool) -> Option<(egui::TextureId, Vec2)>;
+}
+
+/// Measures text with egui's fonts and takes image sizes from the loaded textures.
+pub struct EguiLayoutHost<'f> {
+    pub fonts: &'f egui::text::Fonts,
+    pub images: &'f dyn ImageTextures,
+}
+
+impl LayoutHost for EguiLayoutHost<'_> {
+    fn text_width(&self, text: &str, font: &FontSpec) -> f32 {
+        let id = font_id(font);
+        text.chars().map(|c| self.fonts.glyph_width(&id, c)).sum()
+    }
+
+    fn image_size(&self, node: NodeId) -> Option<Vec2> {
+        self.images.texture(node, false).map(|(_, size)| size)
+    }
+}
+
+/// Paints the items of `layout` that intersect `clip`, with the canvas origin at `origin`.
+pub fn paint(painter: &egui::Painter, origin: egui::Pos2, layout: &PageLayout, clip: Rect, images: &dyn ImageTextures) {
+    let uv = Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0));
+    let offset = origin.to_vec2();
+    for item in &layout.items {
+        let rect = item.rect().translate(offset);
+        if !rect.intersects(clip) {
+            continue;
+        }
+        match item {
+            DisplayItem::Rect { color, .. } => {
+                painter.rect_filled(rect, 0.0, color32(*color));
+            }
+            DisplayItem::Border { widths, styles, colors, .. } => paint_border(painter, rect, widths, styles, colors),
+            DisplayItem::Text { text, font, color, decoration, .. } => {
+                let color = color32(*color);
+                let line = |on: bool| if on { Stroke::new((font.size / 14.0).max(1.0), color) } else { Stroke::NONE };
+                let job = egui::text::LayoutJob::single_section(
+                    text.clone(),
+                    egui::TextFormat {
+                        font_id: font_id(font),
+                        color,
+                        italics: font.italic,
+                        underline: line(decoration.underline),
+                        strikethrough: line(decoration.line_through),
+                        ..Default::default()
+                    },
+                );
+                let galley = painter.layout_job(job);
+                if font.bold {
+                    // 太字フォントが無いので少しずらして重ね塗りする
+                    painter.galley(rect.min + vec2(0.6, 0.0), galley.clone(), color);
+                }
+                painter.galley(rect.min, galley, color);
+                if decoration.overline {
+                    painter.hline(rect.x_range(), rect.top(), line(true));
+                }
+            }
+            DisplayItem::BackgroundImage { node, .. } => {
+                let Some((texture, size)) = images.texture(*node, true) else {
+                    continue;
+                };
+                if size.x < 1.0 || size.y < 1.0 {
+                    continue;
+                }
+                // 左上から敷き詰める (background-repeat: repeat)。見えている範囲のタイルだけ描く
+                let visible = rect.intersect(clip);
+                let painter = painter.with_clip_rect(visible);
+                let first = ((visible.min - rect.min) / size).floor();
+                let mut y = rect.min.y + first.y.max(0.0) * size.y;
+                while y < visible.max.y {
+                    let mut x = rect.min.x + first.x.max(0.0) * size.x;
+                    while x < visible.max.x {
+                        painter.image(texture, Rect::from_min_size(pos2(x, y), size), uv, Color32::WHITE);
+                        x += size.x;
+                    }
+                    y += size.y;
+                }
+            }
+            DisplayItem::Image { node, alt, .. } => {
+                if let Some((texture, _)) = images.texture(*node, false) {
+                    painter.image(texture, rect, uv, Color32::WHITE);
+                    continue;
+                }
+                painter.rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::GRAY), egui::StrokeKind::Inside);
+                if !alt.is_empty() {
+                    painter.with_clip_rect(rect.intersect(clip)).text(
+                        rect.min + vec2(2.0, 2.0),
+                        egui::Align2::LEFT_TOP,
+                        alt,
+                        FontId::proportional(12.0),
+                        Color32::DARK_GRAY,
+                    );
+                }
+            }
+            DisplayItem::Replaced { label, .. } => {
+                painter.rect_filled(rect, 2.0, Color32::from_gray(245));
+                painter.rect_stroke(rect, 2.0, Stroke::new(1.0, Color32::from_gray(150)), egui::StrokeKind::Inside);
+                painter.with_clip_rect(rect.intersect(clip)).text(
+                    rect.left_center() + vec2(3.0, 0.0),
+                    egui::Align2::LEFT_CENTER,
+                    label,
+                    FontId::proportional((rect.height() * 0.7).clamp(8.0, 14.0)),
+                    Color32::BLACK,
+                );
+            }
+        }
+    }
+}
+
+fn paint_border(painter: &egui::Painter, rect: Rect, widths: &[f32; 4], styles: &[BorderStyle; 4], colors: &[Color; 4]) {
+    let [top, right, bottom, left] = *widths;
+    let sides = [
+        Rect::from_min_max(rect.min, pos2(rect.max.x, rect.min.y + top)),
+        Rect::from_min_max(pos2(rect.max.x - right, rect.min.y), rect.max),
+        Rect::from_min_max(pos2(rect.min.x, rect.max.y - bottom), rect.max),
+        Rect::from_min_max(rect.min, pos2(rect.min.x + left, rect.max.y)),
+    ];
+    for side in 0..4 {
+        if widths[side] <= 0.0 || matches!(styles[side], BorderStyle::None | BorderStyle::Hidden) {
+            continue;
+        }
+        let base = color32(colors[side]);
+        // inset/outset などは上左と下右で明暗を変える
+        let top_left = side == 0 || side == 3;
+        let color = match styles[side] {
+            BorderStyle::Inset | BorderStyle::Groove if top_left => base.gamma_multiply(0.6),
+            BorderStyle::Outset | BorderStyle::Ridge if !top_left => base.gamma_multiply(0.6),
+            BorderStyle::Inset | BorderStyle::Outset | BorderStyle::Groove | BorderStyle::Ridge
+                if base == Color32::BLACK =>
+            {
+                Color32::GRAY
+            }
+            _ => base,
+        };
+        painter.rect_filled(sides[side], 0.0, color);
+    }
+}
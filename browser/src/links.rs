@@ -0,0 +1,93 @@
+//! Hyperlinks: the document base URL (`<base href>`), the link under a node
+//! of the page, and the element a URL fragment points at.
+
+use reqwest::Url;
+
+use crate::dom::{Document, NodeId};
+
+/// The URL relative references in `doc` resolve against: the first
+/// `<base href>`, itself resolved against the URL the document came from,
+/// or that URL.
+pub fn base_url(doc: &Document, document_url: &str) -> Option<Url> {
+    let document_url = Url::parse(document_url).ok()?;
+    let base = doc
+        .elements_by_tag_name("base")
+        .into_iter()
+        .find_map(|node| doc.element(node)?.attr("href"))
+        .and_then(|href| document_url.join(href.trim()).ok())
+        // data: など相対 URL の基準にならない URL は無視する
+        .filter(|base| !base.cannot_be_a_base());
+    Some(base.unwrap_or(document_url))
+}
+
+/// Resolves `href` against the base URL of `doc`.
+pub fn resolve(doc: &Document, document_url: &str, href: &str) -> Option<String> {
+    let href = href.trim();
+    match base_url(doc, document_url) {
+        Some(base) => base.join(href).ok().map(String::from),
+        None => Url::parse(href).ok().map(String::from),
+    }
+}
+
+/// The nearest `<a>` or `<area>` with an `href` at or above `node`.
+pub fn link_element(doc: &Document, node: NodeId) -> Option<NodeId> {
+    std::iter::once(node).chain(doc.ancestors(node)).find(|n| {
+        (doc.is_element_named(*n, "a") || doc.is_element_named(*n, "area"))
+            && doc.element(*n).is_some_and(|e| e.has_attr("href"))
+    })
+}
+
+/// Where the link containing `node` goes, resolved to an absolute URL.
+/// Links that can't be followed are left out (see [`is_navigable`]).
+pub fn link_target(doc: &Document, document_url: &str, node: NodeId) -> Option<String> {
+    let link = link_element(doc, node)?;
+    let href = doc.element(link)?.attr("href")?;
+    resolve(doc, document_url, href).filter(|url| is_navigable(url))
+}
+
+/// Whether a link to the absolute URL `url` can be followed: `javascript:`
+/// links can't, since scripts are not run.
+pub fn is_navigable(url: &str) -> bool {
+    !url.starts_with("javascript:")
+}
+
+/// The fragment of `url`, percent-decoded.
+pub fn fragment(url: &str) -> Option<String> {
+    let fragment = Url::parse(url).ok()?.fragment()?.to_string();
+    Some(percent_encoding::percent_decode_str(&fragment).decode_utf8_lossy().into_owned())
+}
+
+/// Whether `a` and `b` are the same URL apart from their fragments.
+pub fn same_resource(a: &str, b: &str) -> bool {
+    let (Ok(mut a), Ok(mut b)) = (Url::parse(a), Url::parse(b)) else {
+        return false;
+    };
+    a.set_fragment(None);
+    b.set_fragment(None);
+    a == b
+}
+
+/// Whether following a link to `target` from the document at `current`
+/// only moves to a fragment of it.
+pub fn is_same_document(current: &str, target: &str) -> bool {
+    target.contains('#') && same_resource(current, target)
+}
+
+/// The element a fragment names: the one with that `id`, else an `<a>` with
+/// that `name`. `None` means the top of the page.
+pub fn fragment_target(doc: &Document, fragment: &str) -> Option<NodeId> {
+    if fragment.is_empty() {
+        return None;
+    }
+    let nodes = doc.descendants(doc.root());
+    nodes
+        .iter()
+        .copied()
+        .find(|n| doc.element(*n).and_then(|e| e.id()) == Some(fragment))
+        .or_else(|| {
+            nodes
+                .iter()
+                .copied()
+                .find(|n| doc.is_element_named(*n, "a") && doc.element(*n).and_then(|e| e.attr("name")) == Some(fragment))
+        })
+}
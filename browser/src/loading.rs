@@ -0,0 +1,89 @@
+//! Page loads in flight: progress reporting from running fetches, the
+//! progress indicator in the URL bar, and the error page shown when a load
+//! fails.
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+use crate::http_client::{FetchError, FetchProgress};
+use crate::{FetchHtmlTask, LoadState};
+
+/// Sent whenever a page fetch of a tab makes progress (bytes or a redirect hop).
+#[derive(Event, Clone, Debug)]
+pub struct FetchProgressed {
+    pub tab: Entity,
+    pub progress: FetchProgress,
+}
+
+// 取得中のタスクから進み具合を受け取り、FetchProgressed イベントにするシステム
+pub fn report_fetch_progress(mut tasks: Query<&mut FetchHtmlTask>, mut events: EventWriter<FetchProgressed>) {
+    for mut fetch in &mut tasks {
+        if !fetch.progress.has_changed().unwrap_or(false) {
+            continue;
+        }
+        let progress = fetch.progress.borrow_and_update().clone();
+        events.write(FetchProgressed { tab: fetch.tab, progress });
+    }
+}
+
+// FetchProgressed を受けて URL バーに出すタブの読み込み状況を更新するシステム
+pub fn track_load_state(mut events: EventReader<FetchProgressed>, mut pages: Query<&mut LoadState>) {
+    for event in events.read() {
+        if let Ok(mut state) = pages.get_mut(event.tab)
+            && matches!(*state, LoadState::Loading(_))
+        {
+            *state = LoadState::Loading(event.progress.clone());
+        }
+    }
+}
+
+/// Draws the progress of a load next to the URL bar.
+pub fn progress_ui(ui: &mut egui::Ui, progress: &FetchProgress) {
+    let received = format!("{} KiB", progress.received.div_ceil(1024));
+    match progress.total {
+        Some(total) if total > 0 => {
+            let fraction = progress.received as f32 / total as f32;
+            ui.add(egui::ProgressBar::new(fraction).desired_width(80.0).text(received));
+        }
+        _ => {
+            ui.spinner();
+            ui.label(received);
+        }
+    }
+    if progress.redirects > 0 {
+        ui.label(format!("({} redirects)", progress.redirects)).on_hover_text(&progress.url);
+    }
+}
+
+/// Draws the page shown instead of the document when a load failed.
+/// Returns whether "Try Again" was clicked.
+pub fn error_page_ui(ui: &mut egui::Ui, url: &str, error: &FetchError) -> bool {
+    let hint = match error {
+        FetchError::InvalidUrl(_) => "Check the address for typing errors.",
+        FetchError::Dns(_) => "Check the address for typing errors and that you are connected to the network.",
+        FetchError::Connect(_) => "The site could be temporarily unavailable or too busy, or a proxy or firewall may be blocking it.",
+        FetchError::Tls(_) => "The certificate of the site could not be verified. `-k` in the curlrc turns verification off.",
+        FetchError::Timeout => "The server at this address is taking too long to respond.",
+        FetchError::TooManyRedirects(_) => "The server is redirecting the request in a way that will never complete.",
+        FetchError::Http(_) => "The server could not give out this page.",
+        FetchError::Gemini { status: 44, .. } => "The capsule asks to slow down; wait a moment before trying again.",
+        FetchError::Gemini { status: 60..=69, .. } => "Put a certificate and its key for this host in `gemini_certs/<host>.pem`, e.g. from `openssl req -x509 -newkey ed25519 -nodes -days 3650 -subj /CN=me -keyout key.pem -out cert.pem`.",
+        FetchError::Gemini { .. } => "The capsule could not give out this page.",
+        FetchError::File(_) => "Check the file name for capitalization or other typing errors, and check that it has not been moved, renamed or deleted.",
+        FetchError::UnsupportedScheme(_) => "The browser doesn't know how to open this address (supported: http, https, gemini, gopher, file, data, p2p and about).",
+        FetchError::Other(_) => "An error occurred while loading this page.",
+    };
+    let mut retry = false;
+    ui.vertical_centered(|ui| {
+        ui.add_space(40.0);
+        ui.heading(error.title());
+        ui.add_space(8.0);
+        ui.label(egui::RichText::new(url).monospace());
+        ui.label(error.to_string());
+        ui.add_space(8.0);
+        ui.label(hint);
+        ui.add_space(16.0);
+        retry = ui.button("Try Again").clicked();
+    });
+    retry
+}
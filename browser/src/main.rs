@@ -1,6 +1,5 @@
 use bevy::{prelude::*, tasks::{AsyncComputeTaskPool, Task}};
 use bevy_egui::EguiPlugin;
-use std::sync::{Arc, Mutex};
 use crate::menu::{CrimeReportData, SafetyMetrics};
 
 mod menu;
@@ -10,23 +9,257 @@ mod animation_logic;
 mod constants;
 mod p2p;
 mod ffmpeg;
+mod dom;
+mod html_tokenizer;
+mod html_parser;
+mod html_analysis;
+mod css;
+mod css_selector;
+mod style;
+mod layout;
+mod history;
+mod tabs;
+mod http_client;
+mod cookies;
+mod http_cache;
+mod charset;
+mod loading;
+mod schemes;
+mod gemini;
+mod gopher;
+mod native_view;
+mod images;
+mod links;
+mod forms;
+mod downloads;
+mod viewers;
+mod bookmarks;
+mod session;
+mod search;
+mod omnibox;
+mod find;
 use bevy_tokio_tasks::TokioTasksPlugin;
 
 #[cfg(not(target_arch = "wasm32"))]
 use argh::FromArgs; // `argh` をインポート
 
-#[derive(Resource, Default)]
-pub struct HtmlContent(pub Arc<Mutex<String>>);
+/// タブ 1 つ。ページの状態は下のコンポーネントとして同じエンティティに持つ
+#[derive(Component, Default)]
+#[require(
+    CurrentUrl,
+    HtmlContent,
+    CurrentDocument,
+    ExternalStylesheets,
+    CurrentStyles,
+    CurrentLayout,
+    BrowsingHistory,
+    CompatModeOverride,
+    ResponseBody,
+    EncodingOverride,
+    DocumentEncoding,
+    LoadState,
+    NativePage,
+    PageImages,
+    ScrollTarget,
+    ScrollPosition,
+    PageForms
+)]
+pub struct Tab;
+/// タブの並び順と選択中のタブ
+#[derive(Resource)]
+pub struct Tabs {
+    pub order: Vec<Entity>,
+    pub active: Entity,
+}
+/// フェッチした HTML のソース
+#[derive(Component, Default, Clone)]
+pub struct HtmlContent(pub String);
+/// デコード前のレスポンス本文 (文字コードを変えて読み直すために残す)
+#[derive(Component, Default, Clone)]
+pub struct ResponseBody {
+    pub url: String,
+    pub bytes: Vec<u8>,
+    pub content_type: Option<String>,
+}
+/// タブごとの文字コードの手動指定 (None なら自動判定)
+#[derive(Component, Default, PartialEq, Eq, Clone, Copy)]
+pub struct EncodingOverride(pub Option<&'static encoding_rs::Encoding>);
+/// 今のページの文字コードと、それを何で決めたか
+#[derive(Component, Clone, Copy)]
+pub struct DocumentEncoding {
+    pub encoding: &'static encoding_rs::Encoding,
+    pub source: charset::EncodingSource,
+}
+impl Default for DocumentEncoding {
+    fn default() -> Self {
+        DocumentEncoding {
+            encoding: encoding_rs::UTF_8,
+            source: charset::EncodingSource::default(),
+        }
+    }
+}
+/// HTML のレイアウトを通さずに egui で直接描くページ (None なら HTML として描く)
+#[derive(Component, Default, Clone)]
+pub enum NativePage {
+    #[default]
+    None,
+    Gemtext(Vec<gemini::Line>),
+    GopherMenu(Vec<gopher::Item>),
+    Text(String),
+    /// Gemini の 10/11 の応答。入力をクエリにして `url` を取り直す
+    Input {
+        url: String,
+        prompt: String,
+        sensitive: bool,
+    },
+    /// JSON のツリー表示 (解析できなければそのエラー)
+    Json(Result<serde_json::Value, String>),
+    /// 画像だけのページ
+    Image(images::ImageState),
+    /// 動画・音声: VideoPlayer エンティティで再生する
+    Media(viewers::MediaState),
+    /// glTF のモデル: 3D シーンに置く
+    Model(viewers::ModelState),
+}
+/// タブのページとして動画やモデルを見せているエンティティの持ち主
+#[derive(Component)]
+pub struct PageViewer {
+    pub tab: Entity,
+    /// 本文を書き出したファイル。片付けるときに消す
+    pub file: std::path::PathBuf,
+}
+/// フェッチした HTML を解析した DOM ツリー
+#[derive(Component, Default, Clone)]
+pub struct CurrentDocument(pub dom::Document);
+/// `<link rel="stylesheet">` や `@import` で読み込んだ外部スタイルシート
+#[derive(Component, Default)]
+pub struct ExternalStylesheets(pub Vec<style::ExternalStylesheet>);
+/// CurrentDocument の各要素に対する計算済みスタイル
+#[derive(Component, Default)]
+pub struct CurrentStyles(pub style::StyleMap);
+/// ページの画像 (`<img>` と背景画像) の読み込み状況とテクスチャ
+#[derive(Component, Default)]
+pub struct PageImages(pub images::ImageSet);
+/// ページのフォーム部品にユーザーが入れた値
+#[derive(Component, Default)]
+pub struct PageForms(pub forms::FormState);
+/// 次に描くときにスクロールさせる先のフラグメント (空ならページの先頭)
+#[derive(Component, Default)]
+pub struct ScrollTarget(pub Option<String>);
+/// ページビューのスクロール位置 (セッションに保存する)
+#[derive(Component, Default)]
+pub struct ScrollPosition {
+    pub offset: Vec2,
+    /// 復元したタブで、ページを読み込んだら戻す位置
+    pub restore: Option<Vec2>,
+}
+/// Html Context View に描画するページのレイアウト結果
+#[derive(Component, Default)]
+pub struct CurrentLayout(pub layout::PageLayout);
 #[derive(Resource, Default)]
 pub struct OtherAI {
     pub api_key: String,
 }
-#[derive(Resource, Default)]
+/// URL バーに表示する URL
+#[derive(Component, Default, Clone)]
 pub struct CurrentUrl(pub String);
+/// 戻る/進むのためのセッション履歴
+#[derive(Component, Default, Clone)]
+pub struct BrowsingHistory(pub history::SessionHistory);
+/// タブの読み込み状況 (URL バーの進捗表示とエラーページに使う)
+#[derive(Component, Default, Clone)]
+pub enum LoadState {
+    #[default]
+    Idle,
+    Loading(http_client::FetchProgress),
+    Failed {
+        url: String,
+        error: http_client::FetchError,
+    },
+}
+#[derive(Component)]
+struct FetchHtmlTask {
+    tab: Entity, // 結果を反映するタブ
+    url: String,
+    task: Task<Result<http_client::Response, http_client::FetchError>>,
+    progress: tokio::sync::watch::Receiver<http_client::FetchProgress>, // 取得中の進み具合
+}
 #[derive(Component)]
-struct FetchHtmlTask(Task<Result<String, String>>); // Result<成功時の文字列, エラー時の文字列>
+struct FetchStylesheetTask {
+    tab: Entity,
+    owner: dom::NodeId, // 読み込み元の <link> / <style> 要素
+    imported: bool,
+    url: String,
+    task: Task<Result<http_client::Response, http_client::FetchError>>,
+}
+#[derive(Component)]
+struct FetchImageTask {
+    tab: Entity,
+    url: String,
+    task: Task<Result<Image, http_client::FetchError>>, // 取得とデコードまで
+}
+#[derive(Component)]
+struct DecodePageImageTask {
+    tab: Entity,
+    url: String, // デコード中にページが変わっていないか確かめる
+    task: Task<Result<Image, http_client::FetchError>>,
+}
+#[derive(Component)]
+struct DownloadTask {
+    id: u64, // Downloads の中のどれか
+    task: Task<Result<(), http_client::FetchError>>,
+    progress: tokio::sync::watch::Receiver<downloads::DownloadProgress>,
+}
+/// ダウンロードの一覧と保存先のディレクトリ
+#[derive(Resource)]
+pub struct Downloads(pub downloads::DownloadList);
+/// ブックマークのツリー (assets の下に RON で保存する)
+#[derive(Resource)]
+pub struct Bookmarks(pub bookmarks::BookmarkStore);
+/// 読み込んだページの全文検索の索引
+#[derive(Resource)]
+pub struct PageIndex(pub search::SearchIndex);
+/// URL でない入力を検索するエンジンの URL (`%s` に検索語が入る)
+#[derive(Resource)]
+pub struct SearchEngine(pub String);
+/// 起動時に読み込んだ前回のセッション (復元し終えたら取り除く)
+#[derive(Resource)]
+pub struct RestoredSession(pub session::Session);
 #[derive(Resource)]
 pub struct ShowHtmlViewer(pub bool);
+/// Html Context View のページ内検索バー (Ctrl+F)
+#[derive(Resource, Default)]
+pub struct FindInPage(pub find::FindBar);
+/// Html Context View の Markup タブの状態
+#[derive(Resource, Default)]
+pub struct MarkupCheckView(pub html_analysis::MarkupCheck);
+/// Html Context View に何を表示するか
+#[derive(Resource, Default, PartialEq, Eq, Clone, Copy)]
+pub enum HtmlViewMode {
+    #[default]
+    Page,
+    Dom,
+    Source,
+    /// 壊れた入れ子などを nana の解析器で調べた結果
+    Markup,
+}
+/// タブごとの互換モードの手動指定 (Auto なら DOCTYPE に従う)
+#[derive(Component, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CompatModeOverride {
+    #[default]
+    Auto,
+    Quirks,
+    Standards,
+}
+impl CompatModeOverride {
+    pub fn forced_mode(self) -> Option<dom::QuirksMode> {
+        match self {
+            CompatModeOverride::Auto => None,
+            CompatModeOverride::Quirks => Some(dom::QuirksMode::Quirks),
+            CompatModeOverride::Standards => Some(dom::QuirksMode::NoQuirks),
+        }
+    }
+}
 #[derive(Resource)]
 pub struct ShowOptionWindow(pub bool);
 #[derive(Resource)]
@@ -37,6 +270,14 @@ pub struct ShowMessageWindow(pub bool);
 pub struct ShowSecurityWindow(pub bool);
 #[derive(Resource)]
 pub struct ShowFfmpegWindow(pub bool);
+#[derive(Resource)]
+pub struct ShowHistoryWindow(pub bool);
+#[derive(Resource)]
+pub struct ShowCookieWindow(pub bool);
+#[derive(Resource)]
+pub struct ShowDownloadsWindow(pub bool);
+#[derive(Resource)]
+pub struct ShowBookmarksWindow(pub bool);
 
 ///Command line arguments for the browser application.
 #[derive(FromArgs, Resource)]
@@ -47,6 +288,36 @@ pub struct Args {
     /// regenerates the asset file; implies `--no-load`
     #[argh(switch)]
     pub save: bool,
+    /// curlrc-style file that configures the HTTP client (defaults to ~/.curlrc)
+    #[argh(option)]
+    pub curlrc: Option<String>,
+    /// name of this node on the p2p network (defaults to the host name)
+    #[argh(option)]
+    pub peer_id: Option<String>,
+    /// UDP port of the p2p node (default 8080)
+    #[argh(option)]
+    pub p2p_port: Option<u16>,
+    /// directory served to other peers as p2p://<peer-id>/
+    #[argh(option)]
+    pub share: Option<String>,
+    /// file of known peers, one `<peer-id> <address>` per line (default peers.txt)
+    #[argh(option)]
+    pub peers: Option<String>,
+    /// don't start the p2p node
+    #[argh(switch)]
+    pub no_p2p: bool,
+    /// directory of Gemini client certificates, `<host>.pem` each (default gemini_certs)
+    #[argh(option)]
+    pub gemini_certs: Option<String>,
+    /// directory downloads are saved to (default downloads)
+    #[argh(option)]
+    pub download_dir: Option<String>,
+    /// start with a new session instead of restoring the tabs and windows of the last one
+    #[argh(switch)]
+    pub fresh_session: bool,
+    /// search URL for text typed in the URL bar, with %s for the query (default: the local page index)
+    #[argh(option)]
+    pub search_engine: Option<String>,
 }
 
 /// The [`AnimationGraph`] asset, which specifies how the animations are to
@@ -63,6 +334,15 @@ pub struct ExampleAnimationWeights { // `pub` をつける
 
 #[derive(Resource, Clone)]
 pub struct TokioRuntimeHandle(pub tokio::runtime::Handle);
+/// curlrc の設定で作った、全フェッチで共有する HTTP クライアント
+#[derive(Resource, Clone)]
+pub struct HttpClient(pub http_client::Client);
+/// gemini:// 用のクライアント (初回に信頼したサーバー証明書を覚えている)
+#[derive(Resource, Clone)]
+pub struct GeminiClient(pub gemini::Client);
+/// p2p:// のページを取り合うローカルのノード (起動できなければ None)
+#[derive(Resource, Clone, Default)]
+pub struct P2pNode(pub Option<std::sync::Arc<p2p::PeerNode>>);
 
 
 fn main() {
@@ -78,34 +358,108 @@ fn main() {
     let tokio_runtime = tokio::runtime::Runtime::new().expect("Failed to create Tokio runtime");
     let tokio_handle = tokio_runtime.handle().clone();
 
+    // curl のスクリプトと同じ設定で HTTP クライアントを作る
+    let curlrc = args.curlrc.as_ref().map(std::path::PathBuf::from).or_else(http_client::CurlConfig::default_path);
+    let curl_config = match &curlrc {
+        Some(path) => {
+            info!("Loading HTTP client settings from {}", path.display());
+            http_client::CurlConfig::load(path).unwrap_or_else(|e| {
+                error!("Failed to read curlrc: {}", e);
+                http_client::CurlConfig::browser_default()
+            })
+        }
+        None => http_client::CurlConfig::browser_default(),
+    };
+    let mut http_client = http_client::Client::new(&curl_config).expect("Failed to build HTTP client");
+    match http_cache::HttpCache::open(std::path::Path::new(http_cache::DEFAULT_DIR)) {
+        Ok(cache) => http_client = http_client.with_cache(cache),
+        Err(e) => error!("Failed to open HTTP cache, continuing without it: {}", e),
+    }
+
+    let search_index = search::SearchIndex::open(std::path::Path::new(search::DEFAULT_DIR)).unwrap_or_else(|e| {
+        error!("Failed to open the search index, starting a new one: {}", e);
+        search::SearchIndex::new(std::path::Path::new(search::DEFAULT_DIR))
+    });
+
+    let gemini_client = gemini::Client::new(
+        Some(gemini::DEFAULT_KNOWN_HOSTS.into()),
+        Some(args.gemini_certs.as_deref().unwrap_or(gemini::DEFAULT_CERT_DIR).into()),
+    );
+
+    // p2p:// 用のノードを起動する (ポートが使えなくてもブラウザは動かす)
+    let p2p_node = if args.no_p2p {
+        None
+    } else {
+        let config = p2p::P2pConfig {
+            peer_id: args.peer_id.clone().unwrap_or_else(p2p::default_peer_id),
+            port: args.p2p_port.unwrap_or(p2p::DEFAULT_PORT),
+            share_dir: args.share.as_ref().map(std::path::PathBuf::from),
+            peers_file: Some(args.peers.as_deref().unwrap_or(p2p::DEFAULT_PEERS_FILE).into()),
+        };
+        tokio_handle.block_on(p2p::PeerNode::start(config)).map_err(|e| error!("Failed to start the p2p node: {}", e)).ok()
+    };
+
+    // 前回のタブとウィンドウを開き直す (--fresh-session なら読まない)
+    let restored = if args.fresh_session {
+        None
+    } else {
+        session::Session::load(std::path::Path::new(session::DEFAULT_FILE))
+    };
+    let search_engine = args
+        .search_engine
+        .clone()
+        .or_else(|| restored.as_ref().and_then(|session| session.search_engine.clone()))
+        .unwrap_or_else(|| search::LOCAL_SEARCH.to_string());
+
     let mut app = App::new();
 
     app.add_plugins(DefaultPlugins)
         .add_plugins(TokioTasksPlugin::default()) // BevyがTokioランタイムを管理するプラグイン
         .add_plugins(EguiPlugin { enable_multipass_for_primary_context: false })
         .insert_resource(TokioRuntimeHandle(tokio_handle)) // TokioRuntimeHandle をリソースとして挿入
+        .insert_resource(HttpClient(http_client))
+        .insert_resource(GeminiClient(gemini_client))
+        .insert_resource(P2pNode(p2p_node))
+        .insert_resource(Downloads(downloads::DownloadList::new(args.download_dir.as_deref().unwrap_or(downloads::DEFAULT_DIR).into())))
+        .insert_resource(PageIndex(search_index))
+        .insert_resource(SearchEngine(search_engine))
+        .insert_resource(Bookmarks(bookmarks::BookmarkStore::load(&bookmarks::store_path())))
         .add_event::<p2p::P2pUdpPacketReceived>()
         .add_event::<img_server::ImageChunkReceived>()
         .add_event::<img_server::ImageReceptionComplete>()
         .add_event::<img_server::ImageReceptionError>()
+        .add_event::<history::Navigate>()
+        .add_event::<tabs::TabAction>()
+        .add_event::<forms::SubmitForm>()
+        .add_event::<downloads::StartDownload>()
+        .add_event::<downloads::DownloadAction>()
+        .add_event::<loading::FetchProgressed>()
 
-        .insert_resource(HtmlContent::default())
-        .insert_resource(CurrentUrl::default())
         .insert_resource(OtherAI::default())
         //.insert_resource(P2pUdpReceiver::default())
         .init_non_send_resource::<ffmpeg::VideoResource>()
         .insert_resource(ShowHtmlViewer(true))
+        .init_resource::<FindInPage>()
+        .init_resource::<MarkupCheckView>()
+        .insert_resource(HtmlViewMode::default())
         .insert_resource(ShowOptionWindow(false))
         .insert_resource(ShowWarningWindow(false))
         .insert_resource(ShowSecurityWindow(false))
         .insert_resource(ShowMessageWindow(false))
         .insert_resource(ShowFfmpegWindow(false))
+        .insert_resource(ShowHistoryWindow(false))
+        .insert_resource(ShowCookieWindow(false))
+        .insert_resource(ShowDownloadsWindow(false))
+        .insert_resource(ShowBookmarksWindow(false))
         .init_resource::<CrimeReportData>()
         .init_resource::<SafetyMetrics>()
         .insert_resource(args)
         .add_systems(Startup, (
             img_server::setup_udp_receiver,
-            menu::setup_ui_panel,
+            (
+                tabs::setup_first_tab,
+                session::restore_session.run_if(resource_exists::<RestoredSession>),
+            ).chain(),
             animation_logic::setup_assets,
             animation_logic::setup_scene,
             animation_ui::setup_ui,
@@ -114,10 +468,51 @@ fn main() {
             ffmpeg::init_video_player_system,
         ))
         .add_systems(Update, (
-            menu::main_input_system,
-            menu::poll_fetch_html_task,
+            // 入力: URL バー・ブックマークバー・タブ操作・ショートカット
+            (
+                session::restore_window_positions.run_if(resource_exists::<RestoredSession>),
+                menu::main_input_system,
+                bookmarks::bookmarks_bar,
+                history::history_shortcut_system,
+                tabs::apply_tab_actions,
+            ).chain(),
+            // ページ読み込み: 履歴移動 → HTML → 外部 CSS → スタイル計算 → 画像・動画・モデル
+            (
+                forms::submit_forms,
+                history::navigate_system,
+                loading::report_fetch_progress,
+                loading::track_load_state,
+                menu::poll_fetch_html_task,
+                history::record_page_title,
+                search::index_loaded_pages,
+                menu::reparse_on_compat_mode_change,
+                menu::redecode_on_encoding_change,
+                forms::reset_form_state,
+                menu::fetch_linked_stylesheets,
+                menu::poll_fetch_stylesheet_tasks,
+                style::restyle_document_system,
+                images::fetch_page_images,
+                images::poll_fetch_image_tasks,
+                viewers::open_page_viewers,
+                viewers::poll_decode_page_image_tasks,
+                viewers::despawn_stale_viewers,
+            ).chain(),
+            // ダウンロード: 開始 → 進み具合 → ボタンの操作
+            (
+                downloads::start_downloads,
+                downloads::poll_download_tasks,
+                downloads::apply_download_actions,
+                ffmpeg::open_downloaded_video,
+            ).chain(),
             menu::html_viewer_system,
             menu::option_window,
+            // ブラウザの管理画面
+            (
+                history::history_window,
+                cookies::cookie_manager_window,
+                downloads::downloads_window,
+                bookmarks::bookmarks_window,
+            ),
             menu::message_window,
             menu::warning_window,
             img_server::poll_udp_packets,
@@ -134,7 +529,11 @@ fn main() {
             menu::warning_window,
             menu::message_window,
         ).chain())
-        .add_systems(Update, animation_logic::init_animations);
+        .add_systems(Update, animation_logic::init_animations)
+        .add_systems(Last, (session::save_session, search::save_search_index));
+    if let Some(session) = restored {
+        app.insert_resource(RestoredSession(session));
+    }
     
     app.run();
 }
@@ -1,15 +1,49 @@
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts};
-use crate::{TokioRuntimeHandle, AsyncComputeTaskPool};
+use crate::{TokioRuntimeHandle, HttpClient, GeminiClient, P2pNode, AsyncComputeTaskPool};
+use crate::cookies::RequestContext;
+use crate::forms::FormBody;
+use crate::http_client::{FetchError, ProgressSender, ResponseSource};
+use crate::history::{Navigate, NavigationAction};
+use crate::tabs::TabAction;
+use bevy::ecs::system::SystemParam;
 use futures_lite::future;
 
 
 // main.rs で定義したリソースやコンポーネントをuseする
-use crate::{CurrentUrl, HtmlContent, FetchHtmlTask, ShowHtmlViewer, ShowOptionWindow, OtherAI, ShowWarningWindow, ShowMessageWindow, ShowSecurityWindow, ShowFfmpegWindow};
+use crate::{CurrentUrl, CurrentDocument, CurrentStyles, CurrentLayout, HtmlViewMode, CompatModeOverride, ExternalStylesheets, HtmlContent, ResponseBody, EncodingOverride, DocumentEncoding, LoadState, NativePage, PageImages, ScrollTarget, ScrollPosition, PageForms, FetchHtmlTask, FetchStylesheetTask, ShowHtmlViewer, FindInPage, MarkupCheckView, ShowOptionWindow, OtherAI, ShowWarningWindow, ShowMessageWindow, ShowSecurityWindow, ShowFfmpegWindow, ShowHistoryWindow, ShowCookieWindow, ShowDownloadsWindow, ShowBookmarksWindow, BrowsingHistory, Tab, Tabs};
 
-pub fn setup_ui_panel(mut current_url: ResMut<CurrentUrl>) {
-    // 初期URLを設定
-    current_url.0 = "https://example.com".to_string();
+/// URL バーのボタンで開け閉めするウィンドウの表示フラグ
+#[derive(SystemParam)]
+pub struct WindowToggles<'w> {
+    html_viewer: ResMut<'w, ShowHtmlViewer>,
+    option: ResMut<'w, ShowOptionWindow>,
+    security: ResMut<'w, ShowSecurityWindow>,
+    message: ResMut<'w, ShowMessageWindow>,
+    ffmpeg: ResMut<'w, ShowFfmpegWindow>,
+    warning: ResMut<'w, ShowWarningWindow>,
+    history: ResMut<'w, ShowHistoryWindow>,
+    cookies: ResMut<'w, ShowCookieWindow>,
+    downloads: ResMut<'w, ShowDownloadsWindow>,
+    bookmarks: ResMut<'w, ShowBookmarksWindow>,
+}
+
+impl WindowToggles<'_> {
+    /// Every toggle as `(name, window title, shown)`. Some windows share a title.
+    pub fn all(&mut self) -> [(&'static str, &'static str, &mut bool); 10] {
+        [
+            ("html_viewer", "Html Context View", &mut self.html_viewer.0),
+            ("option", "Option", &mut self.option.0),
+            ("security", "社会安全度レポート", &mut self.security.0),
+            ("message", "Option", &mut self.message.0),
+            ("ffmpeg", "Video Player & Options", &mut self.ffmpeg.0),
+            ("warning", "Option", &mut self.warning.0),
+            ("history", "History", &mut self.history.0),
+            ("cookies", "Cookies", &mut self.cookies.0),
+            ("downloads", "Downloads", &mut self.downloads.0),
+            ("bookmarks", "Bookmarks", &mut self.bookmarks.0),
+        ]
+    }
 }
 
 #[derive(Default, Resource)]
@@ -23,126 +57,743 @@ pub struct SafetyMetrics {
     pub criminality_coefficient: f32, // 犯罪者係数 (例: 0.0から1.0, 高いほど危険)
 }
 
-// URL入力とリクエストをトリガーするシステム
+/// What the URL bar shows and changes for a tab.
+type UrlBarPage<'a> = (
+    &'a mut CurrentUrl,
+    &'a CurrentDocument,
+    &'a BrowsingHistory,
+    &'a mut EncodingOverride,
+    &'a DocumentEncoding,
+    &'a LoadState,
+);
+
+// タブの切り替えと URL 入力・リクエストをトリガーするシステム
 pub fn main_input_system(
     mut contexts: EguiContexts,
-    mut current_url: ResMut<CurrentUrl>,
-    mut commands: Commands,
-    tokio_runtime: Res<TokioRuntimeHandle>,
-    mut show_html_viewer: ResMut<ShowHtmlViewer>,
-    mut show_option_window: ResMut<ShowOptionWindow>,
-    mut show_security_window: ResMut<ShowSecurityWindow>,
-    mut show_message_window: ResMut<ShowMessageWindow>,
-    mut show_ffmpeg_window: ResMut<ShowFfmpegWindow>,
-    mut show_warning_window: ResMut<ShowWarningWindow>,
+    tabs: Res<Tabs>,
+    mut pages: Query<UrlBarPage, With<Tab>>,
+    mut navigate: EventWriter<Navigate>,
+    mut tab_actions: EventWriter<TabAction>,
+    mut windows: WindowToggles,
+    mut omnibox: crate::omnibox::Omnibox,
 ) {
     let ctx = contexts.ctx_mut();
+    let titles: Vec<String> = tabs
+        .order
+        .iter()
+        .map(|tab| pages.get(*tab).map_or_else(|_| String::new(), |(url, doc, ..)| crate::tabs::tab_title(url, doc)))
+        .collect();
+    // 候補に出す、今見ているもの以外のタブ
+    let open_tabs: Vec<crate::omnibox::OpenTab> = tabs
+        .order
+        .iter()
+        .zip(&titles)
+        .filter(|(tab, _)| **tab != tabs.active)
+        .filter_map(|(&tab, title)| {
+            let (url, ..) = pages.get(tab).ok()?;
+            Some(crate::omnibox::OpenTab { tab, title: title.clone(), url: url.0.clone() })
+        })
+        .collect();
+    let tab = tabs.active;
+    let Ok((mut current_url, _, history, mut encoding_override, document_encoding, load_state)) = pages.get_mut(tab) else {
+        return;
+    };
 
     egui::TopBottomPanel::top("url_panel").show(ctx, |ui| {
+        ui.horizontal_wrapped(|ui| {
+            crate::tabs::tab_strip_ui(ui, &tabs, &titles, &mut tab_actions);
+        });
         ui.horizontal(|ui| {
+            if ui.add_enabled(history.0.can_go_back(), egui::Button::new("◀")).on_hover_text("Back (Alt+←)").clicked() {
+                navigate.write(Navigate { tab, action: NavigationAction::Back });
+            }
+            if ui.add_enabled(history.0.can_go_forward(), egui::Button::new("▶")).on_hover_text("Forward (Alt+→)").clicked() {
+                navigate.write(Navigate { tab, action: NavigationAction::Forward });
+            }
+            // 読み込み中は再読み込みボタンの代わりに中止ボタンを出す
+            if let LoadState::Loading(_) = load_state {
+                if ui.button("✕").on_hover_text("Stop (Esc)").clicked() {
+                    navigate.write(Navigate { tab, action: NavigationAction::Stop });
+                }
+            } else if ui.add_enabled(history.0.current.is_some(), egui::Button::new("⟳")).on_hover_text("Reload").clicked() {
+                navigate.write(Navigate { tab, action: NavigationAction::Reload });
+            }
             ui.label("URL:");
-            let response = ui.text_edit_singleline(&mut current_url.0);
+            // URL でなければ選んだ検索エンジンで検索する
+            match crate::omnibox::url_bar_ui(ui, &mut current_url.0, &open_tabs, &mut omnibox) {
+                Some(crate::omnibox::OmniboxAction::Navigate(url)) => {
+                    info!("URL entered: {}", url);
+                    navigate.write(Navigate { tab, action: NavigationAction::To(url) });
+                }
+                Some(crate::omnibox::OmniboxAction::SwitchTab(other)) => {
+                    tab_actions.write(TabAction::Select(other));
+                }
+                None => {}
+            }
+            crate::omnibox::search_engine_menu_ui(ui, &mut omnibox);
             if ui.button("Toggle HTML Viewer").clicked() {
-                show_html_viewer.0 = !show_html_viewer.0;
-            }
-                if response.lost_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
-                info!("URL entered: {}", current_url.0);
-                let url_to_fetch = current_url.0.clone();
-                let tokio_handle_clone = tokio_runtime.0.clone(); // Handle をクローン
-                let thread_pool = AsyncComputeTaskPool::get();
-
-                let task = thread_pool.spawn(async move {
-                    tokio_handle_clone.spawn(async move { // ★★★ この spawn が重要 ★★★
-                        info!("Attempting to fetch: {}", url_to_fetch);
-                        let fetch_result = reqwest::get(&url_to_fetch).await;
-
-                        match fetch_result {
-                            Ok(res) => {
-                                if res.status().is_success() {
-                                    match res.text().await {
-                                        Ok(text) => Ok(text),
-                                        Err(e) => Err(format!("Failed to get text from response: {}", e)),
-                                    }
-                                } else {
-                                    Err(format!("HTTP Error: {}", res.status()))
-                                }
-                            },
-                            Err(e) => {
-                                Err(format!("Request failed: {}", e))
-                            }
-                        }
-                    }).await.expect("Tokio task join error") // Tokio task の結果を待つ
-                });
-
-                commands.spawn(FetchHtmlTask(task));
+                windows.html_viewer.0 = !windows.html_viewer.0;
+            }
+            if let LoadState::Loading(progress) = load_state {
+                crate::loading::progress_ui(ui, progress);
+            }
+            encoding_menu_ui(ui, &mut encoding_override, document_encoding);
+            if ui.button("Bookmarks").clicked() {
+                windows.bookmarks.0 = !windows.bookmarks.0;
+            }
+            if ui.button("History").clicked() {
+                windows.history.0 = !windows.history.0;
+            }
+            if ui.button("Cookies").clicked() {
+                windows.cookies.0 = !windows.cookies.0;
+            }
+            if ui.button("Downloads").clicked() {
+                windows.downloads.0 = !windows.downloads.0;
             }
             if ui.button("P2P").clicked() {
-                show_message_window.0 = !show_message_window.0;
+                windows.message.0 = !windows.message.0;
             }
             if ui.button("Ffmpeg").clicked() {
-                show_ffmpeg_window.0 = !show_ffmpeg_window.0;
+                windows.ffmpeg.0 = !windows.ffmpeg.0;
             }
             if ui.button("Opption").clicked() {
-                show_option_window.0 = !show_option_window.0;
+                windows.option.0 = !windows.option.0;
             }
             if ui.button("Security").clicked() {
-                show_security_window.0 = !show_security_window.0;
+                windows.security.0 = !windows.security.0;
             }
             if ui.button("warning").clicked() {
-                show_warning_window.0 = !show_warning_window.0;
+                windows.warning.0 = !windows.warning.0;
             }
         });
     });
 }
 
-// HTMLフェッチタスクの完了を監視し、結果を処理するシステム
+// 今の文字コードを表示し、タブごとに手動で選べるメニュー
+fn encoding_menu_ui(ui: &mut egui::Ui, encoding_override: &mut EncodingOverride, document_encoding: &DocumentEncoding) {
+    // 変更時だけ書き込み、毎フレームのデコードし直しを避ける
+    let mut selected = encoding_override.0;
+    ui.menu_button("Text Encoding", |ui| {
+        ui.selectable_value(&mut selected, None, "Auto-detect");
+        ui.separator();
+        for &encoding in crate::charset::OVERRIDE_CHOICES {
+            ui.selectable_value(&mut selected, Some(encoding), encoding.name());
+        }
+    })
+    .response
+    .on_hover_text(format!("{} ({:?})", document_encoding.encoding.name(), document_encoding.source));
+    if selected != encoding_override.0 {
+        encoding_override.0 = selected;
+    }
+}
+
+/// A response body decoded for display.
+struct DecodedPage {
+    text: String,
+    encoding: DocumentEncoding,
+    document: crate::dom::Document,
+    native: NativePage,
+}
+
+// 生のバイト列を文字コードを判定してデコードし、DOM ツリーに変換する
+// gemtext などは直接描くページにし、DOM は同じ内容の HTML から作る
+fn decode_page(
+    body: &ResponseBody,
+    encoding_override: EncodingOverride,
+    compat_mode: CompatModeOverride,
+) -> DecodedPage {
+    // 画像や動画などは文字としてデコードせず、それぞれのビューアに渡す
+    let content_type = crate::viewers::effective_content_type(body.content_type.as_deref(), &body.bytes);
+    if let Some(native) = crate::viewers::binary_page(crate::viewers::viewer_for(content_type.as_deref())) {
+        return DecodedPage {
+            text: String::new(),
+            encoding: DocumentEncoding::default(),
+            document: page_document(&native, &body.url, "", compat_mode),
+            native,
+        };
+    }
+    let (text, encoding, source) = crate::charset::decode_html(&body.bytes, body.content_type.as_deref(), &body.url, encoding_override.0);
+    debug!("Decoding {} as {} ({:?})", body.url, encoding.name(), source);
+    let native = crate::native_view::native_page(content_type.as_deref(), &text, &body.url);
+    let document = page_document(&native, &body.url, &text, compat_mode);
+    if !document.parse_errors.is_empty() {
+        debug!("{} parse errors recovered", document.parse_errors.len());
+    }
+    DecodedPage {
+        text,
+        encoding: DocumentEncoding { encoding, source },
+        document,
+        native,
+    }
+}
+
+// ページの DOM を作る。ネイティブ表示のページは本文ではなく、それと同じ内容の HTML から作る
+fn page_document(native: &NativePage, url: &str, text: &str, compat_mode: CompatModeOverride) -> crate::dom::Document {
+    let html = crate::native_view::equivalent_html(native, url);
+    crate::html_parser::parse_document(html.as_deref().unwrap_or(text), compat_mode.forced_mode())
+}
+
+type PageSource<'a> = (
+    &'a mut LoadState,
+    &'a mut ResponseBody,
+    &'a mut HtmlContent,
+    &'a mut DocumentEncoding,
+    &'a mut CurrentDocument,
+    &'a mut NativePage,
+    &'a EncodingOverride,
+    &'a CompatModeOverride,
+);
+
+impl DecodedPage {
+    // タブのコンポーネントに書き込む
+    fn store(self, html_content: &mut HtmlContent, document_encoding: &mut DocumentEncoding, current_document: &mut CurrentDocument, native_page: &mut NativePage) {
+        html_content.0 = self.text;
+        *document_encoding = self.encoding;
+        current_document.0 = self.document;
+        *native_page = self.native;
+    }
+}
+
+// HTMLフェッチタスクの完了を監視し、取りに行ったタブに結果を反映するシステム
 pub fn poll_fetch_html_task(
     mut commands: Commands,
     mut query_tasks: Query<(Entity, &mut FetchHtmlTask)>,
-    mut html_content: ResMut<HtmlContent>,
+    mut pages: Query<PageSource>,
+    mut scroll_targets: Query<&mut ScrollTarget>,
+    mut histories: Query<(&mut BrowsingHistory, &mut CurrentUrl)>,
+    mut downloads: EventWriter<crate::downloads::StartDownload>,
 ) {
-    for (entity, mut task) in &mut query_tasks {
-        if let Some(result) = future::block_on(future::poll_once(&mut task.0)) {
-            let mut content = html_content.0.lock().unwrap();
+    for (entity, mut fetch) in &mut query_tasks {
+        if let Some(result) = future::block_on(future::poll_once(&mut fetch.task)) {
+            commands.entity(entity).despawn(); // タスクエンティティを削除
+            // 待っている間にタブが閉じられていたら結果は捨てる
+            let Ok((mut load_state, mut body, mut html_content, mut document_encoding, mut current_document, mut native_page, encoding_override, compat_mode)) = pages.get_mut(fetch.tab) else {
+                continue;
+            };
             match result {
-                Ok(html_text) => {
-                    info!("HTML fetch successful for entity {:?}", entity);
-                    *content = html_text;
+                // 文書でないものはダウンロードに回し、タブは前のページのままにする
+                Ok(response) if response.source == ResponseSource::Download || crate::downloads::is_download(&response.headers, Some(&response.body)) => {
+                    info!("{} is a download", response.url);
+                    *load_state = LoadState::Idle;
+                    if let Ok((mut history, mut current_url)) = histories.get_mut(fetch.tab) {
+                        history.0.discard_current();
+                        if let Some(entry) = history.0.current_entry() {
+                            current_url.0 = entry.url.clone();
+                        }
+                    }
+                    downloads.write(crate::downloads::StartDownload { response });
+                }
+                Ok(response) => {
+                    info!("HTML fetch successful for tab {:?}", fetch.tab);
+                    *body = ResponseBody {
+                        url: response.url.to_string(),
+                        content_type: response.headers.get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string),
+                        bytes: response.body,
+                    };
+                    // 取得した HTML を DOM ツリーに変換しておく
+                    decode_page(&body, *encoding_override, *compat_mode).store(&mut html_content, &mut document_encoding, &mut current_document, &mut native_page);
+                    *load_state = LoadState::Idle;
+                    // 新しいページは先頭から、URL にフラグメントがあればその位置から見せる
+                    if let Ok(mut scroll_target) = scroll_targets.get_mut(fetch.tab) {
+                        scroll_target.0 = Some(crate::links::fragment(&fetch.url).unwrap_or_default());
+                    }
                 }
                 Err(e) => {
-                    error!("HTML fetch failed for entity {:?}: {}", entity, e);
-                    *content = format!("Error: {}", e); // エラーメッセージを表示
+                    error!("HTML fetch failed for tab {:?}: {}", fetch.tab, e);
+                    // ソースは書き換えず、ビューアにエラーページを出す
+                    *load_state = LoadState::Failed { url: fetch.url.clone(), error: e };
+                }
+            }
+        }
+    }
+}
+
+// 互換モードの指定が変わったら、今のページを読み直さずに解析し直すシステム
+pub fn reparse_on_compat_mode_change(
+    mut pages: Query<(Ref<CompatModeOverride>, PageSource), Changed<CompatModeOverride>>,
+) {
+    for (compat_mode, (_, body, html_content, _, mut current_document, native_page, ..)) in &mut pages {
+        if compat_mode.is_added() {
+            continue;
+        }
+        current_document.0 = page_document(&native_page, &body.url, &html_content.0, *compat_mode);
+    }
+}
+
+// 文字コードの指定が変わったら、取り直さずに元のバイト列からデコードし直すシステム
+pub fn redecode_on_encoding_change(
+    mut pages: Query<(Ref<EncodingOverride>, PageSource), Changed<EncodingOverride>>,
+) {
+    for (encoding_override, (_, body, mut html_content, mut document_encoding, mut current_document, mut native_page, _, compat_mode)) in &mut pages {
+        // 画像や動画のページは文字コードに関係がない (読み直すと再生し直しになる)
+        if encoding_override.is_added() || body.bytes.is_empty() || matches!(*native_page, NativePage::Image(_) | NativePage::Media(_) | NativePage::Model(_)) {
+            continue;
+        }
+        decode_page(&body, *encoding_override, *compat_mode).store(&mut html_content, &mut document_encoding, &mut current_document, &mut native_page);
+    }
+}
+
+// 新しい DOM が来たら前のページのスタイルシートを捨て、リンクされたものを取りに行くシステム
+pub fn fetch_linked_stylesheets(
+    mut commands: Commands,
+    mut pages: Query<(Entity, &CurrentDocument, &ResponseBody, &mut ExternalStylesheets), Changed<CurrentDocument>>,
+    fetcher: Fetcher,
+    stale_tasks: Query<(Entity, &FetchStylesheetTask)>,
+) {
+    for (tab, current_document, body, mut external_stylesheets) in &mut pages {
+        external_stylesheets.0.clear();
+        for (stale, fetch) in &stale_tasks {
+            if fetch.tab == tab {
+                commands.entity(stale).despawn();
+            }
+        }
+        for (owner, imported, url) in stylesheet_urls(&current_document.0, &body.url) {
+            commands.spawn(FetchStylesheetTask {
+                tab,
+                owner,
+                imported,
+                task: fetcher.spawn(url.clone(), RequestContext::subresource(&body.url), None),
+                url,
+            });
+        }
+    }
+}
+
+// <link rel="stylesheet"> と <style> 内の @import の URL を集める
+fn stylesheet_urls(doc: &crate::dom::Document, document_url: &str) -> Vec<(crate::dom::NodeId, bool, String)> {
+    let Some(base) = crate::links::base_url(doc, document_url) else {
+        return Vec::new();
+    };
+    let base = base.as_str();
+    let mut urls = Vec::new();
+    for node in doc.descendants(doc.root()) {
+        let Some(element) = doc.element(node) else {
+            continue;
+        };
+        if element.name == "link" {
+            let rel = element.attr("rel").unwrap_or("").to_ascii_lowercase();
+            let is_stylesheet = rel.split_ascii_whitespace().any(|r| r == "stylesheet")
+                && !rel.split_ascii_whitespace().any(|r| r == "alternate");
+            if is_stylesheet
+                && let Some(url) = element.attr("href").and_then(|href| resolve_url(base, href))
+            {
+                urls.push((node, false, url));
+            }
+        } else if element.name == "style" {
+            let sheet = crate::css::parse_stylesheet(&doc.text_content(node));
+            urls.extend(sheet.imports.iter().filter_map(|href| resolve_url(base, href)).map(|url| (node, true, url)));
+        }
+    }
+    urls
+}
+
+fn resolve_url(base: &str, href: &str) -> Option<String> {
+    let base = reqwest::Url::parse(base).ok()?;
+    base.join(href.trim()).ok().map(String::from)
+}
+
+/// Aborts the Tokio task when dropped, so a cancelled fetch stops downloading.
+struct AbortOnDrop(tokio::task::AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// What a fetch needs from the world: the Tokio runtime and the protocol
+/// clients.
+#[derive(SystemParam)]
+pub struct Fetcher<'w> {
+    tokio_runtime: Res<'w, TokioRuntimeHandle>,
+    http_client: Res<'w, HttpClient>,
+    gemini_client: Res<'w, GeminiClient>,
+    p2p: Res<'w, P2pNode>,
+}
+
+impl Fetcher<'_> {
+    pub fn client(&self) -> &crate::http_client::Client {
+        &self.http_client.0
+    }
+
+    pub fn p2p(&self) -> Option<&crate::p2p::PeerNode> {
+        self.p2p.0.as_deref()
+    }
+
+    // レスポンスを取得する非同期タスクを Tokio 上で起動する (デコードは受け取る側で行う)
+    pub fn spawn(
+        &self,
+        url: String,
+        context: RequestContext,
+        progress: Option<ProgressSender>,
+    ) -> bevy::tasks::Task<Result<crate::http_client::Response, FetchError>> {
+        self.spawn_request(url, None, context, progress)
+    }
+
+    // フォームの送信: 本文を付けて POST するタスクを起動する
+    pub fn spawn_post(
+        &self,
+        url: String,
+        data: FormBody,
+        context: RequestContext,
+        progress: Option<ProgressSender>,
+    ) -> bevy::tasks::Task<Result<crate::http_client::Response, FetchError>> {
+        self.spawn_request(url, Some(data), context, progress)
+    }
+
+    fn spawn_request(
+        &self,
+        url: String,
+        post: Option<FormBody>,
+        context: RequestContext,
+        progress: Option<ProgressSender>,
+    ) -> bevy::tasks::Task<Result<crate::http_client::Response, FetchError>> {
+        let http = self.http_client.0.clone();
+        let gemini = self.gemini_client.0.clone();
+        let p2p = self.p2p.0.clone();
+        self.run(async move {
+            // フォームのファイルはここで読む (メインスレッドを止めないように)
+            let post = match post {
+                Some(body) => Some(body.into_post_data().await),
+                None => None,
+            };
+            let clients = crate::schemes::Clients { http: &http, gemini: &gemini, p2p: p2p.as_deref() };
+            let res = crate::schemes::fetch(&clients, &url, post.as_ref(), &context, progress.as_ref()).await?;
+            if !res.status.is_success() {
+                return Err(FetchError::Http(res.status));
+            }
+            Ok(res)
+        })
+    }
+
+    // 非同期の処理を Tokio 上で動かし、Bevy のタスクとして待てるようにする
+    pub fn run<T: Send + 'static>(
+        &self,
+        future: impl std::future::Future<Output = Result<T, FetchError>> + Send + 'static,
+    ) -> bevy::tasks::Task<Result<T, FetchError>> {
+        let join = self.tokio_runtime.0.spawn(future);
+        // このタスクが捨てられたら (中止・タブを閉じた) Tokio 側も止める
+        let abort = AbortOnDrop(join.abort_handle());
+        AsyncComputeTaskPool::get().spawn(async move {
+            let _abort = abort;
+            // Tokio 側のパニックはアプリを落とさずエラーとして返す
+            join.await.unwrap_or_else(|e| Err(FetchError::Other(format!("Fetch task failed: {}", e))))
+        })
+    }
+}
+
+// スタイルシートの取得完了を監視し、パースして ExternalStylesheets に追加するシステム
+pub fn poll_fetch_stylesheet_tasks(
+    mut commands: Commands,
+    mut query_tasks: Query<(Entity, &mut FetchStylesheetTask)>,
+    mut pages: Query<(&mut ExternalStylesheets, &ResponseBody, &DocumentEncoding)>,
+    fetcher: Fetcher,
+) {
+    for (entity, mut fetch) in &mut query_tasks {
+        if let Some(result) = future::block_on(future::poll_once(&mut fetch.task)) {
+            commands.entity(entity).despawn();
+            let Ok((mut external_stylesheets, page, document_encoding)) = pages.get_mut(fetch.tab) else {
+                continue;
+            };
+            match result {
+                Ok(response) => {
+                    let content_type = response.headers.get(reqwest::header::CONTENT_TYPE).and_then(|v| v.to_str().ok());
+                    let css_text = crate::charset::decode_stylesheet(&response.body, content_type, document_encoding.encoding);
+                    let mut sheet = crate::css::parse_stylesheet(&css_text);
+                    // url() はシートの URL からの相対なので、ここで絶対 URL にしておく
+                    sheet.resolve_urls(response.url.as_str());
+                    // @import は 1 段だけ辿る。url() と同じくリダイレクト後の URL が基準
+                    if !fetch.imported {
+                        for url in sheet.imports.iter().filter_map(|href| resolve_url(response.url.as_str(), href)) {
+                            commands.spawn(FetchStylesheetTask {
+                                tab: fetch.tab,
+                                owner: fetch.owner,
+                                imported: true,
+                                task: fetcher.spawn(url.clone(), RequestContext::subresource(&page.url), None),
+                                url,
+                            });
+                        }
+                    }
+                    info!("Stylesheet loaded: {}", fetch.url);
+                    external_stylesheets.0.push(crate::style::ExternalStylesheet {
+                        owner: fetch.owner,
+                        imported: fetch.imported,
+                        url: fetch.url.clone(),
+                        sheet,
+                    });
+                }
+                Err(e) => {
+                    warn!("Stylesheet fetch failed for {}: {}", fetch.url, e);
                 }
             }
-            commands.entity(entity).despawn(); // タスクエンティティを削除
         }
     }
 }
 
+/// What the Html Context View shows of a tab.
+type ViewedPage<'a> = (
+    &'a HtmlContent,
+    &'a CurrentDocument,
+    Ref<'a, CurrentStyles>,
+    &'a mut CurrentLayout,
+    &'a LoadState,
+    &'a NativePage,
+    Ref<'a, PageImages>,
+    &'a ResponseBody,
+    &'a mut ScrollTarget,
+    &'a mut ScrollPosition,
+    &'a mut PageForms,
+);
+
+/// Where the page view sends what the user does on a page.
+#[derive(SystemParam)]
+pub struct PageEvents<'w> {
+    navigate: EventWriter<'w, Navigate>,
+    tab_actions: EventWriter<'w, TabAction>,
+    submit_form: EventWriter<'w, crate::forms::SubmitForm>,
+}
+
+/// What the Html Context View shows, shared by all tabs.
+#[derive(SystemParam)]
+pub struct HtmlViewState<'w> {
+    mode: ResMut<'w, HtmlViewMode>,
+    find: ResMut<'w, FindInPage>,
+    markup_check: ResMut<'w, MarkupCheckView>,
+}
+
 // 取得したHTMLコンテンツをEguiウィンドウに表示するシステム
 pub fn html_viewer_system(
     mut contexts: EguiContexts,
-    html_content: Res<HtmlContent>,
-    show_html_viewer: Res<ShowHtmlViewer>,
+    tabs: Res<Tabs>,
+    mut pages: Query<ViewedPage>,
+    mut show_html_viewer: ResMut<ShowHtmlViewer>,
+    view: HtmlViewState,
+    mut events: PageEvents,
 ) {
+    let HtmlViewState { mode: mut view_mode, mut find, mut markup_check } = view;
+    // スタイルが変わったり画像が届いたりしたらレイアウトをやり直す (裏のタブも含めて)
+    for (_, _, current_styles, mut current_layout, _, _, page_images, _, _, _, _) in &mut pages {
+        if current_styles.is_changed() || page_images.is_changed() {
+            current_layout.0 = crate::layout::PageLayout::default();
+        }
+    }
+    let tab = tabs.active;
+    let Ok((html_content, current_document, current_styles, mut current_layout, load_state, native_page, page_images, body, mut scroll_target, mut scroll_position, mut forms)) = pages.get_mut(tab) else {
+        return;
+    };
+    // 読み込んだ画像を egui のテクスチャとして登録しておく (ctx_mut より前に済ませる)
+    let mut textures = crate::images::PageTextures { images: &page_images.0, ids: Default::default() };
+    for state in page_images.0.images.values() {
+        if let crate::images::ImageState::Loaded { handle, .. } = state {
+            textures.ids.insert(handle.id(), contexts.add_image(handle.clone_weak()));
+        }
+    }
+    // 画像のページ・動画のページの絵も同じく登録する
+    let native_texture = match native_page {
+        NativePage::Image(crate::images::ImageState::Loaded { handle, .. }) => Some(contexts.add_image(handle.clone_weak())),
+        NativePage::Media(crate::viewers::MediaState::Playing { texture, .. }) => Some(contexts.add_image(texture.clone_weak())),
+        _ => None,
+    };
     let ctx = contexts.ctx_mut();
+    // Ctrl+F で検索バーを開く (ビューが閉じていれば開く)
+    find.0.shortcuts(ctx);
+    if find.0.open && !show_html_viewer.0 {
+        show_html_viewer.0 = true;
+    }
     if show_html_viewer.0 {
         egui::Window::new("Html Context View")
         .default_size(egui::vec2(600.0, 400.0))
             .show(ctx, |ui| {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-            let content = html_content.0.lock().unwrap();
-            ui.label(egui::RichText::new(content.as_str()).monospace()); // monospaceで表示
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut *view_mode, HtmlViewMode::Page, "Page");
+                ui.selectable_value(&mut *view_mode, HtmlViewMode::Dom, "DOM");
+                ui.selectable_value(&mut *view_mode, HtmlViewMode::Source, "Source");
+                ui.selectable_value(&mut *view_mode, HtmlViewMode::Markup, "Markup");
+                if let Some(title) = current_document.0.title() {
+                    ui.label(title);
+                }
+                let pending = page_images.0.pending();
+                if pending > 0 {
+                    ui.label(egui::RichText::new(format!("{} images loading", pending)).weak());
+                }
             });
+            find.0.bar_ui(ui);
+            if *view_mode == HtmlViewMode::Dom {
+                // 解析済みの DOM をツリー表示
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let doc = &current_document.0;
+                    for child in doc.children(doc.root()) {
+                        dom_tree_ui(ui, doc, &current_styles.0, *child);
+                    }
+                });
+            } else if *view_mode == HtmlViewMode::Source {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    find.0.source_ui(ui, &html_content.0); // monospaceで表示し、検索に当たったところを塗る
+                });
+            } else if *view_mode == HtmlViewMode::Markup {
+                markup_check.0.ui(ui, &html_content.0);
+            } else if let LoadState::Failed { url, error } = load_state {
+                if crate::loading::error_page_ui(ui, url, error) {
+                    events.navigate.write(Navigate { tab, action: NavigationAction::Reload });
+                }
+            } else if !matches!(native_page, NativePage::None) {
+                // gemtext・gopher のメニューなどは egui で直接描く
+                if let Some(url) = crate::native_view::native_page_ui(ui, native_page, native_texture) {
+                    let initiator = body.url.clone();
+                    events.navigate.write(Navigate { tab, action: NavigationAction::Link { url, initiator } });
+                }
+            } else {
+                // レイアウトしたページを描画する。幅が変わったときだけ組み直す
+                let width = (ui.available_width() - ui.spacing().scroll.bar_width).max(100.0);
+                let layout = &mut current_layout.0;
+                if layout.items.is_empty() || (layout.viewport_width - width).abs() > 0.5 {
+                    *layout = ui.fonts(|fonts| {
+                        let host = crate::layout::EguiLayoutHost { fonts, images: &textures };
+                        crate::layout::layout_document(&current_document.0, &current_styles.0, &host, width)
+                    });
+                }
+                // 新しいページやフラグメントへの移動のあとはスクロール位置を合わせる
+                let mut scroll_area = egui::ScrollArea::both().id_salt(tab).auto_shrink([false, false]);
+                if let Some(fragment) = &scroll_target.0
+                    && fragment.is_empty()
+                    && let Some(offset) = scroll_position.restore.take()
+                {
+                    // 復元したタブは前回見ていた位置に戻す
+                    scroll_area = scroll_area.scroll_offset(egui::vec2(offset.x, offset.y));
+                    scroll_target.0 = None;
+                } else if let Some(fragment) = &scroll_target.0 {
+                    let top = crate::links::fragment_target(&current_document.0, fragment)
+                        .and_then(|node| layout.anchors.get(&node).copied())
+                        .unwrap_or(0.0);
+                    scroll_area = scroll_area.vertical_scroll_offset(top);
+                    scroll_target.0 = None;
+                }
+                let output = scroll_area.show(ui, |ui| {
+                    let (rect, response) = ui.allocate_exact_size(layout.size, egui::Sense::click());
+                    crate::layout::paint(ui.painter(), rect.min, layout, ui.clip_rect(), &textures);
+                    // ページ内検索に当たった文字を塗る
+                    let visible = ui.clip_rect().translate(-rect.min.to_vec2());
+                    let matches = ui.fonts(|fonts| {
+                        let host = crate::layout::EguiLayoutHost { fonts, images: &textures };
+                        find.0.page_matches(layout, &host, visible)
+                    });
+                    find.0.paint_page_matches(ui, rect.min, &matches);
+                    let doc = &current_document.0;
+                    // フォームの部品は egui のウィジェットとして上に重ねる
+                    for item in &layout.items {
+                        if let crate::layout::DisplayItem::Replaced { rect: control, node, .. } = item
+                            && crate::forms::control_kind(doc, *node).is_some()
+                        {
+                            let control = control.translate(rect.min.to_vec2());
+                            if control.intersects(ui.clip_rect())
+                                && let Some((form, submitter)) = crate::forms::control_ui(ui, doc, &mut forms.0, *node, control)
+                            {
+                                events.submit_form.write(crate::forms::SubmitForm { tab, form, submitter });
+                            }
+                        }
+                    }
+                    // ポインタの下にあるリンクの行き先
+                    let hovered = response.hover_pos().and_then(|pos| layout.hit_test(pos - rect.min.to_vec2())?.node());
+                    let target = hovered.and_then(|node| crate::links::link_target(doc, &body.url, node));
+                    if let Some(url) = &target {
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                        // 中クリックと Ctrl+クリックは裏のタブで開く
+                        if response.middle_clicked() || (response.clicked() && ui.input(|i| i.modifiers.command)) {
+                            events.tab_actions.write(TabAction::OpenInBackground {
+                                url: url.clone(),
+                                initiator: Some(body.url.clone()),
+                            });
+                        } else if response.clicked() {
+                            let action = NavigationAction::Link {
+                                url: url.clone(),
+                                initiator: body.url.clone(),
+                            };
+                            events.navigate.write(Navigate { tab, action });
+                        }
+                    } else if response.clicked()
+                        && let Some(node) = hovered
+                        && let Some((form, submitter)) = crate::forms::activate(doc, &mut forms.0, node)
+                    {
+                        // <button> と <label> のクリック
+                        events.submit_form.write(crate::forms::SubmitForm { tab, form, submitter });
+                    }
+                    target
+                });
+                let offset = Vec2::new(output.state.offset.x, output.state.offset.y);
+                if scroll_position.offset != offset {
+                    scroll_position.offset = offset;
+                }
+                if let Some(url) = output.inner {
+                    status_bubble(ui, output.inner_rect, &url);
+                }
+            }
         });
     }
 }
 
+// リンクにカーソルを合わせている間、ページの左下に行き先を出す
+fn status_bubble(ui: &egui::Ui, area: egui::Rect, text: &str) {
+    let painter = ui.painter_at(area);
+    let visuals = ui.visuals();
+    let galley = painter.layout_no_wrap(text.to_string(), egui::FontId::proportional(12.0), visuals.text_color());
+    let padding = egui::vec2(6.0, 3.0);
+    let size = galley.size() + padding * 2.0;
+    let rect = egui::Rect::from_min_size(area.left_bottom() - egui::vec2(0.0, size.y), size);
+    painter.rect(rect, 3.0, visuals.window_fill, visuals.window_stroke, egui::StrokeKind::Inside);
+    painter.galley(rect.min + padding, galley, visuals.text_color());
+}
+
+// DOM ノードを 1 つ表示し、要素なら子ノードを折りたたみ表示する
+// 要素にカーソルを合わせると計算済みスタイルが出る
+fn dom_tree_ui(ui: &mut egui::Ui, doc: &crate::dom::Document, styles: &crate::style::StyleMap, id: crate::dom::NodeId) {
+    use crate::dom::NodeData;
+    match &doc.node(id).data {
+        NodeData::Element(e) => {
+            let mut label = format!("<{}", e.name);
+            for a in &e.attrs {
+                label.push_str(&format!(" {}=\"{}\"", a.name, a.value));
+            }
+            label.push('>');
+            let response = egui::CollapsingHeader::new(egui::RichText::new(label).monospace())
+                .id_salt(id.0)
+                .default_open(matches!(e.name.as_str(), "html" | "body"))
+                .show(ui, |ui| {
+                    for child in doc.children(id) {
+                        dom_tree_ui(ui, doc, styles, *child);
+                    }
+                });
+            if let Some(style) = styles.get(id) {
+                response.header_response.on_hover_text(egui::RichText::new(crate::style::describe(style)).monospace());
+            }
+        }
+        NodeData::Text(t) => {
+            let trimmed = t.trim();
+            if !trimmed.is_empty() {
+                ui.label(egui::RichText::new(format!("\"{}\"", trimmed)).monospace());
+            }
+        }
+        NodeData::Comment(c) => {
+            ui.label(egui::RichText::new(format!("<!--{}-->", c)).monospace().weak());
+        }
+        NodeData::Doctype { name, .. } => {
+            ui.label(egui::RichText::new(format!("<!DOCTYPE {}>", name)).monospace().weak());
+        }
+        NodeData::Document => {}
+    }
+}
+
 pub fn option_window(
     mut contexts: EguiContexts,
     show_option_window: Res<ShowOptionWindow>,
     mut other_ai_res: ResMut<OtherAI>,
+    tabs: Res<Tabs>,
+    mut pages: Query<(&mut CompatModeOverride, &CurrentDocument)>,
+    http_client: Res<HttpClient>,
+    mut cache_entries: Local<Option<Vec<crate::http_cache::CacheEntry>>>,
 ) {
+    let Ok((mut compat_mode, current_document)) = pages.get_mut(tabs.active) else {
+        return;
+    };
     let ctx = contexts.ctx_mut();
     if show_option_window.0 {
         egui::Window::new("Option")
@@ -157,8 +808,81 @@ pub fn option_window(
             }
             ui.label("Other AI API Key:");
             ui.text_edit_singleline(&mut other_ai_res.api_key);
+
+            ui.separator();
+            ui.label(format!("Document mode of this tab: {:?}", current_document.0.mode));
+            // 変更時だけ書き込み、毎フレームの再解析を避ける
+            let mut selected = *compat_mode;
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut selected, CompatModeOverride::Auto, "Auto (doctype)");
+                ui.selectable_value(&mut selected, CompatModeOverride::Quirks, "Quirks");
+                ui.selectable_value(&mut selected, CompatModeOverride::Standards, "Standards");
+            });
+            if selected != *compat_mode {
+                *compat_mode = selected;
+            }
+
+            ui.separator();
+            match http_client.0.cache() {
+                Some(cache) => cache_inspector_ui(ui, cache, &mut cache_entries),
+                None => {
+                    ui.label("HTTP cache: off");
+                }
+            }
         });
     });
+    } else {
+        *cache_entries = None;
+    }
+}
+
+// HTTP キャッシュの中身を一覧し、消去できるようにする
+// 一覧は開いたときと更新・削除のときだけ作り、毎フレームは作らない
+fn cache_inspector_ui(ui: &mut egui::Ui, cache: &crate::http_cache::HttpCache, snapshot: &mut Option<Vec<crate::http_cache::CacheEntry>>) {
+    let shown = egui::CollapsingHeader::new("HTTP cache").show(ui, |ui| {
+        let entries = snapshot.get_or_insert_with(|| cache.entries());
+        let mut changed = false;
+        let total: u64 = entries.iter().map(|e| e.body_size).sum();
+        ui.horizontal(|ui| {
+            ui.label(format!("{} entries, {} KiB", entries.len(), total.div_ceil(1024)));
+            if ui.button("Refresh").clicked() {
+                changed = true;
+            }
+            if ui.button("Clear cache").clicked() {
+                cache.clear();
+                changed = true;
+            }
+        });
+        egui::Grid::new("http_cache_grid").striped(true).show(ui, |ui| {
+            ui.strong("URL");
+            ui.strong("Status");
+            ui.strong("Size");
+            ui.strong("Stored");
+            ui.strong("State");
+            ui.strong("Validator");
+            ui.end_row();
+            for entry in entries.iter() {
+                ui.label(&entry.url);
+                ui.label(entry.status.to_string());
+                ui.label(format!("{} B", entry.body_size));
+                ui.label(crate::history::format_timestamp(entry.stored_at));
+                ui.label(if crate::http_cache::is_fresh_now(entry) { "fresh" } else { "stale" });
+                let validator = entry.header("etag").map(|etag| format!("ETag {}", etag)).or_else(|| entry.header("last-modified").map(|m| format!("Last-Modified {}", m)));
+                ui.label(validator.unwrap_or_default());
+                if ui.small_button("Delete").clicked() {
+                    cache.remove(&entry.url);
+                    changed = true;
+                }
+                ui.end_row();
+            }
+        });
+        if changed {
+            *snapshot = None;
+        }
+    });
+    // 閉じたら捨てて、次に開いたときに取り直す
+    if shown.body_returned.is_none() {
+        *snapshot = None;
     }
 }
 
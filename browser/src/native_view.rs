@@ -0,0 +1,284 @@
+//! Pages drawn with egui directly instead of going through the HTML layout:
+//! gemtext, gopher menus, plain text, Gemini input prompts, JSON trees and
+//! the image, media and model viewers.
+//!
+//! The tab still gets a [`CurrentDocument`](crate::CurrentDocument) made
+//! from an equivalent HTML document, so the title, the tab label and the DOM
+//! view work as for any other page.
+
+use bevy_egui::egui;
+
+use crate::gemini::{self, Line};
+use crate::gopher::{self, Item};
+use crate::images::ImageState;
+use crate::viewers::{MediaState, ModelState};
+use crate::NativePage;
+
+/// Picks the native view for a decoded response by its content type, or
+/// [`NativePage::None`] if it is to be laid out as HTML.
+pub fn native_page(content_type: Option<&str>, text: &str, url: &str) -> NativePage {
+    let content_type = content_type.unwrap_or("");
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match essence.as_str() {
+        "text/gemini" => NativePage::Gemtext(gemini::parse_gemtext(text, url)),
+        gopher::MENU_CONTENT_TYPE => NativePage::GopherMenu(gopher::parse_menu(text)),
+        gemini::INPUT_CONTENT_TYPE => NativePage::Input {
+            url: url.to_string(),
+            prompt: text.to_string(),
+            sensitive: content_type.contains("sensitive"),
+        },
+        "text/plain" => NativePage::Text(text.to_string()),
+        _ if crate::viewers::viewer_for(Some(&essence)) == crate::viewers::Viewer::Json => {
+            NativePage::Json(serde_json::from_str(text).map_err(|e| e.to_string()))
+        }
+        _ => NativePage::None,
+    }
+}
+
+/// HTML with the same content as a native page.
+pub fn equivalent_html(page: &NativePage, url: &str) -> Option<String> {
+    match page {
+        NativePage::None => None,
+        NativePage::Gemtext(lines) => Some(gemini::gemtext_to_html(lines)),
+        NativePage::GopherMenu(items) => Some(gopher::menu_to_html(items, url)),
+        NativePage::Text(text) => Some(format!("<!DOCTYPE html>\n<html><body><pre>{}</pre></body></html>\n", escape(text))),
+        NativePage::Input { prompt, .. } => Some(format!("<!DOCTYPE html>\n<html><body><p>{}</p></body></html>\n", escape(prompt))),
+        NativePage::Json(Ok(value)) => {
+            let text = serde_json::to_string_pretty(value).unwrap_or_default();
+            Some(format!("<!DOCTYPE html>\n<html><body><pre>{}</pre></body></html>\n", escape(&text)))
+        }
+        NativePage::Json(Err(error)) => Some(format!("<!DOCTYPE html>\n<html><body><p>{}</p></body></html>\n", escape(error))),
+        // 画像・動画・モデルはファイル名をタイトルにする
+        NativePage::Image(_) | NativePage::Media(_) | NativePage::Model(_) => {
+            let name = file_name(url);
+            Some(format!("<!DOCTYPE html>\n<html><head><title>{}</title></head><body></body></html>\n", escape(&name)))
+        }
+    }
+}
+
+// URL の最後のパスの部分 (なければ URL そのもの)
+fn file_name(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.path_segments()?.next_back().filter(|s| !s.is_empty()).map(|s| percent_encoding::percent_decode_str(s).decode_utf8_lossy().into_owned()))
+        .unwrap_or_else(|| url.to_string())
+}
+
+/// Draws a native page. `texture` is the egui texture of the image or the
+/// video frame, for those pages. Returns the URL of a link the user followed.
+pub fn native_page_ui(ui: &mut egui::Ui, page: &NativePage, texture: Option<egui::TextureId>) -> Option<String> {
+    let mut followed = None;
+    egui::ScrollArea::both().auto_shrink([false, false]).show(ui, |ui| match page {
+        NativePage::None => {}
+        NativePage::Gemtext(lines) => {
+            for (index, line) in lines.iter().enumerate() {
+                if let Some(url) = gemtext_line_ui(ui, index, line) {
+                    followed = Some(url);
+                }
+            }
+        }
+        NativePage::GopherMenu(items) => {
+            for (index, item) in items.iter().enumerate() {
+                if let Some(url) = gopher_item_ui(ui, index, item) {
+                    followed = Some(url);
+                }
+            }
+        }
+        NativePage::Text(text) => {
+            ui.add(egui::Label::new(egui::RichText::new(text).monospace()).extend());
+        }
+        NativePage::Input { url, prompt, sensitive } => {
+            followed = input_ui(ui, url, prompt, *sensitive);
+        }
+        NativePage::Json(Ok(value)) => json_value_ui(ui, None, value, "$".to_string(), 0),
+        NativePage::Json(Err(error)) => {
+            ui.colored_label(ui.visuals().error_fg_color, format!("Invalid JSON: {}", error));
+        }
+        NativePage::Image(ImageState::Loading) | NativePage::Media(MediaState::Opening) | NativePage::Model(ModelState::Opening) => {
+            ui.spinner();
+        }
+        NativePage::Image(ImageState::Loaded { size, .. }) => {
+            if let Some(texture) = texture {
+                picture_ui(ui, texture, egui::vec2(size.x, size.y), format!("{} × {} pixels", size.x, size.y));
+            }
+        }
+        NativePage::Media(MediaState::Playing { size, .. }) => {
+            if let Some(texture) = texture {
+                picture_ui(ui, texture, egui::vec2(size.x, size.y), format!("Video {} × {}", size.x, size.y));
+            }
+        }
+        NativePage::Model(ModelState::Shown(_)) => {
+            ui.add_space(16.0);
+            ui.label("This model is shown in the 3D scene while this tab is selected.");
+        }
+        NativePage::Image(ImageState::Failed(error)) | NativePage::Media(MediaState::Failed(error)) | NativePage::Model(ModelState::Failed(error)) => {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+    });
+    followed
+}
+
+// 画像や動画のフレーム: 幅に収まるように縮め、クリックで原寸と切り替える
+fn picture_ui(ui: &mut egui::Ui, texture: egui::TextureId, size: egui::Vec2, caption: String) {
+    let id = ui.id().with("picture-actual-size");
+    let mut actual_size = ui.data_mut(|d| d.get_temp::<bool>(id).unwrap_or(false));
+    let fits = size.x <= ui.available_width();
+    let shown = if actual_size || fits { size } else { size * (ui.available_width() / size.x) };
+    ui.label(egui::RichText::new(caption).weak());
+    let response = ui.add(egui::Image::new(egui::load::SizedTexture::new(texture, shown)).sense(egui::Sense::click()));
+    if !fits {
+        let response = response.on_hover_cursor(if actual_size { egui::CursorIcon::ZoomOut } else { egui::CursorIcon::ZoomIn });
+        if response.clicked() {
+            actual_size = !actual_size;
+            ui.data_mut(|d| d.insert_temp(id, actual_size));
+        }
+    }
+}
+
+// JSON の値 1 つ。オブジェクトと配列は開閉でき、浅いところは最初から開いておく
+fn json_value_ui(ui: &mut egui::Ui, key: Option<&str>, value: &serde_json::Value, path: String, depth: usize) {
+    use serde_json::Value;
+    let key_text = |ui: &mut egui::Ui| {
+        if let Some(key) = key {
+            ui.label(egui::RichText::new(format!("{}:", key)).monospace().strong());
+        }
+    };
+    let (children, summary): (Vec<(String, &Value)>, String) = match value {
+        Value::Object(map) => (map.iter().map(|(k, v)| (k.clone(), v)).collect(), format!("{{…}} {} keys", map.len())),
+        Value::Array(items) => (items.iter().enumerate().map(|(i, v)| (i.to_string(), v)).collect(), format!("[…] {} items", items.len())),
+        scalar => {
+            let (text, color) = match scalar {
+                Value::String(s) => (format!("{:?}", s), egui::Color32::from_rgb(0x6a, 0x99, 0x55)),
+                Value::Number(n) => (n.to_string(), egui::Color32::from_rgb(0x4f, 0x8f, 0xd8)),
+                other => (other.to_string(), egui::Color32::from_rgb(0xc5, 0x86, 0x3a)),
+            };
+            ui.horizontal_wrapped(|ui| {
+                key_text(ui);
+                ui.label(egui::RichText::new(text).monospace().color(color));
+            });
+            return;
+        }
+    };
+    let header = match key {
+        Some(key) => format!("{}: {}", key, summary),
+        None => summary,
+    };
+    egui::CollapsingHeader::new(egui::RichText::new(header).monospace())
+        .id_salt(&path)
+        .default_open(depth < 2)
+        .show(ui, |ui| {
+            for (child_key, child) in children {
+                let child_path = format!("{}/{}", path, child_key);
+                json_value_ui(ui, Some(&child_key), child, child_path, depth + 1);
+            }
+        });
+}
+
+fn gemtext_line_ui(ui: &mut egui::Ui, index: usize, line: &Line) -> Option<String> {
+    match line {
+        Line::Text(text) if text.trim().is_empty() => ui.add_space(ui.text_style_height(&egui::TextStyle::Body) * 0.5),
+        Line::Text(text) => {
+            ui.label(text);
+        }
+        Line::Heading(level, text) => {
+            let size = match level {
+                1 => 26.0,
+                2 => 21.0,
+                _ => 17.0,
+            };
+            ui.add_space(4.0);
+            ui.label(egui::RichText::new(text).size(size).strong());
+        }
+        Line::Link { url, label } => {
+            let text = format!("⇒ {}", label.as_deref().unwrap_or(url));
+            if ui.link(text).on_hover_text(url).clicked() {
+                return Some(url.clone());
+            }
+        }
+        Line::ListItem(text) => {
+            ui.label(format!("• {}", text));
+        }
+        Line::Quote(text) => {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(egui::RichText::new("│").weak());
+                ui.label(egui::RichText::new(text).italics());
+            });
+        }
+        Line::Preformatted { alt, text } => {
+            // 整形済みブロックは折り返さず、横にスクロールさせる
+            egui::ScrollArea::horizontal().id_salt(("gemtext-pre", index)).show(ui, |ui| {
+                let response = ui.add(egui::Label::new(egui::RichText::new(text).monospace()).extend());
+                if !alt.is_empty() {
+                    response.on_hover_text(alt);
+                }
+            });
+        }
+    }
+    None
+}
+
+fn gopher_item_ui(ui: &mut egui::Ui, index: usize, item: &Item) -> Option<String> {
+    let mut followed = None;
+    ui.horizontal(|ui| {
+        ui.add_sized([32.0, ui.spacing().interact_size.y], egui::Label::new(egui::RichText::new(item.kind_label()).monospace().weak()));
+        match item.kind {
+            'i' => {
+                ui.add(egui::Label::new(egui::RichText::new(&item.display).monospace()).extend());
+            }
+            '3' => {
+                ui.colored_label(ui.visuals().error_fg_color, egui::RichText::new(&item.display).monospace());
+            }
+            '7' => {
+                // 検索項目は入力欄を並べ、送るとセレクタにタブ区切りで付けた URL に移る
+                ui.label(egui::RichText::new(&item.display).monospace());
+                let id = ui.id().with(("gopher-search", index));
+                let mut query = ui.data_mut(|d| d.get_temp::<String>(id).unwrap_or_default());
+                let response = ui.add(egui::TextEdit::singleline(&mut query).desired_width(200.0));
+                let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                if (ui.button("Search").clicked() || submitted) && !query.is_empty() {
+                    followed = Some(item.search_url(&query));
+                }
+                ui.data_mut(|d| d.insert_temp(id, query));
+            }
+            _ => match item.url() {
+                Some(url) => {
+                    if ui.link(egui::RichText::new(&item.display).monospace()).on_hover_text(&url).clicked() {
+                        followed = Some(url);
+                    }
+                }
+                None => {
+                    ui.label(egui::RichText::new(&item.display).monospace());
+                }
+            },
+        }
+    });
+    followed
+}
+
+// Gemini の 10/11 の応答: 入力を URL のクエリにして同じ URL を取り直す
+fn input_ui(ui: &mut egui::Ui, url: &str, prompt: &str, sensitive: bool) -> Option<String> {
+    let mut followed = None;
+    ui.add_space(16.0);
+    ui.label(egui::RichText::new(prompt).size(17.0).strong());
+    ui.add_space(8.0);
+    let id = ui.id().with(("gemini-input", url));
+    let mut input = ui.data_mut(|d| d.get_temp::<String>(id).unwrap_or_default());
+    ui.horizontal(|ui| {
+        let response = ui.add(egui::TextEdit::singleline(&mut input).password(sensitive).desired_width(320.0));
+        let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        if ui.button("Submit").clicked() || submitted {
+            if let Ok(mut target) = reqwest::Url::parse(url) {
+                let query = percent_encoding::utf8_percent_encode(&input, percent_encoding::NON_ALPHANUMERIC).to_string();
+                target.set_query(Some(&query));
+                followed = Some(target.to_string());
+            }
+            input.clear();
+        }
+    });
+    ui.data_mut(|d| d.insert_temp(id, input));
+    followed
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,338 @@
+//! The URL bar's suggestion list: pages from the history ranked by how
+//! often and how lately they were visited, bookmarks, open tabs, completion
+//! of a typed address, and a search for the text with the chosen engine.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+use crate::search::{address_or_search, search_url, LOCAL_SEARCH};
+use crate::{Bookmarks, PageIndex, SearchEngine};
+
+/// Search engines offered in the URL bar; `%s` is replaced by the query.
+pub const SEARCH_ENGINES: &[(&str, &str)] = &[
+    ("Local index", LOCAL_SEARCH),
+    ("DuckDuckGo", "https://duckduckgo.com/html/?q=%s"),
+    ("Wikipedia", "https://en.wikipedia.org/w/index.php?search=%s"),
+    ("Google", "https://www.google.com/search?q=%s"),
+];
+/// Rows in the list, not counting the completion and the search row.
+const MAX_SUGGESTIONS: usize = 8;
+/// Added to the score of a bookmark and of an open tab.
+const BOOKMARK_BONUS: f32 = 60.0;
+const TAB_BONUS: f32 = 80.0;
+/// Added when the address starts with what was typed.
+const PREFIX_BONUS: f32 = 100.0;
+
+/// A tab other than the shown one, for "switch to tab" suggestions.
+pub struct OpenTab {
+    pub tab: Entity,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuggestionKind {
+    /// The typed address completed from the history (accepted with Tab).
+    Completion,
+    Tab(Entity),
+    Bookmark,
+    History,
+    Search,
+}
+
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub title: String,
+    pub url: String,
+    score: f32,
+}
+
+/// What picking in the URL bar asks for.
+pub enum OmniboxAction {
+    Navigate(String),
+    SwitchTab(Entity),
+}
+
+impl Suggestion {
+    fn action(&self) -> OmniboxAction {
+        match self.kind {
+            SuggestionKind::Tab(tab) => OmniboxAction::SwitchTab(tab),
+            _ => OmniboxAction::Navigate(self.url.clone()),
+        }
+    }
+
+    fn label(&self) -> String {
+        let icon = match self.kind {
+            SuggestionKind::Completion => "⇥",
+            SuggestionKind::Tab(_) => "🗂",
+            SuggestionKind::Bookmark => "★",
+            SuggestionKind::History => "🕘",
+            SuggestionKind::Search => "🔍",
+        };
+        match self.kind {
+            SuggestionKind::Search => format!("{icon} {}", self.title),
+            SuggestionKind::Tab(_) => format!("{icon} {} — Switch to tab", self.title),
+            _ if self.title.is_empty() || self.title == self.url => format!("{icon} {}", self.url),
+            _ => format!("{icon} {} — {}", self.title, self.url),
+        }
+    }
+}
+
+/// What the URL bar keeps between frames.
+#[derive(Default)]
+pub struct OmniboxState {
+    suggestions: Vec<Suggestion>,
+    /// Row picked with the arrow keys.
+    selected: Option<usize>,
+    /// The list shows after typing and hides after Esc or picking.
+    open: bool,
+    /// The pointer was over the list last frame, so a click on it keeps it open.
+    popup_hovered: bool,
+}
+
+/// Where the URL bar's suggestions come from.
+#[derive(SystemParam)]
+pub struct Omnibox<'w, 's> {
+    bookmarks: Res<'w, Bookmarks>,
+    index: Res<'w, PageIndex>,
+    engine: ResMut<'w, SearchEngine>,
+    state: Local<'s, OmniboxState>,
+}
+
+/// Display name of a search URL template.
+fn engine_name(engine: &str) -> String {
+    SEARCH_ENGINES
+        .iter()
+        .find(|(_, template)| *template == engine)
+        .map(|(name, _)| name.to_string())
+        .or_else(|| reqwest::Url::parse(engine).ok().and_then(|url| url.host_str().map(str::to_string)))
+        .unwrap_or_else(|| "Custom".to_string())
+}
+
+/// The address without its scheme and `www.`, as people type it.
+fn strip_url(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    rest.strip_prefix("www.").unwrap_or(rest)
+}
+
+/// Higher for pages visited often and lately, in the manner of Firefox's frecency.
+fn frecency(visits: u32, last_visit: u64, now: u64) -> f32 {
+    let recency = match now.saturating_sub(last_visit) / 86_400 {
+        0..=3 => 100.0,
+        4..=14 => 70.0,
+        15..=31 => 50.0,
+        32..=90 => 30.0,
+        _ => 10.0,
+    };
+    recency * (1.0 + (visits.max(1) as f32).ln())
+}
+
+fn matches(words: &[String], fields: &[&str]) -> bool {
+    let fields: Vec<String> = fields.iter().map(|field| field.to_lowercase()).collect();
+    words.iter().all(|word| fields.iter().any(|field| field.contains(word.as_str())))
+}
+
+fn prefix_bonus(typed: &str, url: &str) -> f32 {
+    if strip_url(url).to_lowercase().starts_with(typed) { PREFIX_BONUS } else { 0.0 }
+}
+
+// 打った文字から候補を集めて点の高い順に並べる
+fn suggest(input: &str, tabs: &[OpenTab], omnibox: &Omnibox) -> Vec<Suggestion> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Vec::new();
+    }
+    let lower = input.to_lowercase();
+    let typed = strip_url(&lower);
+    let words: Vec<String> = lower.split_whitespace().map(str::to_string).collect();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+
+    let mut candidates = Vec::new();
+    let mut frecencies: HashMap<&str, f32> = HashMap::new();
+    for page in omnibox.index.0.pages() {
+        let score = frecency(page.visits, page.indexed_at, now);
+        frecencies.insert(&page.url, score);
+        if matches(&words, &[&page.title, &page.url]) {
+            candidates.push(Suggestion {
+                kind: SuggestionKind::History,
+                title: page.title.clone(),
+                url: page.url.clone(),
+                score: score + prefix_bonus(typed, &page.url),
+            });
+        }
+    }
+    for (bookmark, _) in omnibox.bookmarks.0.bookmarks() {
+        let tags = bookmark.tags.join(" ");
+        if matches(&words, &[&bookmark.title, &bookmark.url, &tags]) {
+            candidates.push(Suggestion {
+                kind: SuggestionKind::Bookmark,
+                title: bookmark.title.clone(),
+                url: bookmark.url.clone(),
+                score: BOOKMARK_BONUS + frecencies.get(bookmark.url.as_str()).copied().unwrap_or(0.0) + prefix_bonus(typed, &bookmark.url),
+            });
+        }
+    }
+    for tab in tabs {
+        if !tab.url.is_empty() && matches(&words, &[&tab.title, &tab.url]) {
+            candidates.push(Suggestion {
+                kind: SuggestionKind::Tab(tab.tab),
+                title: tab.title.clone(),
+                url: tab.url.clone(),
+                score: TAB_BONUS + frecencies.get(tab.url.as_str()).copied().unwrap_or(0.0) + prefix_bonus(typed, &tab.url),
+            });
+        }
+    }
+
+    // アドレスの補完: 打った文字で始まる、一番よく行くページのアドレス
+    let completion = (!lower.contains(char::is_whitespace))
+        .then(|| {
+            candidates
+                .iter()
+                .filter(|c| !matches!(c.kind, SuggestionKind::Tab(_)) && strip_url(&c.url).to_lowercase().starts_with(typed))
+                .max_by(|a, b| a.score.total_cmp(&b.score))
+        })
+        .flatten()
+        .map(|best| {
+            let stripped = strip_url(&best.url);
+            // ホスト名までを補う。"/" まで打ってあれば URL 全体を補う
+            let end = if typed.contains('/') { stripped.len() } else { stripped.find('/').map_or(stripped.len(), |i| i + 1) };
+            let url = best.url[..best.url.len() - stripped.len() + end].to_string();
+            Suggestion {
+                kind: SuggestionKind::Completion,
+                title: stripped[..end].to_string(),
+                url,
+                score: f32::MAX,
+            }
+        });
+
+    // 同じ URL は点の高いものだけ残す
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut seen = HashSet::new();
+    let mut suggestions: Vec<Suggestion> = completion.into_iter().collect();
+    for candidate in candidates {
+        if suggestions.len() > MAX_SUGGESTIONS {
+            break;
+        }
+        if seen.insert(candidate.url.clone()) && !suggestions.iter().any(|s| s.url == candidate.url) {
+            suggestions.push(candidate);
+        }
+    }
+    let engine = &omnibox.engine.0;
+    suggestions.push(Suggestion {
+        kind: SuggestionKind::Search,
+        title: format!("Search {} for “{}”", engine_name(engine), input),
+        url: search_url(engine, input),
+        score: 0.0,
+    });
+    suggestions
+}
+
+/// Draws the URL text box with its suggestion list. Returns what Enter or
+/// a click on a suggestion asks for; `text` is set to the address loaded.
+pub fn url_bar_ui(ui: &mut egui::Ui, text: &mut String, tabs: &[OpenTab], omnibox: &mut Omnibox) -> Option<OmniboxAction> {
+    let id = egui::Id::new("url_bar");
+    let has_focus = ui.memory(|memory| memory.has_focus(id));
+    let listed = omnibox.state.open && !omnibox.state.suggestions.is_empty();
+    // 一覧が開いている間は矢印・Tab・Esc を TextEdit より先に受け取る
+    let (mut up, mut down, mut tab, mut escape) = (false, false, false, false);
+    if has_focus && listed {
+        ui.input_mut(|input| {
+            up = input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp);
+            down = input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown);
+            tab = input.consume_key(egui::Modifiers::NONE, egui::Key::Tab);
+            escape = input.consume_key(egui::Modifiers::NONE, egui::Key::Escape);
+        });
+    }
+    let response = ui.add(egui::TextEdit::singleline(text).id(id));
+    if response.gained_focus() {
+        omnibox.state.open = false;
+    }
+    if response.changed() {
+        omnibox.state.suggestions = suggest(text, tabs, omnibox);
+        omnibox.state.selected = None;
+        omnibox.state.open = true;
+    }
+
+    let state = &mut *omnibox.state;
+    let count = state.suggestions.len();
+    if down && count > 0 {
+        state.selected = Some(state.selected.map_or(0, |i| (i + 1).min(count - 1)));
+    }
+    if up {
+        state.selected = state.selected.and_then(|i| i.checked_sub(1));
+    }
+    if escape {
+        state.open = false;
+        state.selected = None;
+    }
+    if tab && let Some(completion) = state.suggestions.iter().find(|s| s.kind == SuggestionKind::Completion) {
+        *text = completion.url.clone();
+        // カーソルを補った文字の後ろへ
+        if let Some(mut edit_state) = egui::TextEdit::load_state(ui.ctx(), id) {
+            let end = egui::text::CCursor::new(text.chars().count());
+            edit_state.cursor.set_char_range(Some(egui::text::CCursorRange::one(end)));
+            edit_state.store(ui.ctx(), id);
+        }
+        state.suggestions.retain(|s| s.kind != SuggestionKind::Completion);
+        state.selected = None;
+    }
+
+    let mut action = None;
+    if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+        let picked = state.selected.and_then(|i| state.suggestions.get(i));
+        action = Some(picked.map_or_else(|| OmniboxAction::Navigate(address_or_search(text, &omnibox.engine.0)), Suggestion::action));
+    }
+
+    let state = &mut *omnibox.state;
+    if action.is_none() && state.open && !state.suggestions.is_empty() && (response.has_focus() || state.popup_hovered) {
+        let popup = egui::Area::new(id.with("suggestions"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(response.rect.left_bottom())
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.set_width(response.rect.width().max(400.0));
+                    for (index, suggestion) in state.suggestions.iter().enumerate() {
+                        let row = ui.selectable_label(state.selected == Some(index), suggestion.label()).on_hover_text(&suggestion.url);
+                        if row.clicked() {
+                            action = Some(suggestion.action());
+                        }
+                    }
+                });
+            });
+        state.popup_hovered = popup.response.contains_pointer();
+    } else {
+        state.popup_hovered = false;
+    }
+    if action.is_some() {
+        state.open = false;
+        state.selected = None;
+    }
+    if let Some(OmniboxAction::Navigate(url)) = &action {
+        text.clone_from(url);
+    }
+    action
+}
+
+/// Menu for picking the engine that text which is not an address is searched with.
+pub fn search_engine_menu_ui(ui: &mut egui::Ui, omnibox: &mut Omnibox) {
+    let mut engine = omnibox.engine.0.clone();
+    ui.menu_button("Search", |ui| {
+        for (name, template) in SEARCH_ENGINES {
+            ui.selectable_value(&mut engine, template.to_string(), *name);
+        }
+        // --search-engine で指定したものが一覧になければ足す
+        if !SEARCH_ENGINES.iter().any(|(_, template)| *template == omnibox.engine.0) {
+            ui.selectable_value(&mut engine, omnibox.engine.0.clone(), engine_name(&omnibox.engine.0));
+        }
+    })
+    .response
+    .on_hover_text(format!("Search with {}", engine_name(&engine)));
+    if engine != omnibox.engine.0 {
+        omnibox.engine.0 = engine;
+    }
+}
@@ -89,3 +89,481 @@ pub fn poll_p2p_udp_packets(
     }
 }
 
+
+// ---------------------------------------------------------------------------
+// p2p:// スキーム: UDP 上でピア同士がページを取り合う
+//
+// リクエスト: `P2P1 GET <id> <送信元のピアID> <パス> <欲しいチャンク番号 (カンマ区切り) か -> [<トークン>]`
+// レスポンス: `P2P1 RES <id> <番号> <チャンク数> <全体のバイト数> <ステータス> <Content-Type> <トークン>\n` + 本文の一部
+//
+// トークンは送信元アドレスに対する HMAC で、レスポンスで渡したものを次のリクエストで
+// 返してきた相手だけがそのアドレスで受信できると分かる。分からない相手には
+// 最初のチャンクしか送らないので、送信元を偽った GET で他人に大量に送りつけられない。
+// ---------------------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::http_client::{FetchError, ProgressSender, Response, ResponseSource};
+
+/// Port the peer node listens on unless `--p2p-port` says otherwise.
+pub const DEFAULT_PORT: u16 = 8080;
+/// Default file of known peers, one `<peer-id> <address>` per line.
+pub const DEFAULT_PEERS_FILE: &str = "peers.txt";
+const MAGIC: &str = "P2P1";
+/// Body bytes per response datagram; well under the UDP limit so the header fits.
+const CHUNK_SIZE: usize = 8192;
+/// Largest file served, to keep a response to a bounded number of datagrams.
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Most datagrams a response can be split into.
+const MAX_CHUNKS: usize = (MAX_FILE_SIZE / CHUNK_SIZE as u64) as usize + 1;
+/// How long to wait for the next datagram before asking again.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_ATTEMPTS: usize = 4;
+/// GET requests answered per source address in each `RATE_WINDOW`.
+const MAX_REQUESTS_PER_WINDOW: u32 = 32;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Settings of the local peer node.
+#[derive(Clone, Debug)]
+pub struct P2pConfig {
+    /// Name other peers know this node by.
+    pub peer_id: String,
+    pub port: u16,
+    /// Directory of pages shared with other peers; nothing is served if `None`.
+    pub share_dir: Option<PathBuf>,
+    pub peers_file: Option<PathBuf>,
+}
+
+/// One datagram of a response.
+struct Chunk {
+    index: usize,
+    count: usize,
+    total: u64,
+    status: u16,
+    content_type: String,
+    /// The sender's token for our address, to send back with the next request.
+    token: String,
+    body: Vec<u8>,
+}
+
+/// A request waiting for datagrams.
+struct Pending {
+    peer: SocketAddr,
+    tx: mpsc::UnboundedSender<Chunk>,
+}
+
+/// The local end of the p2p network: fetches `p2p://<peer-id>/<path>` from
+/// other peers and serves the shared directory to them, over one UDP socket.
+pub struct PeerNode {
+    id: String,
+    socket: UdpSocket,
+    share_dir: Option<PathBuf>,
+    /// Known peers by ID, from the peers file and from peers that asked us.
+    peers: Mutex<HashMap<String, SocketAddr>>,
+    /// Requests waiting for datagrams, by request ID.
+    pending: Mutex<HashMap<u64, Pending>>,
+    /// Key of the tokens handed to requesters.
+    token_key: hmac::Key,
+    /// Tokens other peers handed us, by their address.
+    tokens: Mutex<HashMap<SocketAddr, String>>,
+    /// Start of the current rate window and requests in it, by source address.
+    requests: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl PeerNode {
+    /// Binds the socket and starts answering peers. Must run on the Tokio runtime.
+    pub async fn start(config: P2pConfig) -> Result<Arc<Self>, String> {
+        let bind_address = SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, config.port));
+        let socket = UdpSocket::bind(bind_address).await.map_err(|e| format!("{}: {}", bind_address, e))?;
+        let peers = match &config.peers_file {
+            Some(path) => load_peers(path)?,
+            None => HashMap::new(),
+        };
+        let token_key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).map_err(|_| "no random source for the p2p token key".to_string())?;
+        let node = Arc::new(PeerNode {
+            id: config.peer_id,
+            socket,
+            share_dir: config.share_dir,
+            peers: Mutex::new(peers),
+            pending: Mutex::new(HashMap::new()),
+            token_key,
+            tokens: Mutex::new(HashMap::new()),
+            requests: Mutex::new(HashMap::new()),
+        });
+        info!("P2P node `{}` listening on {}", node.id, bind_address);
+        tokio::spawn(Arc::clone(&node).receive_loop());
+        Ok(node)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn port(&self) -> u16 {
+        self.socket.local_addr().map_or(0, |a| a.port())
+    }
+
+    pub fn share_dir(&self) -> Option<&Path> {
+        self.share_dir.as_deref()
+    }
+
+    /// Known peers, sorted by ID.
+    pub fn peers(&self) -> Vec<(String, SocketAddr)> {
+        let mut peers: Vec<_> = self.peers.lock().unwrap().iter().map(|(id, addr)| (id.clone(), *addr)).collect();
+        peers.sort();
+        peers
+    }
+
+    /// The address of a peer: this node, a known ID, or a literal `[addr]:port`.
+    pub fn resolve(&self, peer: &str) -> Option<SocketAddr> {
+        if peer == self.id {
+            return Some(SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, self.port())));
+        }
+        if let Some(addr) = self.peers.lock().unwrap().get(peer) {
+            return Some(*addr);
+        }
+        peer.parse().ok()
+    }
+
+    /// Fetches a `p2p://` URL from the peer it names.
+    pub async fn fetch(&self, url: &reqwest::Url, progress: Option<&ProgressSender>) -> Result<Response, FetchError> {
+        let peer = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => return Err(FetchError::InvalidUrl(format!("{}: no peer", url))),
+        };
+        let addr = self.resolve(&peer).ok_or_else(|| FetchError::Dns(peer.clone()))?;
+        let path = if url.path().is_empty() { "/" } else { url.path() };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let id = {
+            let mut pending = self.pending.lock().unwrap();
+            let mut id = random_request_id();
+            while pending.contains_key(&id) {
+                id = random_request_id();
+            }
+            pending.insert(id, Pending { peer: addr, tx });
+            id
+        };
+        let result = self.collect_chunks(id, addr, path, &mut rx, url, progress).await;
+        self.pending.lock().unwrap().remove(&id);
+        result
+    }
+
+    // チャンクがそろうまで待ち、途中で止まったら足りない分だけ頼み直す
+    async fn collect_chunks(
+        &self,
+        id: u64,
+        addr: SocketAddr,
+        path: &str,
+        rx: &mut mpsc::UnboundedReceiver<Chunk>,
+        url: &reqwest::Url,
+        progress: Option<&ProgressSender>,
+    ) -> Result<Response, FetchError> {
+        let mut chunks: Vec<Option<Vec<u8>>> = Vec::new();
+        let mut head: Option<(u16, String, u64)> = None;
+        let mut received = 0u64;
+        for _ in 0..MAX_ATTEMPTS {
+            let token = self.tokens.lock().unwrap().get(&addr).cloned();
+            let missing: Vec<String> = chunks.iter().enumerate().filter(|(_, c)| c.is_none()).map(|(i, _)| i.to_string()).collect();
+            let wanted = if missing.is_empty() { "-".to_string() } else { missing.join(",") };
+            let mut request = format!("{} GET {} {} {} {}", MAGIC, id, self.id, path, wanted);
+            if let Some(token) = &token {
+                request = format!("{} {}", request, token);
+            }
+            self.socket.send_to(request.as_bytes(), addr).await.map_err(|e| FetchError::Connect(e.to_string()))?;
+
+            while let Ok(Some(chunk)) = tokio::time::timeout(CHUNK_TIMEOUT, rx.recv()).await {
+                // 新しいトークンをもらったら、残りはそれを付けて頼み直す
+                let new_token = token.as_deref() != Some(chunk.token.as_str());
+                if new_token {
+                    self.tokens.lock().unwrap().insert(addr, chunk.token.clone());
+                }
+                if chunks.is_empty() {
+                    if chunk.total > MAX_FILE_SIZE || chunk.count > MAX_CHUNKS || chunk.count != chunk_count(chunk.total) {
+                        debug!("P2P: dropping chunk with {} parts of {} bytes from {}", chunk.count, chunk.total, addr);
+                        continue;
+                    }
+                    chunks = vec![None; chunk.count];
+                    head = Some((chunk.status, chunk.content_type.clone(), chunk.total));
+                } else if chunk.count != chunks.len() || head.as_ref().is_some_and(|(_, _, total)| *total != chunk.total) {
+                    continue;
+                }
+                let Some(slot) = chunks.get_mut(chunk.index) else {
+                    continue;
+                };
+                if slot.is_none() && chunk.body.len() as u64 == chunk_len(chunk.total, chunk.index) {
+                    received += chunk.body.len() as u64;
+                    *slot = Some(chunk.body);
+                    if let Some(progress) = progress {
+                        progress.send_modify(|p| {
+                            p.url = url.to_string();
+                            p.received = received;
+                            p.total = head.as_ref().map(|(_, _, total)| *total);
+                        });
+                    }
+                }
+                if chunks.iter().all(Option::is_some) {
+                    let (status, content_type, _) = head.take().unwrap_or_default();
+                    let body = chunks.into_iter().flatten().flatten().collect();
+                    let mut response = Response::local(url.clone(), &content_type, body);
+                    response.status = reqwest::StatusCode::from_u16(status).unwrap_or(reqwest::StatusCode::BAD_GATEWAY);
+                    response.source = ResponseSource::Network;
+                    return Ok(response);
+                }
+                if new_token {
+                    break;
+                }
+            }
+            debug!("P2P request {} to {} incomplete, asking again", id, addr);
+        }
+        Err(FetchError::Timeout)
+    }
+
+    // ソケットに届いたものを振り分け続ける
+    async fn receive_loop(self: Arc<Self>) {
+        let mut buf = vec![0u8; 65507]; // UDPパケットの最大サイズ
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    // ICMP の到達不能などで失敗することがあるが、ソケットはまだ使える
+                    debug!("P2P receive error: {}", e);
+                    continue;
+                }
+            };
+            let packet = &buf[..len];
+            let newline = packet.iter().position(|&b| b == b'\n').unwrap_or(len);
+            let header = String::from_utf8_lossy(&packet[..newline]).into_owned();
+            let body = packet.get(newline + 1..).unwrap_or_default().to_vec();
+            let fields: Vec<&str> = header.split(' ').collect();
+            match fields.as_slice() {
+                [MAGIC, "GET", id, peer_id, path, wanted, token @ ..] if token.len() <= 1 => {
+                    let Ok(id) = id.parse() else {
+                        continue;
+                    };
+                    if !self.allow_request(from.ip()) {
+                        debug!("P2P: too many requests from {}, dropping", from);
+                        continue;
+                    }
+                    let verified = token.first().is_some_and(|token| self.verify_token(from, token));
+                    if verified {
+                        // 知っているピアのアドレスは書き換えない (peers.txt のものも、覚えたものも)
+                        self.peers.lock().unwrap().entry(peer_id.to_string()).or_insert(from);
+                    }
+                    let wanted: Vec<usize> = wanted.split(',').filter_map(|i| i.parse().ok()).collect();
+                    let node = Arc::clone(&self);
+                    let path = path.to_string();
+                    tokio::spawn(async move { node.serve(id, &path, &wanted, from, verified).await });
+                }
+                [MAGIC, "RES", id, index, count, total, status, content_type, token] => {
+                    let (Ok(id), Ok(index), Ok(count), Ok(total), Ok(status)) = (id.parse::<u64>(), index.parse(), count.parse(), total.parse(), status.parse()) else {
+                        continue;
+                    };
+                    let pending = self.pending.lock().unwrap();
+                    let Some(request) = pending.get(&id).filter(|p| same_address(p.peer, from)) else {
+                        continue;
+                    };
+                    let _ = request.tx.send(Chunk {
+                        index,
+                        count,
+                        total,
+                        status,
+                        content_type: content_type.to_string(),
+                        token: token.to_string(),
+                        body,
+                    });
+                }
+                _ => debug!("Ignoring {} byte datagram from {}", len, from),
+            }
+        }
+    }
+
+    // 共有ディレクトリからファイルを読み、チャンクに分けて送り返す
+    async fn serve(&self, id: u64, path: &str, wanted: &[usize], to: SocketAddr, verified: bool) {
+        let (status, content_type, body) = match self.read_shared(path) {
+            Ok((content_type, body)) => (200, content_type, body),
+            Err((status, message)) => (status, "text/plain; charset=utf-8".to_string(), message.into_bytes()),
+        };
+        info!("P2P: serving {} to {} ({})", path, to, status);
+        let chunks: Vec<&[u8]> = if body.is_empty() { vec![&[]] } else { body.chunks(CHUNK_SIZE).collect() };
+        // Content-Type の空白は区切りと紛れるので詰める
+        let content_type: String = content_type.chars().filter(|c| !c.is_whitespace()).collect();
+        let token = self.token_for(to);
+        for (index, chunk) in chunks.iter().enumerate() {
+            // トークンを返してこない相手には最初のチャンクだけ
+            let send = if verified { wanted.is_empty() || wanted.contains(&index) } else { index == 0 };
+            if !send {
+                continue;
+            }
+            let mut datagram = format!("{} RES {} {} {} {} {} {} {}\n", MAGIC, id, index, chunks.len(), body.len(), status, content_type, token).into_bytes();
+            datagram.extend_from_slice(chunk);
+            if let Err(e) = self.socket.send_to(&datagram, to).await {
+                warn!("P2P: failed to send to {}: {}", to, e);
+                return;
+            }
+            // 受け手のバッファがあふれないよう少しずつ送る
+            if index % 16 == 15 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+    }
+
+    /// Counts a request from `ip` and says whether it is within the rate limit.
+    fn allow_request(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut requests = self.requests.lock().unwrap();
+        if requests.len() > 1024 {
+            requests.retain(|_, (start, _)| now.duration_since(*start) < RATE_WINDOW);
+        }
+        let (start, count) = requests.entry(ip.to_canonical()).or_insert((now, 0));
+        if now.duration_since(*start) >= RATE_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= MAX_REQUESTS_PER_WINDOW
+    }
+
+    /// The token for `addr`: hex HMAC of the address, so it needs no state.
+    fn token_for(&self, addr: SocketAddr) -> String {
+        let tag = hmac::sign(&self.token_key, address_bytes(addr).as_slice());
+        tag.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn verify_token(&self, addr: SocketAddr, token: &str) -> bool {
+        let Some(tag) = decode_hex(token) else {
+            return false;
+        };
+        hmac::verify(&self.token_key, address_bytes(addr).as_slice(), &tag).is_ok()
+    }
+
+    /// Reads `path` from the shared directory: the file itself, `index.html`
+    /// for a directory, or a listing if it has none.
+    fn read_shared(&self, path: &str) -> Result<(String, Vec<u8>), (u16, String)> {
+        let Some(share_dir) = &self.share_dir else {
+            return Err((403, "This peer shares nothing".to_string()));
+        };
+        let root = share_dir.canonicalize().map_err(|e| (500, e.to_string()))?;
+        let decoded = percent_encoding::percent_decode_str(path).decode_utf8_lossy().into_owned();
+        let mut file = root.clone();
+        for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+            // 共有ディレクトリの外には出さない
+            if segment == ".." || segment == "." || segment.contains(['\\', ':']) {
+                return Err((400, "Bad path".to_string()));
+            }
+            file.push(segment);
+        }
+        let not_found = || (404, format!("Not found: {}", decoded));
+        // シンボリックリンクをたどった先も共有ディレクトリの中でなければならない
+        let mut file = file.canonicalize().map_err(|_| not_found())?;
+        if !file.starts_with(&root) {
+            return Err(not_found());
+        }
+        let metadata = std::fs::metadata(&file).map_err(|_| not_found())?;
+        if metadata.is_dir() {
+            let index = file.join("index.html").canonicalize().ok().filter(|index| index.starts_with(&root) && index.is_file());
+            if let Some(index) = index {
+                file = index;
+            } else {
+                let base = format!("p2p://{}{}", self.id, if decoded.ends_with('/') { decoded.clone() } else { format!("{}/", decoded) });
+                let base = reqwest::Url::parse(&base).map_err(|e| (400, e.to_string()))?;
+                let listing = crate::schemes::directory_listing(&file, &base).map_err(|e| (500, e.to_string()))?;
+                return Ok(("text/html; charset=utf-8".to_string(), listing.into_bytes()));
+            }
+        }
+        if std::fs::metadata(&file).map_or(0, |m| m.len()) > MAX_FILE_SIZE {
+            return Err((413, "File too large to share".to_string()));
+        }
+        let body = std::fs::read(&file).map_err(|e| (500, e.to_string()))?;
+        Ok((crate::schemes::content_type_for(&file).to_string(), body))
+    }
+}
+
+/// Number of datagrams a body of `total` bytes is sent in.
+fn chunk_count(total: u64) -> usize {
+    (total.div_ceil(CHUNK_SIZE as u64) as usize).max(1)
+}
+
+/// Length of chunk `index` of a body of `total` bytes.
+fn chunk_len(total: u64, index: usize) -> u64 {
+    total.saturating_sub(index as u64 * CHUNK_SIZE as u64).min(CHUNK_SIZE as u64)
+}
+
+/// A random request ID, so other hosts can't guess one and inject a response.
+fn random_request_id() -> u64 {
+    let mut bytes = [0u8; 8];
+    // 乱数が取れなければ時刻で代える
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        bytes.copy_from_slice(&nanos.to_le_bytes()[..8]);
+    }
+    u64::from_le_bytes(bytes)
+}
+
+// IPv4 の相手はデュアルスタックのソケットでは ::ffff:a.b.c.d に見えるのでそろえる
+fn same_address(a: SocketAddr, b: SocketAddr) -> bool {
+    a.ip().to_canonical() == b.ip().to_canonical() && a.port() == b.port()
+}
+
+fn address_bytes(addr: SocketAddr) -> Vec<u8> {
+    let mut bytes = match addr.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+    bytes
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+/// Peer ID used when `--peer-id` is not given: the host name.
+pub fn default_peer_id() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace))
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Reads a peers file: `<peer-id> <address>` per line, `#` for comments.
+/// A missing file means no peers.
+fn load_peers(path: &Path) -> Result<HashMap<String, SocketAddr>, String> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(format!("{}: {}", path.display(), e)),
+    };
+    let mut peers = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((id, addr)) = line.split_once(char::is_whitespace) else {
+            warn!("{}:{}: expected `<peer-id> <address>`", path.display(), number + 1);
+            continue;
+        };
+        match addr.trim().parse() {
+            Ok(addr) => {
+                peers.insert(id.to_string(), addr);
+            }
+            Err(e) => warn!("{}:{}: bad address `{}`: {}", path.display(), number + 1, addr.trim(), e),
+        }
+    }
+    Ok(peers)
+}
@@ -0,0 +1,413 @@
+//! URL scheme dispatch in front of the fetch path: http(s) goes to the HTTP
+//! client, `file:` reads local files and directories, `data:` is decoded in
+//! place, `gemini:` and `gopher:` have their own small clients, `p2p:` asks a
+//! peer over UDP and `about:` serves the built-in pages.
+
+use base64::Engine;
+use reqwest::Url;
+
+use crate::cookies::RequestContext;
+use crate::history::{format_timestamp, SessionHistory};
+use crate::http_client::{Client, FetchError, FetchProgress, PostData, ProgressSender, Response, DEFAULT_CONNECT_TIMEOUT, DEFAULT_MAX_REDIRS};
+use crate::p2p::PeerNode;
+use crate::search::SearchIndex;
+
+/// Results listed on one about:search page.
+const MAX_SEARCH_RESULTS: usize = 50;
+
+/// The built-in about: pages, listed on about:about.
+const ABOUT_PAGES: &[(&str, &str)] = &[
+    ("about", "This list"),
+    ("blank", "An empty page"),
+    ("cache", "Entries in the HTTP cache"),
+    ("history", "Session history of this tab"),
+    ("network", "HTTP client settings and loads in progress"),
+    ("peers", "The p2p node and the peers it knows"),
+    ("search", "Full-text search over the pages this browser has loaded"),
+];
+
+/// The protocol clients a fetch can go through.
+pub struct Clients<'a> {
+    pub http: &'a Client,
+    pub gemini: &'a crate::gemini::Client,
+    /// The local peer node, if it is running.
+    pub p2p: Option<&'a PeerNode>,
+}
+
+/// Fetches `url` with whatever its scheme needs. about: pages other than
+/// about:blank need browser state and are made by [`about_page`] instead.
+/// A `post` body is only sent over http(s); other schemes just load the URL.
+pub async fn fetch(
+    clients: &Clients<'_>,
+    url: &str,
+    post: Option<&PostData>,
+    context: &RequestContext,
+    progress: Option<&ProgressSender>,
+) -> Result<Response, FetchError> {
+    let parsed = Url::parse(url).map_err(|e| FetchError::InvalidUrl(format!("{}: {}", url, e)))?;
+    // ページが読み込む画像やスタイルシートは、ページと同じ種類の URL か data: に限る
+    if !context.navigation
+        && let Some(initiator) = context.initiator.as_deref()
+        && !subresource_allowed(initiator, parsed.scheme())
+    {
+        return Err(FetchError::Other(format!("{} can't load {}: subresources", initiator, parsed.scheme())));
+    }
+    match parsed.scheme() {
+        "http" | "https" => match post {
+            Some(data) => clients.http.post(url, data, context, progress).await,
+            None => clients.http.get(url, context, progress).await,
+        },
+        "gemini" => clients.gemini.get(&parsed, progress).await,
+        "gopher" => crate::gopher::fetch(&parsed, progress).await,
+        "file" => {
+            // Web のページからローカルファイルは読ませない
+            if context.initiator.as_deref().is_some_and(|initiator| !initiator.starts_with("file:")) {
+                return Err(FetchError::File(format!("{} can't load local files", context.initiator.as_deref().unwrap_or_default())));
+            }
+            tokio::task::spawn_blocking(move || read_file(parsed))
+                .await
+                .unwrap_or_else(|e| Err(FetchError::Other(format!("File task failed: {}", e))))
+        }
+        "data" => decode_data_url(&parsed),
+        "p2p" => match clients.p2p {
+            Some(node) => node.fetch(&parsed, progress).await,
+            None => Err(FetchError::Connect("the p2p node is not running".to_string())),
+        },
+        "about" if parsed.path() == "blank" => Ok(Response::local(parsed, "text/html; charset=utf-8", Vec::new())),
+        "about" => Err(FetchError::UnsupportedScheme(url.to_string())),
+        scheme => Err(FetchError::UnsupportedScheme(format!("{}:", scheme))),
+    }
+}
+
+/// Whether a page at `initiator` may load images and stylesheets over
+/// `scheme`. Web pages are kept to http(s) and data:, so an `<img>` can't
+/// send requests to local services through gopher:, gemini: or p2p:.
+fn subresource_allowed(initiator: &str, scheme: &str) -> bool {
+    let from = Url::parse(initiator).map(|url| url.scheme().to_string()).unwrap_or_default();
+    match from.as_str() {
+        "http" | "https" => matches!(scheme, "http" | "https" | "data"),
+        "file" => matches!(scheme, "file" | "http" | "https" | "data"),
+        _ => scheme == from || scheme == "data",
+    }
+}
+
+/// Content type for a local file, from its extension. Text types get no
+/// charset so the encoding is sniffed as for any other page.
+pub(crate) fn content_type_for(path: &std::path::Path) -> &'static str {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" | "xhtml" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "text/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        "pdf" => "application/pdf",
+        "glb" => "model/gltf-binary",
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "ogv" => "video/ogg",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "txt" | "md" | "rs" | "toml" | "c" | "cpp" | "h" | "py" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn read_file(url: Url) -> Result<Response, FetchError> {
+    let path = url.to_file_path().map_err(|_| FetchError::InvalidUrl(format!("{}: not a local path", url)))?;
+    let metadata = std::fs::metadata(&path).map_err(|e| FetchError::File(format!("{}: {}", path.display(), e)))?;
+    if metadata.is_dir() {
+        let base = Url::from_directory_path(&path).map_err(|_| FetchError::InvalidUrl(format!("{}: not a local path", url)))?;
+        let listing = directory_listing(&path, &base).map_err(|e| FetchError::File(format!("{}: {}", path.display(), e)))?;
+        return Ok(Response::local(url, "text/html; charset=utf-8", listing.into_bytes()));
+    }
+    let body = std::fs::read(&path).map_err(|e| FetchError::File(format!("{}: {}", path.display(), e)))?;
+    Ok(Response::local(url, content_type_for(&path), body))
+}
+
+// ディレクトリの中身をリンクの一覧にした HTML を作る (ディレクトリが先)
+// リンクは `base` (ディレクトリ自身の URL) からの相対で作るので p2p: の共有ディレクトリにも使える
+pub(crate) fn directory_listing(dir: &std::path::Path, base: &Url) -> std::io::Result<String> {
+    let mut entries: Vec<(bool, String, u64, u64)> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let metadata = entry.metadata().ok();
+            let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+            let size = metadata.as_ref().map_or(0, |m| m.len());
+            let modified = metadata
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            (is_dir, entry.file_name().to_string_lossy().into_owned(), size, modified)
+        })
+        .collect();
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let title = format!("Index of {}", percent_encoding::percent_decode_str(base.path()).decode_utf8_lossy());
+    let mut rows = String::new();
+    if base.path() != "/"
+        && let Ok(parent) = base.join("../")
+    {
+        rows.push_str(&format!("<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n", escape(parent.as_str())));
+    }
+    for (is_dir, name, size, modified) in entries {
+        let segment = percent_encoding::utf8_percent_encode(&name, PATH_SEGMENT).to_string();
+        let Ok(href) = base.join(&if is_dir { format!("{}/", segment) } else { segment }) else {
+            continue;
+        };
+        let (label, size) = if is_dir { (format!("{}/", name), String::new()) } else { (name, size.to_string()) };
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape(href.as_str()),
+            escape(&label),
+            size,
+            format_timestamp(modified)
+        ));
+    }
+    Ok(page(&title, &format!("<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n{}</table>", rows)))
+}
+
+/// Characters escaped in a file name used as one path segment of a link.
+const PATH_SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// The data: URL processor of the Fetch standard.
+fn decode_data_url(url: &Url) -> Result<Response, FetchError> {
+    let invalid = |why: &str| FetchError::InvalidUrl(format!("{}: {}", url, why));
+    let input = url.as_str()["data:".len()..].split('#').next().unwrap_or("");
+    let (mime, encoded) = input.split_once(',').ok_or_else(|| invalid("missing ','"))?;
+    let mut mime = mime.trim_matches(|c: char| c.is_ascii_whitespace()).to_string();
+    let mut body: Vec<u8> = percent_encoding::percent_decode_str(encoded).collect();
+
+    // ";base64" (前に空白があってもよい) で終わっていれば Base64
+    let lower = mime.to_ascii_lowercase();
+    if let Some(rest) = lower.strip_suffix("base64")
+        && rest.trim_end_matches(' ').ends_with(';')
+    {
+        mime.truncate(rest.trim_end_matches(' ').len() - 1);
+        mime.truncate(mime.trim_end().len());
+        body.retain(|b| !b.is_ascii_whitespace());
+        let engine = base64::engine::GeneralPurpose::new(
+            &base64::alphabet::STANDARD,
+            base64::engine::GeneralPurposeConfig::new()
+                .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent)
+                .with_decode_allow_trailing_bits(true),
+        );
+        body = engine.decode(&body).map_err(|e| invalid(&e.to_string()))?;
+    }
+    if mime.starts_with(';') {
+        mime.insert_str(0, "text/plain");
+    }
+    if !mime.contains('/') {
+        mime = "text/plain;charset=US-ASCII".to_string();
+    }
+    Ok(Response::local(url.clone(), &mime, body))
+}
+
+/// Browser state the about: pages show.
+pub struct AboutSources<'a> {
+    pub history: &'a SessionHistory,
+    pub client: &'a Client,
+    /// Page loads in flight in all tabs.
+    pub loads: Vec<FetchProgress>,
+    pub p2p: Option<&'a PeerNode>,
+    pub search: &'a SearchIndex,
+}
+
+/// Makes the about: page for `url`, or `None` if there is no such page.
+pub fn about_page(url: &str, sources: &AboutSources) -> Option<Response> {
+    let parsed = Url::parse(url).ok().filter(|u| u.scheme() == "about")?;
+    let html = match parsed.path() {
+        "blank" => String::new(),
+        "about" => about_about(),
+        "history" => about_history(sources.history),
+        "cache" => about_cache(sources.client),
+        "network" => about_network(sources.client, &sources.loads),
+        "peers" => about_peers(sources.p2p),
+        "search" => {
+            let query = parsed.query_pairs().find(|(name, _)| name == "q").map(|(_, value)| value.into_owned()).unwrap_or_default();
+            about_search(sources.search, &query)
+        }
+        _ => return None,
+    };
+    Some(Response::local(parsed, "text/html; charset=utf-8", html.into_bytes()))
+}
+
+fn about_about() -> String {
+    let rows: String = ABOUT_PAGES
+        .iter()
+        .map(|(name, what)| format!("<tr><td><a href=\"about:{0}\">about:{0}</a></td><td>{1}</td></tr>\n", name, what))
+        .collect();
+    page("About pages", &format!("<table>\n{}</table>", rows))
+}
+
+fn about_history(history: &SessionHistory) -> String {
+    let mut rows = String::new();
+    for (index, entry) in history.entries.iter().enumerate().rev() {
+        let marker = if history.current == Some(index) { "▶" } else { "" };
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td><a href=\"{}\">{}</a></td><td>{}</td></tr>\n",
+            marker,
+            format_timestamp(entry.visited_at),
+            escape(&entry.url),
+            escape(entry.title.as_deref().unwrap_or(&entry.url)),
+            escape(&entry.url)
+        ));
+    }
+    page("History", &format!("<table>\n<tr><th></th><th>Visited (UTC)</th><th>Title</th><th>URL</th></tr>\n{}</table>", rows))
+}
+
+fn about_cache(client: &Client) -> String {
+    let Some(cache) = client.cache() else {
+        return page("HTTP cache", "<p>The HTTP cache is off.</p>");
+    };
+    let entries = cache.entries();
+    let total: u64 = entries.iter().map(|e| e.body_size).sum();
+    let mut rows = String::new();
+    for entry in &entries {
+        rows.push_str(&format!(
+            "<tr><td><a href=\"{0}\">{0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td><td>{4}</td><td>{5}</td></tr>\n",
+            escape(&entry.url),
+            entry.status,
+            entry.body_size,
+            format_timestamp(entry.stored_at),
+            if crate::http_cache::is_fresh_now(entry) { "fresh" } else { "stale" },
+            escape(entry.header("etag").or(entry.header("last-modified")).unwrap_or(""))
+        ));
+    }
+    page(
+        "HTTP cache",
+        &format!(
+            "<p>{} entries, {} bytes in <code>{}</code></p>\n<table>\n<tr><th>URL</th><th>Status</th><th>Size</th><th>Stored (UTC)</th><th>State</th><th>Validator</th></tr>\n{}</table>",
+            entries.len(),
+            total,
+            escape(&cache.dir().display().to_string()),
+            rows
+        ),
+    )
+}
+
+fn about_network(client: &Client, loads: &[FetchProgress]) -> String {
+    let config = client.config();
+    let seconds = |d: Option<std::time::Duration>| d.map_or_else(|| "none".to_string(), |d| format!("{} s", d.as_secs_f32()));
+    let settings = [
+        ("User-Agent", config.user_agent.clone().unwrap_or_else(|| "default".to_string())),
+        ("Extra headers", config.headers.iter().map(|(n, v)| format!("{}: {}", n, v)).collect::<Vec<_>>().join(", ")),
+        ("Connect timeout", seconds(config.connect_timeout.or(Some(DEFAULT_CONNECT_TIMEOUT)))),
+        ("Max time", seconds(config.max_time)),
+        ("Follow redirects", if config.location { format!("yes (max {})", config.max_redirs.unwrap_or(DEFAULT_MAX_REDIRS)) } else { "no".to_string() }),
+        ("Proxy", config.proxy.clone().unwrap_or_else(|| "none".to_string())),
+        ("No proxy for", config.noproxy.clone().unwrap_or_default()),
+        ("Verify certificates", if config.insecure { "no (-k)" } else { "yes" }.to_string()),
+        ("Cookie jar", config.cookie_jar.as_ref().map_or_else(|| "not saved".to_string(), |p| p.display().to_string())),
+        ("Cookies", client.cookie_jar().lock().unwrap().cookies.len().to_string()),
+        ("HTTP cache", client.cache().map_or_else(|| "off".to_string(), |c| format!("{} entries", c.entries().len()))),
+    ];
+    let settings: String = settings
+        .iter()
+        .map(|(name, value)| format!("<tr><td>{}</td><td>{}</td></tr>\n", name, escape(value)))
+        .collect();
+    let loads: String = loads
+        .iter()
+        .map(|p| {
+            let total = p.total.map_or_else(|| "?".to_string(), |t| t.to_string());
+            format!("<tr><td>{}</td><td>{} / {}</td><td>{}</td></tr>\n", escape(&p.url), p.received, total, p.redirects)
+        })
+        .collect();
+    page(
+        "Network",
+        &format!(
+            "<h2>Client settings</h2>\n<table>\n{}</table>\n<h2>Loads in progress</h2>\n<table>\n<tr><th>URL</th><th>Bytes</th><th>Redirects</th></tr>\n{}</table>",
+            settings, loads
+        ),
+    )
+}
+
+fn about_peers(node: Option<&PeerNode>) -> String {
+    let Some(node) = node else {
+        return page("Peers", "<p>The p2p node is not running.</p>");
+    };
+    let shared = node.share_dir().map_or_else(|| "nothing".to_string(), |dir| format!("<code>{}</code>", escape(&dir.display().to_string())));
+    let rows: String = node
+        .peers()
+        .iter()
+        .map(|(id, addr)| format!("<tr><td><a href=\"p2p://{0}/\">{0}</a></td><td>{1}</td></tr>\n", escape(id), addr))
+        .collect();
+    page(
+        "Peers",
+        &format!(
+            "<p>This node is <a href=\"p2p://{0}/\">{0}</a> on UDP port {1}, sharing {2}.</p>\n<table>\n<tr><th>Peer</th><th>Address</th></tr>\n{3}</table>",
+            escape(node.id()),
+            node.port(),
+            shared,
+            rows
+        ),
+    )
+}
+
+fn about_search(index: &SearchIndex, query: &str) -> String {
+    let form = format!(
+        "<form action=\"about:search\"><input type=\"search\" name=\"q\" size=\"40\" value=\"{}\"> <input type=\"submit\" value=\"Search\"></form>\n",
+        escape(query)
+    );
+    if query.trim().is_empty() {
+        return page("Search", &format!("{}<p>{} pages indexed.</p>", form, index.len()));
+    }
+    let (total, hits) = index.search(query, MAX_SEARCH_RESULTS);
+    let mut results = String::new();
+    for hit in &hits {
+        let title = if hit.title.is_empty() { &hit.url } else { &hit.title };
+        // 検索語に一致した部分を太字にする
+        let snippet: String = hit
+            .snippet
+            .iter()
+            .map(|(text, matched)| if *matched { format!("<b>{}</b>", escape(text)) } else { escape(text) })
+            .collect();
+        results.push_str(&format!(
+            "<li><a href=\"{0}\">{1}</a><br><small>{0}</small><br>{2}</li>\n",
+            escape(&hit.url),
+            escape(title),
+            snippet
+        ));
+    }
+    let shown = if total > hits.len() { format!(", showing the first {}", hits.len()) } else { String::new() };
+    page(
+        &format!("{} - Search", query),
+        &format!("{}<p>{} of {} pages match{}.</p>\n<ol>\n{}</ol>", form, total, index.len(), shown, results),
+    )
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body><h1>{0}</h1>\n{1}\n</body></html>\n",
+        escape(title),
+        body
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,424 @@
+//! Local full-text search over the pages the browser has loaded.
+//!
+//! Page text is split into terms with [`terms`]: words for alphabetic
+//! scripts and overlapping character bigrams for Japanese and Chinese,
+//! which are written without spaces. The inverted index (term to the pages
+//! it appears in) is kept in `index.json` and the text of each page in
+//! `pages/<doc>.txt` for snippets. Queries must match every term and are
+//! ranked with BM25.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::dom::{Document, NodeData, NodeId};
+use crate::{CurrentDocument, NativePage, PageIndex, ResponseBody};
+
+/// Directory the index is kept in, relative to the working directory.
+pub const DEFAULT_DIR: &str = "search_index";
+/// How often a changed index is written while the browser runs.
+const SAVE_INTERVAL_SECS: f32 = 10.0;
+/// Title terms count this many times as often as body terms.
+const TITLE_WEIGHT: u32 = 3;
+/// BM25 parameters.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+/// Characters of context around the first match in a snippet.
+const SNIPPET_BEFORE: usize = 60;
+const SNIPPET_AFTER: usize = 180;
+/// Elements whose text is not shown on the page.
+const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "noscript", "template"];
+/// Elements that break words apart even without whitespace between them.
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "br", "dd", "div", "dl", "dt", "figcaption", "footer", "h1", "h2", "h3", "h4", "h5",
+    "h6", "header", "hr", "li", "main", "nav", "ol", "p", "pre", "section", "table", "td", "th", "tr", "ul",
+];
+/// Search URL template of the local index; `%s` is replaced by the query.
+pub const LOCAL_SEARCH: &str = "about:search?q=%s";
+/// Schemes that get an address typed without one.
+const KNOWN_SCHEMES: &[&str] = &["http", "https", "about", "data", "file", "gemini", "gopher", "p2p"];
+
+/// One indexed page.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexedPage {
+    pub url: String,
+    pub title: String,
+    /// Number of terms, title included.
+    pub length: u32,
+    /// Unix seconds.
+    pub indexed_at: u64,
+    /// Times the page has been loaded, for ranking URL bar suggestions.
+    #[serde(default)]
+    pub visits: u32,
+}
+
+/// What `index.json` holds.
+#[derive(Serialize, Deserialize, Default)]
+struct IndexData {
+    pages: BTreeMap<u32, IndexedPage>,
+    /// Term → (doc, term frequency), sorted by doc.
+    postings: HashMap<String, Vec<(u32, u32)>>,
+    next_doc: u32,
+}
+
+pub struct SearchIndex {
+    dir: PathBuf,
+    data: IndexData,
+    by_url: HashMap<String, u32>,
+    /// Changed since it was last written.
+    dirty: bool,
+}
+
+/// A page matching a query.
+#[derive(Clone, Debug)]
+pub struct SearchHit {
+    pub url: String,
+    pub title: String,
+    pub score: f32,
+    /// The text around the first match, split into plain and matched parts.
+    pub snippet: Vec<(String, bool)>,
+}
+
+impl SearchIndex {
+    /// An empty index that will be written to `dir`.
+    pub fn new(dir: &Path) -> Self {
+        SearchIndex {
+            dir: dir.to_path_buf(),
+            data: IndexData::default(),
+            by_url: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Opens (creating if needed) the index in `dir`.
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        std::fs::create_dir_all(dir.join("pages")).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut index = SearchIndex::new(dir);
+        match std::fs::read(dir.join("index.json")) {
+            Ok(bytes) => index.data = serde_json::from_slice(&bytes).map_err(|e| format!("{}: {}", dir.join("index.json").display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("{}: {}", dir.join("index.json").display(), e)),
+        }
+        index.by_url = index.data.pages.iter().map(|(&doc, page)| (page.url.clone(), doc)).collect();
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.pages.len()
+    }
+
+    pub fn pages(&self) -> impl Iterator<Item = &IndexedPage> {
+        self.data.pages.values()
+    }
+
+    fn text_path(&self, doc: u32) -> PathBuf {
+        self.dir.join("pages").join(format!("{doc}.txt"))
+    }
+
+    /// Indexes a page, replacing what was indexed for the same URL before.
+    pub fn add_page(&mut self, url: &str, title: &str, text: &str) {
+        let visits = self.by_url.get(url).and_then(|doc| self.data.pages.get(doc)).map_or(0, |page| page.visits) + 1;
+        self.remove_page(url);
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for term in terms(text) {
+            *frequencies.entry(term).or_default() += 1;
+        }
+        for term in terms(title) {
+            *frequencies.entry(term).or_default() += TITLE_WEIGHT;
+        }
+        if frequencies.is_empty() {
+            return;
+        }
+        let doc = self.data.next_doc;
+        self.data.next_doc += 1;
+        let length = frequencies.values().sum();
+        // 文書番号は増える一方なので、末尾に足せば並びは保たれる
+        for (term, frequency) in frequencies {
+            self.data.postings.entry(term).or_default().push((doc, frequency));
+        }
+        self.data.pages.insert(
+            doc,
+            IndexedPage {
+                url: url.to_string(),
+                title: title.to_string(),
+                length,
+                indexed_at: now(),
+                visits,
+            },
+        );
+        self.by_url.insert(url.to_string(), doc);
+        if let Err(e) = std::fs::write(self.text_path(doc), text) {
+            error!("Failed to store the text of {} for search: {}", url, e);
+        }
+        self.dirty = true;
+    }
+
+    pub fn remove_page(&mut self, url: &str) {
+        let Some(doc) = self.by_url.remove(url) else {
+            return;
+        };
+        self.data.pages.remove(&doc);
+        self.data.postings.retain(|_, list| {
+            if let Ok(position) = list.binary_search_by_key(&doc, |&(d, _)| d) {
+                list.remove(position);
+            }
+            !list.is_empty()
+        });
+        let _ = std::fs::remove_file(self.text_path(doc));
+        self.dirty = true;
+    }
+
+    /// Pages containing every term of `query`, best first, and how many there were.
+    pub fn search(&self, query: &str, limit: usize) -> (usize, Vec<SearchHit>) {
+        let mut query_terms = terms(query);
+        query_terms.sort();
+        query_terms.dedup();
+        if query_terms.is_empty() || self.data.pages.is_empty() {
+            return (0, Vec::new());
+        }
+        let total_pages = self.data.pages.len() as f32;
+        let average_length = self.data.pages.values().map(|p| p.length as f32).sum::<f32>() / total_pages;
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for (position, term) in query_terms.iter().enumerate() {
+            let Some(list) = self.data.postings.get(term) else {
+                return (0, Vec::new());
+            };
+            let idf = (1.0 + (total_pages - list.len() as f32 + 0.5) / (list.len() as f32 + 0.5)).ln();
+            let mut next = HashMap::new();
+            for &(doc, frequency) in list {
+                // 前の語をすべて含む文書だけを残す
+                let so_far = if position == 0 { Some(0.0) } else { scores.get(&doc).copied() };
+                let (Some(so_far), Some(page)) = (so_far, self.data.pages.get(&doc)) else {
+                    continue;
+                };
+                let tf = frequency as f32;
+                let norm = K1 * (1.0 - B + B * page.length as f32 / average_length);
+                next.insert(doc, so_far + idf * tf * (K1 + 1.0) / (tf + norm));
+            }
+            scores = next;
+        }
+        let mut ranked: Vec<(u32, f32)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        let total = ranked.len();
+        let hits = ranked
+            .into_iter()
+            .take(limit)
+            .filter_map(|(doc, score)| {
+                let page = self.data.pages.get(&doc)?;
+                let text = std::fs::read_to_string(self.text_path(doc)).unwrap_or_default();
+                Some(SearchHit {
+                    url: page.url.clone(),
+                    title: page.title.clone(),
+                    score,
+                    snippet: snippet(&text, query),
+                })
+            })
+            .collect();
+        (total, hits)
+    }
+
+    /// Writes the inverted index if it changed, through a temporary file.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let path = self.dir.join("index.json");
+        let temporary = path.with_extension("json.tmp");
+        std::fs::create_dir_all(self.dir.join("pages"))?;
+        std::fs::write(&temporary, serde_json::to_vec(&self.data).map_err(std::io::Error::other)?)?;
+        std::fs::rename(&temporary, &path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Folds a character for matching: full-width ASCII to ASCII, then lower case.
+fn fold(c: char) -> char {
+    let c = match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        _ => c,
+    };
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Kana and CJK ideographs, which are indexed as bigrams.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}' // ひらがな・カタカナ
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF66}'..='\u{FF9F}' // 半角カタカナ
+        | '\u{AC00}'..='\u{D7AF}' // ハングル
+    )
+}
+
+/// Splits text into index terms: lower-cased words, and overlapping
+/// character bigrams for runs of CJK characters (a lone character is its own term).
+pub fn terms(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut word = String::new();
+    let mut run: Vec<char> = Vec::new();
+    let flush_run = |run: &mut Vec<char>, out: &mut Vec<String>| {
+        if run.len() == 1 {
+            out.push(run[0].to_string());
+        }
+        out.extend(run.windows(2).map(|pair| pair.iter().collect::<String>()));
+        run.clear();
+    };
+    for c in text.chars().map(fold) {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                out.push(std::mem::take(&mut word));
+            }
+            run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_run(&mut run, &mut out);
+            word.push(c);
+        } else {
+            flush_run(&mut run, &mut out);
+            if !word.is_empty() {
+                out.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_run(&mut run, &mut out);
+    if !word.is_empty() {
+        out.push(word);
+    }
+    out
+}
+
+// 最初に見つかった検索語のまわりを切り出し、検索語の部分に印を付ける
+fn snippet(text: &str, query: &str) -> Vec<(String, bool)> {
+    let chars: Vec<char> = text.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+    let words: Vec<Vec<char>> = query.split_whitespace().map(|w| w.chars().map(fold).collect()).filter(|w: &Vec<char>| !w.is_empty()).collect();
+    let match_at = |i: usize| words.iter().filter(|w| folded[i..].starts_with(w)).map(|w| w.len()).max();
+    let first = (0..folded.len()).find(|&i| match_at(i).is_some()).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_BEFORE);
+    let end = (first + SNIPPET_AFTER).min(chars.len());
+    let mut parts: Vec<(String, bool)> = Vec::new();
+    let mut push = |text: String, matched: bool| match parts.last_mut() {
+        Some((last, last_matched)) if *last_matched == matched => last.push_str(&text),
+        _ => parts.push((text, matched)),
+    };
+    if start > 0 {
+        push("…".to_string(), false);
+    }
+    let mut i = start;
+    while i < end {
+        match match_at(i) {
+            Some(n) => {
+                let n = n.min(end - i);
+                push(chars[i..i + n].iter().collect(), true);
+                i += n;
+            }
+            None => {
+                push(chars[i].to_string(), false);
+                i += 1;
+            }
+        }
+    }
+    if end < chars.len() {
+        push("…".to_string(), false);
+    }
+    parts
+}
+
+/// The text a reader sees on the page, with whitespace collapsed.
+pub fn visible_text(document: &Document) -> String {
+    let mut out = String::new();
+    collect_text(document, document.root(), &mut out);
+    out.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn collect_text(document: &Document, node: NodeId, out: &mut String) {
+    match &document.node(node).data {
+        NodeData::Text(text) => out.push_str(text),
+        NodeData::Element(element) if HIDDEN_ELEMENTS.contains(&element.name.as_str()) => {}
+        NodeData::Element(element) => {
+            let block = BLOCK_ELEMENTS.contains(&element.name.as_str());
+            if block {
+                out.push(' ');
+            }
+            for &child in document.children(node) {
+                collect_text(document, child, out);
+            }
+            if block {
+                out.push(' ');
+            }
+        }
+        NodeData::Document => {
+            for &child in document.children(node) {
+                collect_text(document, child, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Turns what was typed into the URL bar into an address: a URL as it is,
+/// something that looks like a host name with `https://` in front, and
+/// anything else into a search with the `engine` URL template.
+pub fn address_or_search(input: &str, engine: &str) -> String {
+    let input = input.trim();
+    if let Ok(url) = reqwest::Url::parse(input)
+        && KNOWN_SCHEMES.contains(&url.scheme())
+    {
+        return input.to_string();
+    }
+    let host = input.split(['/', '?', '#']).next().unwrap_or("");
+    let host_name = host.rsplit_once(':').map_or(host, |(name, _)| name);
+    if !input.is_empty() && !input.contains(char::is_whitespace) && (host_name.contains('.') || host_name == "localhost") {
+        let scheme = if host_name == "localhost" { "http" } else { "https" };
+        let address = format!("{scheme}://{input}");
+        if reqwest::Url::parse(&address).is_ok() {
+            return address;
+        }
+    }
+    search_url(engine, input)
+}
+
+/// Fills the `%s` of a search URL template with the query.
+pub fn search_url(engine: &str, query: &str) -> String {
+    engine.replace("%s", &form_urlencoded::byte_serialize(query.as_bytes()).collect::<String>())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// 読み込んだページをローカルの全文検索の索引に入れるシステム
+pub fn index_loaded_pages(mut index: ResMut<PageIndex>, pages: Query<(&CurrentDocument, &ResponseBody, &NativePage), Changed<CurrentDocument>>) {
+    for (document, body, native_page) in &pages {
+        // ブラウザ自身のページと、文字のない画像・動画・モデルは入れない
+        if body.url.is_empty() || body.url.starts_with("about:") || body.url.starts_with("data:") {
+            continue;
+        }
+        if matches!(native_page, NativePage::Image(_) | NativePage::Media(_) | NativePage::Model(_)) {
+            continue;
+        }
+        let title = document.0.title().unwrap_or_default();
+        let text = visible_text(&document.0);
+        index.0.add_page(&body.url, &title, &text);
+    }
+}
+
+// 索引の変更を一定時間ごとと終了時にディスクへ書くシステム (Last で動かす)
+pub fn save_search_index(mut index: ResMut<PageIndex>, time: Res<Time>, mut exits: EventReader<AppExit>, mut last_save: Local<f32>) {
+    let exiting = exits.read().count() > 0;
+    if !exiting && time.elapsed_secs() - *last_save < SAVE_INTERVAL_SECS {
+        return;
+    }
+    *last_save = time.elapsed_secs();
+    // 書き込みがないときは ResMut の変更フラグも立てない
+    if let Err(e) = index.bypass_change_detection().0.save() {
+        error!("Failed to save the search index: {}", e);
+    }
+}
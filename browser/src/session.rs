@@ -0,0 +1,206 @@
+//! Session restore: the open tabs with their history and scroll offsets,
+//! which windows are shown and where, and the video in the player window.
+//! The session is written on exit and every [`SAVE_INTERVAL_SECS`] so a
+//! crash loses at most that much, and read back on the next launch.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::{EguiContexts, egui};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg::VideoPlayer;
+use crate::history::{Navigate, NavigationAction, SessionHistory};
+use crate::menu::WindowToggles;
+use crate::{BrowsingHistory, CurrentUrl, PageViewer, RestoredSession, ScrollPosition, SearchEngine, Tab, Tabs};
+
+/// Session file in the working directory, next to the cookie jar.
+pub const DEFAULT_FILE: &str = "session.ron";
+/// How often the session is saved while the browser runs.
+const SAVE_INTERVAL_SECS: f32 = 30.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Session {
+    pub tabs: Vec<TabSession>,
+    /// Index into `tabs` of the tab that was shown.
+    pub active: usize,
+    /// Whether each window was shown, by the names [`WindowToggles::all`] gives them.
+    pub windows: BTreeMap<String, bool>,
+    /// Top-left corner of each window, by window title.
+    pub positions: BTreeMap<String, [f32; 2]>,
+    /// The file playing in the video player window.
+    pub video: Option<PathBuf>,
+    /// Search URL template picked in the URL bar.
+    #[serde(default)]
+    pub search_engine: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TabSession {
+    /// What the URL bar showed, which may not have been loaded yet.
+    pub url: String,
+    pub history: SessionHistory,
+    /// Scroll offset of the page view.
+    pub scroll: [f32; 2],
+}
+
+impl Session {
+    /// Reads the last session. A missing or unreadable file starts a new one.
+    pub fn load(path: &Path) -> Option<Session> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                error!("Failed to read the session from {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        ron::from_str(&text).map_err(|e| error!("Failed to parse the session in {}: {}", path.display(), e)).ok()
+    }
+}
+
+/// Writes to a temporary file first so a crash halfway through keeps the old session.
+fn write_atomically(path: &Path, text: &str) -> std::io::Result<()> {
+    let temporary = path.with_extension("ron.tmp");
+    std::fs::write(&temporary, text)?;
+    std::fs::rename(&temporary, path)
+}
+
+// 前回のセッションのタブを開き直し、ウィンドウの表示を戻すシステム (setup_first_tab の後に動かす)
+pub fn restore_session(
+    mut commands: Commands,
+    restored: Res<RestoredSession>,
+    mut tabs: ResMut<Tabs>,
+    mut navigate: EventWriter<Navigate>,
+    mut windows: WindowToggles,
+) {
+    let session = &restored.0;
+    if !session.tabs.is_empty() {
+        for tab in tabs.order.drain(..) {
+            commands.entity(tab).despawn();
+        }
+        for saved in &session.tabs {
+            let [x, y] = saved.scroll;
+            let tab = commands
+                .spawn((
+                    Tab,
+                    CurrentUrl(saved.url.clone()),
+                    BrowsingHistory(saved.history.clone()),
+                    ScrollPosition {
+                        restore: Some(Vec2::new(x, y)),
+                        ..default()
+                    },
+                ))
+                .id();
+            tabs.order.push(tab);
+            // 履歴の今の項目を読み込み直す (裏のタブも含めて)
+            if let Some(index) = saved.history.current {
+                navigate.write(Navigate { tab, action: NavigationAction::Entry(index) });
+            }
+        }
+        tabs.active = tabs.order[session.active.min(tabs.order.len() - 1)];
+    }
+    for (name, _, shown) in windows.all() {
+        if let Some(&saved) = session.windows.get(name) {
+            *shown = saved;
+        }
+    }
+    info!("Restored {} tabs from the last session", session.tabs.len());
+}
+
+// 最初のフレームでウィンドウを前回の位置に置くシステム
+pub fn restore_window_positions(mut commands: Commands, mut contexts: EguiContexts, restored: Res<RestoredSession>) {
+    let Some(ctx) = contexts.try_ctx_mut() else {
+        return;
+    };
+    // egui はウィンドウの位置をタイトルから作った Id の Area に覚えている
+    // 同じ Id の空の Area に位置を入れておくと、ウィンドウはそこに開く
+    for (title, &[x, y]) in &restored.0.positions {
+        egui::Area::new(egui::Id::new(title)).current_pos(egui::pos2(x, y)).show(ctx, |_| {});
+    }
+    commands.remove_resource::<RestoredSession>();
+}
+
+/// What goes into the session file.
+#[derive(SystemParam)]
+pub struct SessionSources<'w, 's> {
+    tabs: Res<'w, Tabs>,
+    pages: Query<'w, 's, (&'static CurrentUrl, &'static BrowsingHistory, &'static ScrollPosition), With<Tab>>,
+    players: Query<'w, 's, &'static VideoPlayer, Without<PageViewer>>,
+    windows: WindowToggles<'w>,
+    engine: Res<'w, SearchEngine>,
+}
+
+/// What [`save_session`] keeps between frames.
+#[derive(Default)]
+pub struct SaveState {
+    last_save: f32,
+    /// Window positions seen so far; the egui context is gone on the frame the app exits.
+    positions: BTreeMap<String, [f32; 2]>,
+    /// The text last written, to skip writing an unchanged session.
+    written: String,
+}
+
+// 一定時間ごとと終了時にセッションをファイルに書くシステム (Last で動かす)
+pub fn save_session(
+    mut contexts: EguiContexts,
+    time: Res<Time>,
+    mut exits: EventReader<AppExit>,
+    mut sources: SessionSources,
+    mut state: Local<SaveState>,
+) {
+    let titles: Vec<&'static str> = sources.windows.all().into_iter().map(|(_, title, _)| title).collect();
+    if let Some(ctx) = contexts.try_ctx_mut() {
+        for title in titles {
+            if let Some(rect) = ctx.memory(|memory| memory.area_rect(egui::Id::new(title))) {
+                state.positions.insert(title.to_string(), [rect.min.x, rect.min.y]);
+            }
+        }
+    }
+    let exiting = exits.read().count() > 0;
+    if !exiting && time.elapsed_secs() - state.last_save < SAVE_INTERVAL_SECS {
+        return;
+    }
+    state.last_save = time.elapsed_secs();
+
+    let mut session = Session {
+        positions: state.positions.clone(),
+        video: sources.players.iter().next().map(|player| player.path.clone()),
+        search_engine: Some(sources.engine.0.clone()),
+        ..default()
+    };
+    for &tab in &sources.tabs.order {
+        let Ok((url, history, scroll)) = sources.pages.get(tab) else {
+            continue;
+        };
+        if tab == sources.tabs.active {
+            session.active = session.tabs.len();
+        }
+        session.tabs.push(TabSession {
+            url: url.0.clone(),
+            history: history.0.clone(),
+            scroll: [scroll.offset.x, scroll.offset.y],
+        });
+    }
+    for (name, _, shown) in sources.windows.all() {
+        session.windows.insert(name.to_string(), *shown);
+    }
+
+    let text = match ron::ser::to_string_pretty(&session, PrettyConfig::default()) {
+        Ok(text) => text,
+        Err(e) => {
+            error!("Failed to serialize the session: {}", e);
+            return;
+        }
+    };
+    if text == state.written {
+        return;
+    }
+    match write_atomically(Path::new(DEFAULT_FILE), &text) {
+        Ok(()) => state.written = text,
+        Err(e) => error!("Failed to save the session: {}", e),
+    }
+}
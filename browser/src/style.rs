@@ -0,0 +1,1427 @@
+//! Cascade and computed styles.
+//!
+//! [`compute_styles`] runs the cascade for every element of a [`Document`]:
+//! user agent rules, presentational hints, author stylesheets and `style=""`
+//! attributes are sorted by origin, importance, specificity and source order,
+//! shorthands are expanded, and the winning values are resolved against the
+//! parent's computed style.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+
+use crate::css::{self, Color, ComponentValue, CssToken, Declaration, MediaContext, Stylesheet};
+use crate::css_selector::{SelectorKey, Specificity};
+use crate::dom::{Document, NodeData, NodeId, QuirksMode};
+use crate::{CurrentDocument, CurrentStyles, ExternalStylesheets};
+
+/// Default styles, adapted from the "Rendering" section of the HTML standard.
+pub const USER_AGENT_CSS: &str = r#"
+html, address, blockquote, body, center, dialog, div, figure, figcaption, footer,
+form, header, hr, legend, listing, main, p, plaintext, pre, search, xmp,
+article, aside, h1, h2, h3, h4, h5, h6, hgroup, nav, section,
+dir, dd, dl, dt, menu, ol, ul, details, summary, fieldset, optgroup { display: block; }
+head, link, meta, script, style, template, title, base, basefont, datalist,
+noembed, noframes, param, rp, area, [hidden], input[type=hidden] { display: none; }
+li { display: list-item; }
+table { display: table; border-spacing: 2px; border-collapse: separate; }
+caption { display: table-caption; text-align: center; }
+colgroup { display: table-column-group; }
+col { display: table-column; }
+thead { display: table-header-group; vertical-align: middle; }
+tbody { display: table-row-group; vertical-align: middle; }
+tfoot { display: table-footer-group; vertical-align: middle; }
+tr { display: table-row; vertical-align: inherit; }
+td, th { display: table-cell; padding: 1px; vertical-align: inherit; }
+th { font-weight: bold; text-align: center; }
+img, input, button, select, textarea, iframe, video { display: inline-block; }
+
+body { margin: 8px; }
+p, blockquote, figure, listing, plaintext, pre, xmp, dl, menu, dir, ol, ul { margin-top: 1em; margin-bottom: 1em; }
+blockquote, figure { margin-left: 40px; margin-right: 40px; }
+dd { margin-left: 40px; }
+dir, menu, ol, ul { padding-left: 40px; }
+ol ol, ol ul, ul ol, ul ul, ol menu, ul menu, menu ol, menu ul { margin-top: 0; margin-bottom: 0; }
+ol { list-style-type: decimal; }
+ul, menu, dir { list-style-type: disc; }
+ul ul, ol ul, ul menu, menu ul { list-style-type: circle; }
+ul ul ul, ul ol ul, ol ul ul, ol ol ul { list-style-type: square; }
+
+h1 { margin-top: 0.67em; margin-bottom: 0.67em; font-size: 2em; }
+h2 { margin-top: 0.83em; margin-bottom: 0.83em; font-size: 1.5em; }
+h3 { margin-top: 1em; margin-bottom: 1em; font-size: 1.17em; }
+h4 { margin-top: 1.33em; margin-bottom: 1.33em; font-size: 1em; }
+h5 { margin-top: 1.67em; margin-bottom: 1.67em; font-size: 0.83em; }
+h6 { margin-top: 2.33em; margin-bottom: 2.33em; font-size: 0.67em; }
+h1, h2, h3, h4, h5, h6, b, strong { font-weight: bold; }
+
+address, cite, dfn, em, i, var { font-style: italic; }
+code, kbd, samp, tt, pre, listing, plaintext, xmp { font-family: monospace; }
+pre, listing, plaintext, xmp { white-space: pre; }
+textarea { white-space: pre-wrap; }
+big { font-size: larger; }
+small, sub, sup { font-size: smaller; }
+sub { vertical-align: sub; }
+sup { vertical-align: super; }
+u, ins, abbr[title], acronym[title] { text-decoration: underline; }
+s, strike, del { text-decoration: line-through; }
+center { display: block; text-align: center; }
+nobr { white-space: nowrap; }
+mark { background-color: yellow; color: black; }
+a:link { color: #0000ee; text-decoration: underline; }
+
+hr { color: gray; border-style: inset; border-width: 1px; margin: 0.5em auto; }
+fieldset { margin-left: 2px; margin-right: 2px; border: 2px groove; padding: 0.35em 0.75em 0.625em; }
+button { border: 2px outset; padding: 1px 6px; }
+input, select, textarea { border: 2px inset; padding: 1px 2px; }
+"#;
+
+/// Extra user-agent rules for documents in quirks mode.
+pub const QUIRKS_CSS: &str = r#"
+form { margin-bottom: 1em; }
+table {
+    font-weight: initial; font-style: initial; font-size: initial;
+    line-height: initial; white-space: initial; text-align: initial;
+}
+img[align=left i] { margin-right: 3px; }
+img[align=right i] { margin-left: 3px; }
+"#;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Display {
+    None,
+    Block,
+    #[default]
+    Inline,
+    InlineBlock,
+    ListItem,
+    Table,
+    TableCaption,
+    TableRowGroup,
+    TableHeaderGroup,
+    TableFooterGroup,
+    TableRow,
+    TableCell,
+    TableColumn,
+    TableColumnGroup,
+    Flex,
+    /// The element generates no box but its children do.
+    Contents,
+}
+
+impl Display {
+    /// Whether this display value starts a new block in the flow.
+    pub fn is_block_level(self) -> bool {
+        !matches!(self, Display::Inline | Display::InlineBlock | Display::None | Display::Contents)
+    }
+}
+
+/// A length that may still depend on the containing block.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Dimension {
+    #[default]
+    Auto,
+    Px(f32),
+    Percent(f32),
+}
+
+impl Dimension {
+    /// Resolves against a containing block of `base` pixels. `auto` becomes `None`.
+    pub fn resolve(self, base: f32) -> Option<f32> {
+        match self {
+            Dimension::Auto => None,
+            Dimension::Px(px) => Some(px),
+            Dimension::Percent(p) => Some(base * p / 100.0),
+        }
+    }
+
+    /// Like [`Dimension::resolve`] but treats `auto` as zero.
+    pub fn resolve_or_zero(self, base: f32) -> f32 {
+        self.resolve(base).unwrap_or(0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum LineHeight {
+    #[default]
+    Normal,
+    /// A unitless multiplier, inherited as-is so children scale with their own font size.
+    Number(f32),
+    Px(f32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WhiteSpace {
+    #[default]
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+}
+
+impl WhiteSpace {
+    pub fn collapses_spaces(self) -> bool {
+        matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap | WhiteSpace::PreLine)
+    }
+
+    pub fn preserves_newlines(self) -> bool {
+        !matches!(self, WhiteSpace::Normal | WhiteSpace::Nowrap)
+    }
+
+    pub fn wraps(self) -> bool {
+        !matches!(self, WhiteSpace::Pre | WhiteSpace::Nowrap)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub overline: bool,
+    pub line_through: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum VerticalAlign {
+    #[default]
+    Baseline,
+    Sub,
+    Super,
+    Top,
+    Middle,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BorderStyle {
+    #[default]
+    None,
+    Hidden,
+    Solid,
+    Dotted,
+    Dashed,
+    Double,
+    Groove,
+    Ridge,
+    Inset,
+    Outset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ListStyleType {
+    #[default]
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+    None,
+}
+
+/// Resolved style of one element. Lengths are in CSS pixels except where a
+/// percentage has to wait for layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedStyle {
+    pub display: Display,
+    pub color: Color,
+    pub background_color: Color,
+    pub background_image: Option<String>,
+    pub font_size: f32,
+    pub font_weight: u16,
+    pub font_italic: bool,
+    pub font_family: Vec<String>,
+    pub line_height: LineHeight,
+    pub text_align: TextAlign,
+    pub text_decoration: TextDecoration,
+    pub text_indent: Dimension,
+    pub white_space: WhiteSpace,
+    pub vertical_align: VerticalAlign,
+    /// Top, right, bottom, left.
+    pub margin: [Dimension; 4],
+    pub padding: [Dimension; 4],
+    pub border_width: [f32; 4],
+    pub border_style: [BorderStyle; 4],
+    /// `None` means `currentcolor`.
+    pub border_color: [Option<Color>; 4],
+    pub width: Dimension,
+    pub height: Dimension,
+    pub min_width: Dimension,
+    pub min_height: Dimension,
+    /// `Auto` stands for `none`.
+    pub max_width: Dimension,
+    pub max_height: Dimension,
+    pub list_style_type: ListStyleType,
+    pub visible: bool,
+    /// Gap between table cells.
+    pub border_spacing: f32,
+}
+
+impl Default for ComputedStyle {
+    fn default() -> Self {
+        Self {
+            display: Display::Inline,
+            color: Color::BLACK,
+            background_color: Color::TRANSPARENT,
+            background_image: None,
+            font_size: 16.0,
+            font_weight: 400,
+            font_italic: false,
+            font_family: vec!["serif".to_string()],
+            line_height: LineHeight::Normal,
+            text_align: TextAlign::Left,
+            text_decoration: TextDecoration::default(),
+            text_indent: Dimension::Px(0.0),
+            white_space: WhiteSpace::Normal,
+            vertical_align: VerticalAlign::Baseline,
+            margin: [Dimension::Px(0.0); 4],
+            padding: [Dimension::Px(0.0); 4],
+            border_width: [3.0; 4],
+            border_style: [BorderStyle::None; 4],
+            border_color: [None; 4],
+            width: Dimension::Auto,
+            height: Dimension::Auto,
+            min_width: Dimension::Px(0.0),
+            min_height: Dimension::Px(0.0),
+            max_width: Dimension::Auto,
+            max_height: Dimension::Auto,
+            list_style_type: ListStyleType::Disc,
+            visible: true,
+            border_spacing: 0.0,
+        }
+    }
+}
+
+impl ComputedStyle {
+    /// Initial values for the properties that inherit, taken from `parent`.
+    /// Also used for anonymous boxes, which have no declarations of their own.
+    pub fn inherit_from(parent: &ComputedStyle) -> Self {
+        Self {
+            color: parent.color,
+            font_size: parent.font_size,
+            font_weight: parent.font_weight,
+            font_italic: parent.font_italic,
+            font_family: parent.font_family.clone(),
+            line_height: parent.line_height,
+            text_align: parent.text_align,
+            // text-decoration は継承しないが、子孫のテキストにも線が引かれるので伝播させる
+            text_decoration: parent.text_decoration,
+            text_indent: parent.text_indent,
+            white_space: parent.white_space,
+            list_style_type: parent.list_style_type,
+            visible: parent.visible,
+            border_spacing: parent.border_spacing,
+            ..Self::default()
+        }
+    }
+
+    /// Used line height in pixels.
+    pub fn line_height_px(&self) -> f32 {
+        match self.line_height {
+            LineHeight::Normal => self.font_size * 1.2,
+            LineHeight::Number(n) => self.font_size * n,
+            LineHeight::Px(px) => px,
+        }
+    }
+
+    pub fn is_bold(&self) -> bool {
+        self.font_weight >= 600
+    }
+
+    pub fn is_monospace(&self) -> bool {
+        self.font_family.iter().any(|f| f == "monospace")
+    }
+
+    /// Border width that actually takes up space on `side` (0 = top, clockwise).
+    pub fn used_border_width(&self, side: usize) -> f32 {
+        match self.border_style[side] {
+            BorderStyle::None | BorderStyle::Hidden => 0.0,
+            _ => self.border_width[side],
+        }
+    }
+
+    pub fn used_border_color(&self, side: usize) -> Color {
+        self.border_color[side].unwrap_or(self.color)
+    }
+}
+
+/// Computed styles for a whole document, indexed by [`NodeId`].
+#[derive(Clone, Debug, Default)]
+pub struct StyleMap {
+    styles: Vec<Option<ComputedStyle>>,
+}
+
+impl StyleMap {
+    /// Style of an element. Text nodes have no style of their own.
+    pub fn get(&self, id: NodeId) -> Option<&ComputedStyle> {
+        self.styles.get(id.0).and_then(|s| s.as_ref())
+    }
+
+    /// Style that applies to `id`: its own, or the nearest element ancestor's
+    /// for text nodes.
+    pub fn for_node(&self, doc: &Document, id: NodeId) -> Option<&ComputedStyle> {
+        std::iter::once(id).chain(doc.ancestors(id)).find_map(|n| self.get(n))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.styles.iter().all(|s| s.is_none())
+    }
+}
+
+/// A stylesheet that was fetched from the network for the current document.
+#[derive(Clone, Debug)]
+pub struct ExternalStylesheet {
+    /// The `<link>` or `<style>` element the sheet belongs to.
+    pub owner: NodeId,
+    /// Sheets pulled in by `@import` come before the rules of their owner.
+    pub imported: bool,
+    pub url: String,
+    pub sheet: Stylesheet,
+}
+
+// ---- cascade -----------------------------------------------------------------
+
+/// Where a declaration comes from. Later variants win, which also encodes the
+/// reversal of origins for `!important`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum CascadeLevel {
+    UserAgent,
+    PresentationalHint,
+    Author,
+    StyleAttribute,
+    AuthorImportant,
+    StyleAttributeImportant,
+    UserAgentImportant,
+}
+
+struct IndexedRule<'a> {
+    selector: &'a crate::css_selector::Selector,
+    declarations: &'a [Declaration],
+    user_agent: bool,
+    order: usize,
+}
+
+/// Rules bucketed by the id, class or tag of their subject so each element only
+/// has to test selectors that could possibly match it.
+#[derive(Default)]
+struct RuleIndex<'a> {
+    buckets: HashMap<SelectorKey, Vec<IndexedRule<'a>>>,
+    /// Bucket ids and classes by their lowercase form (quirks mode).
+    fold_case: bool,
+}
+
+impl<'a> RuleIndex<'a> {
+    fn bucket_key(&self, key: SelectorKey) -> SelectorKey {
+        match key {
+            SelectorKey::Id(id) if self.fold_case => SelectorKey::Id(id.to_ascii_lowercase()),
+            SelectorKey::Class(class) if self.fold_case => SelectorKey::Class(class.to_ascii_lowercase()),
+            key => key,
+        }
+    }
+
+    fn add(&mut self, sheet: &'a Stylesheet, media: &MediaContext, user_agent: bool, order: &mut usize) {
+        for rule in sheet.style_rules(media) {
+            for selector in &rule.selectors {
+                let key = self.bucket_key(selector.key());
+                self.buckets.entry(key).or_default().push(IndexedRule {
+                    selector,
+                    declarations: &rule.declarations,
+                    user_agent,
+                    order: *order,
+                });
+            }
+            *order += 1;
+        }
+    }
+
+    fn candidates(&self, element: &crate::dom::ElementData) -> Vec<&IndexedRule<'a>> {
+        let mut keys = vec![SelectorKey::Universal, SelectorKey::Tag(element.name.to_ascii_lowercase())];
+        if let Some(id) = element.id() {
+            keys.push(SelectorKey::Id(id.to_string()));
+        }
+        keys.extend(element.classes().map(|c| SelectorKey::Class(c.to_string())));
+        keys.into_iter()
+            .filter_map(|k| self.buckets.get(&self.bucket_key(k)))
+            .flatten()
+            .collect()
+    }
+}
+
+/// Author stylesheets of `doc` in cascade order: `<style>` elements and fetched
+/// `<link>` sheets interleaved in tree order, imports before their owner.
+fn author_sheets(doc: &Document, external: &[ExternalStylesheet]) -> Vec<Stylesheet> {
+    let mut sheets = Vec::new();
+    for node in doc.descendants(doc.root()) {
+        for imported in [true, false] {
+            sheets.extend(
+                external
+                    .iter()
+                    .filter(|e| e.owner == node && e.imported == imported)
+                    .map(|e| e.sheet.clone()),
+            );
+        }
+        if doc.is_element_named(node, "style") {
+            sheets.push(css::parse_stylesheet(&doc.text_content(node)));
+        }
+    }
+    sheets
+}
+
+/// Runs the cascade over every element of `doc`.
+pub fn compute_styles(doc: &Document, external: &[ExternalStylesheet], media: &MediaContext) -> StyleMap {
+    let quirks = doc.mode == QuirksMode::Quirks;
+    let user_agent = css::parse_stylesheet(USER_AGENT_CSS);
+    let quirks_sheet = quirks.then(|| css::parse_stylesheet(QUIRKS_CSS));
+    let authors = author_sheets(doc, external);
+    let mut index = RuleIndex {
+        fold_case: quirks,
+        ..Default::default()
+    };
+    let mut order = 0;
+    index.add(&user_agent, media, true, &mut order);
+    if let Some(sheet) = &quirks_sheet {
+        index.add(sheet, media, true, &mut order);
+    }
+    for sheet in &authors {
+        index.add(sheet, media, false, &mut order);
+    }
+
+    let mut map = StyleMap {
+        styles: vec![None; doc.nodes.len()],
+    };
+    let root_style = ComputedStyle::default();
+    let mut root_font_size = root_style.font_size;
+    // 親より先に子を計算しないよう、木の順序で処理する
+    for node in doc.descendants(doc.root()) {
+        let Some(element) = doc.element(node) else {
+            continue;
+        };
+        let parent_style = doc
+            .parent(node)
+            .and_then(|p| map.get(p))
+            .unwrap_or(&root_style)
+            .clone();
+
+        let mut matched: Vec<(CascadeLevel, Specificity, usize, &Declaration)> = Vec::new();
+        for rule in index.candidates(element) {
+            if !rule.selector.matches(doc, node) {
+                continue;
+            }
+            let specificity = rule.selector.specificity();
+            for declaration in rule.declarations {
+                let level = match (rule.user_agent, declaration.important) {
+                    (true, false) => CascadeLevel::UserAgent,
+                    (true, true) => CascadeLevel::UserAgentImportant,
+                    (false, false) => CascadeLevel::Author,
+                    (false, true) => CascadeLevel::AuthorImportant,
+                };
+                matched.push((level, specificity, rule.order, declaration));
+            }
+        }
+        let hints = presentational_hints(doc, node);
+        for declaration in &hints {
+            matched.push((CascadeLevel::PresentationalHint, Specificity::default(), 0, declaration));
+        }
+        let inline = element.attr("style").map(css::parse_declarations).unwrap_or_default();
+        for declaration in &inline {
+            let level = if declaration.important {
+                CascadeLevel::StyleAttributeImportant
+            } else {
+                CascadeLevel::StyleAttribute
+            };
+            matched.push((level, Specificity::default(), 0, declaration));
+        }
+        // 安定ソートなので、同じ規則内の宣言は書かれた順のまま残る
+        matched.sort_by_key(|(level, specificity, order, _)| (*level, *specificity, *order));
+
+        let mut winners: Vec<(String, Vec<ComponentValue>)> = Vec::new();
+        for (_, _, _, declaration) in &matched {
+            for (name, value) in expand_shorthand(declaration) {
+                winners.retain(|(n, _)| *n != name);
+                winners.push((name, value));
+            }
+        }
+
+        let is_root = doc.parent(node) == Some(doc.root());
+        let mut style = ComputedStyle::inherit_from(&parent_style);
+        let ctx = ResolveContext {
+            parent: &parent_style,
+            root_font_size: if is_root { parent_style.font_size } else { root_font_size },
+            media,
+            quirks,
+        };
+        // em を解決するために font-size を先に決める
+        if let Some((_, value)) = winners.iter().find(|(n, _)| n == "font-size") {
+            apply_property(&mut style, &ctx, "font-size", value);
+        }
+        for (name, value) in winners.iter().filter(|(n, _)| n != "font-size") {
+            apply_property(&mut style, &ctx, name, value);
+        }
+        fixup(&mut style, &parent_style, doc, node);
+        if is_root {
+            root_font_size = style.font_size;
+        }
+        map.styles[node.0] = Some(style);
+    }
+    map
+}
+
+/// Adjustments the cascade itself does not express.
+fn fixup(style: &mut ComputedStyle, parent: &ComputedStyle, doc: &Document, node: NodeId) {
+    // ルート要素はインライン指定でもブロックとして扱う
+    if doc.parent(node) == Some(doc.root()) && style.display == Display::Inline {
+        style.display = Display::Block;
+    }
+    if parent.display == Display::None {
+        style.display = Display::None;
+    }
+}
+
+// ---- presentational hints ----------------------------------------------------
+
+/// Legacy attributes such as `bgcolor` and `<font size>`, turned into declarations.
+fn presentational_hints(doc: &Document, node: NodeId) -> Vec<Declaration> {
+    let Some(element) = doc.element(node) else {
+        return Vec::new();
+    };
+    let mut css = String::new();
+    let mut push = |property: &str, value: &str| {
+        // 属性値に ; や { が含まれていても他の宣言を壊さないようにする
+        if !value.contains([';', '{', '}', '!']) {
+            css.push_str(&format!("{}: {};", property, value));
+        }
+    };
+    let name = element.name.as_str();
+    if matches!(name, "body" | "table" | "td" | "th" | "tr" | "thead" | "tbody" | "tfoot")
+        && let Some(color) = element.attr("bgcolor").and_then(legacy_color)
+    {
+        push("background-color", &color);
+    }
+    if name == "body"
+        && let Some(color) = element.attr("text").and_then(legacy_color)
+    {
+        push("color", &color);
+    }
+    if name == "font" {
+        if let Some(color) = element.attr("color").and_then(legacy_color) {
+            push("color", &color);
+        }
+        if let Some(face) = element.attr("face") {
+            push("font-family", face);
+        }
+        if let Some(size) = element.attr("size").and_then(legacy_font_size) {
+            push("font-size", size);
+        }
+    }
+    if let Some(align) = element.attr("align").map(|a| a.trim().to_ascii_lowercase()) {
+        match name {
+            "p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "td" | "th" | "tr" | "caption" | "thead" | "tbody" | "tfoot" => {
+                if matches!(align.as_str(), "left" | "right" | "center" | "justify") {
+                    push("text-align", &align);
+                } else if align == "middle" {
+                    push("text-align", "center");
+                }
+            }
+            "table" | "hr" if align == "center" => {
+                push("margin-left", "auto");
+                push("margin-right", "auto");
+            }
+            _ => {}
+        }
+    }
+    if matches!(name, "img" | "table" | "td" | "th" | "hr" | "iframe" | "video" | "canvas" | "col" | "embed" | "object") {
+        for attr in ["width", "height"] {
+            if let Some(value) = element.attr(attr).and_then(legacy_length) {
+                push(attr, &value);
+            }
+        }
+    }
+    if name == "table" {
+        if let Some(border) = element.attr("border").map(|b| b.trim().parse::<u32>().unwrap_or(1)) {
+            push("border-width", &format!("{}px", border));
+            push("border-style", "outset");
+        }
+        if let Some(spacing) = element.attr("cellspacing").and_then(legacy_length) {
+            push("border-spacing", &spacing);
+        }
+    }
+    if matches!(name, "td" | "th")
+        && let Some(table) = doc.ancestors(node).find(|a| doc.is_element_named(*a, "table"))
+        && let Some(table) = doc.element(table)
+    {
+        if table.attr("border").is_some_and(|b| b.trim().parse::<u32>().map_or(true, |b| b > 0)) {
+            push("border", "1px inset");
+        }
+        if let Some(padding) = table.attr("cellpadding").and_then(legacy_length) {
+            push("padding", &padding);
+        }
+    }
+    if name == "hr" && element.has_attr("noshade") {
+        push("border-style", "solid");
+        push("background-color", "gray");
+    }
+    if name == "ol"
+        && let Some(kind) = element.attr("type")
+    {
+        let list = match kind {
+            "1" => "decimal",
+            "a" => "lower-alpha",
+            "A" => "upper-alpha",
+            "i" => "lower-roman",
+            "I" => "upper-roman",
+            _ => "",
+        };
+        if !list.is_empty() {
+            push("list-style-type", list);
+        }
+    }
+    css::parse_declarations(&css)
+}
+
+/// `bgcolor="ff0000"` is accepted by browsers even without the `#`.
+fn legacy_color(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    if Color::from_name(value).is_some() || value.starts_with('#') {
+        return Some(value.to_string());
+    }
+    if value.len() == 6 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Some(format!("#{}", value));
+    }
+    None
+}
+
+/// `<font size>`: 1-7, or relative to 3 with a sign.
+fn legacy_font_size(value: &str) -> Option<&'static str> {
+    let value = value.trim();
+    let size: i32 = match value.strip_prefix('+') {
+        Some(rest) => 3 + rest.parse::<i32>().ok()?,
+        None if value.starts_with('-') => 3 + value.parse::<i32>().ok()?,
+        None => value.parse().ok()?,
+    };
+    Some(match size.clamp(1, 7) {
+        1 => "x-small",
+        2 => "small",
+        3 => "medium",
+        4 => "large",
+        5 => "x-large",
+        6 => "xx-large",
+        _ => "xxx-large",
+    })
+}
+
+/// `width="50%"` or `width="120"`.
+fn legacy_length(value: &str) -> Option<String> {
+    let value = value.trim();
+    if let Some(percent) = value.strip_suffix('%') {
+        return percent.trim().parse::<f32>().ok().map(|p| format!("{}%", p));
+    }
+    let digits: String = value.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+    digits.parse::<f32>().ok().map(|px| format!("{}px", px))
+}
+
+// ---- shorthands --------------------------------------------------------------
+
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+fn keyword(name: &str) -> Vec<ComponentValue> {
+    vec![ComponentValue::Token(CssToken::Ident(name.to_string()))]
+}
+
+/// Splits a value on whitespace, keeping functions and blocks whole.
+fn split_values(value: &[ComponentValue]) -> Vec<&ComponentValue> {
+    value.iter().filter(|v| !v.is_whitespace()).collect()
+}
+
+/// `top right bottom left` from the 1-4 value syntax of `margin` and friends.
+fn four_sides(values: &[&ComponentValue]) -> Option<[ComponentValue; 4]> {
+    let v = |i: usize| values[i].clone();
+    Some(match values.len() {
+        1 => [v(0), v(0), v(0), v(0)],
+        2 => [v(0), v(1), v(0), v(1)],
+        3 => [v(0), v(1), v(2), v(1)],
+        4 => [v(0), v(1), v(2), v(3)],
+        _ => return None,
+    })
+}
+
+fn is_border_style(value: &ComponentValue) -> bool {
+    value.ident().is_some_and(|s| parse_border_style(&s).is_some())
+}
+
+/// Expands a declaration into `(longhand, value)` pairs. Longhands pass through.
+fn expand_shorthand(declaration: &Declaration) -> Vec<(String, Vec<ComponentValue>)> {
+    let name = declaration.name.as_str();
+    let value = &declaration.value;
+    let values = split_values(value);
+    let global = values.len() == 1
+        && values[0].ident().is_some_and(|k| matches!(k.as_str(), "inherit" | "initial" | "unset"));
+    let longhands = |names: &[String]| -> Vec<(String, Vec<ComponentValue>)> {
+        names.iter().map(|n| (n.clone(), value.clone())).collect()
+    };
+
+    match name {
+        "margin" | "padding" => {
+            let names: Vec<String> = SIDES.iter().map(|s| format!("{}-{}", name, s)).collect();
+            if global {
+                return longhands(&names);
+            }
+            let Some(sides) = four_sides(&values) else {
+                return Vec::new();
+            };
+            names.into_iter().zip(sides).map(|(n, v)| (n, vec![v])).collect()
+        }
+        "border-width" | "border-style" | "border-color" => {
+            let suffix = &name["border-".len()..];
+            let names: Vec<String> = SIDES.iter().map(|s| format!("border-{}-{}", s, suffix)).collect();
+            if global {
+                return longhands(&names);
+            }
+            let Some(sides) = four_sides(&values) else {
+                return Vec::new();
+            };
+            names.into_iter().zip(sides).map(|(n, v)| (n, vec![v])).collect()
+        }
+        "border" | "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            let sides: Vec<&str> = match name.strip_prefix("border-") {
+                Some(side) => vec![side],
+                None => SIDES.to_vec(),
+            };
+            let mut width = keyword("medium");
+            let mut style = keyword("none");
+            let mut color = keyword("currentcolor");
+            if global {
+                width = value.clone();
+                style = value.clone();
+                color = value.clone();
+            } else {
+                for v in &values {
+                    if is_border_style(v) {
+                        style = vec![(*v).clone()];
+                    } else if Color::from_value(v).is_some() || v.ident().as_deref() == Some("currentcolor") {
+                        color = vec![(*v).clone()];
+                    } else {
+                        width = vec![(*v).clone()];
+                    }
+                }
+            }
+            let mut out = Vec::new();
+            for side in sides {
+                out.push((format!("border-{}-width", side), width.clone()));
+                out.push((format!("border-{}-style", side), style.clone()));
+                out.push((format!("border-{}-color", side), color.clone()));
+            }
+            out
+        }
+        "font" => {
+            let names = ["font-style", "font-weight", "font-size", "line-height", "font-family"];
+            if global {
+                return longhands(&names.map(String::from));
+            }
+            let mut out = vec![
+                ("font-style".to_string(), keyword("normal")),
+                ("font-weight".to_string(), keyword("normal")),
+                ("line-height".to_string(), keyword("normal")),
+            ];
+            // サイズより前はスタイル・太さ・バリアント、サイズの後 (/ line-height の後) がフォント名
+            let mut i = 0;
+            while i < values.len() {
+                let v = values[i];
+                match v.ident().as_deref() {
+                    Some("italic" | "oblique") => out[0].1 = vec![v.clone()],
+                    Some("bold" | "bolder" | "lighter") => out[1].1 = vec![v.clone()],
+                    Some("normal" | "small-caps") => {}
+                    _ if matches!(v, ComponentValue::Token(CssToken::Number(_))) => out[1].1 = vec![v.clone()],
+                    _ => break,
+                }
+                i += 1;
+            }
+            let Some(size) = values.get(i) else {
+                return Vec::new();
+            };
+            out.push(("font-size".to_string(), vec![(*size).clone()]));
+            i += 1;
+            if matches!(values.get(i), Some(ComponentValue::Token(CssToken::Delim('/')))) {
+                let Some(line_height) = values.get(i + 1) else {
+                    return Vec::new();
+                };
+                out[2].1 = vec![(*line_height).clone()];
+                i += 2;
+            }
+            // フォント名はカンマと空白を含むので元の値から取り出す
+            let family_start = values.get(i).and_then(|first| value.iter().position(|v| std::ptr::eq(v, *first)));
+            let Some(start) = family_start else {
+                return Vec::new();
+            };
+            out.push(("font-family".to_string(), value[start..].to_vec()));
+            out
+        }
+        "background" => {
+            if global {
+                return longhands(&["background-color".to_string(), "background-image".to_string()]);
+            }
+            let mut color = keyword("transparent");
+            let mut image = keyword("none");
+            for v in &values {
+                match v {
+                    ComponentValue::Token(CssToken::Url(_)) => image = vec![(*v).clone()],
+                    ComponentValue::Function { name, .. } if name == "url" => image = vec![(*v).clone()],
+                    _ if Color::from_value(v).is_some() => color = vec![(*v).clone()],
+                    _ => {}
+                }
+            }
+            vec![
+                ("background-color".to_string(), color),
+                ("background-image".to_string(), image),
+            ]
+        }
+        "list-style" => {
+            if global {
+                return longhands(&["list-style-type".to_string()]);
+            }
+            let list_type = values
+                .iter()
+                .find(|v| v.ident().is_some_and(|k| parse_list_style_type(&k).is_some()))
+                .map(|v| vec![(*v).clone()]);
+            match list_type {
+                Some(list_type) => vec![("list-style-type".to_string(), list_type)],
+                None => Vec::new(),
+            }
+        }
+        "text-decoration" | "text-decoration-line" => vec![("text-decoration-line".to_string(), value.clone())],
+        _ => vec![(name.to_string(), value.clone())],
+    }
+}
+
+// ---- value resolution --------------------------------------------------------
+
+struct ResolveContext<'a> {
+    parent: &'a ComputedStyle,
+    root_font_size: f32,
+    media: &'a MediaContext,
+    /// Accept unitless lengths and hashless colors (quirks mode only).
+    quirks: bool,
+}
+
+impl ResolveContext<'_> {
+    /// Absolute length in px, resolving font-relative units against `font_size`.
+    fn length(&self, value: &ComponentValue, font_size: f32) -> Option<f32> {
+        match value {
+            ComponentValue::Token(CssToken::Number(n)) if *n == 0.0 => Some(0.0),
+            ComponentValue::Token(CssToken::Dimension(n, unit)) => {
+                let factor = match unit.to_ascii_lowercase().as_str() {
+                    "px" => 1.0,
+                    "em" => font_size,
+                    "rem" => self.root_font_size,
+                    "ex" | "ch" => font_size * 0.5,
+                    "pt" => 4.0 / 3.0,
+                    "pc" => 16.0,
+                    "in" => 96.0,
+                    "cm" => 96.0 / 2.54,
+                    "mm" => 96.0 / 25.4,
+                    "q" => 96.0 / 101.6,
+                    "vw" => self.media.width / 100.0,
+                    "vh" => self.media.height / 100.0,
+                    "vmin" => self.media.width.min(self.media.height) / 100.0,
+                    "vmax" => self.media.width.max(self.media.height) / 100.0,
+                    _ => return None,
+                };
+                Some(n * factor)
+            }
+            _ => None,
+        }
+    }
+
+    fn dimension(&self, value: &ComponentValue, font_size: f32, allow_auto: bool) -> Option<Dimension> {
+        match value {
+            ComponentValue::Token(CssToken::Percentage(p)) => Some(Dimension::Percent(*p)),
+            v if allow_auto && matches!(v.ident().as_deref(), Some("auto" | "none")) => Some(Dimension::Auto),
+            v => self.length(v, font_size).map(Dimension::Px),
+        }
+    }
+
+    fn font_size(&self, value: &ComponentValue) -> Option<f32> {
+        let parent = self.parent.font_size;
+        if let Some(keyword) = value.ident() {
+            return Some(match keyword.as_str() {
+                "xx-small" => 9.0,
+                "x-small" => 10.0,
+                "small" => 13.0,
+                "medium" => 16.0,
+                "large" => 18.0,
+                "x-large" => 24.0,
+                "xx-large" => 32.0,
+                "xxx-large" => 48.0,
+                "smaller" => parent / 1.2,
+                "larger" => parent * 1.2,
+                _ => return None,
+            });
+        }
+        match value {
+            ComponentValue::Token(CssToken::Percentage(p)) => Some(parent * p / 100.0),
+            v => self.length(v, parent).filter(|px| *px >= 0.0),
+        }
+    }
+}
+
+/// Writes the computed value of one longhand into `style`.
+fn apply_property(style: &mut ComputedStyle, ctx: &ResolveContext, name: &str, value: &[ComponentValue]) {
+    let values = split_values(value);
+    let Some(first) = values.first().copied() else {
+        return;
+    };
+    let quirky = if ctx.quirks { quirky_value(name, first) } else { None };
+    let first = quirky.as_ref().unwrap_or(first);
+    if let Some(global) = first.ident().filter(|k| matches!(k.as_str(), "inherit" | "initial" | "unset")) {
+        let inherit = global == "inherit" || (global == "unset" && is_inherited(name));
+        let source = if inherit { ctx.parent.clone() } else { ComputedStyle::default() };
+        copy_property(style, &source, name);
+        return;
+    }
+    let font_size = style.font_size;
+    let keyword = first.ident().unwrap_or_default();
+
+    if let Some((side, property)) = side_property(name) {
+        match property {
+            "margin" => {
+                if let Some(d) = ctx.dimension(first, font_size, true) {
+                    style.margin[side] = d;
+                }
+            }
+            "padding" => {
+                if let Some(d) = ctx.dimension(first, font_size, false).filter(|d| !is_negative(*d)) {
+                    style.padding[side] = d;
+                }
+            }
+            "width" => {
+                let width = match keyword.as_str() {
+                    "thin" => Some(1.0),
+                    "medium" => Some(3.0),
+                    "thick" => Some(5.0),
+                    _ => ctx.length(first, font_size),
+                };
+                if let Some(width) = width.filter(|w| *w >= 0.0) {
+                    style.border_width[side] = width;
+                }
+            }
+            "style" => {
+                if let Some(border_style) = parse_border_style(&keyword) {
+                    style.border_style[side] = border_style;
+                }
+            }
+            "color" => {
+                if keyword == "currentcolor" {
+                    style.border_color[side] = None;
+                } else if let Some(color) = Color::from_value(first) {
+                    style.border_color[side] = Some(color);
+                }
+            }
+            _ => {}
+        }
+        return;
+    }
+
+    match name {
+        "display" => {
+            let display = match keyword.as_str() {
+                "none" => Display::None,
+                "block" | "flow-root" => Display::Block,
+                "inline" => Display::Inline,
+                "inline-block" | "inline-flex" | "inline-table" | "inline-grid" => Display::InlineBlock,
+                "list-item" => Display::ListItem,
+                "table" => Display::Table,
+                "table-caption" => Display::TableCaption,
+                "table-row-group" => Display::TableRowGroup,
+                "table-header-group" => Display::TableHeaderGroup,
+                "table-footer-group" => Display::TableFooterGroup,
+                "table-row" => Display::TableRow,
+                "table-cell" => Display::TableCell,
+                "table-column" => Display::TableColumn,
+                "table-column-group" => Display::TableColumnGroup,
+                "flex" | "grid" => Display::Flex,
+                "contents" => Display::Contents,
+                _ => return,
+            };
+            style.display = display;
+        }
+        "color" => {
+            if keyword == "currentcolor" {
+                style.color = ctx.parent.color;
+            } else if let Some(color) = Color::from_value(first) {
+                style.color = color;
+            }
+        }
+        "background-color" => {
+            if keyword == "currentcolor" {
+                style.background_color = style.color;
+            } else if let Some(color) = Color::from_value(first) {
+                style.background_color = color;
+            }
+        }
+        "background-image" => {
+            style.background_image = match first {
+                ComponentValue::Token(CssToken::Url(url)) => Some(url.clone()),
+                ComponentValue::Function { name, args } if name == "url" => args.iter().find_map(|a| match a {
+                    ComponentValue::Token(CssToken::String(s)) => Some(s.clone()),
+                    _ => None,
+                }),
+                _ => None,
+            };
+        }
+        "font-size" => {
+            if let Some(size) = ctx.font_size(first) {
+                style.font_size = size;
+            }
+        }
+        "font-weight" => {
+            let parent = ctx.parent.font_weight;
+            let weight = match (keyword.as_str(), first) {
+                ("normal", _) => 400,
+                ("bold", _) => 700,
+                ("bolder", _) => match parent {
+                    0..=349 => 400,
+                    350..=549 => 700,
+                    _ => 900,
+                },
+                ("lighter", _) => match parent {
+                    0..=549 => 100,
+                    550..=749 => 400,
+                    _ => 700,
+                },
+                (_, ComponentValue::Token(CssToken::Number(n))) if (1.0..=1000.0).contains(n) => *n as u16,
+                _ => return,
+            };
+            style.font_weight = weight;
+        }
+        "font-style" => match keyword.as_str() {
+            "italic" | "oblique" => style.font_italic = true,
+            "normal" => style.font_italic = false,
+            _ => {}
+        },
+        "font-family" => {
+            let families: Vec<String> = value
+                .split(|v| matches!(v, ComponentValue::Token(CssToken::Comma)))
+                .filter_map(|family| {
+                    let words: Vec<String> = family
+                        .iter()
+                        .filter_map(|v| match v {
+                            ComponentValue::Token(CssToken::Ident(s) | CssToken::String(s)) => Some(s.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    (!words.is_empty()).then(|| {
+                        let name = words.join(" ");
+                        let generic = name.to_ascii_lowercase();
+                        if matches!(generic.as_str(), "serif" | "sans-serif" | "monospace" | "cursive" | "fantasy" | "system-ui") {
+                            generic
+                        } else {
+                            name
+                        }
+                    })
+                })
+                .collect();
+            if !families.is_empty() {
+                style.font_family = families;
+            }
+        }
+        "line-height" => {
+            style.line_height = match first {
+                _ if keyword == "normal" => LineHeight::Normal,
+                ComponentValue::Token(CssToken::Number(n)) if *n >= 0.0 => LineHeight::Number(*n),
+                ComponentValue::Token(CssToken::Percentage(p)) => LineHeight::Px(font_size * p / 100.0),
+                v => match ctx.length(v, font_size) {
+                    Some(px) if px >= 0.0 => LineHeight::Px(px),
+                    _ => return,
+                },
+            };
+        }
+        "text-align" => {
+            style.text_align = match keyword.as_str() {
+                "left" | "start" | "-webkit-left" => TextAlign::Left,
+                "right" | "end" | "-webkit-right" => TextAlign::Right,
+                "center" | "-webkit-center" => TextAlign::Center,
+                "justify" => TextAlign::Justify,
+                _ => return,
+            };
+        }
+        "text-decoration-line" => {
+            let mut decoration = ctx.parent.text_decoration;
+            for v in &values {
+                match v.ident().as_deref() {
+                    Some("underline") => decoration.underline = true,
+                    Some("overline") => decoration.overline = true,
+                    Some("line-through") => decoration.line_through = true,
+                    Some("none") => {}
+                    _ => {}
+                }
+            }
+            style.text_decoration = decoration;
+        }
+        "text-indent" => {
+            if let Some(d) = ctx.dimension(first, font_size, false) {
+                style.text_indent = d;
+            }
+        }
+        "white-space" => {
+            style.white_space = match keyword.as_str() {
+                "normal" => WhiteSpace::Normal,
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" | "break-spaces" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                _ => return,
+            };
+        }
+        "vertical-align" => {
+            style.vertical_align = match keyword.as_str() {
+                "baseline" => VerticalAlign::Baseline,
+                "sub" => VerticalAlign::Sub,
+                "super" => VerticalAlign::Super,
+                "top" | "text-top" => VerticalAlign::Top,
+                "middle" => VerticalAlign::Middle,
+                "bottom" | "text-bottom" => VerticalAlign::Bottom,
+                _ => return,
+            };
+        }
+        "width" | "height" | "min-width" | "min-height" | "max-width" | "max-height" => {
+            let allow_auto = !name.starts_with("min-");
+            let Some(d) = ctx.dimension(first, font_size, allow_auto).filter(|d| !is_negative(*d)) else {
+                return;
+            };
+            match name {
+                "width" => style.width = d,
+                "height" => style.height = d,
+                "min-width" => style.min_width = d,
+                "min-height" => style.min_height = d,
+                "max-width" => style.max_width = d,
+                _ => style.max_height = d,
+            }
+        }
+        "list-style-type" => {
+            if let Some(list) = parse_list_style_type(&keyword) {
+                style.list_style_type = list;
+            }
+        }
+        "visibility" => match keyword.as_str() {
+            "visible" => style.visible = true,
+            "hidden" | "collapse" => style.visible = false,
+            _ => {}
+        },
+        "border-spacing" => {
+            if let Some(spacing) = ctx.length(first, font_size).filter(|s| *s >= 0.0) {
+                style.border_spacing = spacing;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rewrites `value` under the unitless length and hashless color quirks, if
+/// `name` is one of the properties they apply to.
+fn quirky_value(name: &str, value: &ComponentValue) -> Option<ComponentValue> {
+    let side = side_property(name).map(|(_, property)| property);
+    let unitless_length = matches!(side, Some("margin" | "padding" | "width"))
+        || matches!(
+            name,
+            "width"
+                | "height"
+                | "min-width"
+                | "min-height"
+                | "max-width"
+                | "max-height"
+                | "font-size"
+                | "text-indent"
+                | "border-spacing"
+        );
+    let hashless_color = side == Some("color") || matches!(name, "color" | "background-color");
+
+    match value {
+        ComponentValue::Token(CssToken::Number(n)) if unitless_length => {
+            Some(ComponentValue::Token(CssToken::Dimension(*n, "px".to_string())))
+        }
+        _ if hashless_color && Color::from_value(value).is_none() => {
+            // 数値トークンは先頭の 0 が失われるので 6 桁に詰め直す
+            let digits = match value {
+                ComponentValue::Token(CssToken::Ident(s)) => s.clone(),
+                ComponentValue::Token(CssToken::Number(n)) if n.fract() == 0.0 && *n >= 0.0 => format!("{n:06}"),
+                ComponentValue::Token(CssToken::Dimension(n, unit)) if n.fract() == 0.0 && *n >= 0.0 => {
+                    format!("{:0>6}", format!("{n}{unit}"))
+                }
+                _ => return None,
+            };
+            let valid = matches!(digits.len(), 3 | 6) && digits.chars().all(|c| c.is_ascii_hexdigit());
+            valid.then_some(ComponentValue::Token(CssToken::Hash(digits, false)))
+        }
+        _ => None,
+    }
+}
+
+fn is_negative(d: Dimension) -> bool {
+    matches!(d, Dimension::Px(v) | Dimension::Percent(v) if v < 0.0)
+}
+
+/// `margin-top` → `(0, "margin")`, `border-left-color` → `(3, "color")`.
+fn side_property(name: &str) -> Option<(usize, &str)> {
+    let (prefix, rest) = if let Some(rest) = name.strip_prefix("margin-") {
+        ("margin", rest)
+    } else if let Some(rest) = name.strip_prefix("padding-") {
+        ("padding", rest)
+    } else {
+        let rest = name.strip_prefix("border-")?;
+        let (side, property) = rest.split_once('-')?;
+        let side = SIDES.iter().position(|s| *s == side)?;
+        return Some((side, property));
+    };
+    let side = SIDES.iter().position(|s| *s == rest)?;
+    Some((side, prefix))
+}
+
+fn is_inherited(name: &str) -> bool {
+    matches!(
+        name,
+        "color" | "font-size" | "font-weight" | "font-style" | "font-family" | "line-height" | "text-align"
+            | "text-indent" | "white-space" | "list-style-type" | "visibility" | "border-spacing"
+    )
+}
+
+/// Copies the value of longhand `name` from `source`; used for `inherit` and friends.
+fn copy_property(style: &mut ComputedStyle, source: &ComputedStyle, name: &str) {
+    if let Some((side, property)) = side_property(name) {
+        match property {
+            "margin" => style.margin[side] = source.margin[side],
+            "padding" => style.padding[side] = source.padding[side],
+            "width" => style.border_width[side] = source.border_width[side],
+            "style" => style.border_style[side] = source.border_style[side],
+            "color" => style.border_color[side] = source.border_color[side],
+            _ => {}
+        }
+        return;
+    }
+    match name {
+        "display" => style.display = source.display,
+        "color" => style.color = source.color,
+        "background-color" => style.background_color = source.background_color,
+        "background-image" => style.background_image = source.background_image.clone(),
+        "font-size" => style.font_size = source.font_size,
+        "font-weight" => style.font_weight = source.font_weight,
+        "font-style" => style.font_italic = source.font_italic,
+        "font-family" => style.font_family = source.font_family.clone(),
+        "line-height" => style.line_height = source.line_height,
+        "text-align" => style.text_align = source.text_align,
+        "text-decoration-line" => style.text_decoration = source.text_decoration,
+        "text-indent" => style.text_indent = source.text_indent,
+        "white-space" => style.white_space = source.white_space,
+        "vertical-align" => style.vertical_align = source.vertical_align,
+        "width" => style.width = source.width,
+        "height" => style.height = source.height,
+        "min-width" => style.min_width = source.min_width,
+        "min-height" => style.min_height = source.min_height,
+        "max-width" => style.max_width = source.max_width,
+        "max-height" => style.max_height = source.max_height,
+        "list-style-type" => style.list_style_type = source.list_style_type,
+        "visibility" => style.visible = source.visible,
+        "border-spacing" => style.border_spacing = source.border_spacing,
+        _ => {}
+    }
+}
+
+fn parse_border_style(keyword: &str) -> Option<BorderStyle> {
+    Some(match keyword {
+        "none" => BorderStyle::None,
+        "hidden" => BorderStyle::Hidden,
+        "solid" => BorderStyle::Solid,
+        "dotted" => BorderStyle::Dotted,
+        "dashed" => BorderStyle::Dashed,
+        "double" => BorderStyle::Double,
+        "groove" => BorderStyle::Groove,
+        "ridge" => BorderStyle::Ridge,
+        "inset" => BorderStyle::Inset,
+        "outset" => BorderStyle::Outset,
+        _ => return None,
+    })
+}
+
+fn parse_list_style_type(keyword: &str) -> Option<ListStyleType> {
+    Some(match keyword {
+        "disc" => ListStyleType::Disc,
+        "circle" => ListStyleType::Circle,
+        "square" => ListStyleType::Square,
+        "decimal" | "decimal-leading-zero" => ListStyleType::Decimal,
+        "lower-alpha" | "lower-latin" => ListStyleType::LowerAlpha,
+        "upper-alpha" | "upper-latin" => ListStyleType::UpperAlpha,
+        "lower-roman" => ListStyleType::LowerRoman,
+        "upper-roman" => ListStyleType::UpperRoman,
+        "none" => ListStyleType::None,
+        _ => return None,
+    })
+}
+
+/// Short `property: value` summary of a computed style, for the DOM inspector.
+pub fn describe(style: &ComputedStyle) -> String {
+    let color = |c: Color| format!("#{:02x}{:02x}{:02x}{}", c.r, c.g, c.b, if c.a == 255 { String::new() } else { format!("{:02x}", c.a) });
+    let dimension = |d: Dimension| match d {
+        Dimension::Auto => "auto".to_string(),
+        Dimension::Px(px) => format!("{}px", px),
+        Dimension::Percent(p) => format!("{}%", p),
+    };
+    let sides = |s: [Dimension; 4]| s.map(dimension).join(" ");
+    let mut lines = vec![
+        format!("display: {:?}", style.display),
+        format!("color: {}", color(style.color)),
+        format!("font: {}{}{}px {}", if style.font_italic { "italic " } else { "" }, if style.font_weight != 400 { format!("{} ", style.font_weight) } else { String::new() }, style.font_size, style.font_family.join(", ")),
+        format!("margin: {}", sides(style.margin)),
+        format!("padding: {}", sides(style.padding)),
+    ];
+    if !style.background_color.is_transparent() {
+        lines.push(format!("background-color: {}", color(style.background_color)));
+    }
+    if (0..4).any(|side| style.used_border_width(side) > 0.0) {
+        lines.push(format!("border-width: {}", style.border_width.map(|w| format!("{}px", w)).join(" ")));
+    }
+    if style.width != Dimension::Auto || style.height != Dimension::Auto {
+        lines.push(format!("size: {} x {}", dimension(style.width), dimension(style.height)));
+    }
+    if style.text_align != TextAlign::Left {
+        lines.push(format!("text-align: {:?}", style.text_align));
+    }
+    if style.white_space != WhiteSpace::Normal {
+        lines.push(format!("white-space: {:?}", style.white_space));
+    }
+    lines.join("\n")
+}
+
+/// Tabs whose styles are out of date.
+type NeedsRestyle = Or<(Changed<CurrentDocument>, Changed<ExternalStylesheets>)>;
+
+/// Re-runs the cascade for every tab whose document or stylesheets changed.
+pub fn restyle_document_system(
+    mut pages: Query<(&CurrentDocument, &ExternalStylesheets, &mut CurrentStyles), NeedsRestyle>,
+) {
+    for (current_document, external_stylesheets, mut current_styles) in &mut pages {
+        let doc = &current_document.0;
+        if doc.document_element().is_none() {
+            current_styles.0 = StyleMap::default();
+            continue;
+        }
+        current_styles.0 = compute_styles(doc, &external_stylesheets.0, &MediaContext::default());
+        let styled = doc
+            .nodes
+            .iter()
+            .filter(|n| matches!(n.data, NodeData::Element(_)))
+            .count();
+        debug!("restyled {} elements", styled);
+    }
+}
@@ -0,0 +1,172 @@
+//! Tabs: every tab is an entity carrying its own page state (URL, source,
+//! document, styles, layout, history and compatibility mode), and [`Tabs`]
+//! keeps their order in the tab strip and which one is shown.
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+use crate::history::{Navigate, NavigationAction};
+use crate::viewers::duplicate_page;
+use crate::{BrowsingHistory, CompatModeOverride, CurrentDocument, CurrentUrl, DocumentEncoding, EncodingOverride, HtmlContent, NativePage, ResponseBody, Tab, Tabs};
+
+const START_URL: &str = "https://example.com";
+/// Tab labels longer than this are cut off with an ellipsis.
+const MAX_TITLE_CHARS: usize = 24;
+
+/// A change to the tab strip.
+#[derive(Event, Clone, Debug)]
+pub enum TabAction {
+    /// Open a new tab with this URL in the address bar and switch to it.
+    Open(String),
+    /// Open a new tab loading this URL behind the current one (middle click
+    /// on a link). `initiator` is the page the link is on, if any.
+    OpenInBackground { url: String, initiator: Option<String> },
+    Select(Entity),
+    Close(Entity),
+    /// Open a copy of the tab (page and history) right after it.
+    Duplicate(Entity),
+    /// Move the tab by this many places in the strip.
+    Move(Entity, isize),
+}
+
+/// Raw body and text encoding of a page, and its native view.
+type PageEncoding = (&'static ResponseBody, &'static EncodingOverride, &'static DocumentEncoding, &'static NativePage);
+
+// 起動時に最初のタブを作るシステム
+pub fn setup_first_tab(mut commands: Commands) {
+    let first = commands.spawn((Tab, CurrentUrl(START_URL.to_string()))).id();
+    commands.insert_resource(Tabs {
+        order: vec![first],
+        active: first,
+    });
+}
+
+// タブの追加・切り替え・閉じる・複製・並べ替えを反映するシステム
+pub fn apply_tab_actions(
+    mut commands: Commands,
+    mut events: EventReader<TabAction>,
+    mut tabs: ResMut<Tabs>,
+    pages: Query<(&CurrentUrl, &HtmlContent, &CurrentDocument, &BrowsingHistory, &CompatModeOverride, PageEncoding)>,
+    mut navigate: EventWriter<Navigate>,
+) {
+    for action in events.read() {
+        match action {
+            TabAction::Open(url) => {
+                let tab = commands.spawn((Tab, CurrentUrl(url.clone()))).id();
+                tabs.order.push(tab);
+                tabs.active = tab;
+            }
+            TabAction::OpenInBackground { url, initiator } => {
+                // 選択は動かさずに読み込みだけ始める
+                let tab = commands.spawn((Tab, CurrentUrl(url.clone()))).id();
+                tabs.order.push(tab);
+                let action = match initiator {
+                    Some(initiator) => NavigationAction::Link {
+                        url: url.clone(),
+                        initiator: initiator.clone(),
+                    },
+                    None => NavigationAction::To(url.clone()),
+                };
+                navigate.write(Navigate { tab, action });
+            }
+            TabAction::Select(tab) => {
+                if tabs.order.contains(tab) {
+                    tabs.active = *tab;
+                }
+            }
+            TabAction::Close(tab) => {
+                let Some(index) = tabs.order.iter().position(|t| t == tab) else {
+                    continue;
+                };
+                tabs.order.remove(index);
+                commands.entity(*tab).despawn();
+                // 最後のタブを閉じたら空のタブを 1 つ残す
+                if tabs.order.is_empty() {
+                    tabs.order.push(commands.spawn(Tab).id());
+                }
+                if tabs.active == *tab {
+                    tabs.active = tabs.order[index.min(tabs.order.len() - 1)];
+                }
+            }
+            TabAction::Duplicate(tab) => {
+                let Ok((url, html, document, history, compat_mode, (body, encoding_override, document_encoding, native_page))) = pages.get(*tab) else {
+                    continue;
+                };
+                let copy = commands
+                    .spawn((
+                        Tab,
+                        url.clone(),
+                        html.clone(),
+                        document.clone(),
+                        history.clone(),
+                        *compat_mode,
+                        (body.clone(), *encoding_override, *document_encoding, duplicate_page(native_page)),
+                    ))
+                    .id();
+                let index = tabs.order.iter().position(|t| t == tab).map_or(tabs.order.len(), |i| i + 1);
+                tabs.order.insert(index, copy);
+                tabs.active = copy;
+            }
+            TabAction::Move(tab, offset) => {
+                let Some(index) = tabs.order.iter().position(|t| t == tab) else {
+                    continue;
+                };
+                let target = index.saturating_add_signed(*offset).min(tabs.order.len() - 1);
+                let moved = tabs.order.remove(index);
+                tabs.order.insert(target, moved);
+            }
+        }
+    }
+}
+
+/// Label for a tab: the page title, else its URL.
+pub fn tab_title(url: &CurrentUrl, document: &CurrentDocument) -> String {
+    let title = document.0.title().unwrap_or_else(|| url.0.clone());
+    if title.is_empty() {
+        return "New Tab".to_string();
+    }
+    if title.chars().count() > MAX_TITLE_CHARS {
+        let cut: String = title.chars().take(MAX_TITLE_CHARS - 1).collect();
+        return format!("{cut}…");
+    }
+    title
+}
+
+/// Draws the tab strip. `titles` holds the label of each tab in `tabs.order`.
+pub fn tab_strip_ui(ui: &mut egui::Ui, tabs: &Tabs, titles: &[String], actions: &mut EventWriter<TabAction>) {
+    let last = tabs.order.len() - 1;
+    for (index, (&tab, title)) in tabs.order.iter().zip(titles).enumerate() {
+        let response = ui.selectable_label(tab == tabs.active, title);
+        if response.clicked() {
+            actions.write(TabAction::Select(tab));
+        }
+        if response.middle_clicked() {
+            actions.write(TabAction::Close(tab));
+        }
+        response.context_menu(|ui| {
+            if ui.button("Duplicate").clicked() {
+                actions.write(TabAction::Duplicate(tab));
+                ui.close_menu();
+            }
+            if ui.add_enabled(index > 0, egui::Button::new("Move left")).clicked() {
+                actions.write(TabAction::Move(tab, -1));
+                ui.close_menu();
+            }
+            if ui.add_enabled(index < last, egui::Button::new("Move right")).clicked() {
+                actions.write(TabAction::Move(tab, 1));
+                ui.close_menu();
+            }
+            if ui.button("Close").clicked() {
+                actions.write(TabAction::Close(tab));
+                ui.close_menu();
+            }
+        });
+        if ui.small_button("×").on_hover_text("Close tab").clicked() {
+            actions.write(TabAction::Close(tab));
+        }
+        ui.separator();
+    }
+    if ui.button("+").on_hover_text("New tab").clicked() {
+        actions.write(TabAction::Open(String::new()));
+    }
+}
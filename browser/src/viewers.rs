@@ -0,0 +1,326 @@
+//! Internal viewers for responses that are not HTML: each response is
+//! routed by its MIME type (sniffed from the first bytes when the server
+//! sent none) to the HTML view, the text view, a JSON tree, an image view,
+//! an ffmpeg [`VideoPlayer`](crate::ffmpeg::VideoPlayer) or the 3D scene.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::gltf::GltfAssetLabel;
+use bevy::prelude::*;
+use futures_lite::future;
+
+use crate::ffmpeg::VideoResource;
+use crate::images::ImageState;
+use crate::{AsyncComputeTaskPool, DecodePageImageTask, NativePage, PageViewer, ResponseBody, Tabs};
+
+/// Directory under `assets/` where media and model pages are written, since
+/// ffmpeg and the asset server read from files.
+const PAGE_ASSET_DIR: &str = "pages";
+
+/// Where a page model is put in the scene, next to the fox.
+const MODEL_POSITION: Vec3 = Vec3::new(4.0, 0.0, 0.0);
+
+/// What shows a response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Viewer {
+    /// HTML, XML and the other text formats that go through the HTML view
+    /// (gemtext and gopher menus included).
+    Document,
+    Text,
+    Json,
+    Image,
+    /// Video, played by ffmpeg.
+    Media,
+    /// A glTF binary, put into the 3D scene.
+    Model,
+    /// Nothing; it is saved by the download manager.
+    Download,
+}
+
+/// The MIME type without parameters, lowercased.
+pub fn essence(content_type: Option<&str>) -> String {
+    content_type.unwrap_or("").split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// The viewer for a content type. A missing type counts as a document, so
+/// that the body is read and can be sniffed.
+pub fn viewer_for(content_type: Option<&str>) -> Viewer {
+    let essence = essence(content_type);
+    match essence.as_str() {
+        "" | "text/html" | "application/xhtml+xml" | "application/xml" | "text/xml" => Viewer::Document,
+        "text/plain" => Viewer::Text,
+        "application/json" => Viewer::Json,
+        "model/gltf-binary" => Viewer::Model,
+        "application/ogg" => Viewer::Media,
+        // image クレートでデコードできるものだけ
+        "image/png" | "image/jpeg" | "image/gif" | "image/webp" | "image/bmp" | "image/x-icon" | "image/vnd.microsoft.icon" => Viewer::Image,
+        _ if essence.ends_with("+json") => Viewer::Json,
+        _ if essence.starts_with("text/") || essence.ends_with("+xml") => Viewer::Document,
+        _ if essence.starts_with("video/") => Viewer::Media,
+        // プレイヤーは映像ストリームが要るので、音声だけのものは保存に回す
+        _ if essence.starts_with("audio/") => Viewer::Download,
+        "application/javascript" | "application/x-javascript" => Viewer::Document,
+        crate::gemini::INPUT_CONTENT_TYPE => Viewer::Document,
+        _ => Viewer::Download,
+    }
+}
+
+/// The content type a response is handled as: the declared one, or one
+/// sniffed from `body` when none was sent or `text/plain` came with binary
+/// bytes (servers send that for anything they don't know).
+pub fn effective_content_type(declared: Option<&str>, body: &[u8]) -> Option<String> {
+    let essence = essence(declared);
+    let unknown = matches!(essence.as_str(), "" | "unknown/unknown" | "application/unknown" | "*/*");
+    if unknown || (essence == "text/plain" && is_binary(body)) {
+        return Some(sniff(body).to_string());
+    }
+    declared.map(str::to_string)
+}
+
+/// Guesses a content type from the first bytes, after the signatures of the
+/// MIME Sniffing Standard (plus glTF and JSON).
+pub fn sniff(body: &[u8]) -> &'static str {
+    let head = &body[..body.len().min(512)];
+    let starts = |signature: &[u8]| head.starts_with(signature);
+    let riff = |form: &[u8]| starts(b"RIFF") && head.get(8..12) == Some(form);
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if starts(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        "image/gif"
+    } else if riff(b"WEBP") {
+        "image/webp"
+    } else if starts(b"BM") {
+        "image/bmp"
+    } else if starts(b"\x00\x00\x01\x00") {
+        "image/x-icon"
+    } else if starts(b"glTF") {
+        "model/gltf-binary"
+    } else if head.get(4..8) == Some(b"ftyp") {
+        if head.get(8..11) == Some(b"M4A") { "audio/mp4" } else { "video/mp4" }
+    } else if starts(b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if starts(b"OggS") {
+        "application/ogg"
+    } else if starts(b"ID3") || starts(b"\xff\xfb") || starts(b"\xff\xf3") || starts(b"\xff\xf2") {
+        "audio/mpeg"
+    } else if riff(b"WAVE") {
+        "audio/wav"
+    } else if riff(b"AVI ") {
+        "video/avi"
+    } else if starts(b"fLaC") {
+        "audio/flac"
+    } else if starts(b"%PDF-") {
+        "application/pdf"
+    } else if starts(b"PK\x03\x04") {
+        "application/zip"
+    } else if starts(b"\x1f\x8b") {
+        "application/gzip"
+    } else if let Some(kind) = sniff_markup(head) {
+        kind
+    } else if body.first().is_some_and(|b| matches!(b, b'{' | b'[')) && serde_json::from_slice::<serde_json::Value>(body).is_ok() {
+        "application/json"
+    } else if is_binary(head) {
+        "application/octet-stream"
+    } else {
+        "text/plain"
+    }
+}
+
+// 先頭の空白の後が HTML のタグや XML 宣言か
+fn sniff_markup(head: &[u8]) -> Option<&'static str> {
+    let start = head.iter().position(|b| !b" \t\n\x0c\r".contains(b))?;
+    let rest = &head[start..];
+    const HTML_TAGS: [&[u8]; 17] = [
+        b"<!DOCTYPE HTML", b"<HTML", b"<HEAD", b"<SCRIPT", b"<IFRAME", b"<H1", b"<DIV", b"<FONT", b"<TABLE", b"<A", b"<STYLE", b"<TITLE",
+        b"<B", b"<BODY", b"<BR", b"<P", b"<!--",
+    ];
+    for tag in HTML_TAGS {
+        // タグ名の後は空白か > でなければならない
+        if rest.len() > tag.len() && rest[..tag.len()].eq_ignore_ascii_case(tag) && matches!(rest[tag.len()], b' ' | b'>') {
+            return Some("text/html");
+        }
+    }
+    rest.starts_with(b"<?xml").then_some("text/xml")
+}
+
+/// Whether `bytes` has control characters that plain text never does.
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(512)].iter().any(|b| matches!(b, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f))
+}
+
+/// The native page for a response that has its own viewer, or `None` for
+/// the ones that are decoded as text.
+pub fn binary_page(viewer: Viewer) -> Option<NativePage> {
+    match viewer {
+        Viewer::Image => Some(NativePage::Image(ImageState::Loading)),
+        Viewer::Media => Some(NativePage::Media(MediaState::Opening)),
+        Viewer::Model => Some(NativePage::Model(ModelState::Opening)),
+        _ => None,
+    }
+}
+
+/// A video page.
+#[derive(Clone, Debug)]
+pub enum MediaState {
+    Opening,
+    Playing { player: Entity, texture: Handle<Image>, size: Vec2 },
+    Failed(String),
+}
+
+/// A model page.
+#[derive(Clone, Debug)]
+pub enum ModelState {
+    Opening,
+    Shown(Entity),
+    Failed(String),
+}
+
+/// The page for a copy of a tab. Videos and models are entities owned by
+/// one tab, so the copy opens its own from the body instead of sharing them.
+pub fn duplicate_page(page: &NativePage) -> NativePage {
+    match page {
+        NativePage::Media(MediaState::Playing { .. }) => NativePage::Media(MediaState::Opening),
+        NativePage::Model(ModelState::Shown(_)) => NativePage::Model(ModelState::Opening),
+        page => page.clone(),
+    }
+}
+
+// 画像・動画・モデルのページが来たら、デコードや再生の準備を始めるシステム
+pub fn open_page_viewers(
+    mut commands: Commands,
+    mut pages: Query<(Entity, &ResponseBody, &mut NativePage), Changed<NativePage>>,
+    mut images: ResMut<Assets<Image>>,
+    mut video_resource: NonSendMut<VideoResource>,
+    asset_server: Res<AssetServer>,
+) {
+    for (tab, body, mut native_page) in &mut pages {
+        match &*native_page {
+            NativePage::Image(ImageState::Loading) => {
+                let bytes = body.bytes.clone();
+                let task = AsyncComputeTaskPool::get().spawn(async move { crate::images::decode_image(&bytes) });
+                commands.spawn(DecodePageImageTask { tab, url: body.url.clone(), task });
+            }
+            NativePage::Media(MediaState::Opening) => {
+                let opened = write_page_asset(body, "media").and_then(|path| {
+                    crate::ffmpeg::spawn_video_player(&mut commands, &mut images, &mut video_resource, &path, PageViewer { tab, file: path.clone() })
+                        .map_err(|e| {
+                            remove_page_asset(&path);
+                            format!("ffmpeg could not play this file: {}", e)
+                        })
+                });
+                *native_page = NativePage::Media(match opened {
+                    Ok((player, texture, size)) => MediaState::Playing { player, texture, size },
+                    Err(e) => {
+                        warn!("{}: {}", body.url, e);
+                        MediaState::Failed(e)
+                    }
+                });
+            }
+            NativePage::Model(ModelState::Opening) => {
+                *native_page = NativePage::Model(match write_page_asset(body, "glb") {
+                    Ok(path) => {
+                        // アセットサーバーからは assets/ からの相対パスで読む
+                        let asset_path = format!("{}/{}", PAGE_ASSET_DIR, path.file_name().unwrap_or_default().to_string_lossy());
+                        let scene = commands
+                            .spawn((
+                                SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(asset_path))),
+                                Transform::from_translation(MODEL_POSITION),
+                                PageViewer { tab, file: path },
+                            ))
+                            .id();
+                        ModelState::Shown(scene)
+                    }
+                    Err(e) => ModelState::Failed(e),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+// 本文を assets/pages/<内容のハッシュ>.<拡張子> に書き出す (同じものは書き直さない)
+fn write_page_asset(body: &ResponseBody, extension: &str) -> Result<PathBuf, String> {
+    let dir = FileAssetReader::get_base_path().join("assets").join(PAGE_ASSET_DIR);
+    std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, &body.bytes);
+    let name: String = digest.as_ref()[..12].iter().map(|b| format!("{:02x}", b)).collect();
+    let path = dir.join(format!("{}.{}", name, extension));
+    if !path.exists() {
+        std::fs::write(&path, &body.bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(path)
+}
+
+fn remove_page_asset(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+    }
+}
+
+// 画像ページのデコードの完了を監視し、タブに渡すシステム
+pub fn poll_decode_page_image_tasks(
+    mut commands: Commands,
+    mut tasks: Query<(Entity, &mut DecodePageImageTask)>,
+    mut pages: Query<(&ResponseBody, &mut NativePage)>,
+    mut assets: ResMut<Assets<Image>>,
+) {
+    for (entity, mut decode) in &mut tasks {
+        let Some(result) = future::block_on(future::poll_once(&mut decode.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+        // デコード中に別のページへ移っていたら捨てる
+        let Ok((body, mut native_page)) = pages.get_mut(decode.tab) else {
+            continue;
+        };
+        if body.url != decode.url || !matches!(*native_page, NativePage::Image(ImageState::Loading)) {
+            continue;
+        }
+        *native_page = NativePage::Image(match result {
+            Ok(image) => {
+                let size = image.size_f32();
+                ImageState::Loaded { handle: assets.add(image), size }
+            }
+            Err(e) => ImageState::Failed(e.to_string()),
+        });
+    }
+}
+
+// 別のページに移ったり閉じたりしたタブの動画・モデルを片付け、
+// モデルは選択中のタブのものだけ見せるシステム
+pub fn despawn_stale_viewers(
+    mut commands: Commands,
+    mut viewers: Query<(Entity, &PageViewer, Option<&mut Visibility>)>,
+    pages: Query<&NativePage>,
+    tabs: Res<Tabs>,
+    mut video_resource: NonSendMut<VideoResource>,
+) {
+    let is_current = |entity: Entity, viewer: &PageViewer| match pages.get(viewer.tab) {
+        Ok(NativePage::Media(MediaState::Playing { player, .. })) => *player == entity,
+        Ok(NativePage::Model(ModelState::Shown(scene))) => *scene == entity,
+        _ => false,
+    };
+    // ファイル名は内容のハッシュなので、同じものを見ている別のタブがあれば残す
+    let in_use: HashSet<PathBuf> =
+        viewers.iter().filter(|(entity, viewer, _)| is_current(*entity, viewer)).map(|(_, viewer, _)| viewer.file.clone()).collect();
+    for (entity, viewer, visibility) in &mut viewers {
+        if !is_current(entity, viewer) {
+            commands.entity(entity).despawn();
+            video_resource.video_players.remove(&entity);
+            if !in_use.contains(&viewer.file) {
+                remove_page_asset(&viewer.file);
+            }
+            continue;
+        }
+        if let Some(mut visibility) = visibility {
+            let wanted = if viewer.tab == tabs.active { Visibility::Inherited } else { Visibility::Hidden };
+            visibility.set_if_neq(wanted);
+        }
+    }
+}